@@ -1,13 +1,110 @@
-import { Controller, UseGuards } from '@nestjs/common';
+import {
+  BadRequestException,
+  Body,
+  Controller,
+  Param,
+  ParseIntPipe,
+  Post,
+  UseGuards,
+} from '@nestjs/common';
+import { z } from 'zod';
 import { AdminService } from './admin.service';
 import { AuthGuard } from 'src/auth/auth.guard';
 import { RolesGuard } from 'src/auth/roles.guard';
 import { Roles } from 'src/decorators/roles.decorator';
 import { ROLES } from 'src/constants';
 
+// Without outcomeId the auction covers every outcome of the market
+export const auctionSchema = z
+  .object({
+    outcomeId: z.string().min(1).optional(),
+  })
+  .strict();
+
+export const matchingModeSchema = z.discriminatedUnion('mode', [
+  z.object({ mode: z.literal('continuous') }).strict(),
+  z
+    .object({
+      mode: z.literal('batch'),
+      intervalMs: z.number().int().min(100).max(60000).default(1000),
+    })
+    .strict(),
+]);
+
+export const allocationSchema = z
+  .object({
+    allocation: z.enum(['fifo', 'pro_rata', 'top_order_pro_rata']),
+  })
+  .strict();
+
 @Controller('admin')
 @UseGuards(AuthGuard, RolesGuard)
 @Roles(ROLES.ADMIN)
 export class AdminController {
   constructor(private readonly adminService: AdminService) {}
+
+  @Post('markets/:marketId/cancel-all')
+  async cancelMarketOrders(@Param('marketId', ParseIntPipe) marketId: number) {
+    return await this.adminService.cancelMarketOrders(marketId);
+  }
+
+  @Post('markets/:marketId/auction/start')
+  async startAuction(
+    @Param('marketId', ParseIntPipe) marketId: number,
+    @Body() raw: any,
+  ) {
+    const parsed = await auctionSchema.safeParseAsync(raw ?? {});
+    if (parsed.error) {
+      throw new BadRequestException('Invalid request body');
+    }
+    return await this.adminService.startAuction(
+      marketId,
+      parsed.data.outcomeId,
+    );
+  }
+
+  @Post('markets/:marketId/auction/uncross')
+  async uncrossAuction(
+    @Param('marketId', ParseIntPipe) marketId: number,
+    @Body() raw: any,
+  ) {
+    const parsed = await auctionSchema.safeParseAsync(raw ?? {});
+    if (parsed.error) {
+      throw new BadRequestException('Invalid request body');
+    }
+    return await this.adminService.uncrossAuction(
+      marketId,
+      parsed.data.outcomeId,
+    );
+  }
+
+  @Post('markets/:marketId/matching-mode')
+  async setMatchingMode(
+    @Param('marketId', ParseIntPipe) marketId: number,
+    @Body() raw: any,
+  ) {
+    const parsed = await matchingModeSchema.safeParseAsync(raw ?? {});
+    if (parsed.error) {
+      throw new BadRequestException('Invalid request body');
+    }
+    return await this.adminService.setMatchingMode(
+      marketId,
+      parsed.data.mode === 'batch' ? parsed.data.intervalMs : undefined,
+    );
+  }
+
+  @Post('markets/:marketId/allocation')
+  async setAllocation(
+    @Param('marketId', ParseIntPipe) marketId: number,
+    @Body() raw: any,
+  ) {
+    const parsed = await allocationSchema.safeParseAsync(raw ?? {});
+    if (parsed.error) {
+      throw new BadRequestException('Invalid request body');
+    }
+    return await this.adminService.setAllocation(
+      marketId,
+      parsed.data.allocation,
+    );
+  }
 }
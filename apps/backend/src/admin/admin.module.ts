@@ -3,8 +3,10 @@ import { AdminService } from './admin.service';
 import { AdminController } from './admin.controller';
 import { PrismaService } from 'src/prisma.service';
 import { AuthService } from 'src/auth/auth.service';
+import { RedisModule } from 'src/redis/redis.module';
 
 @Module({
+  imports: [RedisModule],
   providers: [AdminService, PrismaService, AuthService],
   controllers: [AdminController],
 })
@@ -1,6 +1,72 @@
 import { Injectable } from '@nestjs/common';
+import { RedisPublisherService } from 'src/redis/redis.publisher.service';
+import { MarketConfigureEvent } from 'src/redis/redis-publisher.event-types';
 
 @Injectable()
 export class AdminService {
-  constructor() {}
+  constructor(private readonly redisPublisherService: RedisPublisherService) {}
+
+  /** Kill switch: cancels every resting order of the market. */
+  async cancelMarketOrders(marketId: number) {
+    await this.redisPublisherService.pushOrderCommand({
+      type: 'order.cancel_all',
+      market_id: marketId,
+      timestamp: new Date().toISOString(),
+    });
+    return { success: true };
+  }
+
+  /**
+   * Starts a call auction in the outcome's book, or in every book of the
+   * market: orders rest without matching until the auction is uncrossed.
+   */
+  async startAuction(marketId: number, outcomeId?: string) {
+    await this.redisPublisherService.pushOrderCommand({
+      type: 'auction.start',
+      market_id: marketId,
+      ...(outcomeId !== undefined && { outcome_id: outcomeId }),
+      timestamp: new Date().toISOString(),
+    });
+    return { success: true };
+  }
+
+  /** Ends the auctions of the market, or the outcome's, at one price each. */
+  async uncrossAuction(marketId: number, outcomeId?: string) {
+    await this.redisPublisherService.pushOrderCommand({
+      type: 'auction.uncross',
+      market_id: marketId,
+      ...(outcomeId !== undefined && { outcome_id: outcomeId }),
+      timestamp: new Date().toISOString(),
+    });
+    return { success: true };
+  }
+
+  /**
+   * Switches the market to frequent batch auctions cleared every
+   * `intervalMs`, or back to continuous matching when it is undefined.
+   */
+  async setMatchingMode(marketId: number, intervalMs?: number) {
+    await this.redisPublisherService.pushOrderCommand({
+      type: 'market.configure',
+      market_id: marketId,
+      matching_mode:
+        intervalMs === undefined ? 'continuous' : `batch:${intervalMs}`,
+      timestamp: new Date().toISOString(),
+    });
+    return { success: true };
+  }
+
+  /** Changes how takers are shared among the orders at a price. */
+  async setAllocation(
+    marketId: number,
+    allocation: NonNullable<MarketConfigureEvent['allocation']>,
+  ) {
+    await this.redisPublisherService.pushOrderCommand({
+      type: 'market.configure',
+      market_id: marketId,
+      allocation,
+      timestamp: new Date().toISOString(),
+    });
+    return { success: true };
+  }
 }
@@ -20,7 +20,9 @@ import { OrderSide, OrderType, TimeInForce } from 'generated/prisma/enums';
 const createOrderSchema = z
   .object({
     outcomeId: z.uuid(),
-    quantity: z.number().positive('Quantity must be greater than 0'),
+    quantity: z.number().positive('Quantity must be greater than 0').optional(),
+    // Coins a MARKET buy spends instead of buying a quantity
+    notional: z.number().int().positive().optional(),
     price: z
       .number()
       .gt(0, 'Price must be greater than 0')
@@ -34,8 +36,50 @@ const createOrderSchema = z
     timeInForce: z
       .enum([TimeInForce.GTC, TimeInForce.IOC, TimeInForce.FOK])
       .optional(),
+    // Slippage protection of MARKET orders: the worst price to trade at, or
+    // how many cents past the best price on arrival
+    protectionPrice: z
+      .number()
+      .gt(0, 'Protection price must be greater than 0')
+      .lte(1, 'Protection price must be less than or equal to 1')
+      .refine((p) => Number.isInteger(p * 100), {
+        message: 'Protection price must have at most 2 decimal places',
+      })
+      .optional(),
+    maxSlippageTicks: z.number().int().min(0).max(100).optional(),
+    // Holds a MARKET order as a trailing stop, its trigger trailing the last
+    // trade by this many cents
+    trailTicks: z.number().int().min(1).max(99).optional(),
+    // Part of a LIMIT order shown in the book, the rest stays hidden
+    displayQuantity: z.number().int().positive().optional(),
+    // LIMIT orders can refuse fills below a size, or any fill but one of
+    // all they have left
+    minQuantity: z.number().int().positive().optional(),
+    allOrNone: z.boolean().optional(),
+    // GTC LIMIT orders can follow the book instead of resting at their
+    // price, which then caps them; offset is in cents
+    peg: z
+      .object({
+        reference: z.enum(['best_bid', 'best_ask', 'mid']),
+        offset: z.number().int().min(-100).max(100).default(0),
+      })
+      .optional(),
   })
   .strict()
+  .refine(
+    (data) => {
+      if (data.notional === undefined) return data.quantity !== undefined;
+      return (
+        data.quantity === undefined &&
+        data.orderType === OrderType.MARKET &&
+        data.side === OrderSide.Buy
+      );
+    },
+    {
+      message: 'Give a quantity, or a notional for MARKET buys',
+      path: ['quantity'],
+    },
+  )
   .refine(
     (data) => {
       if (data.orderType === OrderType.MARKET && data.price !== undefined)
@@ -49,6 +93,60 @@ const createOrderSchema = z
       path: ['price'],
     },
   )
+  .refine(
+    (data) =>
+      data.displayQuantity === undefined ||
+      (data.orderType === OrderType.LIMIT &&
+        data.quantity !== undefined &&
+        data.displayQuantity <= data.quantity),
+    {
+      message: 'Display quantity only applies to LIMIT orders, up to quantity',
+      path: ['displayQuantity'],
+    },
+  )
+  .refine(
+    (data) =>
+      (data.minQuantity === undefined && !data.allOrNone) ||
+      (data.orderType === OrderType.LIMIT &&
+        data.displayQuantity === undefined &&
+        (data.minQuantity === undefined ||
+          (data.quantity !== undefined &&
+            data.minQuantity <= data.quantity))),
+    {
+      message:
+        'Fill constraints only apply to LIMIT orders without a display ' +
+        'quantity, with a minimum up to quantity',
+      path: ['minQuantity'],
+    },
+  )
+  .refine(
+    (data) =>
+      data.minQuantity === undefined ||
+      (!data.allOrNone && data.timeInForce !== TimeInForce.FOK),
+    {
+      message: 'All-or-none and FOK orders take no minimum quantity',
+      path: ['minQuantity'],
+    },
+  )
+  .refine(
+    (data) =>
+      data.trailTicks === undefined ||
+      (data.orderType === OrderType.MARKET && data.notional === undefined),
+    {
+      message: 'Only MARKET orders with a quantity can be trailing stops',
+      path: ['trailTicks'],
+    },
+  )
+  .refine(
+    (data) =>
+      data.peg === undefined ||
+      (data.orderType === OrderType.LIMIT &&
+        data.timeInForce === TimeInForce.GTC),
+    {
+      message: 'Only GTC LIMIT orders can be pegged',
+      path: ['peg'],
+    },
+  )
   .refine(
     (data) => {
       if (data.orderType === OrderType.MARKET && data.timeInForce) {
@@ -60,6 +158,20 @@ const createOrderSchema = z
       message: "Market order doesn't support time in force",
       path: ['timeInForce'],
     },
+  )
+  .refine(
+    (data) => {
+      const protections =
+        Number(data.protectionPrice !== undefined) +
+        Number(data.maxSlippageTicks !== undefined);
+      if (data.orderType === OrderType.LIMIT) return protections === 0;
+      return protections <= 1;
+    },
+    {
+      message:
+        'Market orders take a protection price or max slippage ticks, not both',
+      path: ['protectionPrice'],
+    },
   );
 
 export type TCreateOrderSchema = z.infer<typeof createOrderSchema>;
@@ -71,6 +183,26 @@ export const canSellOrderSchema = z.object({
 
 export type TCanSellOrderSchema = z.infer<typeof canSellOrderSchema>;
 
+export const cancelAllOrdersSchema = z
+  .object({
+    marketId: z.number().int().positive().optional(),
+  })
+  .strict();
+
+export const heartbeatSchema = z
+  .object({
+    marketId: z.number().int().positive().optional(),
+    // 0 leaves cancel-on-disconnect
+    timeoutMs: z
+      .number()
+      .int()
+      .refine((ms) => ms === 0 || (ms >= 1000 && ms <= 300000), {
+        message: 'timeoutMs must be 0 or between 1000 and 300000',
+      })
+      .optional(),
+  })
+  .strict();
+
 @Controller('order')
 @UseGuards(AuthGuard, RolesGuard, AccountGuard)
 @Roles(ROLES.COMMON)
@@ -93,6 +225,33 @@ export class OrderController {
     return await this.orderService.cancelOrder(+accountId, +orderId);
   }
 
+  @Post('cancel-all')
+  async cancelAll(@Req() req: AppRequest, @Body() raw: any) {
+    const accountId = req.user.accountId!;
+    const parsed = await cancelAllOrdersSchema.safeParseAsync(raw ?? {});
+    if (parsed.error) {
+      throw new BadRequestException('Invalid request body');
+    }
+    return await this.orderService.cancelAllOrders(
+      +accountId,
+      parsed.data.marketId,
+    );
+  }
+
+  @Post('heartbeat')
+  async heartbeat(@Req() req: AppRequest, @Body() raw: any) {
+    const accountId = req.user.accountId!;
+    const parsed = await heartbeatSchema.safeParseAsync(raw ?? {});
+    if (parsed.error) {
+      throw new BadRequestException('Invalid request body');
+    }
+    return await this.orderService.heartbeat(
+      +accountId,
+      parsed.data.marketId,
+      parsed.data.timeoutMs,
+    );
+  }
+
   @Post('can-sell')
   async canSell(@Req() req: AppRequest, @Body() raw: any) {
     const accountId = req.user.accountId!;
@@ -0,0 +1,67 @@
+import { OrderService } from './order.service';
+import { PrismaService } from 'src/prisma.service';
+import { RedisPublisherService } from 'src/redis/redis.publisher.service';
+import { TCreateOrderSchema } from './order.controller';
+import { OrderSide, OrderType } from 'generated/prisma/enums';
+
+describe('OrderService', () => {
+  const accountUpdate = jest.fn();
+  const prisma = {
+    account: {
+      findUnique: jest.fn().mockResolvedValue({
+        id: 1,
+        coins: 10_000,
+        reservedCoins: 0,
+      }),
+      update: accountUpdate,
+    },
+    outcome: {
+      findUnique: jest.fn().mockResolvedValue({
+        id: 'outcome-1',
+        name: 'Yes',
+        marketId: 1,
+      }),
+    },
+  };
+  // The fair price is 40 cents
+  const redis = {
+    getOrderBook: jest.fn().mockResolvedValue(40),
+    pushOrderCommand: jest.fn().mockResolvedValue(undefined),
+  };
+  const service = new OrderService(
+    prisma as unknown as PrismaService,
+    redis as unknown as RedisPublisherService,
+  );
+
+  const reserved = () =>
+    accountUpdate.mock.calls[0][0].data.reservedCoins.increment as number;
+
+  beforeEach(() => accountUpdate.mockClear());
+
+  it('reserves a full coin per contract for a slippage bound', async () => {
+    // The best ask may sit at 70 cents, far above the fair price, and the
+    // engine bounds the order from there: it can pay up to 75 cents
+    const body = {
+      outcomeId: 'outcome-1',
+      side: OrderSide.Buy,
+      orderType: OrderType.MARKET,
+      quantity: 10,
+      maxSlippageTicks: 5,
+    } as TCreateOrderSchema;
+    await service.placeOrder(1, body);
+    expect(reserved()).toBe(100 * 10);
+    expect(reserved()).toBeGreaterThanOrEqual(75 * 10);
+  });
+
+  it('reserves up to the protection price', async () => {
+    const body = {
+      outcomeId: 'outcome-1',
+      side: OrderSide.Buy,
+      orderType: OrderType.MARKET,
+      quantity: 10,
+      protectionPrice: 0.45,
+    } as TCreateOrderSchema;
+    await service.placeOrder(1, body);
+    expect(reserved()).toBe(45 * 10);
+  });
+});
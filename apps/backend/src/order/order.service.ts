@@ -96,17 +96,18 @@ export class OrderService {
     if (!account) {
       throw new BadRequestException('Account does not exist');
     }
+    // Notional orders buy however many contracts their coins pay for
+    const quantity = body.quantity ?? 0;
     if (body.side === 'Sell') {
       const canSell = await this.canSell(accountId, {
         outcomeId: body.outcomeId,
-        requestedQuantity: body.quantity,
+        requestedQuantity: quantity,
       });
       if (!canSell.success || !canSell.canSell) {
         throw new BadRequestException('Insufficient shares to sell');
       }
     }
     const scaledPrice = body.price ? Math.round(body.price * 100) : 0;
-    const quantity = body.quantity;
     if (body.orderType === 'LIMIT' && account.coins < scaledPrice * quantity) {
       throw new BadRequestException('Not enough balance available');
     }
@@ -121,6 +122,8 @@ export class OrderService {
     let orderCost = 0;
     if (body.orderType === 'LIMIT') {
       orderCost = scaledPrice * quantity;
+    } else if (body.notional !== undefined) {
+      orderCost = body.notional;
     } else if (body.orderType === 'MARKET') {
       const fairPrice = await this.getFairPrice(body.outcomeId);
       if (!fairPrice) {
@@ -128,7 +131,16 @@ export class OrderService {
           'Fair price not available for this outcome',
         );
       }
-      orderCost = fairPrice * quantity;
+      // A protection price bounds what the order can cost. A slippage bound
+      // only does so from the best ask on arrival, which the engine alone
+      // knows and which can sit anywhere up to a full coin
+      if (body.protectionPrice !== undefined) {
+        orderCost = Math.round(body.protectionPrice * 100) * quantity;
+      } else if (body.maxSlippageTicks !== undefined) {
+        orderCost = 100 * quantity;
+      } else {
+        orderCost = fairPrice * quantity;
+      }
     }
     const available = account.coins - account.reservedCoins;
     if (orderCost > available) {
@@ -152,6 +164,23 @@ export class OrderService {
       qty_remaining: quantity,
       qty_original: quantity,
       time_in_force: body.timeInForce ?? TimeInForce.IOC, // default IOC for market orders
+      ...(body.protectionPrice !== undefined && {
+        price: Math.round(body.protectionPrice * 100),
+      }),
+      ...(body.maxSlippageTicks !== undefined && {
+        max_slippage_ticks: body.maxSlippageTicks,
+      }),
+      ...(body.notional !== undefined && { notional: body.notional }),
+      ...(body.displayQuantity !== undefined && {
+        display_qty: body.displayQuantity,
+      }),
+      ...(body.trailTicks !== undefined && { trail: body.trailTicks }),
+      ...(body.minQuantity !== undefined && { min_qty: body.minQuantity }),
+      ...(body.allOrNone && { all_or_none: true }),
+      ...(body.peg !== undefined && {
+        peg: body.peg.reference,
+        peg_offset: body.peg.offset,
+      }),
     };
     await this.redisPublisherService.pushOrderCommand(eventData);
     return {
@@ -163,6 +192,7 @@ export class OrderService {
   async cancelOrder(accountId: number, orderId: number) {
     const order = await this.prismaService.order.findUnique({
       where: { id: orderId },
+      include: { outcome: true },
     });
     if (!order || order.accountId !== accountId) {
       throw new BadRequestException('Order not found or unauthorized');
@@ -179,11 +209,69 @@ export class OrderService {
       order_id: orderId,
       account_id: accountId,
       outcome_id: order.outcomeId,
+      market_id: order.outcome.marketId,
       timestamp: new Date().toISOString(),
     });
     return { success: true };
   }
 
+  /**
+   * Cancels the account's resting orders, in one market or in every market
+   * it has open orders in. The engine only sees a market's own stream, so
+   * one command goes out per market.
+   */
+  async cancelAllOrders(accountId: number, marketId?: number) {
+    const marketIds =
+      marketId !== undefined
+        ? [marketId]
+        : await this.openOrderMarketIds(accountId);
+    for (const market_id of marketIds) {
+      await this.redisPublisherService.pushOrderCommand({
+        type: 'order.cancel_all',
+        market_id,
+        account_id: accountId,
+        timestamp: new Date().toISOString(),
+      });
+    }
+    return { success: true, markets: marketIds.length };
+  }
+
+  /**
+   * Keeps the account's orders alive under cancel-on-disconnect. Like
+   * cancel-all, the heartbeat goes to every market the account has open
+   * orders in unless one is given; the engine cancels them all in a market
+   * once its heartbeats stop for longer than the timeout.
+   */
+  async heartbeat(accountId: number, marketId?: number, timeoutMs?: number) {
+    const marketIds =
+      marketId !== undefined
+        ? [marketId]
+        : await this.openOrderMarketIds(accountId);
+    for (const market_id of marketIds) {
+      await this.redisPublisherService.pushOrderCommand({
+        type: 'account.heartbeat',
+        market_id,
+        account_id: accountId,
+        ...(timeoutMs !== undefined && { timeout_ms: timeoutMs }),
+        timestamp: new Date().toISOString(),
+      });
+    }
+    return { success: true, markets: marketIds.length };
+  }
+
+  private async openOrderMarketIds(accountId: number) {
+    const outcomes = await this.prismaService.outcome.findMany({
+      where: {
+        orders: {
+          some: { accountId, status: { in: ['OPEN', 'PARTIAL'] } },
+        },
+      },
+      select: { marketId: true },
+      distinct: ['marketId'],
+    });
+    return outcomes.map((outcome) => outcome.marketId);
+  }
+
   private async getFairPrice(outcomeId: string) {
     const fair_price = await this.redisPublisherService.getOrderBook(outcomeId);
     if (!fair_price) return null;
@@ -8,10 +8,25 @@ export type OrderNewEvent = {
   account_id: number;
   side: OrderSide;
   order_type: OrderType;
+  // 0 for MARKET orders, unless it is their protection price
   price: number;
   qty_remaining: number;
   qty_original: number;
   time_in_force?: TimeInForce;
+  max_slippage_ticks?: number;
+  // Coins a notional MARKET buy spends, its quantities being 0
+  notional?: number;
+  // Slice of an iceberg LIMIT order shown in depth
+  display_qty?: number;
+  // What a pegged LIMIT order follows, its price capping it
+  peg?: 'best_bid' | 'best_ask' | 'mid';
+  peg_offset?: number;
+  // Cents a trailing stop's trigger trails the last trade by
+  trail?: number;
+  // Smallest fill of a LIMIT order, or all it has left if less
+  min_qty?: number;
+  // A LIMIT order that only executes all it has left at once
+  all_or_none?: boolean;
 };
 
 export type OrderCancelledEvent = {
@@ -19,7 +34,61 @@ export type OrderCancelledEvent = {
   order_id: number;
   account_id: number;
   outcome_id: string;
+  market_id: number;
+  timestamp: string;
+};
+
+// Cancels the resting orders of a market, only the account's if given
+export type OrderCancelAllEvent = {
+  type: 'order.cancel_all';
+  market_id: number;
+  account_id?: number;
+  timestamp: string;
+};
+
+// Keeps the account enrolled in cancel-on-disconnect for the market;
+// timeout_ms of 0 leaves it
+export type AccountHeartbeatEvent = {
+  type: 'account.heartbeat';
+  market_id: number;
+  account_id: number;
+  timeout_ms?: number;
+  timestamp: string;
+};
+
+// Stops matching in the outcome's book, or in every book of the market:
+// orders rest until the auction is uncrossed
+export type AuctionStartEvent = {
+  type: 'auction.start';
+  market_id: number;
+  outcome_id?: string;
+  timestamp: string;
+};
+
+// Executes the crossing orders of books in auction at a single price and
+// resumes continuous matching
+export type AuctionUncrossEvent = {
+  type: 'auction.uncross';
+  market_id: number;
+  outcome_id?: string;
+  timestamp: string;
+};
+
+// Changes how the market matches; settings left out are kept.
+// matching_mode is 'continuous' or 'batch:<interval_ms>'
+export type MarketConfigureEvent = {
+  type: 'market.configure';
+  market_id: number;
+  matching_mode?: string;
+  allocation?: 'fifo' | 'pro_rata' | 'top_order_pro_rata';
   timestamp: string;
 };
 
-export type EngineEvent = OrderNewEvent | OrderCancelledEvent;
+export type EngineEvent =
+  | OrderNewEvent
+  | OrderCancelledEvent
+  | OrderCancelAllEvent
+  | AccountHeartbeatEvent
+  | AuctionStartEvent
+  | AuctionUncrossEvent
+  | MarketConfigureEvent;
@@ -33,8 +33,16 @@ export type OrderFilledEvent = {
   quantity: number;
   price: number;
   time_in_force: TimeInForce | null;
+  // Coins a notional order spent on its quantity
+  notional?: number;
 };
 
+export type OrderCancelReason =
+  | 'cancel_all'
+  | 'replaced'
+  | 'disconnected'
+  | 'price_protection';
+
 export type OrderCancelledEvent = {
   type: 'order.cancelled';
   side: OrderSide;
@@ -44,9 +52,80 @@ export type OrderCancelledEvent = {
   order_id: number;
   account_id: number;
   outcome_id: string;
+  // Quantity left unfilled, and thus cancelled
+  remaining: number;
+  // Coins a notional order spent before the rest of its budget was cancelled
+  notional?: number;
+  reason?: OrderCancelReason;
   timestamp: string;
 };
 
+// A pegged order followed the book to a new price
+export type OrderRepricedEvent = {
+  type: 'order.repriced';
+  order_id: number;
+  account_id: number;
+  outcome_id: string;
+  side: OrderSide;
+  // Quantity still resting
+  quantity: number;
+  price: number;
+  previous_price: number;
+};
+
+// A trailing stop was accepted and waits for its trigger
+export type StopPlacedEvent = {
+  type: 'stop.placed';
+  stop_id: number;
+  account_id: number;
+  outcome_id: string;
+  side: OrderSide;
+  quantity: number;
+  trail: number;
+  trigger: number;
+};
+
+// The last trade moved in a trailing stop's favour, and its trigger with it
+export type StopMovedEvent = {
+  type: 'stop.moved';
+  stop_id: number;
+  account_id: number;
+  outcome_id: string;
+  side: OrderSide;
+  trigger: number;
+  previous_trigger: number;
+};
+
+// The last trade reached a trailing stop's trigger; its MARKET order's
+// events follow
+export type StopTriggeredEvent = {
+  type: 'stop.triggered';
+  stop_id: number;
+  account_id: number;
+  outcome_id: string;
+  side: OrderSide;
+  trigger: number;
+  last_trade: number;
+};
+
+// A trailing stop was removed before it fired
+export type StopCancelledEvent = {
+  type: 'stop.cancelled';
+  stop_id: number;
+  account_id: number;
+  outcome_id: string;
+  side: OrderSide;
+  quantity: number;
+  trigger: number;
+  reason: OrderCancelReason;
+};
+
+export type StopEvent =
+  | StopPlacedEvent
+  | StopMovedEvent
+  | StopTriggeredEvent
+  | StopCancelledEvent;
+
 export type OrderRejectedEvent = {
   type: 'order.rejected';
   account_id: number;
@@ -63,16 +142,23 @@ export type BookDepthEvent = {
   outcome_id: string;
   bids: [number, number][];
   asks: [number, number][];
+  seq: number;
   timestamp: number;
 };
 
 export type MarketDataEvent = {
   type: 'market.data';
   marketId: number;
+  fairPriceStrategy: string;
   data: {
     outcomeId: string;
-    fairPrice: number;
+    fairPrice: number | null;
     totalVolume: number;
+    totalContracts: number;
+    totalTrades: number;
+    volume24h: number;
+    contracts24h: number;
+    trades24h: number;
   }[];
   timestamp: number;
 };
@@ -93,12 +179,38 @@ export type TradeEvent = {
   time_in_force: TimeInForce | null;
 };
 
+// Where a book in auction would clear now; price is null while it is not
+// crossed
+export type AuctionIndicativeEvent = {
+  type: 'auction.indicative';
+  outcome_id: string;
+  price: number | null;
+  volume: number;
+  imbalance: number;
+  imbalance_side: OrderSide | null;
+};
+
+// How an auction ended, after the trades it executed
+export type AuctionResultEvent = {
+  type: 'auction.result';
+  outcome_id: string;
+  price: number | null;
+  volume: number;
+  imbalance: number;
+  imbalance_side: OrderSide | null;
+  trades: number;
+};
+
 export type EngineEvent =
   | OrderPlacedEvent
   | OrderPartialEvent
   | OrderFilledEvent
   | OrderCancelledEvent
+  | OrderRepricedEvent
+  | StopEvent
   | OrderRejectedEvent
   | TradeEvent
   | BookDepthEvent
-  | MarketDataEvent;
+  | MarketDataEvent
+  | AuctionIndicativeEvent
+  | AuctionResultEvent;
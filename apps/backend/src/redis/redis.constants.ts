@@ -1,7 +1,8 @@
 export const REDIS_PUBLISHER = 'REDIS_PUBLISHER';
 export const REDIS_SUBSCRIBER = 'REDIS_SUBSCRIBER';
 export const ORDER_COMMAND_CHANNEL = 'orders.commands'; // PUBSUB
-export const ORDER_COMMANDS_STREAM = 'orders.commands.stream'; // Redis Streams
+export const ORDER_COMMANDS_STREAM_PREFIX = 'orders.commands.'; // Redis Streams, one per market
+export const ORDER_COMMANDS_MARKETS = 'orders.commands.markets'; // Set of markets with a stream
 export const ENGINE_EVENT_CHANNEL = 'engine.events';
 export const ENGINE_EVENT_PROCESSED_CHANNEL = 'engine.events.processed';
 export const ENGINE_GROUPS = 'engine_group';
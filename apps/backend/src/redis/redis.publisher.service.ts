@@ -1,10 +1,10 @@
 import { Injectable, Logger } from '@nestjs/common';
 import { createClient, type RedisClientType } from 'redis';
-import { ORDER_COMMANDS_STREAM } from './redis.constants';
 import {
-  OrderCancelledEvent,
-  OrderNewEvent,
-} from './redis-publisher.event-types';
+  ORDER_COMMANDS_MARKETS,
+  ORDER_COMMANDS_STREAM_PREFIX,
+} from './redis.constants';
+import { EngineEvent } from './redis-publisher.event-types';
 
 @Injectable()
 export class RedisPublisherService {
@@ -18,10 +18,10 @@ export class RedisPublisherService {
   }
 
   /**
-   * Push a command into the Redis Stream for the matching engine.
-   * Stream name: orders.commands.stream
+   * Push a command into the Redis Stream of its market for the matching engine.
+   * Stream name: orders.commands.{market_id}
    */
-  async pushOrderCommand(eventData: OrderNewEvent | OrderCancelledEvent) {
+  async pushOrderCommand(eventData: EngineEvent) {
     try {
       const fields: Record<string, string> = Object.fromEntries(
         Object.entries(eventData).map(([k, v]) => [
@@ -30,7 +30,12 @@ export class RedisPublisherService {
         ]),
       );
       if (this.client) {
-        await this.client.xAdd(ORDER_COMMANDS_STREAM, '*', fields);
+        const marketId = String(eventData.market_id);
+        await this.client
+          .multi()
+          .sAdd(ORDER_COMMANDS_MARKETS, marketId)
+          .xAdd(`${ORDER_COMMANDS_STREAM_PREFIX}${marketId}`, '*', fields)
+          .exec();
       }
       this.logger.debug(`Queued order command: ${eventData.type}`);
     } catch (err) {
@@ -11,6 +11,9 @@ import { EngineEvent } from './redis-subscriber.event-types';
 @Injectable()
 export class RedisSubscriberService implements OnModuleInit {
   private readonly logger = new Logger(RedisSubscriberService.name);
+  // Depth can arrive twice: straight from the engine's pub/sub fan-out and
+  // again once the worker has processed the stream entry.
+  private readonly lastDepthSeq = new Map<string, number>();
 
   constructor(
     @Inject(REDIS_SUBSCRIBER)
@@ -53,6 +56,11 @@ export class RedisSubscriberService implements OnModuleInit {
       this.logger.debug(`Received engine event: ${event.type}`);
       switch (event.type) {
         case 'book.depth': {
+          const lastSeq = this.lastDepthSeq.get(event.outcome_id);
+          if (lastSeq !== undefined && event.seq <= lastSeq) {
+            break;
+          }
+          this.lastDepthSeq.set(event.outcome_id, event.seq);
           this.gateway.broadcastDepth(event.outcome_id, event);
           break;
         }
@@ -83,6 +91,17 @@ export class RedisSubscriberService implements OnModuleInit {
           this.gateway.broadcastOrderPlaced(event.account_id, event);
           break;
         }
+        case 'order.repriced': {
+          this.gateway.broadcastOrderRepriced(event.account_id, event);
+          break;
+        }
+        case 'stop.placed':
+        case 'stop.moved':
+        case 'stop.triggered':
+        case 'stop.cancelled': {
+          this.gateway.broadcastStop(event.account_id, event);
+          break;
+        }
         case 'trade': {
           this.gateway.broadcastTrade(
             event.account_id,
@@ -91,6 +110,11 @@ export class RedisSubscriberService implements OnModuleInit {
           );
           break;
         }
+        case 'auction.indicative':
+        case 'auction.result': {
+          this.gateway.broadcastAuction(event.outcome_id, event);
+          break;
+        }
         default:
           this.logger.warn(`Unknown event received: ${JSON.stringify(event)}`);
       }
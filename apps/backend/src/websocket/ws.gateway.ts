@@ -7,6 +7,8 @@ import {
 import { Server, Socket } from 'socket.io';
 import { Logger } from '@nestjs/common';
 import {
+  AuctionIndicativeEvent,
+  AuctionResultEvent,
   BookDepthEvent,
   MarketDataEvent,
   OrderCancelledEvent,
@@ -14,6 +16,8 @@ import {
   OrderPartialEvent,
   OrderPlacedEvent,
   OrderRejectedEvent,
+  OrderRepricedEvent,
+  StopEvent,
   TradeEvent,
 } from 'src/redis/redis-subscriber.event-types';
 
@@ -134,6 +138,15 @@ export class WsGateway implements OnGatewayConnection, OnGatewayDisconnect {
     for (const client of listeners) client.emit('book.depth', payload);
   }
 
+  broadcastAuction(
+    outcomeId: string,
+    payload: AuctionIndicativeEvent | AuctionResultEvent,
+  ) {
+    const listeners = this.subscribersByOutcome.get(outcomeId);
+    if (!listeners) return;
+    for (const client of listeners) client.emit(payload.type, payload);
+  }
+
   broadcastMarketData(marketId: number, payload: MarketDataEvent) {
     const listeners = this.subscribersByMarket.get(marketId);
     if (!listeners) return;
@@ -172,4 +185,12 @@ export class WsGateway implements OnGatewayConnection, OnGatewayDisconnect {
   broadcastOrderPlaced(accountId: number, payload: OrderPlacedEvent) {
     this.clientsByAccount.get(accountId)?.emit('order.placed', payload);
   }
+
+  broadcastOrderRepriced(accountId: number, payload: OrderRepricedEvent) {
+    this.clientsByAccount.get(accountId)?.emit('order.repriced', payload);
+  }
+
+  broadcastStop(accountId: number, payload: StopEvent) {
+    this.clientsByAccount.get(accountId)?.emit(payload.type, payload);
+  }
 }
@@ -0,0 +1,247 @@
+//! Benchmarks of the `OrderBook` operations on books shaped like our
+//! prediction markets; see `matching_engine::workload` for the flow model.
+//!
+//! Run with `cargo bench --bench order_book`.
+
+use criterion::{BatchSize, BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use matching_engine::{
+    orderbook::{OrderBook, OrderBookBuilder, OrderId, Side, order::AccountId},
+    workload::{OrderFlow, SyntheticOrder, WorkloadConfig},
+};
+use std::hint::black_box;
+
+const BOOK_SIZES: [usize; 3] = [1_000, 10_000, 100_000];
+const SEED: u64 = 42;
+
+/// Builds a book holding `resting` orders from passive flow only, and returns
+/// the ids of the orders still resting on it.
+fn seeded_book(resting: usize) -> (OrderBook, Vec<OrderId>) {
+    let config = WorkloadConfig {
+        market_ratio: 0.0,
+        cancel_ratio: 0.0,
+        ..WorkloadConfig::default()
+    };
+    let mut flow = OrderFlow::new(config, SEED);
+    let mut book = OrderBookBuilder::new("bench").build();
+    let mut ids = Vec::with_capacity(resting);
+    while ids.len() < resting {
+        if let SyntheticOrder::Limit {
+            side,
+            price,
+            quantity,
+            account_id,
+        } = flow.next_order()
+        {
+            let report = book
+                .limit_raw(side, quantity, price, None, None, account_id)
+                .unwrap();
+            if report.remaining_qty.value() > 0 {
+                ids.push(report.order_id);
+            }
+        }
+    }
+    ids.retain(|id| book.get_order(*id).is_ok());
+    (book, ids)
+}
+
+fn bench_limit(c: &mut Criterion) {
+    let mut group = c.benchmark_group("limit");
+    for size in BOOK_SIZES {
+        let (book, _) = seeded_book(size);
+        // Joins the back of the best bid, or sweeps a couple of ask levels
+        let passive_price = book.best_bid().unwrap().value();
+        let crossing_price = book.best_ask().unwrap().value() + 2;
+        group.bench_with_input(BenchmarkId::new("passive", size), &size, |b, _| {
+            b.iter_batched(
+                || {
+                    OrderBookBuilder::new("bench")
+                        .with_snapshot(book.snapshot())
+                        .build()
+                },
+                |mut book| {
+                    black_box(
+                        book.limit_raw(Side::Buy, 5, passive_price, None, None, AccountId(1))
+                            .unwrap(),
+                    );
+                    book
+                },
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_with_input(BenchmarkId::new("crossing", size), &size, |b, _| {
+            b.iter_batched(
+                || {
+                    OrderBookBuilder::new("bench")
+                        .with_snapshot(book.snapshot())
+                        .build()
+                },
+                |mut book| {
+                    black_box(
+                        book.limit_raw(Side::Buy, 50, crossing_price, None, None, AccountId(1))
+                            .unwrap(),
+                    );
+                    book
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn bench_market(c: &mut Criterion) {
+    let mut group = c.benchmark_group("market");
+    for size in BOOK_SIZES {
+        let (book, _) = seeded_book(size);
+        for quantity in [1, 100, 1_000] {
+            group.bench_with_input(
+                BenchmarkId::new(format!("book_{}", size), quantity),
+                &quantity,
+                |b, &quantity| {
+                    b.iter_batched(
+                        || {
+                            OrderBookBuilder::new("bench")
+                                .with_snapshot(book.snapshot())
+                                .build()
+                        },
+                        |mut book| {
+                            black_box(book.market_raw(AccountId(1), Side::Buy, quantity).unwrap());
+                            book
+                        },
+                        BatchSize::LargeInput,
+                    )
+                },
+            );
+        }
+    }
+    group.finish();
+}
+
+fn bench_cancel(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cancel");
+    for size in BOOK_SIZES {
+        let (book, ids) = seeded_book(size);
+        let target = ids[ids.len() / 2];
+        group.bench_with_input(BenchmarkId::from_parameter(size), &size, |b, _| {
+            b.iter_batched(
+                || {
+                    OrderBookBuilder::new("bench")
+                        .with_snapshot(book.snapshot())
+                        .build()
+                },
+                |mut book| {
+                    black_box(book.cancel(target).unwrap());
+                    book
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn bench_depth(c: &mut Criterion) {
+    let mut group = c.benchmark_group("depth");
+    for size in BOOK_SIZES {
+        let (book, _) = seeded_book(size);
+        group.bench_with_input(BenchmarkId::new("top_10", size), &size, |b, _| {
+            b.iter(|| black_box(book.depth(Some(10))))
+        });
+        group.bench_with_input(BenchmarkId::new("full", size), &size, |b, _| {
+            b.iter(|| black_box(book.depth(None)))
+        });
+    }
+    group.finish();
+}
+
+fn bench_snapshot(c: &mut Criterion) {
+    let mut group = c.benchmark_group("snapshot");
+    group.sample_size(20);
+    for size in BOOK_SIZES {
+        let (book, _) = seeded_book(size);
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(BenchmarkId::new("take", size), &size, |b, _| {
+            b.iter(|| black_box(book.snapshot()))
+        });
+        group.bench_with_input(BenchmarkId::new("restore", size), &size, |b, _| {
+            b.iter_batched(
+                || book.snapshot(),
+                |snapshot| {
+                    OrderBookBuilder::new("bench")
+                        .with_snapshot(snapshot)
+                        .build()
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// Mixed flow of limits, market orders and cancels against a warm book.
+fn bench_mixed_flow(c: &mut Criterion) {
+    const OPS: usize = 10_000;
+    let mut group = c.benchmark_group("mixed_flow");
+    group.sample_size(20);
+    group.throughput(Throughput::Elements(OPS as u64));
+    let (book, ids) = seeded_book(10_000);
+    let mut flow = OrderFlow::new(WorkloadConfig::default(), SEED + 1);
+    let ops: Vec<SyntheticOrder> = (0..OPS).map(|_| flow.next_order()).collect();
+    group.bench_function("10k_ops", |b| {
+        b.iter_batched(
+            || {
+                let book = OrderBookBuilder::new("bench")
+                    .with_snapshot(book.snapshot())
+                    .build();
+                (book, ids.clone())
+            },
+            |(mut book, mut ids)| {
+                for op in &ops {
+                    match *op {
+                        SyntheticOrder::Limit {
+                            side,
+                            price,
+                            quantity,
+                            account_id,
+                        } => {
+                            let report = book
+                                .limit_raw(side, quantity, price, None, None, account_id)
+                                .unwrap();
+                            if report.remaining_qty.value() > 0 {
+                                ids.push(report.order_id);
+                            }
+                        }
+                        SyntheticOrder::Market {
+                            side,
+                            quantity,
+                            account_id,
+                        } => {
+                            let _ = black_box(book.market_raw(account_id, side, quantity));
+                        }
+                        SyntheticOrder::Cancel { pick } => {
+                            if !ids.is_empty() {
+                                let id = ids.swap_remove(pick as usize % ids.len());
+                                // Already filled orders simply fail to cancel
+                                let _ = black_box(book.cancel(id));
+                            }
+                        }
+                    }
+                }
+                book
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_limit,
+    bench_market,
+    bench_cancel,
+    bench_depth,
+    bench_snapshot,
+    bench_mixed_flow
+);
+criterion_main!(benches);
@@ -0,0 +1,154 @@
+//! Compares the slab-backed price levels of `OrderBook` with the previous
+//! `VecDeque<OrderId>` + `HashMap` level layout on the operations that
+//! dominate market maker traffic: cancels deep inside a level and fills that
+//! partially consume the head order.
+
+use criterion::{BatchSize, BenchmarkId, Criterion, criterion_group, criterion_main};
+use matching_engine::orderbook::{OrderBook, OrderBookBuilder, OrderId, Side, order::AccountId};
+use std::collections::{HashMap, VecDeque};
+use std::hint::black_box;
+
+const LEVEL_SIZES: [u64; 3] = [100, 1_000, 10_000];
+const PRICE: u64 = 50;
+
+/// The level layout `OrderBook` used before intrusive queues.
+mod legacy {
+    use super::*;
+
+    #[derive(Clone, Copy)]
+    pub struct Order {
+        pub id: OrderId,
+        pub orig_qty: u64,
+        pub executed_qty: u64,
+    }
+
+    #[derive(Clone, Default)]
+    pub struct Level {
+        pub orders: HashMap<OrderId, Order>,
+        pub queue: VecDeque<OrderId>,
+    }
+
+    impl Level {
+        pub fn with_orders(count: u64) -> Self {
+            let mut level = Level::default();
+            for id in 1..=count {
+                let id = OrderId(id);
+                level.orders.insert(
+                    id,
+                    Order {
+                        id,
+                        orig_qty: 10,
+                        executed_qty: 0,
+                    },
+                );
+                level.queue.push_back(id);
+            }
+            level
+        }
+
+        pub fn cancel(&mut self, id: OrderId) -> Option<Order> {
+            let order = self.orders.remove(&id)?;
+            if let Some(pos) = self.queue.iter().position(|x| *x == id) {
+                self.queue.remove(pos);
+            }
+            Some(order)
+        }
+
+        /// Mirrors the old `process_queue`: the head order is removed from the
+        /// map and reinserted on every partial fill.
+        pub fn fill(&mut self, mut quantity: u64) -> u64 {
+            while quantity > 0 {
+                let Some(head_id) = self.queue.front() else {
+                    break;
+                };
+                let Some(mut head) = self.orders.remove(head_id) else {
+                    break;
+                };
+                let remaining = head.orig_qty - head.executed_qty;
+                if quantity < remaining {
+                    head.executed_qty += quantity;
+                    self.orders.insert(head.id, head);
+                    quantity = 0;
+                } else {
+                    self.queue.pop_front();
+                    quantity -= remaining;
+                }
+            }
+            quantity
+        }
+    }
+}
+
+fn book_with_level(count: u64) -> OrderBook {
+    let mut book = OrderBookBuilder::new("bench").build();
+    for account in 1..=count {
+        book.limit_raw(Side::Sell, 10, PRICE, None, None, AccountId(account))
+            .unwrap();
+    }
+    book
+}
+
+fn bench_cancel(c: &mut Criterion) {
+    let mut group = c.benchmark_group("cancel_mid_level");
+    for size in LEVEL_SIZES {
+        let target = OrderId(size / 2);
+        group.bench_with_input(BenchmarkId::new("intrusive", size), &size, |b, &size| {
+            b.iter_batched(
+                || book_with_level(size),
+                // Hand the book back so dropping it stays out of the measurement
+                |mut book| {
+                    black_box(book.cancel(target).unwrap());
+                    book
+                },
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_with_input(BenchmarkId::new("vecdeque", size), &size, |b, &size| {
+            b.iter_batched(
+                || legacy::Level::with_orders(size),
+                |mut level| {
+                    black_box(level.cancel(target));
+                    level
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn bench_partial_fills(c: &mut Criterion) {
+    let mut group = c.benchmark_group("partial_fills");
+    for size in LEVEL_SIZES {
+        // Nibble 3 contracts at a time: every fill is a partial or crosses one order
+        let fills = size as usize;
+        group.bench_with_input(BenchmarkId::new("intrusive", size), &size, |b, &size| {
+            b.iter_batched(
+                || book_with_level(size),
+                |mut book| {
+                    for _ in 0..fills {
+                        black_box(book.market_raw(AccountId(0), Side::Buy, 3).unwrap());
+                    }
+                    book
+                },
+                BatchSize::LargeInput,
+            )
+        });
+        group.bench_with_input(BenchmarkId::new("vecdeque", size), &size, |b, &size| {
+            b.iter_batched(
+                || legacy::Level::with_orders(size),
+                |mut level| {
+                    for _ in 0..fills {
+                        black_box(level.fill(3));
+                    }
+                    level
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_cancel, bench_partial_fills);
+criterion_main!(benches);
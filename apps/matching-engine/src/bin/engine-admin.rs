@@ -0,0 +1,61 @@
+//! Operator commands for a running engine deployment.
+//!
+//! Usage:
+//! - `engine-admin dlq list [count]`: print the oldest dead-lettered commands
+//! - `engine-admin dlq redrive [count]`: move the oldest dead-lettered
+//!   commands back to their markets' streams
+//!
+//! `count` defaults to 100. `REDIS_URL` is read from the environment (or `.env`).
+
+use matching_engine::{
+    error::{EngineError, EngineResult},
+    infra::dead_letter::{list_dead_letters, redrive_dead_letters},
+};
+use std::env;
+use tracing::info;
+
+const DEFAULT_COUNT: usize = 100;
+const USAGE: &str = "usage: engine-admin dlq <list|redrive> [count]";
+
+#[tokio::main]
+async fn main() -> EngineResult<()> {
+    dotenvy::dotenv().ok();
+    tracing_subscriber::fmt().with_target(false).init();
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let (command, count) = match args.as_slice() {
+        ["dlq", command] => (*command, DEFAULT_COUNT),
+        ["dlq", command, count] => (
+            *command,
+            count
+                .parse()
+                .map_err(|_| EngineError::Configuration(format!("Invalid count: '{}'", count)))?,
+        ),
+        _ => return Err(EngineError::Configuration(USAGE.to_string())),
+    };
+
+    let redis_url = env::var("REDIS_URL").map_err(|_| {
+        EngineError::Configuration("REDIS_URL environment variable is not set".to_string())
+    })?;
+    let client = redis::Client::open(redis_url)
+        .map_err(|e| EngineError::Configuration(format!("Invalid Redis URL: {}", e)))?;
+    let mut conn = client.get_async_connection().await?;
+
+    match command {
+        "list" => {
+            for (id, fields) in list_dead_letters(&mut conn, count).await? {
+                let fields: Vec<String> = fields
+                    .into_iter()
+                    .map(|(key, value)| format!("{}={}", key, value))
+                    .collect();
+                println!("{} {}", id, fields.join(" "));
+            }
+        }
+        "redrive" => {
+            let moved = redrive_dead_letters(&mut conn, count).await?;
+            info!("Re-drove {} dead-lettered commands", moved);
+        }
+        _ => return Err(EngineError::Configuration(USAGE.to_string())),
+    }
+    Ok(())
+}
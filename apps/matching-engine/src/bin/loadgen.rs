@@ -0,0 +1,260 @@
+//! Load generator for the matching engine.
+//!
+//! Pushes synthetic `order.new` commands into the market's command stream,
+//! `orders.commands.{market_id}`, at a fixed rate and tails `engine.events`
+//! to measure end-to-end latency: from the `XADD` of a command to the first
+//! engine event reporting on that order (placed, filled, partial, cancelled
+//! or rejected). Every generated order uses its own account id so events can
+//! be matched back to commands.
+//!
+//! Configuration is read from the environment (or `.env`):
+//! - `REDIS_URL` (required)
+//! - `LOADGEN_RATE`: orders per second, default 1000
+//! - `LOADGEN_ORDERS`: total orders to send, default 10000
+//! - `LOADGEN_OUTCOMES`: outcomes to spread the flow over, default 4
+//! - `LOADGEN_MARKET_ID`: market id stamped on orders, default 1
+//! - `LOADGEN_SEED`: RNG seed, default 1
+//!
+//! Run a local engine against the same Redis, then `cargo run --release --bin loadgen`.
+
+use matching_engine::{
+    error::{EngineError, EngineResult},
+    infra::{
+        shard_map::{MARKETS_KEY, command_stream},
+        view_emitter::EVENTS_STREAM,
+    },
+    orderbook::Side,
+    workload::{OrderFlow, SyntheticOrder, WorkloadConfig},
+};
+use redis::{AsyncCommands, streams::StreamReadOptions, streams::StreamReadReply};
+use std::{
+    collections::HashMap,
+    env,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::{info, warn};
+
+/// Account ids of generated orders start here, out of the way of real users.
+const ACCOUNT_BASE: u64 = 9_000_000_000;
+/// How long to wait for outstanding events after the last order was sent.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+struct LoadgenConfig {
+    redis_url: String,
+    rate: u64,
+    orders: u64,
+    outcomes: u64,
+    market_id: u32,
+    seed: u64,
+}
+
+type InFlight = Arc<Mutex<HashMap<u64, Instant>>>;
+
+#[tokio::main]
+async fn main() -> EngineResult<()> {
+    dotenvy::dotenv().ok();
+    tracing_subscriber::fmt().with_target(false).init();
+    let config = load_config()?;
+    let client = redis::Client::open(config.redis_url.as_str())
+        .map_err(|e| EngineError::Configuration(format!("Invalid Redis URL: {}", e)))?;
+    let mut producer = client.get_async_connection().await?;
+    let mut consumer = client.get_async_connection().await?;
+
+    // Start tailing from the current end of the stream so old events are ignored
+    let start_id = last_event_id(&mut consumer).await?;
+    let in_flight: InFlight = Arc::new(Mutex::new(HashMap::new()));
+    let collector = tokio::spawn(collect_latencies(
+        consumer,
+        start_id,
+        in_flight.clone(),
+        config.orders,
+    ));
+
+    info!(
+        "Sending {} orders at {}/s over {} outcomes",
+        config.orders, config.rate, config.outcomes
+    );
+    let flow_config = WorkloadConfig {
+        // Cancels need order ids assigned by the engine; keep to new orders
+        cancel_ratio: 0.0,
+        ..WorkloadConfig::default()
+    };
+    let mut flows: Vec<OrderFlow> = (0..config.outcomes)
+        .map(|i| OrderFlow::new(flow_config, config.seed.wrapping_add(i)))
+        .collect();
+    let mut ticker = tokio::time::interval(Duration::from_secs_f64(1.0 / config.rate as f64));
+    let stream_key = command_stream(config.market_id);
+    // Registers the market so an engine claiming unassigned markets picks it up
+    let _: () = producer.sadd(MARKETS_KEY, config.market_id).await?;
+    let started = Instant::now();
+    for seq in 0..config.orders {
+        ticker.tick().await;
+        let outcome = (seq % config.outcomes) as usize;
+        let account_id = ACCOUNT_BASE + seq;
+        let fields = order_fields(
+            flows[outcome].next_order(),
+            &format!("loadgen-outcome-{}", outcome),
+            config.market_id,
+            account_id,
+        );
+        in_flight
+            .lock()
+            .expect("in-flight map poisoned")
+            .insert(account_id, Instant::now());
+        let _: String = producer.xadd(&stream_key, "*", &fields).await?;
+    }
+    let send_elapsed = started.elapsed();
+
+    let latencies = match tokio::time::timeout(send_elapsed + DRAIN_TIMEOUT, collector).await {
+        Ok(Ok(result)) => result?,
+        Ok(Err(e)) => return Err(EngineError::Internal(format!("Collector failed: {}", e))),
+        Err(_) => {
+            warn!("Timed out waiting for engine events");
+            Vec::new()
+        }
+    };
+    report(&config, send_elapsed, latencies);
+    Ok(())
+}
+
+fn load_config() -> EngineResult<LoadgenConfig> {
+    let redis_url = env::var("REDIS_URL").map_err(|_| {
+        EngineError::Configuration("REDIS_URL environment variable is not set".to_string())
+    })?;
+    Ok(LoadgenConfig {
+        redis_url,
+        rate: env_or("LOADGEN_RATE", 1_000)?.max(1),
+        orders: env_or("LOADGEN_ORDERS", 10_000)?,
+        outcomes: env_or("LOADGEN_OUTCOMES", 4)?.max(1),
+        market_id: env_or("LOADGEN_MARKET_ID", 1)?,
+        seed: env_or("LOADGEN_SEED", 1)?,
+    })
+}
+
+fn env_or<T: FromStr>(key: &str, default: T) -> EngineResult<T> {
+    match env::var(key) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| EngineError::Configuration(format!("Invalid {}: '{}'", key, value))),
+        Err(_) => Ok(default),
+    }
+}
+
+/// Builds the stream fields of an `order.new` command, as the backend sends them.
+fn order_fields(
+    order: SyntheticOrder,
+    outcome_id: &str,
+    market_id: u32,
+    account_id: u64,
+) -> Vec<(&'static str, String)> {
+    let (side, order_type, price, quantity, time_in_force) = match order {
+        SyntheticOrder::Limit {
+            side,
+            price,
+            quantity,
+            ..
+        } => (side, "LIMIT", price, quantity, "GTC"),
+        SyntheticOrder::Market { side, quantity, .. } => (side, "MARKET", 0, quantity, "IOC"),
+        SyntheticOrder::Cancel { .. } => unreachable!("cancels are disabled in the load generator"),
+    };
+    let side = match side {
+        Side::Buy => "BUY",
+        Side::Sell => "SELL",
+    };
+    vec![
+        ("type", "order.new".to_string()),
+        ("outcome_id", outcome_id.to_string()),
+        ("outcome_name", outcome_id.to_string()),
+        ("market_id", market_id.to_string()),
+        ("account_id", account_id.to_string()),
+        ("side", side.to_string()),
+        ("order_type", order_type.to_string()),
+        ("price", price.to_string()),
+        ("qty_remaining", quantity.to_string()),
+        ("qty_original", quantity.to_string()),
+        ("time_in_force", time_in_force.to_string()),
+    ]
+}
+
+async fn last_event_id(conn: &mut redis::aio::Connection) -> EngineResult<String> {
+    let reply: redis::streams::StreamRangeReply =
+        conn.xrevrange_count(EVENTS_STREAM, "+", "-", 1).await?;
+    Ok(reply
+        .ids
+        .first()
+        .map(|entry| entry.id.clone())
+        .unwrap_or_else(|| "0-0".to_string()))
+}
+
+/// Tails `engine.events` until every sent order has been reported on.
+async fn collect_latencies(
+    mut conn: redis::aio::Connection,
+    mut last_id: String,
+    in_flight: InFlight,
+    expected: u64,
+) -> EngineResult<Vec<Duration>> {
+    let mut latencies = Vec::with_capacity(expected as usize);
+    let options = StreamReadOptions::default().block(1_000).count(1_000);
+    while (latencies.len() as u64) < expected {
+        let reply: StreamReadReply = conn
+            .xread_options(&[EVENTS_STREAM], &[&last_id], &options)
+            .await?;
+        for key in reply.keys {
+            for entry in key.ids {
+                last_id = entry.id.clone();
+                let Some(account_id) = entry
+                    .get::<String>("payload")
+                    .and_then(|payload| order_status_account(&payload))
+                else {
+                    continue;
+                };
+                let sent_at = in_flight
+                    .lock()
+                    .expect("in-flight map poisoned")
+                    .remove(&account_id);
+                if let Some(sent_at) = sent_at {
+                    latencies.push(sent_at.elapsed());
+                }
+            }
+        }
+    }
+    Ok(latencies)
+}
+
+/// Account id of an order status event, if the payload is one.
+fn order_status_account(payload: &str) -> Option<u64> {
+    let event: serde_json::Value = serde_json::from_str(payload).ok()?;
+    match event.get("type")?.as_str()? {
+        "order.placed" | "order.filled" | "order.partial" | "order.cancelled"
+        | "order.rejected" => event.get("account_id")?.as_u64(),
+        _ => None,
+    }
+}
+
+fn report(config: &LoadgenConfig, send_elapsed: Duration, mut latencies: Vec<Duration>) {
+    latencies.sort();
+    let percentile = |p: f64| -> Duration {
+        if latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        let rank = ((latencies.len() as f64 * p).ceil() as usize).clamp(1, latencies.len());
+        latencies[rank - 1]
+    };
+    info!(
+        "Sent {} orders in {:.2?} ({:.0} orders/s), {} acknowledged by engine events",
+        config.orders,
+        send_elapsed,
+        config.orders as f64 / send_elapsed.as_secs_f64(),
+        latencies.len()
+    );
+    info!(
+        "End-to-end latency: p50 {:.2?}, p90 {:.2?}, p99 {:.2?}, p99.9 {:.2?}, max {:.2?}",
+        percentile(0.50),
+        percentile(0.90),
+        percentile(0.99),
+        percentile(0.999),
+        latencies.last().copied().unwrap_or_default()
+    );
+}
@@ -0,0 +1,244 @@
+//! Call auctions run by commands.
+//!
+//! `auction.start` stops matching in one book, or in every book of a market:
+//! orders sent meanwhile rest and an `auction.indicative` event tells where
+//! the book would clear each time that changes. `auction.uncross` executes
+//! the crossing orders at a single price, publishes them as trades, then an
+//! `auction.result`, and resumes continuous matching.
+//!
+//! Both commands are ledgered, so a replay goes through the same auctions.
+//! So is `auction.refresh`, which the stream loop queues every
+//! [`INDICATIVE_REFRESH_MS`] for markets with books in auction, to publish
+//! their indicatives again for whoever missed the last change.
+
+use crate::engine::engine::MatchingEngine;
+use crate::engine::order::OrderSide;
+use crate::engine::publish_events::PublishEngineEvent;
+use crate::error::{EngineError, EngineResult};
+use crate::orderbook::{AuctionResult, Price, Quantity, Side, Uncross};
+use std::collections::BTreeMap;
+
+/// How long the indicatives of a market go without being published again.
+pub const INDICATIVE_REFRESH_MS: i64 = 1_000;
+
+impl MatchingEngine {
+    /// Puts `outcome_id`, or every known book of `market_id`, in auction and
+    /// returns the books it applied to.
+    ///
+    /// Nothing changes if one of the books may not go into auction, see
+    /// [`crate::orderbook::OrderBook::check_auction`].
+    pub fn start_auction(
+        &mut self,
+        market_id: u32,
+        outcome_id: Option<&str>,
+    ) -> EngineResult<Vec<String>> {
+        let outcome_ids = match outcome_id {
+            Some(outcome_id) => {
+                self.check_outcome_market(market_id, outcome_id)?;
+                self.outcome_markets
+                    .insert(outcome_id.to_string(), market_id);
+                vec![outcome_id.to_string()]
+            }
+            None => self.market_outcomes(market_id),
+        };
+        self.check_auction(&outcome_ids)?;
+        for outcome_id in &outcome_ids {
+            self.get_or_create_book(outcome_id).start_auction()?;
+        }
+        Ok(outcome_ids)
+    }
+
+    /// Fails unless every existing book of `outcome_ids` may go into
+    /// auction.
+    pub(crate) fn check_auction(&self, outcome_ids: &[String]) -> EngineResult<()> {
+        for outcome_id in outcome_ids {
+            if let Some(book) = self.books.get(outcome_id) {
+                book.check_auction()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Uncrosses `outcome_id`, or every book of `market_id`, that is in
+    /// auction at `ts`.
+    ///
+    /// Returns the books it applied to and, for each, one trade per
+    /// execution followed by an `auction.result`.
+    pub fn uncross_auction(
+        &mut self,
+        market_id: u32,
+        outcome_id: Option<&str>,
+        ts: i64,
+    ) -> EngineResult<(Vec<String>, Vec<PublishEngineEvent>)> {
+        let outcome_ids = match outcome_id {
+            Some(outcome_id) => {
+                self.check_outcome_market(market_id, outcome_id)?;
+                vec![outcome_id.to_string()]
+            }
+            None => self.market_outcomes(market_id),
+        };
+        Ok(self.uncross_books(market_id, outcome_ids, ts))
+    }
+
+    /// Uncrosses those of `outcome_ids` in auction, see
+    /// [`MatchingEngine::uncross_auction`].
+    pub(crate) fn uncross_books(
+        &mut self,
+        market_id: u32,
+        outcome_ids: Vec<String>,
+        ts: i64,
+    ) -> (Vec<String>, Vec<PublishEngineEvent>) {
+        let mut uncrossed = Vec::new();
+        let mut events = Vec::new();
+        for outcome_id in outcome_ids {
+            let Some(book) = self.books.get_mut(&outcome_id) else {
+                continue;
+            };
+            if !book.in_auction() {
+                continue;
+            }
+            let result = book.uncross();
+            self.indicatives.remove(&outcome_id);
+            let fills: Vec<(Price, Quantity)> = result
+                .fills
+                .iter()
+                .map(|fill| (fill.price, fill.quantity))
+                .collect();
+            self.record_fills(market_id, &outcome_id, &fills, ts);
+            events.extend(self.auction_events(&outcome_id, &result));
+            uncrossed.push(outcome_id);
+        }
+        (uncrossed, events)
+    }
+
+    /// The `auction.indicative` event of `outcome_id`, `None` unless its
+    /// book is in auction and would clear otherwise than last published.
+    pub fn auction_indicative(&mut self, outcome_id: &str) -> Option<PublishEngineEvent> {
+        let uncross = self.books.get(outcome_id)?.indicative_uncross();
+        if self.indicatives.get(outcome_id) == Some(&uncross) {
+            return None;
+        }
+        self.indicative_event(outcome_id)
+    }
+
+    /// Publishes the indicatives of the books of `market_id` in auction
+    /// again, unless they already were less than [`INDICATIVE_REFRESH_MS`]
+    /// before `ts`.
+    pub fn refresh_indicatives(&mut self, market_id: u32, ts: i64) -> Vec<PublishEngineEvent> {
+        if self
+            .indicative_deadlines()
+            .get(&market_id)
+            .is_none_or(|deadline| ts < *deadline)
+        {
+            return Vec::new();
+        }
+        self.indicative_refreshes.insert(market_id, ts);
+        self.market_outcomes(market_id)
+            .iter()
+            .filter_map(|outcome_id| self.indicative_event(outcome_id))
+            .collect()
+    }
+
+    /// When the indicatives of every market with books in auction are next
+    /// due, see [`MatchingEngine::refresh_indicatives`].
+    pub fn indicative_deadlines(&self) -> BTreeMap<u32, i64> {
+        self.books
+            .iter()
+            .filter(|(_, book)| book.in_auction())
+            .filter_map(|(outcome_id, _)| self.outcome_markets.get(outcome_id))
+            .map(|market_id| {
+                let refreshed = self.indicative_refreshes.get(market_id).copied();
+                (*market_id, refreshed.unwrap_or(0) + INDICATIVE_REFRESH_MS)
+            })
+            .collect()
+    }
+
+    /// The `auction.indicative` event of `outcome_id`, recorded as the last
+    /// published; `None` unless its book is in auction.
+    fn indicative_event(&mut self, outcome_id: &str) -> Option<PublishEngineEvent> {
+        let book = self.books.get(outcome_id)?;
+        if !book.in_auction() {
+            self.indicatives.remove(outcome_id);
+            return None;
+        }
+        let uncross = book.indicative_uncross();
+        self.indicatives.insert(outcome_id.to_string(), uncross);
+        let (price, volume, imbalance, imbalance_side) = uncross_fields(uncross);
+        Some(PublishEngineEvent::AuctionIndicative {
+            outcome_id: outcome_id.to_string(),
+            price,
+            volume,
+            imbalance,
+            imbalance_side,
+        })
+    }
+
+    fn auction_events(
+        &mut self,
+        outcome_id: &str,
+        result: &AuctionResult,
+    ) -> Vec<PublishEngineEvent> {
+        let mut events: Vec<PublishEngineEvent> = result
+            .fills
+            .iter()
+            .map(|fill| PublishEngineEvent::Trade {
+                trade_id: self.generate_trade_id(),
+                order_id: fill.buy.order_id,
+                filled_order_id: fill.sell.order_id,
+                filled_account_id: fill.sell.account_id,
+                account_id: fill.buy.account_id,
+                outcome_id: outcome_id.to_string(),
+                side: OrderSide(Side::Buy),
+                quantity: fill.quantity,
+                price: fill.price,
+                remaining: fill.buy.remaining_qty,
+                original_quantity: fill.buy.orig_qty,
+                time_in_force: Some(fill.buy.time_in_force),
+            })
+            .collect();
+        let (price, volume, imbalance, imbalance_side) = uncross_fields(result.uncross);
+        events.push(PublishEngineEvent::AuctionResult {
+            outcome_id: outcome_id.to_string(),
+            price,
+            volume,
+            imbalance,
+            imbalance_side,
+            trades: result.fills.len(),
+        });
+        events
+    }
+
+    /// Rejects commands of `market_id` naming an outcome of another market.
+    fn check_outcome_market(&self, market_id: u32, outcome_id: &str) -> EngineResult<()> {
+        match self.outcome_markets.get(outcome_id) {
+            Some(owner) if *owner != market_id => Err(EngineError::OrderValidation(format!(
+                "Outcome {} belongs to market {}, not {}",
+                outcome_id, owner, market_id
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    /// Every known outcome of `market_id`.
+    pub(crate) fn market_outcomes(&self, market_id: u32) -> Vec<String> {
+        self.outcome_markets
+            .iter()
+            .filter(|(_, market)| **market == market_id)
+            .map(|(outcome_id, _)| outcome_id.clone())
+            .collect()
+    }
+}
+
+fn uncross_fields(
+    uncross: Option<Uncross>,
+) -> (Option<Price>, Quantity, Quantity, Option<OrderSide>) {
+    match uncross {
+        Some(uncross) => (
+            Some(uncross.price),
+            uncross.volume,
+            uncross.imbalance,
+            uncross.imbalance_side.map(OrderSide),
+        ),
+        None => (None, Quantity(0), Quantity(0), None),
+    }
+}
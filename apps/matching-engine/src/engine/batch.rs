@@ -0,0 +1,249 @@
+//! Frequent batch auctions.
+//!
+//! A market in [`MatchingMode::Batch`] never matches continuously: its books
+//! stay in auction and, at the end of every interval, a `batch.clear` on the
+//! market's stream uncrosses each crossed book at one uniform price and opens
+//! the next batch. Orders of the same batch compete on price alone, so
+//! reaching Redis first earns nothing.
+//!
+//! Batches are only cleared by `batch.clear` commands, which are ledgered like
+//! any other command, and batch boundaries follow from command timestamps,
+//! never from the clock of the core. Replaying a ledger therefore clears the
+//! same orders at the same prices. The stream loop queues a clear once the
+//! core reports the current batch over.
+
+use crate::engine::engine::MatchingEngine;
+use crate::engine::publish_events::PublishEngineEvent;
+use crate::error::{EngineError, EngineResult};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+use tracing::info;
+
+/// Batch length of a market switched to `batch` without one.
+pub const DEFAULT_BATCH_INTERVAL_MS: u64 = 1_000;
+pub const MIN_BATCH_INTERVAL_MS: u64 = 100;
+pub const MAX_BATCH_INTERVAL_MS: u64 = 60_000;
+
+/// How the books of a market match incoming orders.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum MatchingMode {
+    /// Orders match on arrival in price-time priority
+    #[default]
+    Continuous,
+    /// Orders are collected for `interval_ms`, then cleared at one price
+    Batch { interval_ms: u64 },
+}
+
+impl fmt::Display for MatchingMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MatchingMode::Continuous => write!(f, "continuous"),
+            MatchingMode::Batch { interval_ms } => write!(f, "batch:{}", interval_ms),
+        }
+    }
+}
+
+impl FromStr for MatchingMode {
+    type Err = EngineError;
+
+    /// Accepts `continuous`, `batch` or `batch:<interval_ms>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.trim().to_lowercase();
+        let (name, arg) = match lower.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (lower.as_str(), None),
+        };
+        match (name, arg) {
+            ("continuous", None) => Ok(MatchingMode::Continuous),
+            ("batch", None) => Ok(MatchingMode::Batch {
+                interval_ms: DEFAULT_BATCH_INTERVAL_MS,
+            }),
+            ("batch", Some(arg)) => match arg.parse::<u64>() {
+                Ok(interval_ms)
+                    if (MIN_BATCH_INTERVAL_MS..=MAX_BATCH_INTERVAL_MS).contains(&interval_ms) =>
+                {
+                    Ok(MatchingMode::Batch { interval_ms })
+                }
+                _ => Err(EngineError::OrderValidation(format!(
+                    "Invalid batch interval '{}': must be between {} and {} milliseconds",
+                    arg, MIN_BATCH_INTERVAL_MS, MAX_BATCH_INTERVAL_MS
+                ))),
+            },
+            _ => Err(EngineError::OrderValidation(format!(
+                "Invalid matching mode: '{}'. Must be 'continuous' or 'batch[:interval_ms]'",
+                s
+            ))),
+        }
+    }
+}
+
+/// Batch timing of a market in batch mode.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BatchSchedule {
+    pub interval_ms: u64,
+    /// Start of the batch being collected
+    pub opened_at: i64,
+}
+
+impl BatchSchedule {
+    /// Time from which the batch may be cleared.
+    pub fn deadline(&self) -> i64 {
+        self.opened_at + self.interval_ms as i64
+    }
+}
+
+impl MatchingEngine {
+    pub fn matching_mode(&self, market_id: u32) -> MatchingMode {
+        self.batches
+            .get(&market_id)
+            .map_or(MatchingMode::Continuous, |schedule| MatchingMode::Batch {
+                interval_ms: schedule.interval_ms,
+            })
+    }
+
+    /// Switches `market_id` to `mode` at `ts`.
+    ///
+    /// Entering batch mode puts every book of the market in auction; a new
+    /// interval applies from the batch being collected. Leaving it clears
+    /// that batch at once. Returns the books it applied to and the events of
+    /// the clear.
+    ///
+    /// Batch mode is refused while a book of the market may not go into
+    /// auction, see [`MatchingEngine::start_auction`].
+    pub fn set_matching_mode(
+        &mut self,
+        market_id: u32,
+        mode: MatchingMode,
+        ts: i64,
+    ) -> EngineResult<(Vec<String>, Vec<PublishEngineEvent>)> {
+        if mode != MatchingMode::Continuous {
+            self.check_auction(&self.market_outcomes(market_id))?;
+        }
+        info!("Market {} matching mode set to {}", market_id, mode);
+        Ok(match mode {
+            MatchingMode::Batch { interval_ms } => {
+                let opened_at = self
+                    .batches
+                    .get(&market_id)
+                    .map_or(ts, |schedule| schedule.opened_at);
+                self.batches.insert(
+                    market_id,
+                    BatchSchedule {
+                        interval_ms,
+                        opened_at,
+                    },
+                );
+                let outcome_ids = self.market_outcomes(market_id);
+                for outcome_id in &outcome_ids {
+                    self.get_or_create_book(outcome_id).start_auction()?;
+                }
+                (outcome_ids, Vec::new())
+            }
+            MatchingMode::Continuous => {
+                if self.batches.remove(&market_id).is_none() {
+                    return Ok((Vec::new(), Vec::new()));
+                }
+                let outcome_ids = self.market_outcomes(market_id);
+                self.uncross_books(market_id, outcome_ids, ts)
+            }
+        })
+    }
+
+    /// Clears the batch of `market_id` if it is over at `ts`, then opens the
+    /// next one.
+    ///
+    /// Only crossed books are uncrossed, and returned with the events of
+    /// their uncross. Batch boundaries stay on the market's interval however
+    /// late the clear comes.
+    pub fn clear_batch(
+        &mut self,
+        market_id: u32,
+        ts: i64,
+    ) -> (Vec<String>, Vec<PublishEngineEvent>) {
+        let Some(schedule) = self.batches.get_mut(&market_id) else {
+            return (Vec::new(), Vec::new());
+        };
+        if ts < schedule.deadline() {
+            return (Vec::new(), Vec::new());
+        }
+        schedule.opened_at = ts - (ts - schedule.opened_at) % schedule.interval_ms as i64;
+        let crossed: Vec<String> = self
+            .market_outcomes(market_id)
+            .into_iter()
+            .filter(|outcome_id| {
+                self.books
+                    .get(outcome_id)
+                    .is_some_and(|book| book.indicative_uncross().is_some())
+            })
+            .collect();
+        let (cleared, events) = self.uncross_books(market_id, crossed, ts);
+        for outcome_id in &cleared {
+            // Constrained orders are refused in auction, so none rest here
+            let _ = self.get_or_create_book(outcome_id).start_auction();
+        }
+        (cleared, events)
+    }
+
+    /// When the current batch of every market in batch mode is over.
+    pub fn batch_deadlines(&self) -> BTreeMap<u32, i64> {
+        self.batches
+            .iter()
+            .map(|(market_id, schedule)| (*market_id, schedule.deadline()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_matching_modes() {
+        assert_eq!(
+            "continuous".parse::<MatchingMode>().unwrap(),
+            MatchingMode::Continuous
+        );
+        assert_eq!(
+            "Batch".parse::<MatchingMode>().unwrap(),
+            MatchingMode::Batch {
+                interval_ms: DEFAULT_BATCH_INTERVAL_MS
+            }
+        );
+        let mode = "batch:250".parse::<MatchingMode>().unwrap();
+        assert_eq!(mode, MatchingMode::Batch { interval_ms: 250 });
+        assert_eq!(mode.to_string().parse::<MatchingMode>().unwrap(), mode);
+        for invalid in ["batch:10", "batch:x", "continuous:5", "sealed"] {
+            assert!(invalid.parse::<MatchingMode>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn batches_keep_their_boundaries() {
+        let mut engine = MatchingEngine::new(false);
+        engine
+            .set_matching_mode(1, MatchingMode::Batch { interval_ms: 100 }, 1_000)
+            .unwrap();
+        assert_eq!(engine.batch_deadlines(), BTreeMap::from([(1, 1_100)]));
+
+        // Too early: nothing happens
+        engine.clear_batch(1, 1_099);
+        assert_eq!(engine.batch_deadlines()[&1], 1_100);
+
+        // A late clear still ends the batch on the interval
+        engine.clear_batch(1, 1_345);
+        assert_eq!(engine.batch_deadlines()[&1], 1_400);
+
+        engine
+            .set_matching_mode(1, MatchingMode::Batch { interval_ms: 500 }, 1_350)
+            .unwrap();
+        assert_eq!(engine.batch_deadlines()[&1], 1_800);
+        engine
+            .set_matching_mode(1, MatchingMode::Continuous, 1_400)
+            .unwrap();
+        assert!(engine.batch_deadlines().is_empty());
+        assert_eq!(engine.matching_mode(1), MatchingMode::Continuous);
+    }
+}
@@ -0,0 +1,1453 @@
+use crate::engine::batch::MatchingMode;
+use crate::engine::engine::{MatchingEngine, OutcomeMarketData};
+use crate::engine::fair_price::FairPriceStrategy;
+use crate::engine::order::{
+    AccountHeartbeat, AuctionWire, BatchClearWire, CancelAllWire, HeartbeatSweepWire,
+    HeartbeatWire, MarketConfigWire, MarketHandoffWire, Order, OrderSide, OrderWire, QuoteReplace,
+    QuoteReplaceWire, parse_ts,
+};
+use crate::engine::publish_events::{CancelReason, PublishEngineEvent};
+use crate::engine::snapshot::MarketSnapshot;
+use crate::engine::stop::stop_cancelled;
+use crate::error::{EngineError, EngineResult};
+use crate::orderbook::{
+    Allocation, Depth, DepthDelta, ExecutionReport, OrderStatus, Price, order::AccountId,
+};
+use serde_json::Value as SerdeJsonValue;
+use std::collections::BTreeSet;
+use tracing::warn;
+
+/// Something the matching core wants written to Redis once a command has
+/// been applied.
+///
+/// The core never performs I/O itself; the publisher task turns these into
+/// `engine.events` entries and `fair_price:{outcome_id}` keys.
+#[derive(Debug)]
+pub enum EngineOutput {
+    FairPrice {
+        outcome_id: String,
+        price: Price,
+    },
+    BookDelta {
+        outcome_id: String,
+        delta: DepthDelta,
+    },
+    BookDepth {
+        outcome_id: String,
+        depth: Depth,
+    },
+    MarketData {
+        market_id: u32,
+        strategy: FairPriceStrategy,
+        data: Vec<OutcomeMarketData>,
+    },
+    Event(PublishEngineEvent),
+    /// The market was released; its snapshot must be stored and the shard
+    /// map pointed at `target_engine`.
+    Handoff {
+        market_id: u32,
+        target_engine: String,
+        snapshot: Box<MarketSnapshot>,
+    },
+}
+
+impl MatchingEngine {
+    /// Applies a command read from `market_id`'s stream and recorded in its
+    /// ledger as `ledger_id`.
+    pub fn apply_ledgered(
+        &mut self,
+        market_id: u32,
+        ledger_id: &str,
+        payload: &SerdeJsonValue,
+    ) -> EngineResult<Vec<EngineOutput>> {
+        if self.released_markets.contains(&market_id) {
+            return Err(EngineError::MarketNotOwned(market_id));
+        }
+        self.ledger_positions
+            .insert(market_id, ledger_id.to_string());
+        self.begin_op(format!("{}/{}", market_id, ledger_id), Some(market_id));
+        self.apply_command(payload)
+    }
+
+    /// Applies one command from the command stream or the ledger.
+    ///
+    /// The payload must already carry its `ts` (stamped at ingest, or the
+    /// original one on replay). In replay mode the state is updated exactly
+    /// as live, but no outputs are produced.
+    pub fn handle_command(&mut self, payload: &SerdeJsonValue) -> EngineResult<Vec<EngineOutput>> {
+        self.local_ops += 1;
+        self.begin_op(format!("local/{}", self.local_ops), None);
+        self.apply_command(payload)
+    }
+
+    /// Starts numbering the trades of the command `op_id`, from the ledger
+    /// of `market_id` if any, from zero.
+    fn begin_op(&mut self, op_id: String, market_id: Option<u32>) {
+        self.op_id = op_id;
+        self.op_market = market_id;
+        self.op_trades = 0;
+    }
+
+    fn apply_command(&mut self, payload: &SerdeJsonValue) -> EngineResult<Vec<EngineOutput>> {
+        let msg_type = payload
+            .get("type")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| EngineError::MissingField("type".to_string()))?;
+        match msg_type {
+            "order.new" => self.handle_new_order(payload),
+            "order.cancel_all" => self.handle_cancel_all(payload),
+            "quote.replace" => self.handle_quote_replace(payload),
+            "account.heartbeat" => self.handle_heartbeat(payload),
+            "heartbeat.sweep" => self.handle_heartbeat_sweep(payload),
+            "auction.start" => self.handle_auction_start(payload),
+            "auction.uncross" => self.handle_auction_uncross(payload),
+            "batch.clear" => self.handle_batch_clear(payload),
+            "auction.refresh" => self.handle_auction_refresh(payload),
+            "market.configure" => self.handle_market_configure(payload),
+            "market.handoff" => self.handle_market_handoff(payload),
+            _ => Err(EngineError::UnknownEventType(msg_type.to_string())),
+        }
+    }
+
+    fn handle_new_order(&mut self, payload: &SerdeJsonValue) -> EngineResult<Vec<EngineOutput>> {
+        let wire =
+            serde_json::from_value::<OrderWire>(payload.clone()).map_err(EngineError::Json)?;
+        let order = Order::try_from(wire)
+            .map_err(|e| EngineError::OrderValidation(format!("Order validation failed: {}", e)))?;
+        if order.trail.is_some() {
+            // Held until it fires, so the book is unchanged
+            let events = self.place_trailing_stop(&order);
+            if self.is_replay_mode {
+                return Ok(Vec::new());
+            }
+            return Ok(events.into_iter().map(EngineOutput::Event).collect());
+        }
+        let (publish_events, _, market_data) = self.order_execution(&order);
+        // Taken in replay too, so delta sequence numbers survive a restart
+        let orderbook = self.get_or_create_book(&order.outcome_id);
+        let book_delta = orderbook.take_depth_delta();
+        if self.is_replay_mode {
+            return Ok(Vec::new());
+        }
+        let book_depth = self.get_or_create_book(&order.outcome_id).depth(None);
+
+        let mut outputs = Vec::with_capacity(publish_events.len() + 4);
+        if let Some(price) = self.fair_price(&order.outcome_id) {
+            outputs.push(EngineOutput::FairPrice {
+                outcome_id: order.outcome_id.clone(),
+                price,
+            });
+        }
+        if let Some(delta) = book_delta {
+            outputs.push(EngineOutput::BookDelta {
+                outcome_id: order.outcome_id.clone(),
+                delta,
+            });
+        }
+        outputs.push(EngineOutput::BookDepth {
+            outcome_id: order.outcome_id.clone(),
+            depth: book_depth,
+        });
+        outputs.push(EngineOutput::MarketData {
+            market_id: order.market_id,
+            strategy: self.fair_price_strategy(order.market_id),
+            data: market_data,
+        });
+        outputs.extend(publish_events.into_iter().map(EngineOutput::Event));
+        if let Some(indicative) = self.auction_indicative(&order.outcome_id) {
+            outputs.push(EngineOutput::Event(indicative));
+        }
+        Ok(outputs)
+    }
+
+    /// Cancels the resting orders and trailing stops of `market_id`, only
+    /// the account's if given.
+    ///
+    /// Without a market, the account's are cancelled in every market it has
+    /// some in, but a ledgered command stays within the market of its
+    /// ledger, so that replaying a ledger gives the same books.
+    fn handle_cancel_all(&mut self, payload: &SerdeJsonValue) -> EngineResult<Vec<EngineOutput>> {
+        let wire =
+            serde_json::from_value::<CancelAllWire>(payload.clone()).map_err(EngineError::Json)?;
+        let market_id = wire
+            .market_id
+            .map(|market_id| {
+                market_id.parse::<u32>().map_err(|e| {
+                    EngineError::OrderValidation(format!(
+                        "Invalid market_id '{}': {}",
+                        market_id, e
+                    ))
+                })
+            })
+            .transpose()?;
+        let account_id = wire
+            .account_id
+            .map(|account_id| {
+                account_id.parse::<u64>().map(AccountId).map_err(|e| {
+                    EngineError::OrderValidation(format!(
+                        "Invalid account_id '{}': {}",
+                        account_id, e
+                    ))
+                })
+            })
+            .transpose()?;
+        let markets = match (market_id.or(self.op_market), account_id) {
+            (Some(market_id), _) => BTreeSet::from([market_id]),
+            (None, Some(account_id)) => self.account_markets(account_id),
+            (None, None) => {
+                return Err(EngineError::MissingField(
+                    "market_id or account_id".to_string(),
+                ));
+            }
+        };
+        let mut outputs = Vec::new();
+        for market_id in markets {
+            outputs.extend(self.cancel_market_orders(market_id, account_id));
+        }
+        Ok(outputs)
+    }
+
+    /// Markets where `account_id` has resting orders or trailing stops.
+    fn account_markets(&self, account_id: AccountId) -> BTreeSet<u32> {
+        self.outcome_markets
+            .iter()
+            .filter(|(outcome_id, _)| {
+                let resting = self
+                    .books
+                    .get(*outcome_id)
+                    .is_some_and(|book| !book.account_orders(account_id).is_empty());
+                let stops = self.trailing_stops.get(*outcome_id).is_some_and(|stops| {
+                    stops
+                        .iter()
+                        .any(|stop| stop.order.account_id == account_id.0)
+                });
+                resting || stops
+            })
+            .map(|(_, market_id)| *market_id)
+            .collect()
+    }
+
+    /// Cancels what `account_id`, or everyone when `None`, has resting and
+    /// waiting to fire in `market_id`.
+    fn cancel_market_orders(
+        &mut self,
+        market_id: u32,
+        account_id: Option<AccountId>,
+    ) -> Vec<EngineOutput> {
+        let outcome_ids = self.market_outcomes(market_id);
+        let mut touched = BTreeSet::new();
+        let mut events = Vec::new();
+        for outcome_id in outcome_ids {
+            let Some(book) = self.books.get_mut(&outcome_id) else {
+                continue;
+            };
+            let reports = book.cancel_all(account_id);
+            if reports.is_empty() {
+                continue;
+            }
+            events.extend(
+                reports
+                    .iter()
+                    .map(|report| order_cancelled(&outcome_id, report, CancelReason::CancelAll)),
+            );
+            touched.insert(outcome_id);
+        }
+        for (outcome_id, stop) in self.take_trailing_stops(market_id, account_id) {
+            events.push(stop_cancelled(&outcome_id, &stop, CancelReason::CancelAll));
+            touched.insert(outcome_id);
+        }
+        self.book_outputs(market_id, touched, events)
+    }
+
+    /// Cancels the account's previous quotes in the market and places the
+    /// new ones, as a single command.
+    ///
+    /// Every quote is validated before anything changes, so a bad quote
+    /// leaves the previous set resting. Books touched by the cancels and the
+    /// placements get one depth update each.
+    fn handle_quote_replace(
+        &mut self,
+        payload: &SerdeJsonValue,
+    ) -> EngineResult<Vec<EngineOutput>> {
+        let wire = serde_json::from_value::<QuoteReplaceWire>(payload.clone())
+            .map_err(EngineError::Json)?;
+        let quote = QuoteReplace::try_from(wire)?;
+        let account_id = AccountId(quote.account_id);
+        for order in &quote.orders {
+            match self.outcome_markets.get(&order.outcome_id) {
+                Some(market_id) if *market_id != quote.market_id => {
+                    return Err(EngineError::OrderValidation(format!(
+                        "Outcome {} belongs to market {}, not {}",
+                        order.outcome_id, market_id, quote.market_id
+                    )));
+                }
+                _ => {}
+            }
+            // Books refuse orders only on their own checks, so running them
+            // first means no quote is refused once the old ones are gone
+            if let Some(book) = self.books.get(&order.outcome_id) {
+                book.validate_limit_order(&order.limit_options())
+                    .map_err(|e| {
+                        EngineError::OrderValidation(format!(
+                            "Quote on {} rejected: {}",
+                            order.outcome_id, e
+                        ))
+                    })?;
+            }
+        }
+
+        let previous = self
+            .quote_sets
+            .get_mut(&quote.market_id)
+            .and_then(|sets| sets.remove(&account_id))
+            .unwrap_or_default();
+        let mut touched = BTreeSet::new();
+        let mut events = Vec::new();
+        for (outcome_id, order_id) in previous {
+            // Quotes filled since are gone already
+            let Some(Ok(report)) = self
+                .books
+                .get_mut(&outcome_id)
+                .map(|book| book.cancel(order_id))
+            else {
+                continue;
+            };
+            events.push(order_cancelled(
+                &outcome_id,
+                &report,
+                CancelReason::Replaced,
+            ));
+            touched.insert(outcome_id);
+        }
+
+        let mut resting = Vec::new();
+        for order in &quote.orders {
+            let (order_events, report) = self.place_order(order);
+            events.extend(order_events);
+            touched.insert(order.outcome_id.clone());
+            if let Some(report) = report
+                && matches!(
+                    report.status,
+                    OrderStatus::New | OrderStatus::PartiallyFilled
+                )
+            {
+                resting.push((order.outcome_id.clone(), report.order_id));
+            }
+        }
+        if !resting.is_empty() {
+            self.quote_sets
+                .entry(quote.market_id)
+                .or_default()
+                .insert(account_id, resting);
+        }
+        Ok(self.book_outputs(quote.market_id, touched, events))
+    }
+
+    fn handle_heartbeat(&mut self, payload: &SerdeJsonValue) -> EngineResult<Vec<EngineOutput>> {
+        let wire =
+            serde_json::from_value::<HeartbeatWire>(payload.clone()).map_err(EngineError::Json)?;
+        let heartbeat = AccountHeartbeat::try_from(wire)?;
+        self.advance_clock(heartbeat.ts);
+        self.heartbeat(
+            heartbeat.market_id,
+            AccountId(heartbeat.account_id),
+            heartbeat.timeout_ms,
+            heartbeat.ts,
+        );
+        Ok(Vec::new())
+    }
+
+    /// Cancels every resting order, in every book of the market, of the
+    /// accounts whose heartbeat deadline passed before the sweep's `ts`.
+    fn handle_heartbeat_sweep(
+        &mut self,
+        payload: &SerdeJsonValue,
+    ) -> EngineResult<Vec<EngineOutput>> {
+        let wire = serde_json::from_value::<HeartbeatSweepWire>(payload.clone())
+            .map_err(EngineError::Json)?;
+        let market_id = wire.market_id.parse::<u32>().map_err(|e| {
+            EngineError::OrderValidation(format!("Invalid market_id '{}': {}", wire.market_id, e))
+        })?;
+        let ts = parse_ts(wire.ts)?;
+        self.advance_clock(ts);
+        let expired = self.take_expired_heartbeats(market_id, ts);
+        if expired.is_empty() {
+            return Ok(Vec::new());
+        }
+        warn!(
+            "Cancelling the orders of {} disconnected accounts in market {}",
+            expired.len(),
+            market_id
+        );
+        if let Some(quote_sets) = self.quote_sets.get_mut(&market_id) {
+            for account_id in &expired {
+                quote_sets.remove(account_id);
+            }
+        }
+        let outcome_ids: Vec<String> = self
+            .outcome_markets
+            .iter()
+            .filter(|(_, market)| **market == market_id)
+            .map(|(outcome_id, _)| outcome_id.clone())
+            .collect();
+        let mut touched = BTreeSet::new();
+        let mut events = Vec::new();
+        for outcome_id in outcome_ids {
+            let Some(book) = self.books.get_mut(&outcome_id) else {
+                continue;
+            };
+            for account_id in &expired {
+                let reports = book.cancel_all(Some(*account_id));
+                if reports.is_empty() {
+                    continue;
+                }
+                events.extend(reports.iter().map(|report| {
+                    order_cancelled(&outcome_id, report, CancelReason::Disconnected)
+                }));
+                touched.insert(outcome_id.clone());
+            }
+        }
+        for account_id in &expired {
+            for (outcome_id, stop) in self.take_trailing_stops(market_id, Some(*account_id)) {
+                events.push(stop_cancelled(
+                    &outcome_id,
+                    &stop,
+                    CancelReason::Disconnected,
+                ));
+                touched.insert(outcome_id);
+            }
+        }
+        Ok(self.book_outputs(market_id, touched, events))
+    }
+
+    fn handle_auction_start(
+        &mut self,
+        payload: &SerdeJsonValue,
+    ) -> EngineResult<Vec<EngineOutput>> {
+        let wire =
+            serde_json::from_value::<AuctionWire>(payload.clone()).map_err(EngineError::Json)?;
+        let market_id = wire.market_id.parse::<u32>().map_err(|e| {
+            EngineError::OrderValidation(format!("Invalid market_id '{}': {}", wire.market_id, e))
+        })?;
+        self.advance_clock(parse_ts(wire.ts)?);
+        let started = self.start_auction(market_id, wire.outcome_id.as_deref())?;
+        Ok(self.book_outputs(market_id, started.into_iter().collect(), Vec::new()))
+    }
+
+    fn handle_auction_uncross(
+        &mut self,
+        payload: &SerdeJsonValue,
+    ) -> EngineResult<Vec<EngineOutput>> {
+        let wire =
+            serde_json::from_value::<AuctionWire>(payload.clone()).map_err(EngineError::Json)?;
+        let market_id = wire.market_id.parse::<u32>().map_err(|e| {
+            EngineError::OrderValidation(format!("Invalid market_id '{}': {}", wire.market_id, e))
+        })?;
+        let ts = parse_ts(wire.ts)?;
+        self.advance_clock(ts);
+        let (uncrossed, events) =
+            self.uncross_auction(market_id, wire.outcome_id.as_deref(), ts)?;
+        Ok(self.book_outputs(market_id, uncrossed.into_iter().collect(), events))
+    }
+
+    /// Views of the books a command changed, the market's data, `events`
+    /// with those of the trailing stops they fired, the pegs it moved, then
+    /// where the books still in auction would clear.
+    ///
+    /// Nothing is returned in replay, but the depth deltas are still taken
+    /// so sequence numbers survive a restart.
+    fn book_outputs(
+        &mut self,
+        market_id: u32,
+        touched: BTreeSet<String>,
+        mut events: Vec<PublishEngineEvent>,
+    ) -> Vec<EngineOutput> {
+        if touched.is_empty() {
+            return Vec::new();
+        }
+        for outcome_id in &touched {
+            events.extend(self.run_trailing_stops(outcome_id));
+        }
+        let mut outputs = Vec::with_capacity(touched.len() * 4 + events.len() + 1);
+        let mut indicative = Vec::new();
+        let mut repriced = Vec::new();
+        for outcome_id in touched {
+            repriced.extend(self.repriced_events(&outcome_id));
+            let Some(book) = self.books.get_mut(&outcome_id) else {
+                continue;
+            };
+            let delta = book.take_depth_delta();
+            if self.is_replay_mode {
+                continue;
+            }
+            let depth = book.depth(None);
+            indicative.extend(self.auction_indicative(&outcome_id));
+            if let Some(price) = self.fair_price(&outcome_id) {
+                outputs.push(EngineOutput::FairPrice {
+                    outcome_id: outcome_id.clone(),
+                    price,
+                });
+            }
+            if let Some(delta) = delta {
+                outputs.push(EngineOutput::BookDelta {
+                    outcome_id: outcome_id.clone(),
+                    delta,
+                });
+            }
+            outputs.push(EngineOutput::BookDepth { outcome_id, depth });
+        }
+        if self.is_replay_mode {
+            return Vec::new();
+        }
+        outputs.push(EngineOutput::MarketData {
+            market_id,
+            strategy: self.fair_price_strategy(market_id),
+            data: self.market_data(market_id),
+        });
+        outputs.extend(events.into_iter().map(EngineOutput::Event));
+        outputs.extend(repriced.into_iter().map(EngineOutput::Event));
+        outputs.extend(indicative.into_iter().map(EngineOutput::Event));
+        outputs
+    }
+
+    fn handle_market_configure(
+        &mut self,
+        payload: &SerdeJsonValue,
+    ) -> EngineResult<Vec<EngineOutput>> {
+        let wire = serde_json::from_value::<MarketConfigWire>(payload.clone())
+            .map_err(EngineError::Json)?;
+        let market_id = wire.market_id.parse::<u32>().map_err(|e| {
+            EngineError::OrderValidation(format!("Invalid market_id '{}': {}", wire.market_id, e))
+        })?;
+        if wire.fair_price_strategy.is_none()
+            && wire.matching_mode.is_none()
+            && wire.allocation.is_none()
+        {
+            return Err(EngineError::MissingField(
+                "fair_price_strategy, matching_mode or allocation".to_string(),
+            ));
+        }
+        // Every setting is validated before any is applied
+        let strategy = wire
+            .fair_price_strategy
+            .map(|strategy| strategy.parse::<FairPriceStrategy>())
+            .transpose()?;
+        let mode = wire
+            .matching_mode
+            .map(|mode| mode.parse::<MatchingMode>())
+            .transpose()?;
+        let allocation = wire
+            .allocation
+            .map(|allocation| {
+                allocation
+                    .parse::<Allocation>()
+                    .map_err(EngineError::OrderValidation)
+            })
+            .transpose()?;
+        if let Some(mode) = mode
+            && mode != MatchingMode::Continuous
+        {
+            self.check_auction(&self.market_outcomes(market_id))?;
+        }
+        let ts = parse_ts(wire.ts)?;
+        self.advance_clock(ts);
+        if let Some(strategy) = strategy {
+            self.configure_market(market_id, strategy);
+        }
+        if let Some(allocation) = allocation {
+            self.configure_allocation(market_id, allocation);
+        }
+        let (touched, events) = match mode {
+            Some(mode) => self.set_matching_mode(market_id, mode, ts)?,
+            None => (Vec::new(), Vec::new()),
+        };
+        if !touched.is_empty() {
+            return Ok(self.book_outputs(market_id, touched.into_iter().collect(), events));
+        }
+        if self.is_replay_mode {
+            return Ok(Vec::new());
+        }
+        Ok(vec![EngineOutput::MarketData {
+            market_id,
+            strategy: self.fair_price_strategy(market_id),
+            data: self.market_data(market_id),
+        }])
+    }
+
+    /// Clears the batch of a market in batch mode once it is over, see
+    /// [`crate::engine::batch`].
+    fn handle_batch_clear(&mut self, payload: &SerdeJsonValue) -> EngineResult<Vec<EngineOutput>> {
+        let wire =
+            serde_json::from_value::<BatchClearWire>(payload.clone()).map_err(EngineError::Json)?;
+        let market_id = wire.market_id.parse::<u32>().map_err(|e| {
+            EngineError::OrderValidation(format!("Invalid market_id '{}': {}", wire.market_id, e))
+        })?;
+        let ts = parse_ts(wire.ts)?;
+        self.advance_clock(ts);
+        let (cleared, events) = self.clear_batch(market_id, ts);
+        Ok(self.book_outputs(market_id, cleared.into_iter().collect(), events))
+    }
+
+    /// Publishes the indicatives of a market's books in auction again once
+    /// they are due, see [`crate::engine::auction`].
+    fn handle_auction_refresh(
+        &mut self,
+        payload: &SerdeJsonValue,
+    ) -> EngineResult<Vec<EngineOutput>> {
+        let wire =
+            serde_json::from_value::<BatchClearWire>(payload.clone()).map_err(EngineError::Json)?;
+        let market_id = wire.market_id.parse::<u32>().map_err(|e| {
+            EngineError::OrderValidation(format!("Invalid market_id '{}': {}", wire.market_id, e))
+        })?;
+        let ts = parse_ts(wire.ts)?;
+        self.advance_clock(ts);
+        let events = self.refresh_indicatives(market_id, ts);
+        if self.is_replay_mode {
+            return Ok(Vec::new());
+        }
+        Ok(events.into_iter().map(EngineOutput::Event).collect())
+    }
+
+    fn handle_market_handoff(
+        &mut self,
+        payload: &SerdeJsonValue,
+    ) -> EngineResult<Vec<EngineOutput>> {
+        let wire = serde_json::from_value::<MarketHandoffWire>(payload.clone())
+            .map_err(EngineError::Json)?;
+        let market_id = wire.market_id.parse::<u32>().map_err(|e| {
+            EngineError::OrderValidation(format!("Invalid market_id '{}': {}", wire.market_id, e))
+        })?;
+        // Replay goes through the whole ledger of the market, handoffs included
+        if self.is_replay_mode || wire.target_engine == self.engine_id {
+            return Ok(Vec::new());
+        }
+        let snapshot = self.release_market(market_id);
+        Ok(vec![EngineOutput::Handoff {
+            market_id,
+            target_engine: wire.target_engine,
+            snapshot: Box::new(snapshot),
+        }])
+    }
+}
+
+/// The `OrderCancelled` event of a cancel report.
+fn order_cancelled(
+    outcome_id: &str,
+    report: &ExecutionReport,
+    reason: CancelReason,
+) -> PublishEngineEvent {
+    PublishEngineEvent::OrderCancelled {
+        order_id: report.order_id,
+        account_id: report.account_id,
+        outcome_id: outcome_id.to_string(),
+        side: OrderSide(report.side),
+        quantity: report.orig_qty,
+        price: report.price,
+        time_in_force: Some(report.time_in_force),
+        remaining: report.remaining_qty,
+        notional: None,
+        reason: Some(reason),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::Quantity;
+    use serde_json::json;
+
+    fn limit(account_id: u64, side: &str, price: u64, qty: u64) -> SerdeJsonValue {
+        json!({
+            "type": "order.new",
+            "outcome_id": "outcome-1",
+            "outcome_name": "Yes",
+            "market_id": "1",
+            "account_id": account_id.to_string(),
+            "side": side,
+            "order_type": "LIMIT",
+            "price": price.to_string(),
+            "qty_remaining": qty.to_string(),
+            "qty_original": qty.to_string(),
+            "time_in_force": "GTC",
+            "ts": "1000",
+        })
+    }
+
+    fn event_types(outputs: &[EngineOutput]) -> Vec<&'static str> {
+        outputs
+            .iter()
+            .map(|output| match output {
+                EngineOutput::FairPrice { .. } => "fair_price",
+                EngineOutput::BookDelta { .. } => "book.delta",
+                EngineOutput::BookDepth { .. } => "book.depth",
+                EngineOutput::MarketData { .. } => "market.data",
+                EngineOutput::Event(PublishEngineEvent::OrderPlaced { .. }) => "order.placed",
+                EngineOutput::Event(PublishEngineEvent::OrderFilled { .. }) => "order.filled",
+                EngineOutput::Event(PublishEngineEvent::Trade { .. }) => "trade",
+                EngineOutput::Event(PublishEngineEvent::OrderCancelled { .. }) => "order.cancelled",
+                EngineOutput::Event(PublishEngineEvent::OrderRepriced { .. }) => "order.repriced",
+                EngineOutput::Event(PublishEngineEvent::AuctionIndicative { .. }) => {
+                    "auction.indicative"
+                }
+                EngineOutput::Event(PublishEngineEvent::AuctionResult { .. }) => "auction.result",
+                EngineOutput::Event(_) => "other",
+                EngineOutput::Handoff { .. } => "handoff",
+            })
+            .collect()
+    }
+
+    #[test]
+    fn new_orders_produce_views_and_events() {
+        let mut engine = MatchingEngine::new(false);
+        let outputs = engine.handle_command(&limit(1, "SELL", 60, 10)).unwrap();
+        assert_eq!(
+            event_types(&outputs),
+            vec!["book.delta", "book.depth", "market.data", "order.placed"]
+        );
+
+        let outputs = engine.handle_command(&limit(2, "BUY", 60, 10)).unwrap();
+        assert_eq!(
+            event_types(&outputs),
+            vec![
+                "fair_price",
+                "book.delta",
+                "book.depth",
+                "market.data",
+                "order.filled",
+                "trade"
+            ]
+        );
+        let EngineOutput::FairPrice { price, .. } = &outputs[0] else {
+            unreachable!()
+        };
+        assert_eq!(*price, Price(60));
+    }
+
+    #[test]
+    fn protected_market_orders_cancel_what_is_past_their_bound() {
+        let mut engine = MatchingEngine::new(false);
+        engine.handle_command(&limit(1, "SELL", 60, 10)).unwrap();
+        engine.handle_command(&limit(1, "SELL", 90, 10)).unwrap();
+        let market = |price: u64, ticks: Option<u64>| {
+            let mut order = limit(2, "BUY", price, 15);
+            order["order_type"] = json!("MARKET");
+            order["time_in_force"] = json!("IOC");
+            if let Some(ticks) = ticks {
+                order["max_slippage_ticks"] = json!(ticks.to_string());
+            }
+            order
+        };
+
+        let outputs = engine.handle_command(&market(70, None)).unwrap();
+        assert_eq!(event_types(&outputs)[4..], ["trade", "order.cancelled"]);
+        let Some(EngineOutput::Event(PublishEngineEvent::OrderCancelled {
+            remaining, reason, ..
+        })) = outputs.last()
+        else {
+            unreachable!()
+        };
+        assert_eq!(*remaining, Quantity(5));
+        assert_eq!(*reason, Some(CancelReason::PriceProtection));
+
+        // Ticks count from the best ask on arrival, now 90
+        let outputs = engine.handle_command(&market(0, Some(10))).unwrap();
+        assert_eq!(event_types(&outputs)[4..], ["trade", "order.cancelled"]);
+        let Some(EngineOutput::Event(PublishEngineEvent::Trade { price, .. })) = outputs.get(4)
+        else {
+            unreachable!()
+        };
+        assert_eq!(*price, Price(90));
+
+        assert!(engine.handle_command(&market(70, Some(10))).is_err());
+    }
+
+    #[test]
+    fn notional_orders_report_what_they_spent() {
+        let mut engine = MatchingEngine::new(false);
+        engine.handle_command(&limit(1, "SELL", 60, 10)).unwrap();
+        engine.handle_command(&limit(1, "SELL", 70, 10)).unwrap();
+        let buy = |amount: u64| {
+            let mut order = limit(2, "BUY", 0, 0);
+            order["order_type"] = json!("MARKET");
+            order["time_in_force"] = json!("IOC");
+            order["notional"] = json!(amount.to_string());
+            order
+        };
+
+        // 10 at 60, then 2 at 70, with 50 left
+        let outputs = engine.handle_command(&buy(790)).unwrap();
+        assert_eq!(
+            event_types(&outputs)[4..],
+            ["order.filled", "trade", "trade"]
+        );
+        let EngineOutput::Event(PublishEngineEvent::OrderFilled {
+            quantity, notional, ..
+        }) = &outputs[4]
+        else {
+            unreachable!()
+        };
+        assert_eq!(*quantity, Quantity(12));
+        assert_eq!(*notional, Some(740));
+
+        // The asks run out with budget to spare
+        let outputs = engine.handle_command(&buy(1_000)).unwrap();
+        let Some(EngineOutput::Event(PublishEngineEvent::OrderCancelled {
+            quantity,
+            notional,
+            ..
+        })) = outputs.last()
+        else {
+            unreachable!()
+        };
+        assert_eq!(*quantity, Quantity(8));
+        assert_eq!(*notional, Some(560));
+
+        let mut sell = buy(1_000);
+        sell["side"] = json!("SELL");
+        assert!(engine.handle_command(&sell).is_err());
+        let mut sized = buy(1_000);
+        sized["qty_original"] = json!("5");
+        assert!(engine.handle_command(&sized).is_err());
+    }
+
+    #[test]
+    fn pegged_orders_are_repriced_with_their_reference() {
+        let mut engine = MatchingEngine::new(false);
+        engine.handle_command(&limit(1, "BUY", 40, 10)).unwrap();
+        engine.handle_command(&limit(1, "SELL", 60, 10)).unwrap();
+        let mut pegged = limit(2, "BUY", 50, 5);
+        pegged["peg"] = json!("best_bid");
+        pegged["peg_offset"] = json!("2");
+        let outputs = engine.handle_command(&pegged).unwrap();
+        let Some(EngineOutput::Event(PublishEngineEvent::OrderPlaced { price, .. })) =
+            outputs.last()
+        else {
+            unreachable!()
+        };
+        assert_eq!(*price, Price(42));
+
+        let outputs = engine.handle_command(&limit(3, "BUY", 45, 1)).unwrap();
+        assert_eq!(event_types(&outputs).last(), Some(&"order.repriced"));
+
+        // The reference leaves: the peg follows the next best bid
+        let mut cancel_all = json!({
+            "type": "order.cancel_all",
+            "market_id": "1",
+            "account_id": "3",
+            "ts": "2000",
+        });
+        let outputs = engine.handle_command(&cancel_all).unwrap();
+        assert_eq!(
+            event_types(&outputs)[3..],
+            ["order.cancelled", "order.repriced"]
+        );
+        let Some(EngineOutput::Event(PublishEngineEvent::OrderRepriced {
+            price,
+            previous_price,
+            ..
+        })) = outputs.last()
+        else {
+            unreachable!()
+        };
+        assert_eq!((*previous_price, *price), (Price(47), Price(42)));
+
+        // Without a reference the peg stays where it is
+        cancel_all["account_id"] = json!("1");
+        let outputs = engine.handle_command(&cancel_all).unwrap();
+        assert!(!event_types(&outputs).contains(&"order.repriced"));
+
+        let mut ioc = pegged.clone();
+        ioc["time_in_force"] = json!("IOC");
+        assert!(engine.handle_command(&ioc).is_err());
+        let mut offset_only = limit(2, "BUY", 50, 5);
+        offset_only["peg_offset"] = json!("1");
+        assert!(engine.handle_command(&offset_only).is_err());
+        let mut far = pegged.clone();
+        far["peg_offset"] = json!(i64::MIN.to_string());
+        assert!(engine.handle_command(&far).is_err());
+    }
+
+    #[test]
+    fn all_or_none_orders_only_trade_in_full() {
+        let mut engine = MatchingEngine::new(false);
+        let mut aon = limit(1, "SELL", 60, 10);
+        aon["all_or_none"] = json!("true");
+        engine.handle_command(&aon).unwrap();
+
+        // Too small to take it whole: the buy rests beside it
+        let outputs = engine.handle_command(&limit(2, "BUY", 60, 4)).unwrap();
+        assert_eq!(event_types(&outputs).last(), Some(&"order.placed"));
+        let outputs = engine.handle_command(&limit(3, "BUY", 60, 10)).unwrap();
+        assert_eq!(event_types(&outputs)[4..], ["order.filled", "trade"]);
+
+        let mut with_min = limit(2, "BUY", 60, 5);
+        with_min["min_qty"] = json!("0");
+        assert!(engine.handle_command(&with_min).is_err());
+        with_min["min_qty"] = json!("6");
+        assert!(engine.handle_command(&with_min).is_err());
+        with_min["min_qty"] = json!("5");
+        with_min["display_qty"] = json!("1");
+        assert!(engine.handle_command(&with_min).is_err());
+        let mut market = aon.clone();
+        market["order_type"] = json!("MARKET");
+        market["time_in_force"] = json!("IOC");
+        assert!(engine.handle_command(&market).is_err());
+        aon["all_or_none"] = json!("yes");
+        assert!(engine.handle_command(&aon).is_err());
+    }
+
+    #[test]
+    fn iceberg_refills_get_their_own_trade_ids() {
+        let trade_ids = |engine: &mut MatchingEngine| {
+            let mut iceberg = limit(1, "SELL", 60, 25);
+            iceberg["display_qty"] = json!("10");
+            engine.apply_ledgered(1, "1-0", &iceberg).unwrap();
+            engine
+                .apply_ledgered(1, "2-0", &limit(2, "BUY", 60, 25))
+                .unwrap()
+                .into_iter()
+                .filter_map(|output| match output {
+                    EngineOutput::Event(PublishEngineEvent::Trade {
+                        trade_id, quantity, ..
+                    }) => Some((trade_id, quantity.value())),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+        let trades = trade_ids(&mut MatchingEngine::new(false));
+        let quantities: Vec<u64> = trades.iter().map(|(_, qty)| *qty).collect();
+        assert_eq!(quantities, vec![10, 10, 5]);
+        let ids: BTreeSet<&String> = trades.iter().map(|(id, _)| id).collect();
+        assert_eq!(ids.len(), 3);
+
+        // The same ledger gives the same ids
+        assert_eq!(trade_ids(&mut MatchingEngine::new(false)), trades);
+    }
+
+    #[test]
+    fn replay_updates_state_without_outputs() {
+        let mut replayed = MatchingEngine::new(true);
+        assert!(
+            replayed
+                .handle_command(&limit(1, "SELL", 60, 10))
+                .unwrap()
+                .is_empty()
+        );
+        replayed.is_replay_mode = false;
+
+        // The delta sequence continues where the replayed commands left it
+        let outputs = replayed.handle_command(&limit(2, "SELL", 61, 5)).unwrap();
+        let Some(EngineOutput::BookDelta { delta, .. }) = outputs.first() else {
+            panic!("expected a book delta first");
+        };
+        assert_eq!(delta.seq, 2);
+    }
+
+    #[test]
+    fn market_configure_changes_strategy() {
+        let mut engine = MatchingEngine::new(false);
+        let outputs = engine
+            .handle_command(&json!({
+                "type": "market.configure",
+                "market_id": "7",
+                "fair_price_strategy": "mid",
+            }))
+            .unwrap();
+        assert!(matches!(
+            outputs.as_slice(),
+            [EngineOutput::MarketData {
+                market_id: 7,
+                strategy: FairPriceStrategy::Mid,
+                ..
+            }]
+        ));
+        assert_eq!(engine.fair_price_strategy(7), FairPriceStrategy::Mid);
+
+        let configure = |allocation: &str| {
+            json!({
+                "type": "market.configure",
+                "market_id": "1",
+                "allocation": allocation,
+            })
+        };
+        engine.handle_command(&limit(1, "SELL", 60, 10)).unwrap();
+        engine.handle_command(&configure("pro_rata")).unwrap();
+        assert_eq!(engine.allocation(1), Allocation::ProRata);
+        assert_eq!(engine.books["outcome-1"].allocation(), Allocation::ProRata);
+        assert!(engine.handle_command(&configure("lottery")).is_err());
+        assert!(
+            engine
+                .handle_command(&json!({ "type": "market.configure", "market_id": "1" }))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_and_untyped_commands() {
+        let mut engine = MatchingEngine::new(false);
+        assert!(matches!(
+            engine.handle_command(&json!({ "type": "order.teleport" })),
+            Err(EngineError::UnknownEventType(_))
+        ));
+        assert!(matches!(
+            engine.handle_command(&json!({})),
+            Err(EngineError::MissingField(_))
+        ));
+    }
+
+    #[test]
+    fn cancel_all_cancels_by_account_then_market() {
+        let mut engine = MatchingEngine::new(false);
+        for payload in [
+            limit(1, "BUY", 40, 10),
+            limit(1, "SELL", 60, 10),
+            limit(2, "BUY", 41, 10),
+        ] {
+            engine.handle_command(&payload).unwrap();
+        }
+        let cancel_all = |market_id: &str, account_id: Option<&str>| {
+            let mut payload = json!({ "type": "order.cancel_all", "market_id": market_id });
+            if let Some(account_id) = account_id {
+                payload["account_id"] = json!(account_id);
+            }
+            payload
+        };
+
+        // Another market's command does not touch this one's books
+        assert!(
+            engine
+                .handle_command(&cancel_all("2", Some("1")))
+                .unwrap()
+                .is_empty()
+        );
+        let outputs = engine.handle_command(&cancel_all("1", Some("1"))).unwrap();
+        assert_eq!(
+            event_types(&outputs),
+            vec![
+                "book.delta",
+                "book.depth",
+                "market.data",
+                "order.cancelled",
+                "order.cancelled"
+            ]
+        );
+        assert_eq!(engine.books["outcome-1"].resting_orders(), 1);
+
+        let outputs = engine.handle_command(&cancel_all("1", None)).unwrap();
+        let cancelled: Vec<u64> = outputs
+            .iter()
+            .filter_map(|output| match output {
+                EngineOutput::Event(PublishEngineEvent::OrderCancelled { account_id, .. }) => {
+                    Some(account_id.0)
+                }
+                _ => None,
+            })
+            .collect();
+        assert_eq!(cancelled, vec![2]);
+        assert_eq!(engine.books["outcome-1"].resting_orders(), 0);
+        assert!(
+            engine
+                .handle_command(&cancel_all("1", None))
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn account_cancel_all_reaches_every_market_holding_the_account() {
+        let in_market = |mut payload: SerdeJsonValue, market_id: &str, outcome_id: &str| {
+            payload["market_id"] = json!(market_id);
+            payload["outcome_id"] = json!(outcome_id);
+            payload
+        };
+        let setup = || {
+            let mut engine = MatchingEngine::new(false);
+            for payload in [
+                limit(5, "BUY", 40, 10),
+                limit(6, "BUY", 41, 10),
+                in_market(limit(5, "SELL", 60, 10), "2", "outcome-2"),
+                in_market(limit(6, "SELL", 61, 10), "3", "outcome-3"),
+            ] {
+                engine.handle_command(&payload).unwrap();
+            }
+            engine
+        };
+        let cancel_all = json!({ "type": "order.cancel_all", "account_id": "5", "ts": "2000" });
+
+        // Read from market 1's stream, it stays within market 1
+        let mut ledgered = setup();
+        ledgered.apply_ledgered(1, "1-0", &cancel_all).unwrap();
+        assert_eq!(ledgered.books["outcome-1"].resting_orders(), 1);
+        assert_eq!(ledgered.books["outcome-2"].resting_orders(), 1);
+
+        // Applied directly, it reaches both markets holding the account, and
+        // only those
+        let mut engine = setup();
+        let outputs = engine.handle_command(&cancel_all).unwrap();
+        let markets: Vec<u32> = outputs
+            .iter()
+            .filter_map(|output| match output {
+                EngineOutput::MarketData { market_id, .. } => Some(*market_id),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(markets, vec![1, 2]);
+        assert_eq!(engine.books["outcome-1"].resting_orders(), 1);
+        assert_eq!(engine.books["outcome-2"].resting_orders(), 0);
+        assert_eq!(engine.books["outcome-3"].resting_orders(), 1);
+        assert!(engine.handle_command(&cancel_all).unwrap().is_empty());
+
+        // Everyone's orders can only go market by market
+        let everyone = json!({ "type": "order.cancel_all", "ts": "2000" });
+        assert!(matches!(
+            engine.handle_command(&everyone),
+            Err(EngineError::MissingField(_))
+        ));
+    }
+
+    #[test]
+    fn quote_replace_swaps_the_accounts_quotes() {
+        let mut engine = MatchingEngine::new(false);
+        let replace = |quotes: SerdeJsonValue| {
+            json!({
+                "type": "quote.replace",
+                "market_id": "1",
+                "account_id": "7",
+                "quotes": quotes.to_string(),
+                "ts": "1000",
+            })
+        };
+        let depth_updates = |outputs: &[EngineOutput]| {
+            event_types(outputs)
+                .into_iter()
+                .filter(|kind| *kind == "book.depth")
+                .count()
+        };
+
+        let outputs = engine
+            .handle_command(&replace(json!([
+                { "outcome_id": "outcome-1", "side": "BUY", "price": "40", "qty": "10" },
+                { "outcome_id": "outcome-1", "side": "SELL", "price": "60", "qty": "10" },
+                { "outcome_id": "outcome-2", "side": "BUY", "price": "30", "qty": "5" },
+            ])))
+            .unwrap();
+        assert_eq!(depth_updates(&outputs), 2);
+        assert_eq!(engine.books["outcome-1"].resting_orders(), 2);
+        assert_eq!(engine.books["outcome-2"].resting_orders(), 1);
+
+        // A quote on another market's outcome rejects the whole command
+        let mut other = limit(2, "BUY", 10, 1);
+        other["outcome_id"] = json!("outcome-9");
+        other["market_id"] = json!("2");
+        engine.handle_command(&other).unwrap();
+        assert!(matches!(
+            engine.handle_command(&replace(json!([
+                { "outcome_id": "outcome-9", "side": "BUY", "price": "10", "qty": "1" },
+            ]))),
+            Err(EngineError::OrderValidation(_))
+        ));
+        assert_eq!(engine.books["outcome-2"].resting_orders(), 1);
+
+        // So does an invalid quote, even the last one
+        let outputs = engine.handle_command(&replace(json!([
+            { "outcome_id": "outcome-1", "side": "BUY", "price": "42", "qty": "10" },
+            { "outcome_id": "outcome-2", "side": "SELL", "price": "0", "qty": "5" },
+        ])));
+        assert!(outputs.is_err());
+        assert_eq!(engine.books["outcome-1"].resting_orders(), 2);
+        assert_eq!(engine.books["outcome-1"].best_bid(), Some(Price(40)));
+        assert_eq!(engine.books["outcome-2"].resting_orders(), 1);
+        assert_eq!(engine.quote_sets[&1][&AccountId(7)].len(), 3);
+
+        let outputs = engine
+            .handle_command(&replace(json!([
+                { "outcome_id": "outcome-1", "side": "BUY", "price": "41", "qty": "10" },
+            ])))
+            .unwrap();
+        assert_eq!(depth_updates(&outputs), 2);
+        assert_eq!(
+            event_types(&outputs)
+                .into_iter()
+                .filter(|kind| *kind == "order.cancelled")
+                .count(),
+            3
+        );
+        assert_eq!(engine.books["outcome-1"].best_bid(), Some(Price(41)));
+        assert_eq!(engine.books["outcome-2"].resting_orders(), 0);
+
+        engine.handle_command(&replace(json!([]))).unwrap();
+        assert_eq!(engine.books["outcome-1"].resting_orders(), 0);
+        assert!(engine.quote_sets[&1].is_empty());
+    }
+
+    #[test]
+    fn heartbeat_sweep_cancels_disconnected_accounts() {
+        let mut engine = MatchingEngine::new(false);
+        for payload in [
+            limit(1, "BUY", 40, 10),
+            limit(1, "SELL", 60, 10),
+            limit(2, "BUY", 41, 10),
+        ] {
+            engine.handle_command(&payload).unwrap();
+        }
+        let heartbeat = |account_id: &str, ts: &str| {
+            json!({
+                "type": "account.heartbeat",
+                "market_id": "1",
+                "account_id": account_id,
+                "timeout_ms": "2000",
+                "ts": ts,
+            })
+        };
+        let sweep = |ts: &str| json!({ "type": "heartbeat.sweep", "market_id": "1", "ts": ts });
+        assert!(matches!(
+            engine.handle_command(&json!({
+                "type": "account.heartbeat",
+                "market_id": "1",
+                "account_id": "1",
+                "timeout_ms": "10",
+            })),
+            Err(EngineError::OrderValidation(_))
+        ));
+        engine.handle_command(&heartbeat("1", "1000")).unwrap();
+        engine.handle_command(&heartbeat("2", "1000")).unwrap();
+        engine.handle_command(&heartbeat("2", "2500")).unwrap();
+
+        assert!(engine.handle_command(&sweep("3000")).unwrap().is_empty());
+        let outputs = engine.handle_command(&sweep("3001")).unwrap();
+        let cancelled: Vec<(u64, Option<CancelReason>)> = outputs
+            .iter()
+            .filter_map(|output| match output {
+                EngineOutput::Event(PublishEngineEvent::OrderCancelled {
+                    account_id,
+                    reason,
+                    ..
+                }) => Some((account_id.0, *reason)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            cancelled,
+            vec![
+                (1, Some(CancelReason::Disconnected)),
+                (1, Some(CancelReason::Disconnected))
+            ]
+        );
+        assert_eq!(engine.books["outcome-1"].resting_orders(), 1);
+        // The account stays disarmed until it sends a heartbeat again
+        assert_eq!(engine.heartbeat_deadlines()[&1], 4_500);
+    }
+
+    #[test]
+    fn auctions_rest_orders_then_uncross_at_one_price() {
+        let mut engine = MatchingEngine::new(false);
+        let start = json!({
+            "type": "auction.start",
+            "market_id": "1",
+            "outcome_id": "outcome-1",
+            "ts": "500",
+        });
+        let outputs = engine.handle_command(&start).unwrap();
+        assert_eq!(
+            event_types(&outputs),
+            vec!["book.depth", "market.data", "auction.indicative"]
+        );
+
+        // Still not crossed: nothing new to tell
+        let outputs = engine.handle_command(&limit(1, "BUY", 55, 10)).unwrap();
+        assert!(!event_types(&outputs).contains(&"auction.indicative"));
+        engine.handle_command(&limit(1, "BUY", 50, 5)).unwrap();
+        let outputs = engine.handle_command(&limit(2, "SELL", 48, 8)).unwrap();
+        assert_eq!(
+            event_types(&outputs),
+            vec![
+                "book.delta",
+                "book.depth",
+                "market.data",
+                "order.placed",
+                "auction.indicative"
+            ]
+        );
+        let Some(EngineOutput::Event(PublishEngineEvent::AuctionIndicative {
+            price, volume, ..
+        })) = outputs.last()
+        else {
+            panic!("expected an indicative uncross");
+        };
+        assert_eq!((*price, *volume), (Some(Price(55)), Quantity(8)));
+        let outputs = engine.handle_command(&limit(1, "BUY", 40, 5)).unwrap();
+        assert!(!event_types(&outputs).contains(&"auction.indicative"));
+
+        // Refreshes publish it again, at most once a period
+        let refresh = |ts: &str| {
+            json!({
+                "type": "auction.refresh",
+                "market_id": "1",
+                "ts": ts,
+            })
+        };
+        assert_eq!(engine.indicative_deadlines()[&1], 1_000);
+        let outputs = engine.handle_command(&refresh("1500")).unwrap();
+        assert_eq!(event_types(&outputs), vec!["auction.indicative"]);
+        assert!(engine.handle_command(&refresh("1600")).unwrap().is_empty());
+        assert_eq!(engine.indicative_deadlines()[&1], 2_500);
+
+        let mut uncross = start.clone();
+        uncross["type"] = json!("auction.uncross");
+        uncross["ts"] = json!("2000");
+        let outputs = engine.handle_command(&uncross).unwrap();
+        assert_eq!(
+            event_types(&outputs),
+            vec![
+                "fair_price",
+                "book.delta",
+                "book.depth",
+                "market.data",
+                "trade",
+                "auction.result"
+            ]
+        );
+        assert_eq!(engine.fair_price("outcome-1"), Some(Price(55)));
+        assert!(!engine.books["outcome-1"].in_auction());
+        assert!(engine.indicative_deadlines().is_empty());
+
+        // Matching is continuous again, and a second uncross does nothing
+        let outputs = engine.handle_command(&limit(2, "SELL", 50, 5)).unwrap();
+        assert!(event_types(&outputs).contains(&"trade"));
+        assert!(engine.handle_command(&uncross).unwrap().is_empty());
+
+        let mut other_market = start;
+        other_market["market_id"] = json!("2");
+        assert!(engine.handle_command(&other_market).is_err());
+    }
+
+    #[test]
+    fn auctions_wait_for_constrained_orders_to_leave() {
+        let mut engine = MatchingEngine::new(false);
+        engine.handle_command(&limit(1, "BUY", 40, 5)).unwrap();
+        let mut aon = limit(2, "SELL", 60, 10);
+        aon["outcome_id"] = json!("outcome-2");
+        aon["all_or_none"] = json!("true");
+        engine.handle_command(&aon).unwrap();
+
+        // The uncross could not honour the all-or-none order, so neither an
+        // auction of the market nor batch mode starts, in any of its books
+        let start = json!({ "type": "auction.start", "market_id": "1", "ts": "500" });
+        assert!(engine.handle_command(&start).is_err());
+        let configure = json!({
+            "type": "market.configure",
+            "market_id": "1",
+            "matching_mode": "batch",
+            "fair_price_strategy": "mid",
+            "ts": "500",
+        });
+        assert!(engine.handle_command(&configure).is_err());
+        assert!(engine.books.values().all(|book| !book.in_auction()));
+        assert_eq!(engine.matching_mode(1), MatchingMode::Continuous);
+        assert_ne!(engine.fair_price_strategy(1), FairPriceStrategy::Mid);
+
+        // Once it is gone, both can
+        engine
+            .handle_command(&json!({
+                "type": "order.cancel_all",
+                "market_id": "1",
+                "account_id": "2",
+                "ts": "600",
+            }))
+            .unwrap();
+        engine.handle_command(&configure).unwrap();
+        assert_eq!(engine.books.len(), 2);
+        assert!(engine.books.values().all(|book| book.in_auction()));
+    }
+
+    #[test]
+    fn batch_markets_clear_at_one_price_per_interval() {
+        let configure = json!({
+            "type": "market.configure",
+            "market_id": "1",
+            "matching_mode": "batch:100",
+            "ts": "950",
+        });
+        let clear = |ts: i64| {
+            json!({
+                "type": "batch.clear",
+                "market_id": "1",
+                "ts": ts.to_string(),
+            })
+        };
+        let ledger = vec![
+            configure,
+            limit(1, "SELL", 50, 5),
+            limit(2, "BUY", 55, 3),
+            limit(3, "BUY", 52, 4),
+            clear(1_020),
+            limit(4, "BUY", 60, 2),
+        ];
+
+        let mut engine = MatchingEngine::new(false);
+        let mut outputs = Vec::new();
+        for payload in &ledger {
+            outputs = engine.handle_command(payload).unwrap();
+        }
+        // The first order of a new book already waits for the batch, and a
+        // clear before the end of the batch does nothing
+        assert!(!event_types(&outputs).contains(&"trade"));
+        assert!(engine.books["outcome-1"].in_auction());
+        assert_eq!(
+            engine.matching_mode(1),
+            MatchingMode::Batch { interval_ms: 100 }
+        );
+        assert!(engine.handle_command(&clear(1_049)).unwrap().is_empty());
+
+        // The two best bids fill at 54, the middle of the prices from 53 to
+        // 55 that leave nothing over
+        let outputs = engine.handle_command(&clear(1_051)).unwrap();
+        assert_eq!(
+            event_types(&outputs),
+            vec![
+                "fair_price",
+                "book.delta",
+                "book.depth",
+                "market.data",
+                "trade",
+                "trade",
+                "auction.result",
+                "auction.indicative"
+            ]
+        );
+        assert_eq!(engine.fair_price("outcome-1"), Some(Price(54)));
+        assert!(engine.books["outcome-1"].in_auction());
+        assert_eq!(engine.batch_deadlines()[&1], 1_150);
+
+        // Replaying the same ledger clears the same batches
+        let mut replayed = MatchingEngine::new(true);
+        for payload in ledger.iter().chain([&clear(1_051)]) {
+            replayed.handle_command(payload).unwrap();
+        }
+        assert_eq!(
+            replayed.books["outcome-1"].depth(None),
+            engine.books["outcome-1"].depth(None)
+        );
+        assert_eq!(replayed.batch_deadlines(), engine.batch_deadlines());
+
+        // The schedule moves with the market
+        let snapshot = engine.release_market(1);
+        let mut target = MatchingEngine::new(false);
+        target.adopt_market(1, Some(snapshot), Vec::new(), &BTreeSet::new());
+        assert_eq!(target.batch_deadlines()[&1], 1_150);
+
+        // Back to continuous matching, clearing what is left
+        target.handle_command(&limit(5, "SELL", 50, 5)).unwrap();
+        let outputs = target
+            .handle_command(&json!({
+                "type": "market.configure",
+                "market_id": "1",
+                "matching_mode": "continuous",
+                "ts": "1200",
+            }))
+            .unwrap();
+        assert!(event_types(&outputs).contains(&"trade"));
+        assert!(!target.books["outcome-1"].in_auction());
+        assert!(target.batch_deadlines().is_empty());
+    }
+}
@@ -1,22 +1,77 @@
-use super::order::Order;
-use crate::engine::{order::OrderType, publish_events::PublishEngineEvent};
+use super::order::{Order, OrderSide};
+use crate::engine::batch::BatchSchedule;
+use crate::engine::fair_price::{DEFAULT_VWAP_HALF_LIFE_MS, FairPriceState, FairPriceStrategy};
+use crate::engine::heartbeat::Heartbeat;
+use crate::engine::stop::TrailingStop;
+use crate::engine::volume::{OutcomeVolume, VolumeTotals};
+use crate::engine::{
+    order::OrderType,
+    publish_events::{CancelReason, PublishEngineEvent},
+};
 use crate::error::EngineError;
 use crate::orderbook::order::AccountId;
 use crate::orderbook::{
-    ExecutionReport, LimitOrderOptions, MarketOrderOptions, OrderBook, OrderBookBuilder, OrderId,
-    OrderStatus, Price, Quantity, TimeInForce,
+    Allocation, ExecutionReport, MarketOrderOptions, NotionalOrderOptions, OrderBook,
+    OrderBookBuilder, OrderId, OrderStatus, Price, Quantity, Uncross,
 };
-use redis::aio::Connection;
-use redis::{AsyncCommands, RedisError};
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 use tracing::{debug, info};
 use uuid::Uuid;
 
+/// Engine name used when `ENGINE_ID` is not set.
+pub const DEFAULT_ENGINE_ID: &str = "engine-1";
+
 pub struct MatchingEngine {
     pub books: BTreeMap<String, OrderBook>,
-    pub fair_prices: BTreeMap<String, Price>,
-    pub total_outcome_volumes: BTreeMap<String, Price>, // outcomeId -> u64
+    pub fair_prices: BTreeMap<String, FairPriceState>,
+    pub outcome_volumes: BTreeMap<String, OutcomeVolume>,
+    pub outcome_markets: BTreeMap<String, u32>,
+    pub fair_price_strategies: BTreeMap<u32, FairPriceStrategy>,
+    pub default_fair_price_strategy: FairPriceStrategy,
+    /// Latest command `ts` seen; the engine's notion of "now"
+    pub clock: i64,
     pub is_replay_mode: bool,
+    /// Instance name used in the shard map
+    pub engine_id: String,
+    /// Last ledger entry applied, per market
+    pub ledger_positions: BTreeMap<u32, String>,
+    /// Markets handed off to another engine
+    pub released_markets: BTreeSet<u32>,
+    /// Orders of each account's last `quote.replace`, per market, as
+    /// `(outcome_id, order_id)`
+    pub quote_sets: BTreeMap<u32, BTreeMap<AccountId, Vec<(String, OrderId)>>>,
+    /// Accounts enrolled in cancel-on-disconnect, per market
+    pub heartbeats: BTreeMap<u32, BTreeMap<AccountId, Heartbeat>>,
+    /// Markets in batch matching mode
+    pub batches: BTreeMap<u32, BatchSchedule>,
+    /// Last `auction.indicative` published, per book in auction
+    pub indicatives: BTreeMap<String, Option<Uncross>>,
+    /// When each market last republished the indicatives of its books
+    pub indicative_refreshes: BTreeMap<u32, i64>,
+    /// Allocation of the books of markets configured with one
+    pub allocations: BTreeMap<u32, Allocation>,
+    /// Trailing stops waiting to fire, per outcome, oldest first
+    pub trailing_stops: BTreeMap<String, Vec<TrailingStop>>,
+    /// Next stop id, per outcome
+    pub next_stop_ids: BTreeMap<String, OrderId>,
+    /// Command being applied: its market and ledger entry, or a local
+    /// sequence for commands applied outside a ledger
+    pub(crate) op_id: String,
+    /// Market whose ledger holds the command being applied, if any
+    pub(crate) op_market: Option<u32>,
+    /// Commands applied outside a ledger so far
+    pub(crate) local_ops: u64,
+    /// Trades generated by the command being applied so far
+    pub(crate) op_trades: u64,
+}
+
+/// Per-outcome figures published in `market.data`.
+#[derive(Debug, Clone)]
+pub struct OutcomeMarketData {
+    pub outcome_id: String,
+    pub fair_price: Option<Price>,
+    pub total_volume: VolumeTotals,
+    pub rolling_volume: VolumeTotals,
 }
 
 impl MatchingEngine {
@@ -25,30 +80,148 @@ impl MatchingEngine {
         Self {
             books: BTreeMap::new(),
             fair_prices: BTreeMap::new(),
-            total_outcome_volumes: BTreeMap::new(),
+            outcome_volumes: BTreeMap::new(),
+            outcome_markets: BTreeMap::new(),
+            fair_price_strategies: BTreeMap::new(),
+            default_fair_price_strategy: FairPriceStrategy::default(),
+            clock: 0,
             is_replay_mode: replay,
+            engine_id: DEFAULT_ENGINE_ID.to_string(),
+            ledger_positions: BTreeMap::new(),
+            released_markets: BTreeSet::new(),
+            quote_sets: BTreeMap::new(),
+            heartbeats: BTreeMap::new(),
+            batches: BTreeMap::new(),
+            indicatives: BTreeMap::new(),
+            indicative_refreshes: BTreeMap::new(),
+            allocations: BTreeMap::new(),
+            trailing_stops: BTreeMap::new(),
+            next_stop_ids: BTreeMap::new(),
+            op_id: String::new(),
+            op_market: None,
+            local_ops: 0,
+            op_trades: 0,
+        }
+    }
+
+    pub fn with_engine_id(mut self, engine_id: impl Into<String>) -> Self {
+        self.engine_id = engine_id.into();
+        self
+    }
+
+    /// Strategy used for markets without an explicit `market.configure`.
+    pub fn with_default_fair_price_strategy(mut self, strategy: FairPriceStrategy) -> Self {
+        self.default_fair_price_strategy = strategy;
+        self
+    }
+
+    pub fn fair_price_strategy(&self, market_id: u32) -> FairPriceStrategy {
+        self.fair_price_strategies
+            .get(&market_id)
+            .copied()
+            .unwrap_or(self.default_fair_price_strategy)
+    }
+
+    pub fn configure_market(&mut self, market_id: u32, strategy: FairPriceStrategy) {
+        info!(
+            "Market {} fair price strategy set to {}",
+            market_id, strategy
+        );
+        self.fair_price_strategies.insert(market_id, strategy);
+    }
+
+    pub fn allocation(&self, market_id: u32) -> Allocation {
+        self.allocations
+            .get(&market_id)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Sets the allocation of every book of `market_id`, current and future.
+    pub fn configure_allocation(&mut self, market_id: u32, allocation: Allocation) {
+        info!("Market {} allocation set to {}", market_id, allocation);
+        self.allocations.insert(market_id, allocation);
+        for (outcome_id, _) in self
+            .outcome_markets
+            .iter()
+            .filter(|(_, market)| **market == market_id)
+        {
+            if let Some(book) = self.books.get_mut(outcome_id) {
+                book.set_allocation(allocation);
+            }
         }
     }
 
+    /// Current fair price of an outcome under its market's strategy.
+    pub fn fair_price(&self, outcome_id: &str) -> Option<Price> {
+        let strategy = self
+            .outcome_markets
+            .get(outcome_id)
+            .map(|market_id| self.fair_price_strategy(*market_id))
+            .unwrap_or(self.default_fair_price_strategy);
+        self.fair_prices
+            .get(outcome_id)
+            .cloned()
+            .unwrap_or_default()
+            .fair_price(strategy, self.books.get(outcome_id))
+    }
+
     pub fn get_or_create_book(&mut self, outcome_id: &str) -> &mut OrderBook {
         if !self.books.contains_key(outcome_id) {
             debug!("Creating new order book for outcome: {}", outcome_id);
         }
-        self.books
-            .entry(outcome_id.to_string())
-            .or_insert_with(|| OrderBookBuilder::new(outcome_id).build())
+        let allocation = self
+            .outcome_markets
+            .get(outcome_id)
+            .map(|market_id| self.allocation(*market_id))
+            .unwrap_or_default();
+        self.books.entry(outcome_id.to_string()).or_insert_with(|| {
+            OrderBookBuilder::new(outcome_id)
+                .with_allocation(allocation)
+                .build()
+        })
     }
 
-    pub async fn order_execution(
+    pub fn order_execution(
         &mut self,
-        redis: &mut Connection,
         order: &Order,
-    ) -> (
-        Vec<PublishEngineEvent>,
-        &OrderBook,
-        Vec<(String, Price, Price)>,
-    ) {
+    ) -> (Vec<PublishEngineEvent>, &OrderBook, Vec<OutcomeMarketData>) {
+        let (events, _) = self.place_order(order);
+        let market_data = self.market_data(order.market_id);
+        let book = self.books.get(&order.outcome_id).unwrap();
+        (events, book, market_data)
+    }
+
+    /// Executes `order` on its book and describes what happened, along with
+    /// the trailing stops its trades fire.
+    ///
+    /// The report is `None` if the book refused the order.
+    pub(crate) fn place_order(
+        &mut self,
+        order: &Order,
+    ) -> (Vec<PublishEngineEvent>, Option<ExecutionReport>) {
+        let (mut events, report) = self.execute_order(order);
+        if report.is_some() {
+            events.extend(self.run_trailing_stops(&order.outcome_id));
+        }
+        (events, report)
+    }
+
+    /// [`MatchingEngine::place_order`] without running the trailing stops.
+    pub(crate) fn execute_order(
+        &mut self,
+        order: &Order,
+    ) -> (Vec<PublishEngineEvent>, Option<ExecutionReport>) {
         let mut events = Vec::new();
+        self.advance_clock(order.ts);
+        self.outcome_markets
+            .insert(order.outcome_id.clone(), order.market_id);
+        // New books of a batch market join the batch being collected
+        if self.batches.contains_key(&order.market_id) {
+            // A book of a batch market is new or already in auction, so it
+            // holds no constrained orders
+            let _ = self.get_or_create_book(&order.outcome_id).start_auction();
+        }
         let execution_result = self.execute_order_on_book(order);
         let execution_report = match execution_result {
             Ok(report) => report,
@@ -56,26 +229,29 @@ impl MatchingEngine {
                 events.push(PublishEngineEvent::OrderRejected {
                     outcome_id: order.outcome_id.clone(),
                     account_id: AccountId(order.account_id),
-                    side: order.side.clone().into(),
+                    side: order.side.clone(),
                     price: Price(order.price),
                     time_in_force: Some(order.time_in_force),
                     quantity: Quantity(order.qty_original),
                 });
-                let fair_prices_and_total_volumes = self.get_fair_prices_and_total_volumes().await;
-                let book = self.books.get(&order.outcome_id).unwrap();
-                return (events, book, fair_prices_and_total_volumes);
+                return (events, None);
             }
         };
 
-        let outcome_id = order.outcome_id.clone();
-        let total_volume = self
-            .total_outcome_volumes
-            .get(&outcome_id)
-            .unwrap_or(&Price(0));
-        let new_total_volume =
-            total_volume.clone() + execution_report.executed_qty * execution_report.price;
-        self.total_outcome_volumes
-            .insert(outcome_id.clone(), new_total_volume);
+        // Notional orders get their size from matching
+        let sized;
+        let order = match order.notional {
+            Some(_) => {
+                sized = Order {
+                    qty_original: execution_report.executed_qty.value(),
+                    qty_remaining: 0,
+                    ..order.clone()
+                };
+                &sized
+            }
+            None => order,
+        };
+        let notional = order.notional.map(|_| execution_report.notional());
 
         // Process the execution report and create appropriate events
         match execution_report.status {
@@ -84,42 +260,31 @@ impl MatchingEngine {
                     order_id: execution_report.order_id,
                     outcome_id: order.outcome_id.clone(),
                     account_id: AccountId(order.account_id),
-                    side: order.side.clone().into(),
-                    price: Price(order.price),
+                    side: order.side.clone(),
+                    // Where it rests, which for a peg is not its cap
+                    price: execution_report.price,
                     time_in_force: Some(execution_report.time_in_force),
                     quantity: Quantity(order.qty_original),
                 });
             }
             OrderStatus::Filled | OrderStatus::PartiallyFilled => {
-                self.fair_prices
-                    .insert(outcome_id.clone(), execution_report.price);
-                if !self.is_replay_mode {
-                    let _: Result<String, RedisError> = redis
-                        .set(
-                            format!("fair_price:{}", &order.outcome_id),
-                            execution_report.price.0.to_string(),
-                        )
-                        .await;
-                } else {
-                    self.fair_prices
-                        .insert(outcome_id.clone(), execution_report.price);
-                }
                 if execution_report.status == OrderStatus::Filled {
                     events.push(PublishEngineEvent::OrderFilled {
                         order_id: execution_report.order_id,
                         outcome_id: order.outcome_id.clone(),
                         account_id: AccountId(order.account_id),
-                        side: order.side.clone().into(),
+                        side: order.side.clone(),
                         price: Price(order.price),
                         time_in_force: Some(execution_report.time_in_force),
                         quantity: Quantity(order.qty_original),
+                        notional,
                     });
                 } else {
                     events.push(PublishEngineEvent::OrderPartial {
                         order_id: execution_report.order_id,
                         outcome_id: order.outcome_id.clone(),
                         account_id: AccountId(order.account_id),
-                        side: order.side.clone().into(),
+                        side: order.side.clone(),
                         price: Price(order.price),
                         time_in_force: Some(execution_report.time_in_force),
                         quantity: Quantity(order.qty_original),
@@ -127,43 +292,31 @@ impl MatchingEngine {
                         original_quantity: execution_report.orig_qty,
                     });
                 }
-                for fill in &execution_report.fills {
-                    events.push(PublishEngineEvent::Trade {
-                        trade_id: self.generate_trade_id(
-                            &execution_report.order_id,
-                            &fill.order_id,
-                            &fill.account_id,
-                        ),
-                        account_id: AccountId(order.account_id),
-                        outcome_id: order.outcome_id.clone(),
-                        order_id: execution_report.order_id,
-                        filled_order_id: fill.order_id,
-                        filled_account_id: fill.account_id,
-                        price: Price(fill.price.0),
-                        quantity: Quantity(fill.quantity.0),
-                        side: order.side.clone().into(),
-                        remaining: Quantity(order.qty_remaining),
-                        original_quantity: Quantity(order.qty_original),
-                        time_in_force: Some(execution_report.time_in_force),
-                    });
-                }
+                events.extend(self.taker_trades(order, &execution_report));
             }
             OrderStatus::Canceled => {
+                // IOC and protected market orders may fill some before the
+                // rest is cancelled
+                events.extend(self.taker_trades(order, &execution_report));
+                let reason = order.protection().map(|_| CancelReason::PriceProtection);
                 events.push(PublishEngineEvent::OrderCancelled {
                     order_id: execution_report.order_id,
                     outcome_id: order.outcome_id.clone(),
                     account_id: AccountId(order.account_id),
-                    side: order.side.clone().into(),
+                    side: order.side.clone(),
                     price: Price(order.price),
                     time_in_force: Some(execution_report.time_in_force),
                     quantity: Quantity(order.qty_original),
+                    remaining: execution_report.remaining_qty,
+                    notional,
+                    reason,
                 });
             }
             OrderStatus::Rejected => {
                 events.push(PublishEngineEvent::OrderRejected {
                     outcome_id: order.outcome_id.clone(),
                     account_id: AccountId(order.account_id),
-                    side: order.side.clone().into(),
+                    side: order.side.clone(),
                     price: Price(order.price),
                     time_in_force: Some(execution_report.time_in_force),
                     quantity: Quantity(order.qty_original),
@@ -171,33 +324,136 @@ impl MatchingEngine {
             }
         }
 
-        let fair_prices_and_total_volumes = self.get_fair_prices_and_total_volumes().await;
-        let book = self.books.get(&order.outcome_id).unwrap();
-        (events, book, fair_prices_and_total_volumes)
+        self.record_trades(order, &execution_report);
+        events.extend(self.repriced_events(&order.outcome_id));
+        (events, Some(execution_report))
+    }
+
+    /// One `order.repriced` per pegged order of `outcome_id` that moved
+    /// since the last call.
+    pub(crate) fn repriced_events(&mut self, outcome_id: &str) -> Vec<PublishEngineEvent> {
+        let Some(book) = self.books.get_mut(outcome_id) else {
+            return Vec::new();
+        };
+        book.take_repriced()
+            .into_iter()
+            .map(|repricing| PublishEngineEvent::OrderRepriced {
+                order_id: repricing.order_id,
+                account_id: repricing.account_id,
+                outcome_id: outcome_id.to_string(),
+                side: OrderSide(repricing.side),
+                quantity: repricing.remaining_qty,
+                price: repricing.price,
+                previous_price: repricing.previous_price,
+            })
+            .collect()
+    }
+
+    /// One trade per fill of `order` as the taker.
+    fn taker_trades(
+        &mut self,
+        order: &Order,
+        execution_report: &ExecutionReport,
+    ) -> Vec<PublishEngineEvent> {
+        execution_report
+            .fills
+            .iter()
+            .map(|fill| PublishEngineEvent::Trade {
+                trade_id: self.generate_trade_id(),
+                account_id: AccountId(order.account_id),
+                outcome_id: order.outcome_id.clone(),
+                order_id: execution_report.order_id,
+                filled_order_id: fill.order_id,
+                filled_account_id: fill.account_id,
+                price: Price(fill.price.0),
+                quantity: Quantity(fill.quantity.0),
+                side: order.side.clone(),
+                remaining: Quantity(order.qty_remaining),
+                original_quantity: Quantity(order.qty_original),
+                time_in_force: Some(execution_report.time_in_force),
+            })
+            .collect()
+    }
+
+    /// Moves the engine clock forward.
+    ///
+    /// Rolling volume windows are not expired here but as they are read, see
+    /// [`OutcomeVolume`].
+    pub fn advance_clock(&mut self, ts: i64) {
+        self.clock = self.clock.max(ts);
+    }
+
+    /// Feeds every fill of the taker into the outcome's fair price state and
+    /// volume statistics.
+    ///
+    /// Fills carry the maker's price, which is the actual trade price even for
+    /// market orders whose report price is the `0` placeholder.
+    fn record_trades(&mut self, order: &Order, execution_report: &ExecutionReport) {
+        let fills: Vec<(Price, Quantity)> = execution_report
+            .fills
+            .iter()
+            .map(|fill| (fill.price, fill.quantity))
+            .collect();
+        self.record_fills(order.market_id, &order.outcome_id, &fills, order.ts);
+    }
+
+    /// Feeds executions of `outcome_id` at `ts` into its fair price and
+    /// volume.
+    pub(crate) fn record_fills(
+        &mut self,
+        market_id: u32,
+        outcome_id: &str,
+        fills: &[(Price, Quantity)],
+        ts: i64,
+    ) {
+        if fills.is_empty() {
+            return;
+        }
+        let half_life_ms = match self.fair_price_strategy(market_id) {
+            FairPriceStrategy::Vwap { half_life_ms } => half_life_ms,
+            _ => DEFAULT_VWAP_HALF_LIFE_MS,
+        };
+        let state = self.fair_prices.entry(outcome_id.to_string()).or_default();
+        for (price, quantity) in fills {
+            state.record_trade(*price, *quantity, ts, half_life_ms);
+        }
+        let volume = self
+            .outcome_volumes
+            .entry(outcome_id.to_string())
+            .or_default();
+        for (price, quantity) in fills {
+            volume.record_fill(*price, *quantity, ts);
+        }
     }
 
     fn execute_order_on_book(&mut self, order: &Order) -> Result<ExecutionReport, EngineError> {
         let book = self.get_or_create_book(&order.outcome_id);
-        let execution_report = match order.order_type {
-            OrderType::LIMIT => {
-                let opts = LimitOrderOptions {
-                    price: Price(order.price),
-                    time_in_force: Some(order.time_in_force),
-                    side: order.side.clone().into(),
-                    quantity: Quantity(order.qty_original),
-                    post_only: Some(false),
+        let execution_report = match (&order.order_type, order.notional) {
+            (OrderType::LIMIT, _) => {
+                book.limit(order.limit_options())
+                    .map_err(|e| EngineError::OrderExecution {
+                        reason: format!("Limit order failed: {}", e),
+                        order_id: None,
+                    })?
+            }
+            (OrderType::MARKET, Some(notional)) => {
+                let opts = NotionalOrderOptions {
+                    notional,
                     account_id: AccountId(order.account_id),
+                    protection: order.protection(),
                 };
-                book.limit(opts).map_err(|e| EngineError::OrderExecution {
-                    reason: format!("Limit order failed: {}", e),
-                    order_id: None,
-                })?
+                book.market_notional(opts)
+                    .map_err(|e| EngineError::OrderExecution {
+                        reason: format!("Notional order failed: {}", e),
+                        order_id: None,
+                    })?
             }
-            OrderType::MARKET => {
+            (OrderType::MARKET, None) => {
                 let opts = MarketOrderOptions {
                     side: order.side.clone().into(),
                     quantity: Quantity(order.qty_original),
                     account_id: AccountId(order.account_id),
+                    protection: order.protection(),
                 };
                 book.market(opts).map_err(|e| EngineError::OrderExecution {
                     reason: format!("Market order failed: {}", e),
@@ -208,38 +464,40 @@ impl MatchingEngine {
         Ok(execution_report)
     }
 
-    async fn get_fair_prices_and_total_volumes(&mut self) -> Vec<(String, Price, Price)> {
-        let mut fair_prices_and_total_volumes = Vec::new();
-        for (outcome_id, _) in &self.books {
-            let fair_price = self
-                .fair_prices
-                .get(outcome_id)
-                .copied()
-                .unwrap_or(Price(0));
-            let total_volume = self
-                .total_outcome_volumes
-                .get(outcome_id)
-                .copied()
-                .unwrap_or(Price(0));
-            fair_prices_and_total_volumes.push((outcome_id.clone(), fair_price, total_volume));
-        }
-        fair_prices_and_total_volumes
+    /// Collects the figures of every known outcome of `market_id`.
+    pub fn market_data(&self, market_id: u32) -> Vec<OutcomeMarketData> {
+        self.outcome_markets
+            .iter()
+            .filter(|(_, outcome_market_id)| **outcome_market_id == market_id)
+            .map(|(outcome_id, _)| {
+                let volume = self.outcome_volumes.get(outcome_id);
+                OutcomeMarketData {
+                    outcome_id: outcome_id.clone(),
+                    fair_price: self.fair_price(outcome_id),
+                    total_volume: volume.map(|v| v.total()).unwrap_or_default(),
+                    rolling_volume: volume.map(|v| v.rolling(self.clock)).unwrap_or_default(),
+                }
+            })
+            .collect()
     }
 
-    fn generate_trade_id(
-        &self,
-        order_id: &OrderId,
-        filled_order_id: &OrderId,
-        fill_account_id: &AccountId,
-    ) -> String {
+    /// Id of the next trade of the command being applied.
+    ///
+    /// Built from the command's ledger entry and the trade's index within
+    /// it, so every fill gets its own id, even the repeated fills of an
+    /// iceberg, and replaying the ledger gives the same ids.
+    pub(crate) fn generate_trade_id(&mut self) -> String {
         let namespace = Uuid::NAMESPACE_OID;
-        let input = format!("{}-{}-{}", order_id, filled_order_id, fill_account_id);
+        let input = format!("{}-{}", self.op_id, self.op_trades);
+        self.op_trades += 1;
         Uuid::new_v5(&namespace, input.as_bytes()).to_string()
     }
 
     pub fn stats(&self) -> EngineStats {
         EngineStats {
             total_books: self.books.len(),
+            total_markets: self.markets().len(),
+            resting_orders: self.books.values().map(OrderBook::resting_orders).sum(),
         }
     }
 }
@@ -247,4 +505,6 @@ impl MatchingEngine {
 #[derive(Debug, Clone)]
 pub struct EngineStats {
     pub total_books: usize,
+    pub total_markets: usize,
+    pub resting_orders: usize,
 }
@@ -0,0 +1,233 @@
+use crate::{
+    error::EngineError,
+    orderbook::{OrderBook, Price, Quantity},
+};
+use serde::{Deserialize, Serialize};
+use std::{fmt, str::FromStr};
+
+/// Half-life used by the VWAP accumulator when the market is configured with
+/// another strategy, so switching to `vwap` later starts from a warm state.
+pub const DEFAULT_VWAP_HALF_LIFE_MS: u64 = 5 * 60 * 1000;
+
+/// How the engine derives the fair price of an outcome.
+///
+/// The fair price is published in `market.data` and stored under
+/// `fair_price:{outcome_id}`; the backend uses it to price reservations for
+/// market orders, so it must never be the `0` placeholder of a market report.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FairPriceStrategy {
+    /// Price of the last fill.
+    #[default]
+    LastTrade,
+    /// Average of best bid and best ask.
+    Mid,
+    /// Top-of-book mid weighted by the size resting on the opposite side.
+    Microprice,
+    /// Volume-weighted average trade price with exponential time decay.
+    Vwap { half_life_ms: u64 },
+}
+
+impl fmt::Display for FairPriceStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FairPriceStrategy::LastTrade => write!(f, "last_trade"),
+            FairPriceStrategy::Mid => write!(f, "mid"),
+            FairPriceStrategy::Microprice => write!(f, "microprice"),
+            FairPriceStrategy::Vwap { half_life_ms } => write!(f, "vwap:{}", half_life_ms),
+        }
+    }
+}
+
+impl FromStr for FairPriceStrategy {
+    type Err = EngineError;
+
+    /// Accepts `last_trade`, `mid`, `microprice`, `vwap` or `vwap:<half_life_ms>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.trim().to_lowercase();
+        let (name, arg) = match lower.split_once(':') {
+            Some((name, arg)) => (name, Some(arg)),
+            None => (lower.as_str(), None),
+        };
+        match (name, arg) {
+            ("last_trade", None) => Ok(FairPriceStrategy::LastTrade),
+            ("mid", None) => Ok(FairPriceStrategy::Mid),
+            ("microprice", None) => Ok(FairPriceStrategy::Microprice),
+            ("vwap", None) => Ok(FairPriceStrategy::Vwap {
+                half_life_ms: DEFAULT_VWAP_HALF_LIFE_MS,
+            }),
+            ("vwap", Some(arg)) => match arg.parse::<u64>() {
+                Ok(half_life_ms) if half_life_ms > 0 => {
+                    Ok(FairPriceStrategy::Vwap { half_life_ms })
+                }
+                _ => Err(EngineError::OrderValidation(format!(
+                    "Invalid VWAP half-life '{}': must be a positive number of milliseconds",
+                    arg
+                ))),
+            },
+            _ => Err(EngineError::OrderValidation(format!(
+                "Invalid fair price strategy: '{}'. Must be 'last_trade', 'mid', 'microprice' or 'vwap[:half_life_ms]'",
+                s
+            ))),
+        }
+    }
+}
+
+/// Per-outcome trade state needed by every [`FairPriceStrategy`].
+///
+/// All strategies are fed on every fill so a market can switch strategy at any
+/// time without a cold start. Time only advances through command timestamps,
+/// which keeps the result identical on ledger replay.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FairPriceState {
+    last_trade: Option<Price>,
+    vwap_notional: f64,
+    vwap_quantity: f64,
+    vwap_ts: i64,
+}
+
+impl FairPriceState {
+    /// Records a fill at `price` for `quantity` contracts executed at `ts`.
+    pub fn record_trade(&mut self, price: Price, quantity: Quantity, ts: i64, half_life_ms: u64) {
+        if quantity.value() == 0 {
+            return;
+        }
+        self.decay_to(ts, half_life_ms);
+        self.vwap_notional += price.value() as f64 * quantity.value() as f64;
+        self.vwap_quantity += quantity.value() as f64;
+        self.last_trade = Some(price);
+    }
+
+    pub fn last_trade(&self) -> Option<Price> {
+        self.last_trade
+    }
+
+    /// Computes the fair price for `book` under `strategy`.
+    ///
+    /// Book-based strategies fall back to the last trade while one side of the
+    /// book is empty; `None` means the outcome has neither trades nor a
+    /// two-sided book yet.
+    pub fn fair_price(
+        &self,
+        strategy: FairPriceStrategy,
+        book: Option<&OrderBook>,
+    ) -> Option<Price> {
+        let from_book = book.and_then(|book| match strategy {
+            FairPriceStrategy::Mid => book.mid_price(),
+            FairPriceStrategy::Microprice => microprice(book),
+            FairPriceStrategy::LastTrade | FairPriceStrategy::Vwap { .. } => None,
+        });
+        match strategy {
+            FairPriceStrategy::Vwap { .. } if self.vwap_quantity > 0.0 => Some(Price(
+                (self.vwap_notional / self.vwap_quantity).round() as u64,
+            )),
+            _ => from_book.or(self.last_trade),
+        }
+    }
+
+    fn decay_to(&mut self, ts: i64, half_life_ms: u64) {
+        let elapsed = ts.saturating_sub(self.vwap_ts);
+        if elapsed > 0 && half_life_ms > 0 {
+            let factor = 0.5_f64.powf(elapsed as f64 / half_life_ms as f64);
+            self.vwap_notional *= factor;
+            self.vwap_quantity *= factor;
+        }
+        self.vwap_ts = self.vwap_ts.max(ts);
+    }
+}
+
+/// Size-weighted mid: leans towards the side with less resting quantity,
+/// since that side is the one more likely to be consumed next.
+fn microprice(book: &OrderBook) -> Option<Price> {
+    let depth = book.depth(Some(1));
+    let (bid, bid_qty) = depth.bids.first()?;
+    let (ask, ask_qty) = depth.asks.first()?;
+    let total = bid_qty.value() as u128 + ask_qty.value() as u128;
+    if total == 0 {
+        return book.mid_price();
+    }
+    let weighted = bid.value() as u128 * ask_qty.value() as u128
+        + ask.value() as u128 * bid_qty.value() as u128;
+    Some(Price(((weighted + total / 2) / total) as u64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::{OrderBookBuilder, Side, order::AccountId};
+
+    fn two_sided_book() -> OrderBook {
+        let mut book = OrderBookBuilder::new("outcome").build();
+        book.limit_raw(Side::Buy, 30, 40, None, None, AccountId(1))
+            .unwrap();
+        book.limit_raw(Side::Sell, 10, 60, None, None, AccountId(2))
+            .unwrap();
+        book
+    }
+
+    #[test]
+    fn parses_strategies() {
+        assert_eq!(
+            "last_trade".parse::<FairPriceStrategy>().unwrap(),
+            FairPriceStrategy::LastTrade
+        );
+        assert_eq!(
+            "MID".parse::<FairPriceStrategy>().unwrap(),
+            FairPriceStrategy::Mid
+        );
+        assert_eq!(
+            "vwap:60000".parse::<FairPriceStrategy>().unwrap(),
+            FairPriceStrategy::Vwap {
+                half_life_ms: 60_000
+            }
+        );
+        assert!("vwap:0".parse::<FairPriceStrategy>().is_err());
+        assert!("median".parse::<FairPriceStrategy>().is_err());
+    }
+
+    #[test]
+    fn book_strategies_fall_back_to_last_trade() {
+        let mut state = FairPriceState::default();
+        let empty = OrderBookBuilder::new("outcome").build();
+        assert_eq!(state.fair_price(FairPriceStrategy::Mid, Some(&empty)), None);
+        state.record_trade(Price(55), Quantity(1), 0, DEFAULT_VWAP_HALF_LIFE_MS);
+        assert_eq!(
+            state.fair_price(FairPriceStrategy::Mid, Some(&empty)),
+            Some(Price(55))
+        );
+        assert_eq!(
+            state.fair_price(FairPriceStrategy::Microprice, Some(&empty)),
+            Some(Price(55))
+        );
+    }
+
+    #[test]
+    fn mid_and_microprice_use_top_of_book() {
+        let book = two_sided_book();
+        let state = FairPriceState::default();
+        assert_eq!(
+            state.fair_price(FairPriceStrategy::Mid, Some(&book)),
+            Some(Price(50))
+        );
+        // 40 * 10 + 60 * 30 = 2200, / 40 = 55: the thin ask pulls the price up
+        assert_eq!(
+            state.fair_price(FairPriceStrategy::Microprice, Some(&book)),
+            Some(Price(55))
+        );
+    }
+
+    #[test]
+    fn vwap_decays_older_trades() {
+        let strategy = FairPriceStrategy::Vwap { half_life_ms: 1000 };
+        let mut state = FairPriceState::default();
+        state.record_trade(Price(40), Quantity(10), 0, 1000);
+        state.record_trade(Price(60), Quantity(10), 0, 1000);
+        assert_eq!(state.fair_price(strategy, None), Some(Price(50)));
+
+        // One half-life later the first two trades weigh half as much
+        state.record_trade(Price(80), Quantity(10), 1000, 1000);
+        // (400 + 600) / 2 + 800 = 1300 over 10 + 10 = 20 contracts
+        assert_eq!(state.fair_price(strategy, None), Some(Price(65)));
+        assert_eq!(state.last_trade(), Some(Price(80)));
+    }
+}
@@ -0,0 +1,127 @@
+use crate::engine::engine::MatchingEngine;
+use crate::error::EngineResult;
+use crate::infra::lease::{Fence, Lease};
+use crate::infra::ledger::{LedgerEntry, read_ledger};
+use crate::infra::metrics::METRICS;
+use crate::infra::shard_map::{assigned_markets, load_snapshot};
+use redis::aio::Connection;
+use std::collections::BTreeSet;
+use std::time::Duration;
+use tracing::{debug, error, info, warn};
+
+/// How often a follower polls the ledgers and the lease.
+const FOLLOW_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// What a follower hands to the stream loop once it wins the lease.
+#[derive(Debug)]
+pub struct Promotion {
+    pub lease: Lease,
+    pub fence: Fence,
+    /// Markets already restored and caught up with their ledgers
+    pub markets: BTreeSet<u32>,
+}
+
+/// Keeps `engine` a hot standby until `lease` is acquired.
+///
+/// While another instance leads, the markets assigned to this `ENGINE_ID`
+/// are restored from their snapshots and follow their ledgers in replay
+/// mode, so nothing is emitted. Once the lease is taken, the previous
+/// leader is fenced off the ledgers and one last catch-up leaves `engine`
+/// exactly where the leader stopped. A lone instance takes the lease on the
+/// first attempt and just restores its markets.
+pub async fn follow_until_leader(
+    redis: &mut Connection,
+    engine: &mut MatchingEngine,
+    lease: &Lease,
+) -> EngineResult<Promotion> {
+    let was_replay_mode = engine.is_replay_mode;
+    engine.is_replay_mode = true;
+    let mut markets = BTreeSet::new();
+    let mut following = false;
+    loop {
+        let acquired = lease.try_acquire(redis).await.unwrap_or_else(|e| {
+            warn!("Failed to check the lease: {}", e);
+            None
+        });
+        if let Some(fence) = acquired {
+            catch_up(redis, engine, &mut markets).await?;
+            info!(
+                "Acquired lease for {} (term {}), {} markets restored",
+                engine.engine_id,
+                fence.token(),
+                markets.len()
+            );
+            engine.is_replay_mode = was_replay_mode;
+            return Ok(Promotion {
+                lease: lease.clone(),
+                fence,
+                markets,
+            });
+        }
+        if !following {
+            info!(
+                "Another instance of {} is leading, following its ledgers",
+                engine.engine_id
+            );
+            following = true;
+        }
+        if let Err(e) = catch_up(redis, engine, &mut markets).await {
+            METRICS.record_error(&e);
+            error!(
+                "Failed to follow ledgers (severity: {}): {}",
+                e.severity(),
+                e
+            );
+        }
+        tokio::time::sleep(FOLLOW_POLL_INTERVAL).await;
+    }
+}
+
+/// Applies the ledger entries written since the last call, restoring newly
+/// assigned markets and dropping the ones handed to another engine.
+async fn catch_up(
+    redis: &mut Connection,
+    engine: &mut MatchingEngine,
+    markets: &mut BTreeSet<u32>,
+) -> EngineResult<()> {
+    let assigned = assigned_markets(redis, &engine.engine_id, false).await?;
+    let lost: Vec<u32> = markets.difference(&assigned).copied().collect();
+    for market_id in lost {
+        engine.release_market(market_id);
+        markets.remove(&market_id);
+    }
+    for market_id in assigned {
+        if !markets.contains(&market_id) {
+            let snapshot = load_snapshot(redis, market_id).await?;
+            let after_id = snapshot.as_ref().and_then(|s| s.ledger_id.clone());
+            let ledger = read_ledger(redis, market_id, after_id.as_deref()).await?;
+            let ledger = ledger
+                .into_iter()
+                .map(|entry| (entry.ledger_id, entry.payload))
+                .collect();
+            engine.adopt_market(market_id, snapshot, ledger, &BTreeSet::new());
+            markets.insert(market_id);
+            continue;
+        }
+        let after_id = engine.ledger_positions.get(&market_id).cloned();
+        let ledger = read_ledger(redis, market_id, after_id.as_deref()).await?;
+        if !ledger.is_empty() {
+            debug!(
+                "Following {} ledger entries of market {}",
+                ledger.len(),
+                market_id
+            );
+            METRICS.record_replayed(ledger.len());
+        }
+        for LedgerEntry {
+            ledger_id, payload, ..
+        } in ledger
+        {
+            if let Err(e) = engine.apply_ledgered(market_id, &ledger_id, &payload) {
+                warn!("Skipping ledger entry {}: {}", ledger_id, e);
+            }
+        }
+    }
+    METRICS.observe_books(engine);
+    Ok(())
+}
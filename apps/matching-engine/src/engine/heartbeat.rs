@@ -0,0 +1,137 @@
+//! Cancel-on-disconnect for market makers.
+//!
+//! An account enrolls in a market by sending `account.heartbeat` commands to
+//! it and must keep sending them within its timeout. Once a deadline has
+//! passed, a `heartbeat.sweep` on the market's stream cancels the account's
+//! resting orders in every book of the market and disarms it until its next
+//! heartbeat.
+//!
+//! Deadlines are only acted upon by sweeps, which are ledgered like any other
+//! command, never by the clock of the core. Replaying a ledger therefore
+//! cancels exactly what the live engine cancelled. The stream loop queues a
+//! sweep once the core reports an overdue deadline.
+
+use crate::engine::engine::MatchingEngine;
+use crate::orderbook::order::AccountId;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tracing::info;
+
+/// Timeout of an account enrolling without one.
+pub const DEFAULT_HEARTBEAT_TIMEOUT_MS: i64 = 5_000;
+pub const MIN_HEARTBEAT_TIMEOUT_MS: i64 = 1_000;
+pub const MAX_HEARTBEAT_TIMEOUT_MS: i64 = 5 * 60 * 1000;
+
+/// Cancel-on-disconnect state of one account in one market.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Heartbeat {
+    pub timeout_ms: i64,
+    /// `ts` of the last heartbeat
+    pub last_seen: i64,
+}
+
+impl Heartbeat {
+    /// Time after which the account counts as disconnected.
+    pub fn deadline(&self) -> i64 {
+        self.last_seen + self.timeout_ms
+    }
+}
+
+impl MatchingEngine {
+    /// Records a heartbeat of `account_id` in `market_id` sent at `ts`.
+    ///
+    /// `timeout_ms` changes the account's timeout, `Some(0)` disarms it and
+    /// `None` keeps the current one, or the default when enrolling.
+    pub fn heartbeat(
+        &mut self,
+        market_id: u32,
+        account_id: AccountId,
+        timeout_ms: Option<i64>,
+        ts: i64,
+    ) {
+        let heartbeats = self.heartbeats.entry(market_id).or_default();
+        if timeout_ms == Some(0) {
+            if heartbeats.remove(&account_id).is_some() {
+                info!(
+                    "Account {} left cancel-on-disconnect in market {}",
+                    account_id, market_id
+                );
+            }
+            return;
+        }
+        let timeout_ms = timeout_ms
+            .or_else(|| heartbeats.get(&account_id).map(|h| h.timeout_ms))
+            .unwrap_or(DEFAULT_HEARTBEAT_TIMEOUT_MS);
+        // Heartbeats reordered by a retry must not move the deadline back
+        let last_seen = heartbeats
+            .get(&account_id)
+            .map_or(ts, |h| h.last_seen.max(ts));
+        heartbeats.insert(
+            account_id,
+            Heartbeat {
+                timeout_ms,
+                last_seen,
+            },
+        );
+    }
+
+    /// Disarms and returns the accounts of `market_id` whose deadline passed
+    /// before `now`.
+    pub fn take_expired_heartbeats(&mut self, market_id: u32, now: i64) -> Vec<AccountId> {
+        let Some(heartbeats) = self.heartbeats.get_mut(&market_id) else {
+            return Vec::new();
+        };
+        let expired: Vec<AccountId> = heartbeats
+            .iter()
+            .filter(|(_, heartbeat)| heartbeat.deadline() < now)
+            .map(|(account_id, _)| *account_id)
+            .collect();
+        for account_id in &expired {
+            heartbeats.remove(account_id);
+        }
+        expired
+    }
+
+    /// Earliest heartbeat deadline of every market with enrolled accounts.
+    pub fn heartbeat_deadlines(&self) -> BTreeMap<u32, i64> {
+        self.heartbeats
+            .iter()
+            .filter_map(|(market_id, heartbeats)| {
+                let deadline = heartbeats.values().map(Heartbeat::deadline).min()?;
+                Some((*market_id, deadline))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn heartbeats_renew_change_and_expire() {
+        let mut engine = MatchingEngine::new(false);
+        engine.heartbeat(1, AccountId(7), None, 1_000);
+        engine.heartbeat(1, AccountId(8), Some(2_000), 1_000);
+        engine.heartbeat(2, AccountId(7), Some(10_000), 1_000);
+        assert_eq!(
+            engine.heartbeat_deadlines(),
+            BTreeMap::from([(1, 3_000), (2, 11_000)])
+        );
+
+        // A late retry does not move the deadline back
+        engine.heartbeat(1, AccountId(8), None, 2_500);
+        engine.heartbeat(1, AccountId(8), None, 2_000);
+        assert_eq!(engine.heartbeats[&1][&AccountId(8)].deadline(), 4_500);
+
+        assert!(engine.take_expired_heartbeats(1, 4_500).is_empty());
+        assert_eq!(
+            engine.take_expired_heartbeats(1, 6_001),
+            vec![AccountId(7), AccountId(8)]
+        );
+        assert!(engine.take_expired_heartbeats(1, 100_000).is_empty());
+
+        engine.heartbeat(2, AccountId(7), Some(0), 2_000);
+        assert!(engine.heartbeat_deadlines().is_empty());
+    }
+}
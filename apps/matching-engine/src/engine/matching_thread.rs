@@ -0,0 +1,222 @@
+use crate::engine::command::EngineOutput;
+use crate::engine::engine::MatchingEngine;
+use crate::engine::query::QueryRequest;
+use crate::engine::snapshot::MarketSnapshot;
+use crate::error::{EngineError, EngineResult};
+use crate::infra::metrics::METRICS;
+use serde_json::Value as SerdeJsonValue;
+use std::collections::{BTreeMap, BTreeSet};
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tracing::{info, warn};
+
+/// Commands buffered between the Redis ingest task and the matching thread.
+pub const COMMAND_QUEUE_CAPACITY: usize = 4096;
+/// Results buffered between the matching thread and the publisher task.
+pub const RESULT_QUEUE_CAPACITY: usize = 4096;
+
+/// Work for the matching core.
+#[derive(Debug)]
+pub enum CoreCommand {
+    /// A command read from a market's stream, already stamped and ledgered.
+    Apply {
+        entry_id: String,
+        market_id: u32,
+        ledger_id: String,
+        payload: SerdeJsonValue,
+        /// Deliveries of the entry so far, this one included
+        attempts: u64,
+    },
+    /// Take over a market from its snapshot and the ledger tail after it.
+    Adopt {
+        market_id: u32,
+        snapshot: Option<Box<MarketSnapshot>>,
+        ledger: Vec<(String, SerdeJsonValue)>,
+        /// Ledger entries whose outputs were never published, by ledger id,
+        /// as the stream entry to ACK once they are and its deliveries
+        unpublished: BTreeMap<String, (String, u64)>,
+    },
+    /// Answer a read-only query, see [`crate::engine::query`].
+    Query(QueryRequest),
+}
+
+/// What applying a [`CoreCommand`] produced, tagged with its stream entry so
+/// the publisher can ACK it once the outputs are written, or dead-letter it.
+#[derive(Debug)]
+pub struct CoreResult {
+    pub entry_id: String,
+    pub market_id: u32,
+    pub payload: SerdeJsonValue,
+    pub attempts: u64,
+    pub outcome: EngineResult<Vec<EngineOutput>>,
+}
+
+/// Runs the matching engine on a dedicated OS thread.
+///
+/// The thread owns the engine and never touches Redis: it applies commands
+/// in arrival order and hands the outputs of stream entries back over
+/// `results`. It stops when `commands` is closed or the publisher goes away,
+/// and returns the engine from its join handle.
+pub fn spawn_matching_core(
+    mut engine: MatchingEngine,
+    mut commands: Receiver<CoreCommand>,
+    results: Sender<CoreResult>,
+) -> std::io::Result<JoinHandle<MatchingEngine>> {
+    thread::Builder::new()
+        .name("matching-core".to_string())
+        .spawn(move || {
+            info!("Matching core started");
+            'commands: while let Some(command) = commands.blocking_recv() {
+                let (entry_id, market_id, ledger_id, payload, attempts) = match command {
+                    CoreCommand::Apply {
+                        entry_id,
+                        market_id,
+                        ledger_id,
+                        payload,
+                        attempts,
+                    } => (entry_id, market_id, ledger_id, payload, attempts),
+                    CoreCommand::Adopt {
+                        market_id,
+                        snapshot,
+                        ledger,
+                        mut unpublished,
+                    } => {
+                        let payloads: BTreeMap<String, SerdeJsonValue> = ledger
+                            .iter()
+                            .filter(|(ledger_id, _)| unpublished.contains_key(ledger_id))
+                            .cloned()
+                            .collect();
+                        let publish: BTreeSet<String> = unpublished.keys().cloned().collect();
+                        let regenerated =
+                            engine.adopt_market(market_id, snapshot.map(|s| *s), ledger, &publish);
+                        METRICS.observe_books(&engine);
+                        // Published and ACKed like the outputs of live commands
+                        for (ledger_id, outcome) in regenerated {
+                            let Some((entry_id, attempts)) = unpublished.remove(&ledger_id) else {
+                                continue;
+                            };
+                            let result = CoreResult {
+                                entry_id,
+                                market_id,
+                                payload: payloads.get(&ledger_id).cloned().unwrap_or_default(),
+                                attempts,
+                                outcome,
+                            };
+                            if results.blocking_send(result).is_err() {
+                                warn!("Publisher stopped, shutting down matching core");
+                                break 'commands;
+                            }
+                        }
+                        continue;
+                    }
+                    CoreCommand::Query(request) => {
+                        // The caller may have given up waiting
+                        let _ = request.reply.send(engine.query(&request.query));
+                        continue;
+                    }
+                };
+                let started = Instant::now();
+                let outcome = engine.apply_ledgered(market_id, &ledger_id, &payload);
+                record_command(&engine, &payload, &outcome, started);
+                let result = CoreResult {
+                    entry_id,
+                    market_id,
+                    payload,
+                    attempts,
+                    outcome,
+                };
+                if results.blocking_send(result).is_err() {
+                    warn!("Publisher stopped, shutting down matching core");
+                    break;
+                }
+            }
+            info!("Matching core stopped");
+            engine
+        })
+}
+
+/// Records what applying `payload` took and left in its book.
+fn record_command(
+    engine: &MatchingEngine,
+    payload: &SerdeJsonValue,
+    outcome: &EngineResult<Vec<EngineOutput>>,
+    started: Instant,
+) {
+    let elapsed = started.elapsed();
+    let command_type = match outcome {
+        Err(EngineError::UnknownEventType(_)) => None,
+        _ => payload.get("type").and_then(|v| v.as_str()),
+    };
+    METRICS.record_command(command_type, outcome.is_ok(), elapsed);
+    if let Err(e) = outcome {
+        METRICS.record_error(e);
+    }
+    match command_type {
+        // Commands touching every book of a market
+        Some(
+            "market.handoff" | "market.configure" | "order.cancel_all" | "quote.replace"
+            | "heartbeat.sweep" | "auction.start" | "auction.uncross" | "batch.clear",
+        ) => METRICS.observe_books(engine),
+        _ => {
+            let book = payload
+                .get("outcome_id")
+                .and_then(|v| v.as_str())
+                .and_then(|outcome_id| Some((outcome_id, engine.books.get(outcome_id)?)));
+            if let Some((outcome_id, book)) = book {
+                METRICS.observe_book(outcome_id, book);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn applies_commands_in_order_and_returns_engine() {
+        let (command_tx, command_rx) = mpsc::channel(8);
+        let (result_tx, mut result_rx) = mpsc::channel(8);
+        let core = spawn_matching_core(MatchingEngine::new(false), command_rx, result_tx).unwrap();
+
+        for (i, (id, payload)) in [
+            ("1-0", json!({ "type": "order.teleport" })),
+            (
+                "2-0",
+                json!({
+                    "type": "market.configure",
+                    "market_id": "1",
+                    "fair_price_strategy": "mid",
+                }),
+            ),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            command_tx
+                .blocking_send(CoreCommand::Apply {
+                    entry_id: id.to_string(),
+                    market_id: 1,
+                    ledger_id: format!("{}-0", i + 1),
+                    payload,
+                    attempts: 1,
+                })
+                .unwrap();
+        }
+        drop(command_tx);
+
+        let first = result_rx.blocking_recv().unwrap();
+        assert_eq!(first.entry_id, "1-0");
+        assert!(first.outcome.is_err());
+        let second = result_rx.blocking_recv().unwrap();
+        assert_eq!(second.entry_id, "2-0");
+        assert_eq!(second.outcome.unwrap().len(), 1);
+
+        let engine = core.join().unwrap();
+        assert_eq!(engine.fair_price_strategies.len(), 1);
+        assert_eq!(engine.ledger_positions[&1], "2-0");
+    }
+}
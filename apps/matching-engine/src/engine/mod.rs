@@ -1,4 +1,16 @@
+pub mod auction;
+pub mod batch;
+pub mod command;
+#[allow(clippy::module_inception)]
 pub mod engine;
+pub mod fair_price;
+pub mod follower;
+pub mod heartbeat;
+pub mod matching_thread;
 pub mod order;
 pub mod publish_events;
+pub mod query;
+pub mod snapshot;
+pub mod stop;
 pub mod stream;
+pub mod volume;
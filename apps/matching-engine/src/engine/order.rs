@@ -1,6 +1,10 @@
 use crate::{
+    engine::heartbeat::{MAX_HEARTBEAT_TIMEOUT_MS, MIN_HEARTBEAT_TIMEOUT_MS},
     error::{EngineError, EngineResult},
-    orderbook::{Side, TimeInForce},
+    orderbook::{
+        LimitOrderOptions, MarketProtection, Peg, PegReference, Price, Quantity, Side, TimeInForce,
+        order::{AccountId, MAX_PRICE},
+    },
 };
 use serde::{Deserialize, Serialize};
 use std::{fmt, str::FromStr};
@@ -87,6 +91,258 @@ pub struct OrderWire {
     pub qty_remaining: String,
     pub qty_original: String,
     pub time_in_force: String,
+    /// Slippage bound of a MARKET order, in ticks from the best opposite
+    /// price; a MARKET `price` above 0 bounds it at that price instead
+    #[serde(default)]
+    pub max_slippage_ticks: Option<String>,
+    /// Amount a notional MARKET buy spends, its quantities being `"0"`
+    #[serde(default)]
+    pub notional: Option<String>,
+    /// Slice of a LIMIT order shown in depth, making it an iceberg
+    #[serde(default)]
+    pub display_qty: Option<String>,
+    /// Price a GTC LIMIT order follows, its `price` capping it
+    #[serde(default)]
+    pub peg: Option<String>,
+    /// Signed offset of a pegged order from its reference
+    #[serde(default)]
+    pub peg_offset: Option<String>,
+    /// Makes a MARKET order a trailing stop, its trigger trailing the last
+    /// trade by this many cents
+    #[serde(default)]
+    pub trail: Option<String>,
+    /// Smallest fill a LIMIT order accepts
+    #[serde(default)]
+    pub min_qty: Option<String>,
+    /// `"true"` for a LIMIT order that only executes in full
+    #[serde(default)]
+    pub all_or_none: Option<String>,
+    /// Engine receive time in millis, stamped before the ledger append
+    #[serde(default)]
+    pub ts: Option<String>,
+}
+
+/// Wire format for `market.configure` commands
+///
+/// Settings left out keep their current value; at least one must be given.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MarketConfigWire {
+    pub market_id: String,
+    #[serde(default)]
+    pub fair_price_strategy: Option<String>,
+    /// `continuous` or `batch[:interval_ms]`, see [`crate::engine::batch`]
+    #[serde(default)]
+    pub matching_mode: Option<String>,
+    /// `fifo`, `pro_rata` or `top_order_pro_rata`, see
+    /// [`crate::orderbook::allocation`]
+    #[serde(default)]
+    pub allocation: Option<String>,
+    #[serde(default)]
+    pub ts: Option<String>,
+}
+
+/// Wire format for `market.handoff` commands
+#[derive(Debug, Clone, Deserialize)]
+pub struct MarketHandoffWire {
+    pub market_id: String,
+    pub target_engine: String,
+}
+
+/// Wire format for `order.cancel_all` commands
+///
+/// Cancels the resting orders of `market_id`, only `account_id`'s if given.
+/// Without `market_id`, it cancels `account_id`'s in every market, or only
+/// in the market of the stream it was read from, see
+/// [`crate::engine::engine::MatchingEngine::apply_ledgered`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct CancelAllWire {
+    #[serde(default)]
+    pub market_id: Option<String>,
+    #[serde(default)]
+    pub account_id: Option<String>,
+}
+
+/// Wire format for `account.heartbeat` commands
+///
+/// `timeout_ms` sets the account's cancel-on-disconnect timeout in the
+/// market, `"0"` leaves it, see [`crate::engine::heartbeat`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct HeartbeatWire {
+    pub market_id: String,
+    pub account_id: String,
+    #[serde(default)]
+    pub timeout_ms: Option<String>,
+    #[serde(default)]
+    pub ts: Option<String>,
+}
+
+/// Wire format for `heartbeat.sweep` commands, queued by the engine itself
+#[derive(Debug, Clone, Deserialize)]
+pub struct HeartbeatSweepWire {
+    pub market_id: String,
+    #[serde(default)]
+    pub ts: Option<String>,
+}
+
+/// Wire format for `auction.start` and `auction.uncross` commands
+///
+/// Without `outcome_id` the command applies to every book of the market.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AuctionWire {
+    pub market_id: String,
+    #[serde(default)]
+    pub outcome_id: Option<String>,
+    #[serde(default)]
+    pub ts: Option<String>,
+}
+
+/// Wire format for `batch.clear` and `auction.refresh` commands, queued by
+/// the engine itself
+#[derive(Debug, Clone, Deserialize)]
+pub struct BatchClearWire {
+    pub market_id: String,
+    #[serde(default)]
+    pub ts: Option<String>,
+}
+
+/// A validated `account.heartbeat`.
+#[derive(Debug, Clone)]
+pub struct AccountHeartbeat {
+    pub market_id: u32,
+    pub account_id: u64,
+    pub timeout_ms: Option<i64>,
+    pub ts: i64,
+}
+
+impl TryFrom<HeartbeatWire> for AccountHeartbeat {
+    type Error = EngineError;
+    fn try_from(w: HeartbeatWire) -> Result<Self, Self::Error> {
+        let market_id = w.market_id.parse::<u32>().map_err(|e| {
+            EngineError::OrderValidation(format!("Invalid market_id '{}': {}", w.market_id, e))
+        })?;
+        let account_id = w.account_id.parse::<u64>().map_err(|e| {
+            EngineError::OrderValidation(format!("Invalid account_id '{}': {}", w.account_id, e))
+        })?;
+        if account_id == 0 {
+            return Err(EngineError::OrderValidation(
+                "account_id cannot be empty".to_string(),
+            ));
+        }
+        let timeout_ms = match w.timeout_ms {
+            Some(timeout_ms) => {
+                let parsed = timeout_ms.parse::<i64>().map_err(|e| {
+                    EngineError::OrderValidation(format!(
+                        "Invalid timeout_ms '{}': {}",
+                        timeout_ms, e
+                    ))
+                })?;
+                if parsed != 0
+                    && !(MIN_HEARTBEAT_TIMEOUT_MS..=MAX_HEARTBEAT_TIMEOUT_MS).contains(&parsed)
+                {
+                    return Err(EngineError::OrderValidation(format!(
+                        "timeout_ms must be 0 or between {} and {}, got {}",
+                        MIN_HEARTBEAT_TIMEOUT_MS, MAX_HEARTBEAT_TIMEOUT_MS, parsed
+                    )));
+                }
+                Some(parsed)
+            }
+            None => None,
+        };
+        Ok(AccountHeartbeat {
+            market_id,
+            account_id,
+            timeout_ms,
+            ts: parse_ts(w.ts)?,
+        })
+    }
+}
+
+/// Most quotes a single `quote.replace` may carry.
+pub const MAX_QUOTES: usize = 200;
+
+/// Wire format for `quote.replace` commands
+///
+/// `quotes` is a JSON array of [`QuoteWire`], encoded as a string like every
+/// other stream field. An empty array just pulls the account's quotes.
+#[derive(Debug, Clone, Deserialize)]
+pub struct QuoteReplaceWire {
+    pub market_id: String,
+    pub account_id: String,
+    pub quotes: String,
+    #[serde(default)]
+    pub ts: Option<String>,
+}
+
+/// One side of a quote on one outcome
+#[derive(Debug, Clone, Deserialize)]
+pub struct QuoteWire {
+    pub outcome_id: String,
+    #[serde(default)]
+    pub outcome_name: String,
+    pub side: String,
+    pub price: String,
+    pub qty: String,
+}
+
+/// A validated `quote.replace`: its quotes as GTC limit orders.
+#[derive(Debug, Clone)]
+pub struct QuoteReplace {
+    pub market_id: u32,
+    pub account_id: u64,
+    pub orders: Vec<Order>,
+}
+
+impl TryFrom<QuoteReplaceWire> for QuoteReplace {
+    type Error = EngineError;
+    fn try_from(w: QuoteReplaceWire) -> Result<Self, Self::Error> {
+        let quotes: Vec<QuoteWire> = serde_json::from_str(&w.quotes)
+            .map_err(|e| EngineError::OrderValidation(format!("Invalid quotes: {}", e)))?;
+        if quotes.len() > MAX_QUOTES {
+            return Err(EngineError::OrderValidation(format!(
+                "{} quotes exceed the maximum of {}",
+                quotes.len(),
+                MAX_QUOTES
+            )));
+        }
+        let market_id = w.market_id.parse::<u32>().map_err(|e| {
+            EngineError::OrderValidation(format!("Invalid market_id '{}': {}", w.market_id, e))
+        })?;
+        let account_id = w.account_id.parse::<u64>().map_err(|e| {
+            EngineError::OrderValidation(format!("Invalid account_id '{}': {}", w.account_id, e))
+        })?;
+        // Every quote goes through the same checks as an `order.new`
+        let orders = quotes
+            .into_iter()
+            .map(|quote| {
+                Order::try_from(OrderWire {
+                    outcome_id: quote.outcome_id,
+                    account_id: w.account_id.clone(),
+                    market_id: w.market_id.clone(),
+                    outcome_name: quote.outcome_name,
+                    side: quote.side,
+                    order_type: "LIMIT".to_string(),
+                    price: quote.price,
+                    qty_remaining: quote.qty.clone(),
+                    qty_original: quote.qty,
+                    time_in_force: "GTC".to_string(),
+                    max_slippage_ticks: None,
+                    notional: None,
+                    display_qty: None,
+                    peg: None,
+                    peg_offset: None,
+                    trail: None,
+                    min_qty: None,
+                    all_or_none: None,
+                    ts: w.ts.clone(),
+                })
+            })
+            .collect::<EngineResult<Vec<Order>>>()?;
+        Ok(QuoteReplace {
+            market_id,
+            account_id,
+            orders,
+        })
+    }
 }
 
 /// Internal order representation with validated fields
@@ -98,10 +354,35 @@ pub struct Order {
     pub account_id: u64,
     pub side: OrderSide,
     pub order_type: OrderType,
-    pub price: u64, // In smallest unit (e.g., cents). 0 for pure MARKET orders
+    pub price: u64, // In smallest unit (e.g., cents). 0 for pure MARKET orders, else their protection price
     pub qty_remaining: u64,
     pub qty_original: u64,
     pub time_in_force: TimeInForce,
+    /// Slippage bound of a MARKET order, see [`Order::protection`]
+    #[serde(default)]
+    pub max_slippage_ticks: Option<u64>,
+    /// Budget of a notional MARKET buy, which gets its size from matching
+    #[serde(default)]
+    pub notional: Option<u64>,
+    /// Slice an iceberg LIMIT order shows while resting
+    #[serde(default)]
+    pub display_qty: Option<u64>,
+    /// Price a pegged LIMIT order follows, `peg_offset` away
+    #[serde(default)]
+    pub peg: Option<PegReference>,
+    #[serde(default)]
+    pub peg_offset: i64,
+    /// Distance of a trailing stop's trigger from the last trade, see
+    /// [`crate::engine::stop`]
+    #[serde(default)]
+    pub trail: Option<u64>,
+    /// Smallest fill of a LIMIT order, or all that is left of it if less
+    #[serde(default)]
+    pub min_qty: Option<u64>,
+    /// Whether a LIMIT order only ever executes all it has left at once
+    #[serde(default)]
+    pub all_or_none: bool,
+    pub ts: i64,
 }
 
 impl Order {
@@ -120,7 +401,23 @@ impl Order {
             ));
         }
         // Validate quantities
-        if self.qty_original == 0 {
+        if let Some(notional) = self.notional {
+            if self.order_type != OrderType::MARKET || self.side.0 != Side::Buy {
+                return Err(EngineError::OrderValidation(
+                    "Only MARKET buys can be sent as a notional".to_string(),
+                ));
+            }
+            if notional == 0 {
+                return Err(EngineError::OrderValidation(
+                    "notional must be greater than 0".to_string(),
+                ));
+            }
+            if self.qty_original != 0 || self.qty_remaining != 0 {
+                return Err(EngineError::OrderValidation(
+                    "Notional orders cannot have a quantity".to_string(),
+                ));
+            }
+        } else if self.qty_original == 0 {
             return Err(EngineError::OrderValidation(
                 "qty_original must be greater than 0".to_string(),
             ));
@@ -137,6 +434,80 @@ impl Order {
                 "LIMIT orders must have a price greater than 0".to_string(),
             ));
         }
+        if self.max_slippage_ticks.is_some() {
+            if !matches!(self.order_type, OrderType::MARKET) {
+                return Err(EngineError::OrderValidation(
+                    "max_slippage_ticks only applies to MARKET orders".to_string(),
+                ));
+            }
+            if self.price > 0 {
+                return Err(EngineError::OrderValidation(
+                    "MARKET orders take a protection price or max_slippage_ticks, not both"
+                        .to_string(),
+                ));
+            }
+        }
+        if let Some(display_qty) = self.display_qty {
+            if !matches!(self.order_type, OrderType::LIMIT) {
+                return Err(EngineError::OrderValidation(
+                    "display_qty only applies to LIMIT orders".to_string(),
+                ));
+            }
+            if display_qty == 0 || display_qty > self.qty_original {
+                return Err(EngineError::OrderValidation(format!(
+                    "display_qty must be between 1 and the order quantity {}, got {}",
+                    self.qty_original, display_qty
+                )));
+            }
+        }
+        if self.peg.is_some()
+            && (!matches!(self.order_type, OrderType::LIMIT)
+                || self.time_in_force != TimeInForce::GTC)
+        {
+            return Err(EngineError::OrderValidation(
+                "Only GTC LIMIT orders can be pegged".to_string(),
+            ));
+        }
+        if self.peg.is_none() && self.peg_offset != 0 {
+            return Err(EngineError::OrderValidation(
+                "peg_offset only applies to pegged orders".to_string(),
+            ));
+        }
+        if let Some(trail) = self.trail {
+            if !matches!(self.order_type, OrderType::MARKET) || self.notional.is_some() {
+                return Err(EngineError::OrderValidation(
+                    "Only MARKET orders with a quantity can be trailing stops".to_string(),
+                ));
+            }
+            if trail == 0 {
+                return Err(EngineError::OrderValidation(
+                    "trail must be greater than 0".to_string(),
+                ));
+            }
+        }
+        if self.min_qty.is_some() || self.all_or_none {
+            if !matches!(self.order_type, OrderType::LIMIT) || self.display_qty.is_some() {
+                return Err(EngineError::OrderValidation(
+                    "Only LIMIT orders without a display_qty take min_qty or all_or_none"
+                        .to_string(),
+                ));
+            }
+            if let Some(min_qty) = self.min_qty
+                && (min_qty == 0 || min_qty > self.qty_original)
+            {
+                return Err(EngineError::OrderValidation(format!(
+                    "min_qty must be between 1 and the order quantity {}, got {}",
+                    self.qty_original, min_qty
+                )));
+            }
+            if self.min_qty.is_some()
+                && (self.all_or_none || self.time_in_force == TimeInForce::FOK)
+            {
+                return Err(EngineError::OrderValidation(
+                    "all_or_none and FOK orders take no min_qty".to_string(),
+                ));
+            }
+        }
         // Validate time in force for MARKET orders
         if matches!(self.order_type, OrderType::MARKET) && self.time_in_force == TimeInForce::GTC {
             return Err(EngineError::OrderValidation(
@@ -144,13 +515,19 @@ impl Order {
             ));
         }
         // Price should be reasonable (add your own bounds)
-        const MAX_PRICE: u64 = 1_000_000_000; // 10 million in cents = $100k
         if self.price > MAX_PRICE {
             return Err(EngineError::OrderValidation(format!(
                 "Price {} exceeds maximum allowed price of {}",
                 self.price, MAX_PRICE
             )));
         }
+        // A peg never needs to move further than the whole price range
+        if self.peg_offset.unsigned_abs() > MAX_PRICE {
+            return Err(EngineError::OrderValidation(format!(
+                "peg_offset {} exceeds the maximum offset of {}",
+                self.peg_offset, MAX_PRICE
+            )));
+        }
         // Quantity should be reasonable (add your own bounds)
         const MAX_QUANTITY: u64 = 1_000_000_000; // 1 billion units
         if self.qty_original > MAX_QUANTITY {
@@ -165,6 +542,37 @@ impl Order {
         );
         Ok(())
     }
+
+    /// Bound on the prices a MARKET order may trade at, if it has one.
+    pub fn protection(&self) -> Option<MarketProtection> {
+        match (&self.order_type, self.max_slippage_ticks) {
+            (OrderType::MARKET, Some(ticks)) => Some(MarketProtection::Ticks(ticks)),
+            (OrderType::MARKET, None) if self.price > 0 => {
+                Some(MarketProtection::Price(Price(self.price)))
+            }
+            _ => None,
+        }
+    }
+
+    /// How a LIMIT order is sent to its book.
+    pub fn limit_options(&self) -> LimitOrderOptions {
+        LimitOrderOptions {
+            price: Price(self.price),
+            time_in_force: Some(self.time_in_force),
+            side: self.side.clone().into(),
+            quantity: Quantity(self.qty_original),
+            post_only: Some(false),
+            account_id: AccountId(self.account_id),
+            display_qty: self.display_qty.map(Quantity),
+            peg: self.peg.map(|reference| Peg {
+                reference,
+                offset: self.peg_offset,
+                limit: Price(self.price),
+            }),
+            min_qty: self.min_qty.map(Quantity),
+            all_or_none: self.all_or_none,
+        }
+    }
 }
 
 impl TryFrom<OrderWire> for Order {
@@ -199,6 +607,81 @@ impl TryFrom<OrderWire> for Order {
                 w.time_in_force, e
             ))
         })?;
+        let max_slippage_ticks = w
+            .max_slippage_ticks
+            .map(|ticks| {
+                ticks.parse::<u64>().map_err(|e| {
+                    EngineError::OrderValidation(format!(
+                        "Invalid max_slippage_ticks '{}': {}",
+                        ticks, e
+                    ))
+                })
+            })
+            .transpose()?;
+        let notional = w
+            .notional
+            .map(|notional| {
+                notional.parse::<u64>().map_err(|e| {
+                    EngineError::OrderValidation(format!("Invalid notional '{}': {}", notional, e))
+                })
+            })
+            .transpose()?;
+        let display_qty = w
+            .display_qty
+            .map(|display_qty| {
+                display_qty.parse::<u64>().map_err(|e| {
+                    EngineError::OrderValidation(format!(
+                        "Invalid display_qty '{}': {}",
+                        display_qty, e
+                    ))
+                })
+            })
+            .transpose()?;
+        let peg = w
+            .peg
+            .map(|peg| {
+                peg.parse::<PegReference>()
+                    .map_err(|e| EngineError::OrderValidation(format!("Invalid peg: {}", e)))
+            })
+            .transpose()?;
+        let peg_offset = w
+            .peg_offset
+            .map(|offset| {
+                offset.parse::<i64>().map_err(|e| {
+                    EngineError::OrderValidation(format!("Invalid peg_offset '{}': {}", offset, e))
+                })
+            })
+            .transpose()?
+            .unwrap_or(0);
+        let trail = w
+            .trail
+            .map(|trail| {
+                trail.parse::<u64>().map_err(|e| {
+                    EngineError::OrderValidation(format!("Invalid trail '{}': {}", trail, e))
+                })
+            })
+            .transpose()?;
+        let min_qty = w
+            .min_qty
+            .map(|min_qty| {
+                min_qty.parse::<u64>().map_err(|e| {
+                    EngineError::OrderValidation(format!("Invalid min_qty '{}': {}", min_qty, e))
+                })
+            })
+            .transpose()?;
+        let all_or_none = w
+            .all_or_none
+            .map(|all_or_none| {
+                all_or_none.parse::<bool>().map_err(|e| {
+                    EngineError::OrderValidation(format!(
+                        "Invalid all_or_none '{}': {}",
+                        all_or_none, e
+                    ))
+                })
+            })
+            .transpose()?
+            .unwrap_or(false);
+        let ts = parse_ts(w.ts)?;
         let order = Order {
             market_id,
             outcome_name: w.outcome_name,
@@ -210,6 +693,15 @@ impl TryFrom<OrderWire> for Order {
             qty_remaining,
             qty_original,
             time_in_force,
+            max_slippage_ticks,
+            notional,
+            display_qty,
+            peg,
+            peg_offset,
+            trail,
+            min_qty,
+            all_or_none,
+            ts,
         };
 
         // Validate the constructed order
@@ -223,3 +715,13 @@ impl TryFrom<OrderWire> for Order {
         Ok(order)
     }
 }
+
+/// The `ts` of a command, or now for commands that were never stamped.
+pub fn parse_ts(ts: Option<String>) -> EngineResult<i64> {
+    match ts {
+        Some(ts) => ts
+            .parse::<i64>()
+            .map_err(|e| EngineError::OrderValidation(format!("Invalid ts '{}': {}", ts, e))),
+        None => Ok(chrono::Utc::now().timestamp_millis()),
+    }
+}
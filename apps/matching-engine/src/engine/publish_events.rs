@@ -53,6 +53,9 @@ pub enum PublishEngineEvent {
         quantity: Quantity,
         price: Price,
         time_in_force: Option<TimeInForce>,
+        /// Amount a notional order spent on its `quantity`
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        notional: Option<u64>,
     },
     #[serde(rename = "order.cancelled")]
     OrderCancelled {
@@ -63,6 +66,29 @@ pub enum PublishEngineEvent {
         quantity: Quantity,
         price: Price,
         time_in_force: Option<TimeInForce>,
+        /// Quantity left unfilled, and thus cancelled
+        remaining: Quantity,
+        /// Amount a notional order spent before the rest of its budget was
+        /// cancelled
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        notional: Option<u64>,
+        /// Why the engine cancelled the order, absent for unfilled IOC
+        /// remainders
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        reason: Option<CancelReason>,
+    },
+    /// A pegged order followed its reference to a new price, joining the
+    /// back of that level
+    #[serde(rename = "order.repriced")]
+    OrderRepriced {
+        order_id: OrderId,
+        account_id: AccountId,
+        outcome_id: String,
+        side: OrderSide,
+        /// Quantity still resting
+        quantity: Quantity,
+        price: Price,
+        previous_price: Price,
     },
     #[serde(rename = "order.rejected")]
     OrderRejected {
@@ -73,4 +99,107 @@ pub enum PublishEngineEvent {
         price: Price,
         time_in_force: Option<TimeInForce>,
     },
+    /// A trailing stop was accepted; its order waits for the trigger
+    #[serde(rename = "stop.placed")]
+    StopPlaced {
+        stop_id: OrderId,
+        account_id: AccountId,
+        outcome_id: String,
+        side: OrderSide,
+        quantity: Quantity,
+        trail: u64,
+        trigger: Price,
+    },
+    /// The last trade moved in a trailing stop's favour, and its trigger
+    /// along with it
+    #[serde(rename = "stop.moved")]
+    StopMoved {
+        stop_id: OrderId,
+        account_id: AccountId,
+        outcome_id: String,
+        side: OrderSide,
+        trigger: Price,
+        previous_trigger: Price,
+    },
+    /// The last trade reached a trailing stop's trigger; the events of its
+    /// MARKET order follow
+    #[serde(rename = "stop.triggered")]
+    StopTriggered {
+        stop_id: OrderId,
+        account_id: AccountId,
+        outcome_id: String,
+        side: OrderSide,
+        trigger: Price,
+        last_trade: Price,
+    },
+    /// A trailing stop was removed before it fired
+    #[serde(rename = "stop.cancelled")]
+    StopCancelled {
+        stop_id: OrderId,
+        account_id: AccountId,
+        outcome_id: String,
+        side: OrderSide,
+        quantity: Quantity,
+        trigger: Price,
+        reason: CancelReason,
+    },
+    /// Where a book in auction would clear now, sent whenever it changes and
+    /// on every `auction.refresh`
+    #[serde(rename = "auction.indicative")]
+    AuctionIndicative {
+        outcome_id: String,
+        /// `None` while the book is not crossed
+        price: Option<Price>,
+        volume: Quantity,
+        imbalance: Quantity,
+        imbalance_side: Option<OrderSide>,
+    },
+    /// How an auction ended; its executions are published as trades
+    /// beforehand
+    #[serde(rename = "auction.result")]
+    AuctionResult {
+        outcome_id: String,
+        /// `None` when the book was not crossed and nothing executed
+        price: Option<Price>,
+        volume: Quantity,
+        imbalance: Quantity,
+        imbalance_side: Option<OrderSide>,
+        trades: usize,
+    },
+}
+
+/// Why a resting order was cancelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CancelReason {
+    /// An `order.cancel_all` covered it
+    CancelAll,
+    /// A `quote.replace` of its account superseded it
+    Replaced,
+    /// Its account enrolled in cancel-on-disconnect stopped sending
+    /// heartbeats
+    Disconnected,
+    /// A market order reached its protection price
+    PriceProtection,
+}
+
+impl PublishEngineEvent {
+    /// The `type` tag the event is published with
+    pub fn kind(&self) -> &'static str {
+        match self {
+            PublishEngineEvent::Trade { .. } => "trade",
+            PublishEngineEvent::OrderPlaced { .. } => "order.placed",
+            PublishEngineEvent::OrderPartial { .. } => "order.partial",
+            PublishEngineEvent::OrderFilled { .. } => "order.filled",
+            PublishEngineEvent::OrderCancelled { .. } => "order.cancelled",
+            PublishEngineEvent::OrderRepriced { .. } => "order.repriced",
+            PublishEngineEvent::OrderRejected { .. } => "order.rejected",
+            PublishEngineEvent::StopPlaced { .. } => "stop.placed",
+            PublishEngineEvent::StopMoved { .. } => "stop.moved",
+            PublishEngineEvent::StopTriggered { .. } => "stop.triggered",
+            PublishEngineEvent::StopCancelled { .. } => "stop.cancelled",
+            PublishEngineEvent::AuctionIndicative { .. } => "auction.indicative",
+            PublishEngineEvent::AuctionResult { .. } => "auction.result",
+        }
+    }
 }
@@ -0,0 +1,250 @@
+//! Read-only queries against the live engine.
+//!
+//! The matching core owns the engine, so queries travel to it over the
+//! command queue and are answered between two commands. Reads never lock
+//! the engine and matching never waits on a reader; a query only waits for
+//! the commands queued ahead of it.
+
+use crate::engine::engine::MatchingEngine;
+use crate::error::{EngineError, EngineResult};
+use crate::orderbook::order::AccountId;
+use crate::orderbook::{OrderId, Price, Side};
+use serde_json::{Value, json};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+/// Queries waiting to be forwarded to the matching core.
+pub const QUERY_QUEUE_CAPACITY: usize = 64;
+/// How long a caller waits for the core to answer.
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EngineQuery {
+    /// Aggregated levels of a book, up to `limit` per side
+    Depth {
+        outcome_id: String,
+        limit: Option<usize>,
+    },
+    /// Best bid and ask, mid price and spread of a book
+    Quote {
+        outcome_id: String,
+    },
+    Order {
+        outcome_id: String,
+        order_id: OrderId,
+    },
+    OrdersAtPrice {
+        outcome_id: String,
+        side: Side,
+        price: Price,
+    },
+    /// Resting orders of an account across all books
+    AccountOrders {
+        account_id: AccountId,
+    },
+    Stats,
+    /// Earliest cancel-on-disconnect deadline of each market, see
+    /// [`crate::engine::heartbeat`]
+    HeartbeatDeadlines,
+    /// When the current batch of each market in batch mode is over, see
+    /// [`crate::engine::batch`]
+    BatchDeadlines,
+    /// When the indicatives of each market with books in auction are next
+    /// due, see [`crate::engine::auction`]
+    IndicativeDeadlines,
+}
+
+/// A query on its way to the core, with where to send the answer.
+#[derive(Debug)]
+pub struct QueryRequest {
+    pub query: EngineQuery,
+    pub reply: oneshot::Sender<Option<Value>>,
+}
+
+/// Sends queries to whichever matching core is running.
+///
+/// Queries sent while no core runs, on standby, time out.
+#[derive(Debug, Clone)]
+pub struct QueryHandle {
+    requests: mpsc::Sender<QueryRequest>,
+}
+
+impl QueryHandle {
+    /// A handle and the receiving end to pass to the stream loop.
+    pub fn new() -> (Self, mpsc::Receiver<QueryRequest>) {
+        let (requests, rx) = mpsc::channel(QUERY_QUEUE_CAPACITY);
+        (Self { requests }, rx)
+    }
+
+    /// The answer to `query`, `None` if what it asks for does not exist.
+    pub async fn ask(&self, query: EngineQuery) -> EngineResult<Option<Value>> {
+        let (reply, answer) = oneshot::channel();
+        self.requests
+            .try_send(QueryRequest { query, reply })
+            .map_err(|_| EngineError::Internal("Query queue is full".to_string()))?;
+        match tokio::time::timeout(QUERY_TIMEOUT, answer).await {
+            Ok(Ok(answer)) => Ok(answer),
+            _ => Err(EngineError::Internal(
+                "Matching core did not answer, it may not be leading".to_string(),
+            )),
+        }
+    }
+}
+
+impl MatchingEngine {
+    /// JSON answer to `query`, `None` if what it asks for does not exist.
+    pub fn query(&self, query: &EngineQuery) -> Option<Value> {
+        match query {
+            EngineQuery::Depth { outcome_id, limit } => {
+                let depth = self.books.get(outcome_id)?.depth(*limit);
+                Some(json!({
+                    "outcome_id": outcome_id,
+                    "bids": depth.bids,
+                    "asks": depth.asks,
+                    "seq": depth.seq,
+                }))
+            }
+            EngineQuery::Quote { outcome_id } => {
+                let book = self.books.get(outcome_id)?;
+                Some(json!({
+                    "outcome_id": outcome_id,
+                    "best_bid": book.best_bid(),
+                    "best_ask": book.best_ask(),
+                    "mid": book.mid_price(),
+                    "spread": book.spread(),
+                }))
+            }
+            EngineQuery::Order {
+                outcome_id,
+                order_id,
+            } => {
+                let order = self.books.get(outcome_id)?.get_order(*order_id).ok()?;
+                Some(json!(order))
+            }
+            EngineQuery::OrdersAtPrice {
+                outcome_id,
+                side,
+                price,
+            } => {
+                let orders = self
+                    .books
+                    .get(outcome_id)?
+                    .get_orders_at_price(*price, *side);
+                Some(json!(orders))
+            }
+            EngineQuery::AccountOrders { account_id } => {
+                let orders: Vec<Value> = self
+                    .books
+                    .iter()
+                    .flat_map(|(outcome_id, book)| {
+                        book.account_orders(*account_id)
+                            .into_iter()
+                            .map(move |order| json!({ "outcome_id": outcome_id, "order": order }))
+                    })
+                    .collect();
+                Some(json!(orders))
+            }
+            EngineQuery::Stats => {
+                let stats = self.stats();
+                Some(json!({
+                    "engine_id": self.engine_id,
+                    "total_books": stats.total_books,
+                    "total_markets": stats.total_markets,
+                    "resting_orders": stats.resting_orders,
+                }))
+            }
+            EngineQuery::HeartbeatDeadlines => Some(json!(self.heartbeat_deadlines())),
+            EngineQuery::BatchDeadlines => Some(json!(self.batch_deadlines())),
+            EngineQuery::IndicativeDeadlines => Some(json!(self.indicative_deadlines())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limit(account_id: u64, side: &str, price: u64, qty: u64) -> Value {
+        json!({
+            "type": "order.new",
+            "outcome_id": "outcome-1",
+            "outcome_name": "Yes",
+            "market_id": "1",
+            "account_id": account_id.to_string(),
+            "side": side,
+            "order_type": "LIMIT",
+            "price": price.to_string(),
+            "qty_remaining": qty.to_string(),
+            "qty_original": qty.to_string(),
+            "time_in_force": "GTC",
+            "ts": "1000",
+        })
+    }
+
+    #[test]
+    fn answers_book_and_account_queries() {
+        let mut engine = MatchingEngine::new(false);
+        for payload in [
+            limit(1, "BUY", 40, 10),
+            limit(2, "BUY", 40, 5),
+            limit(1, "SELL", 60, 3),
+        ] {
+            engine.handle_command(&payload).unwrap();
+        }
+        let outcome_id = "outcome-1".to_string();
+
+        let quote = engine
+            .query(&EngineQuery::Quote {
+                outcome_id: outcome_id.clone(),
+            })
+            .unwrap();
+        assert_eq!(quote["best_bid"], 40);
+        assert_eq!(quote["spread"], 20);
+
+        let depth = engine
+            .query(&EngineQuery::Depth {
+                outcome_id: outcome_id.clone(),
+                limit: Some(1),
+            })
+            .unwrap();
+        assert_eq!(depth["bids"], json!([[40, 15]]));
+
+        let level = engine
+            .query(&EngineQuery::OrdersAtPrice {
+                outcome_id: outcome_id.clone(),
+                side: Side::Buy,
+                price: Price(40),
+            })
+            .unwrap();
+        assert_eq!(level.as_array().unwrap().len(), 2);
+
+        let orders = engine
+            .query(&EngineQuery::AccountOrders {
+                account_id: AccountId(1),
+            })
+            .unwrap();
+        assert_eq!(orders.as_array().unwrap().len(), 2);
+
+        assert!(
+            engine
+                .query(&EngineQuery::Order {
+                    outcome_id: outcome_id.clone(),
+                    order_id: OrderId(1),
+                })
+                .is_some()
+        );
+        assert_eq!(
+            engine.query(&EngineQuery::Order {
+                outcome_id,
+                order_id: OrderId(99),
+            }),
+            None
+        );
+        assert_eq!(
+            engine.query(&EngineQuery::Quote {
+                outcome_id: "missing".to_string(),
+            }),
+            None
+        );
+    }
+}
@@ -0,0 +1,402 @@
+use crate::engine::batch::BatchSchedule;
+use crate::engine::command::EngineOutput;
+use crate::engine::engine::MatchingEngine;
+use crate::engine::fair_price::{FairPriceState, FairPriceStrategy};
+use crate::engine::heartbeat::Heartbeat;
+use crate::engine::stop::TrailingStop;
+use crate::engine::volume::OutcomeVolume;
+use crate::error::EngineResult;
+use crate::infra::metrics::METRICS;
+use crate::orderbook::{Allocation, OrderBookBuilder, OrderId, Snapshot, order::AccountId};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as SerdeJsonValue;
+use std::collections::BTreeSet;
+use tracing::{info, warn};
+
+/// Everything the engine knows about one market, as handed between engines.
+///
+/// `ledger_id` is the id of the last `engine.ledger.{market_id}` entry the
+/// snapshot includes; the ledger tail after it must be replayed on top.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MarketSnapshot {
+    pub market_id: u32,
+    pub ledger_id: Option<String>,
+    pub clock: i64,
+    pub fair_price_strategy: Option<FairPriceStrategy>,
+    pub outcomes: Vec<OutcomeSnapshot>,
+    /// Resting orders of each account's last `quote.replace`
+    #[serde(default)]
+    pub quote_sets: Vec<QuoteSetSnapshot>,
+    /// Accounts enrolled in cancel-on-disconnect
+    #[serde(default)]
+    pub heartbeats: Vec<HeartbeatSnapshot>,
+    /// Set when the market is in batch matching mode
+    #[serde(default)]
+    pub batch: Option<BatchSchedule>,
+    /// Allocation of books created after the snapshot; existing books carry
+    /// their own
+    #[serde(default)]
+    pub allocation: Option<Allocation>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutcomeSnapshot {
+    pub outcome_id: String,
+    pub book: Snapshot,
+    pub fair_price: FairPriceState,
+    pub volume: OutcomeVolume,
+    /// Trailing stops waiting to fire, oldest first
+    #[serde(default)]
+    pub trailing_stops: Vec<TrailingStop>,
+    /// Next stop id; snapshots from before stops had their own ids go on
+    /// after the stops they hold
+    #[serde(default)]
+    pub next_stop_id: Option<OrderId>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuoteSetSnapshot {
+    pub account_id: AccountId,
+    pub orders: Vec<(String, OrderId)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeartbeatSnapshot {
+    pub account_id: AccountId,
+    pub heartbeat: Heartbeat,
+}
+
+impl MatchingEngine {
+    /// Every market the engine holds state for.
+    pub fn markets(&self) -> BTreeSet<u32> {
+        self.outcome_markets
+            .values()
+            .chain(self.fair_price_strategies.keys())
+            .chain(self.batches.keys())
+            .chain(self.allocations.keys())
+            .chain(self.ledger_positions.keys())
+            .copied()
+            .collect()
+    }
+
+    /// Captures the state of every outcome of `market_id`.
+    pub fn snapshot_market(&self, market_id: u32) -> MarketSnapshot {
+        let outcomes = self
+            .outcome_markets
+            .iter()
+            .filter(|(_, outcome_market_id)| **outcome_market_id == market_id)
+            .filter_map(|(outcome_id, _)| {
+                Some(OutcomeSnapshot {
+                    outcome_id: outcome_id.clone(),
+                    book: self.books.get(outcome_id)?.snapshot(),
+                    fair_price: self
+                        .fair_prices
+                        .get(outcome_id)
+                        .cloned()
+                        .unwrap_or_default(),
+                    volume: self
+                        .outcome_volumes
+                        .get(outcome_id)
+                        .cloned()
+                        .unwrap_or_default(),
+                    trailing_stops: self
+                        .trailing_stops
+                        .get(outcome_id)
+                        .cloned()
+                        .unwrap_or_default(),
+                    next_stop_id: self.next_stop_ids.get(outcome_id).copied(),
+                })
+            })
+            .collect();
+        let quote_sets = self
+            .quote_sets
+            .get(&market_id)
+            .into_iter()
+            .flatten()
+            .map(|(account_id, orders)| QuoteSetSnapshot {
+                account_id: *account_id,
+                orders: orders.clone(),
+            })
+            .collect();
+        let heartbeats = self
+            .heartbeats
+            .get(&market_id)
+            .into_iter()
+            .flatten()
+            .map(|(account_id, heartbeat)| HeartbeatSnapshot {
+                account_id: *account_id,
+                heartbeat: *heartbeat,
+            })
+            .collect();
+        MarketSnapshot {
+            market_id,
+            ledger_id: self.ledger_positions.get(&market_id).cloned(),
+            clock: self.clock,
+            fair_price_strategy: self.fair_price_strategies.get(&market_id).copied(),
+            outcomes,
+            quote_sets,
+            heartbeats,
+            batch: self.batches.get(&market_id).copied(),
+            allocation: self.allocations.get(&market_id).copied(),
+        }
+    }
+
+    /// Snapshots `market_id` and drops its state.
+    ///
+    /// Later commands for the market are refused with
+    /// [`crate::error::EngineError::MarketNotOwned`] until it is adopted again.
+    pub fn release_market(&mut self, market_id: u32) -> MarketSnapshot {
+        let snapshot = self.snapshot_market(market_id);
+        self.forget_market(market_id);
+        self.released_markets.insert(market_id);
+        info!(
+            "Released market {} ({} outcomes)",
+            market_id,
+            snapshot.outcomes.len()
+        );
+        snapshot
+    }
+
+    /// Drops everything the engine holds for `market_id`.
+    fn forget_market(&mut self, market_id: u32) {
+        for outcome_id in self.market_outcomes(market_id) {
+            self.books.remove(&outcome_id);
+            self.fair_prices.remove(&outcome_id);
+            self.outcome_volumes.remove(&outcome_id);
+            self.outcome_markets.remove(&outcome_id);
+            self.trailing_stops.remove(&outcome_id);
+            self.next_stop_ids.remove(&outcome_id);
+            self.indicatives.remove(&outcome_id);
+        }
+        self.fair_price_strategies.remove(&market_id);
+        self.ledger_positions.remove(&market_id);
+        self.quote_sets.remove(&market_id);
+        self.heartbeats.remove(&market_id);
+        self.batches.remove(&market_id);
+        self.indicative_refreshes.remove(&market_id);
+        self.allocations.remove(&market_id);
+    }
+
+    /// Takes ownership of `market_id`: drops whatever the engine held for
+    /// it, restores its snapshot, if any, then replays the ledger entries
+    /// recorded after it without producing outputs.
+    ///
+    /// Entries whose ledger id is in `unpublished` were applied by the
+    /// previous owner, but their outputs never went out. They are applied
+    /// with outputs instead, which are returned with their ledger id, so
+    /// they can be published now.
+    pub fn adopt_market(
+        &mut self,
+        market_id: u32,
+        snapshot: Option<MarketSnapshot>,
+        ledger: Vec<(String, SerdeJsonValue)>,
+        unpublished: &BTreeSet<String>,
+    ) -> Vec<(String, EngineResult<Vec<EngineOutput>>)> {
+        self.forget_market(market_id);
+        self.released_markets.remove(&market_id);
+        if let Some(snapshot) = snapshot {
+            self.restore_market(snapshot);
+        }
+        let was_replay_mode = self.is_replay_mode;
+        let replayed = ledger.len();
+        let mut regenerated = Vec::new();
+        METRICS.start_replay(replayed);
+        for (ledger_id, payload) in ledger {
+            let publish = unpublished.contains(&ledger_id);
+            self.is_replay_mode = was_replay_mode || !publish;
+            let outcome = self.apply_ledgered(market_id, &ledger_id, &payload);
+            // Commands rejected live were ledgered too; skip them the same way
+            if let Err(e) = &outcome {
+                warn!("Skipping ledger entry {}: {}", ledger_id, e);
+            }
+            if publish {
+                regenerated.push((ledger_id, outcome));
+            }
+            METRICS.record_replayed(1);
+        }
+        self.is_replay_mode = was_replay_mode;
+        info!(
+            "Adopted market {} ({} ledger entries replayed, {} to publish)",
+            market_id,
+            replayed,
+            regenerated.len()
+        );
+        regenerated
+    }
+
+    fn restore_market(&mut self, snapshot: MarketSnapshot) {
+        let market_id = snapshot.market_id;
+        for outcome in snapshot.outcomes {
+            let mut book = OrderBookBuilder::new(&outcome.outcome_id).build();
+            book.restore_snapshot(outcome.book);
+            self.books.insert(outcome.outcome_id.clone(), book);
+            self.fair_prices
+                .insert(outcome.outcome_id.clone(), outcome.fair_price);
+            self.outcome_volumes
+                .insert(outcome.outcome_id.clone(), outcome.volume);
+            let next_stop_id = outcome.next_stop_id.or_else(|| {
+                let last = outcome
+                    .trailing_stops
+                    .iter()
+                    .map(|stop| stop.stop_id)
+                    .max()?;
+                Some(OrderId(last.0 + 1))
+            });
+            if let Some(next_stop_id) = next_stop_id {
+                self.next_stop_ids
+                    .insert(outcome.outcome_id.clone(), next_stop_id);
+            }
+            if !outcome.trailing_stops.is_empty() {
+                self.trailing_stops
+                    .insert(outcome.outcome_id.clone(), outcome.trailing_stops);
+            }
+            self.outcome_markets.insert(outcome.outcome_id, market_id);
+        }
+        if !snapshot.quote_sets.is_empty() {
+            let quote_sets = self.quote_sets.entry(market_id).or_default();
+            for quote_set in snapshot.quote_sets {
+                quote_sets.insert(quote_set.account_id, quote_set.orders);
+            }
+        }
+        if !snapshot.heartbeats.is_empty() {
+            let heartbeats = self.heartbeats.entry(market_id).or_default();
+            for enrolled in snapshot.heartbeats {
+                heartbeats.insert(enrolled.account_id, enrolled.heartbeat);
+            }
+        }
+        if let Some(allocation) = snapshot.allocation {
+            self.allocations.insert(market_id, allocation);
+        }
+        if let Some(schedule) = snapshot.batch {
+            self.batches.insert(market_id, schedule);
+        }
+        if let Some(strategy) = snapshot.fair_price_strategy {
+            self.fair_price_strategies.insert(market_id, strategy);
+        }
+        if let Some(ledger_id) = snapshot.ledger_id {
+            self.ledger_positions.insert(market_id, ledger_id);
+        }
+        self.advance_clock(snapshot.clock);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::EngineError;
+    use crate::orderbook::Price;
+    use serde_json::json;
+    use std::collections::BTreeMap;
+
+    fn limit(account_id: u64, side: &str, price: u64, qty: u64) -> SerdeJsonValue {
+        json!({
+            "type": "order.new",
+            "outcome_id": "outcome-1",
+            "outcome_name": "Yes",
+            "market_id": "1",
+            "account_id": account_id.to_string(),
+            "side": side,
+            "order_type": "LIMIT",
+            "price": price.to_string(),
+            "qty_remaining": qty.to_string(),
+            "qty_original": qty.to_string(),
+            "time_in_force": "GTC",
+            "ts": "1000",
+        })
+    }
+
+    #[test]
+    fn handoff_moves_market_state_between_engines() {
+        let mut source = MatchingEngine::new(false);
+        source
+            .apply_ledgered(1, "1-0", &limit(1, "SELL", 60, 10))
+            .unwrap();
+        source
+            .apply_ledgered(1, "2-0", &limit(2, "BUY", 60, 4))
+            .unwrap();
+        source
+            .apply_ledgered(1, "3-0", &limit(3, "SELL", 62, 5))
+            .unwrap();
+
+        source
+            .apply_ledgered(
+                1,
+                "4-0",
+                &json!({
+                    "type": "quote.replace",
+                    "market_id": "1",
+                    "account_id": "5",
+                    "quotes": r#"[{"outcome_id":"outcome-1","side":"BUY","price":"50","qty":"2"}]"#,
+                    "ts": "1000",
+                }),
+            )
+            .unwrap();
+        source.heartbeat(1, AccountId(5), Some(2_000), 1_000);
+
+        assert_eq!(source.markets(), BTreeSet::from([1]));
+        assert_eq!(source.stats().resting_orders, 3);
+
+        let snapshot = source.release_market(1);
+        assert_eq!(snapshot.ledger_id.as_deref(), Some("4-0"));
+        assert_eq!(snapshot.quote_sets.len(), 1);
+        assert!(source.books.is_empty());
+        assert!(source.quote_sets.is_empty());
+        assert!(source.heartbeats.is_empty());
+        assert!(source.markets().is_empty());
+        assert!(matches!(
+            source.apply_ledgered(1, "5-0", &limit(4, "BUY", 60, 1)),
+            Err(EngineError::MarketNotOwned(1))
+        ));
+
+        // The snapshot travels through Redis as JSON
+        let snapshot: MarketSnapshot =
+            serde_json::from_str(&serde_json::to_string(&snapshot).unwrap()).unwrap();
+        let mut target = MatchingEngine::new(false);
+        target.adopt_market(
+            1,
+            Some(snapshot),
+            vec![("5-0".to_string(), limit(4, "BUY", 60, 1))],
+            &BTreeSet::new(),
+        );
+
+        assert_eq!(target.fair_price("outcome-1"), Some(Price(60)));
+        let depth = target.books["outcome-1"].depth(None);
+        assert_eq!(depth.asks.len(), 2);
+        assert_eq!(depth.asks[0].1.value(), 5);
+        assert_eq!(
+            target.ledger_positions.get(&1).map(String::as_str),
+            Some("5-0")
+        );
+        assert_eq!(target.quote_sets[&1][&AccountId(5)].len(), 1);
+        assert_eq!(target.heartbeat_deadlines(), BTreeMap::from([(1, 3_000)]));
+        assert!(!target.is_replay_mode);
+    }
+
+    #[test]
+    fn adoption_regenerates_outputs_of_unpublished_entries() {
+        let ledger = vec![
+            ("1-0".to_string(), limit(1, "SELL", 60, 10)),
+            ("2-0".to_string(), limit(2, "BUY", 60, 4)),
+        ];
+        let mut engine = MatchingEngine::new(false);
+        // A follower already replayed the market silently
+        engine.adopt_market(1, None, ledger.clone(), &BTreeSet::new());
+
+        let regenerated =
+            engine.adopt_market(1, None, ledger, &BTreeSet::from(["2-0".to_string()]));
+
+        assert_eq!(regenerated.len(), 1);
+        let (ledger_id, outcome) = &regenerated[0];
+        assert_eq!(ledger_id, "2-0");
+        let outputs = outcome.as_ref().unwrap();
+        assert!(
+            outputs
+                .iter()
+                .any(|output| matches!(output, EngineOutput::Event(_)))
+        );
+        // State was rebuilt rather than replayed twice on top of itself
+        assert_eq!(engine.stats().resting_orders, 1);
+        assert_eq!(engine.books["outcome-1"].depth(None).asks[0].1.value(), 6);
+        assert!(!engine.is_replay_mode);
+    }
+}
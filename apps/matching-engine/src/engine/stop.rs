@@ -0,0 +1,395 @@
+//! Trailing stops.
+//!
+//! An `order.new` MARKET order with a `trail` is not executed on arrival. The
+//! engine holds it as a stop whose trigger trails the outcome's last trade
+//! by `trail` cents: a sell stop's trigger rises with the highest last
+//! trade since it was placed, and a buy stop's falls with the lowest. The
+//! trigger never moves back. Once a last trade reaches it, the stop fires
+//! and its MARKET order is executed like any other.
+//!
+//! Stops are checked, in arrival order, after every command that can trade
+//! in their outcome. Orders fired by stops can trade in turn and fire more
+//! stops, which are checked again once each fired order is done rather
+//! than from within it. Books in auction keep their stops armed until they are back to
+//! continuous matching. Since last trades only move with ledgered commands,
+//! replaying a ledger moves and fires the same stops.
+
+use crate::engine::engine::MatchingEngine;
+use crate::engine::order::{Order, OrderSide};
+use crate::engine::publish_events::{CancelReason, PublishEngineEvent};
+use crate::orderbook::order::AccountId;
+use crate::orderbook::{OrderId, Price, Quantity, Side};
+use serde::{Deserialize, Serialize};
+
+/// A MARKET order waiting for the last trade to reach its trigger.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrailingStop {
+    /// Counted per outcome apart from its order ids
+    pub stop_id: OrderId,
+    /// Executed once the stop fires
+    pub order: Order,
+    pub trail: u64,
+    /// Best last trade for the stop since it was placed: the highest for a
+    /// sell, the lowest for a buy
+    pub extreme: Price,
+}
+
+impl TrailingStop {
+    pub fn side(&self) -> Side {
+        self.order.side.0
+    }
+
+    /// Last trade at or past which the stop fires.
+    pub fn trigger(&self) -> Price {
+        match self.side() {
+            Side::Sell => Price(self.extreme.value().saturating_sub(self.trail)),
+            Side::Buy => Price(self.extreme.value().saturating_add(self.trail)),
+        }
+    }
+
+    /// Moves the trigger along if `last_trade` is in the stop's favour;
+    /// `true` if it moved.
+    fn follow(&mut self, last_trade: Price) -> bool {
+        let better = match self.side() {
+            Side::Sell => last_trade > self.extreme,
+            Side::Buy => last_trade < self.extreme,
+        };
+        if better {
+            self.extreme = last_trade;
+        }
+        better
+    }
+
+    fn is_triggered(&self, last_trade: Price) -> bool {
+        match self.side() {
+            Side::Sell => last_trade <= self.trigger(),
+            Side::Buy => last_trade >= self.trigger(),
+        }
+    }
+}
+
+impl MatchingEngine {
+    /// Holds `order` as a trailing stop from the outcome's last trade.
+    ///
+    /// The stop is rejected while the outcome has not traded yet.
+    pub(crate) fn place_trailing_stop(&mut self, order: &Order) -> Vec<PublishEngineEvent> {
+        self.advance_clock(order.ts);
+        self.outcome_markets
+            .insert(order.outcome_id.clone(), order.market_id);
+        let last_trade = self
+            .fair_prices
+            .get(&order.outcome_id)
+            .and_then(|state| state.last_trade());
+        let (Some(trail), Some(last_trade)) = (order.trail, last_trade) else {
+            return vec![PublishEngineEvent::OrderRejected {
+                outcome_id: order.outcome_id.clone(),
+                account_id: AccountId(order.account_id),
+                side: order.side.clone(),
+                price: Price(order.price),
+                time_in_force: Some(order.time_in_force),
+                quantity: Quantity(order.qty_original),
+            }];
+        };
+        let stop = TrailingStop {
+            stop_id: self.new_stop_id(&order.outcome_id),
+            order: Order {
+                trail: None,
+                ..order.clone()
+            },
+            trail,
+            extreme: last_trade,
+        };
+        let placed = PublishEngineEvent::StopPlaced {
+            stop_id: stop.stop_id,
+            account_id: AccountId(order.account_id),
+            outcome_id: order.outcome_id.clone(),
+            side: order.side.clone(),
+            quantity: Quantity(order.qty_original),
+            trail,
+            trigger: stop.trigger(),
+        };
+        self.trailing_stops
+            .entry(order.outcome_id.clone())
+            .or_default()
+            .push(stop);
+        vec![placed]
+    }
+
+    /// Moves the stops of `outcome_id` along its last trade and executes
+    /// those that fire, with the events of both.
+    pub(crate) fn run_trailing_stops(&mut self, outcome_id: &str) -> Vec<PublishEngineEvent> {
+        let mut events = Vec::new();
+        loop {
+            let Some(last_trade) = self
+                .fair_prices
+                .get(outcome_id)
+                .and_then(|state| state.last_trade())
+            else {
+                return events;
+            };
+            let in_auction = self.books.get(outcome_id).is_some_and(|b| b.in_auction());
+            let Some(stops) = self.trailing_stops.get_mut(outcome_id) else {
+                return events;
+            };
+            for stop in stops.iter_mut() {
+                let previous_trigger = stop.trigger();
+                if stop.follow(last_trade) {
+                    events.push(PublishEngineEvent::StopMoved {
+                        stop_id: stop.stop_id,
+                        account_id: AccountId(stop.order.account_id),
+                        outcome_id: outcome_id.to_string(),
+                        side: stop.order.side.clone(),
+                        trigger: stop.trigger(),
+                        previous_trigger,
+                    });
+                }
+            }
+            let fired = match stops.iter().position(|stop| stop.is_triggered(last_trade)) {
+                Some(index) if !in_auction => stops.remove(index),
+                _ => return events,
+            };
+            if stops.is_empty() {
+                self.trailing_stops.remove(outcome_id);
+            }
+            events.push(PublishEngineEvent::StopTriggered {
+                stop_id: fired.stop_id,
+                account_id: AccountId(fired.order.account_id),
+                outcome_id: outcome_id.to_string(),
+                side: fired.order.side.clone(),
+                trigger: fired.trigger(),
+                last_trade,
+            });
+            // The fired order trades now, not when the stop was placed
+            let order = Order {
+                ts: self.clock,
+                ..fired.order
+            };
+            let (order_events, _) = self.execute_order(&order);
+            events.extend(order_events);
+        }
+    }
+
+    fn new_stop_id(&mut self, outcome_id: &str) -> OrderId {
+        let next = self
+            .next_stop_ids
+            .entry(outcome_id.to_string())
+            .or_insert(OrderId(1));
+        let id = *next;
+        next.0 += 1;
+        id
+    }
+
+    /// Removes the stops of `account_id`, or of everyone when `None`, in
+    /// `market_id`, and returns them by outcome.
+    pub(crate) fn take_trailing_stops(
+        &mut self,
+        market_id: u32,
+        account_id: Option<AccountId>,
+    ) -> Vec<(String, TrailingStop)> {
+        let mut taken = Vec::new();
+        for outcome_id in self.market_outcomes(market_id) {
+            let Some(stops) = self.trailing_stops.get_mut(&outcome_id) else {
+                continue;
+            };
+            let (matching, kept) = std::mem::take(stops)
+                .into_iter()
+                .partition(|stop| account_id.is_none_or(|id| stop.order.account_id == id.0));
+            *stops = kept;
+            if stops.is_empty() {
+                self.trailing_stops.remove(&outcome_id);
+            }
+            taken.extend(
+                matching
+                    .into_iter()
+                    .map(|stop: TrailingStop| (outcome_id.clone(), stop)),
+            );
+        }
+        taken
+    }
+}
+
+/// The `StopCancelled` event of a stop removed before it fired.
+pub(crate) fn stop_cancelled(
+    outcome_id: &str,
+    stop: &TrailingStop,
+    reason: CancelReason,
+) -> PublishEngineEvent {
+    PublishEngineEvent::StopCancelled {
+        stop_id: stop.stop_id,
+        account_id: AccountId(stop.order.account_id),
+        outcome_id: outcome_id.to_string(),
+        side: OrderSide(stop.side()),
+        quantity: Quantity(stop.order.qty_original),
+        trigger: stop.trigger(),
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::engine::command::EngineOutput;
+    use serde_json::{Value, json};
+    use std::collections::BTreeSet;
+
+    fn limit(account_id: u64, side: &str, price: u64, qty: u64) -> Value {
+        json!({
+            "type": "order.new",
+            "outcome_id": "outcome-1",
+            "outcome_name": "Yes",
+            "market_id": "1",
+            "account_id": account_id.to_string(),
+            "side": side,
+            "order_type": "LIMIT",
+            "price": price.to_string(),
+            "qty_remaining": qty.to_string(),
+            "qty_original": qty.to_string(),
+            "time_in_force": "GTC",
+            "ts": "1000",
+        })
+    }
+
+    fn stop(account_id: u64, side: &str, qty: u64, trail: u64) -> Value {
+        let mut order = limit(account_id, side, 0, qty);
+        order["order_type"] = json!("MARKET");
+        order["time_in_force"] = json!("IOC");
+        order["trail"] = json!(trail.to_string());
+        order
+    }
+
+    fn events(outputs: Vec<EngineOutput>) -> Vec<PublishEngineEvent> {
+        outputs
+            .into_iter()
+            .filter_map(|output| match output {
+                EngineOutput::Event(event) => Some(event),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn trade_at(engine: &mut MatchingEngine, price: u64) -> Vec<PublishEngineEvent> {
+        engine.handle_command(&limit(1, "SELL", price, 1)).unwrap();
+        events(engine.handle_command(&limit(2, "BUY", price, 1)).unwrap())
+    }
+
+    #[test]
+    fn trailing_stops_follow_the_last_trade_then_fire() {
+        let mut engine = MatchingEngine::new(false);
+        // No last trade to trail yet
+        let rejected = events(engine.handle_command(&stop(3, "SELL", 2, 5)).unwrap());
+        assert_eq!(rejected[0].kind(), "order.rejected");
+
+        trade_at(&mut engine, 60);
+        engine.handle_command(&limit(4, "BUY", 58, 5)).unwrap();
+        let placed = events(engine.handle_command(&stop(3, "SELL", 2, 5)).unwrap());
+        let [PublishEngineEvent::StopPlaced { trigger, .. }] = placed.as_slice() else {
+            panic!("expected stop.placed, got {:?}", placed);
+        };
+        assert_eq!(*trigger, Price(55));
+
+        // A trade in the stop's favour drags the trigger up, one against it
+        // leaves it
+        let moved = trade_at(&mut engine, 65);
+        let Some(PublishEngineEvent::StopMoved {
+            trigger,
+            previous_trigger,
+            ..
+        }) = moved.last()
+        else {
+            panic!("expected stop.moved, got {:?}", moved);
+        };
+        assert_eq!((*previous_trigger, *trigger), (Price(55), Price(60)));
+        assert_eq!(trade_at(&mut engine, 62).last().unwrap().kind(), "trade");
+
+        // The stop travels with the market
+        let snapshot = engine.release_market(1);
+        let snapshot = serde_json::from_str(&serde_json::to_string(&snapshot).unwrap()).unwrap();
+        let mut target = MatchingEngine::new(false);
+        target.adopt_market(1, Some(snapshot), Vec::new(), &BTreeSet::new());
+        assert_eq!(target.trailing_stops["outcome-1"][0].trigger(), Price(60));
+
+        // Reaching the trigger fires the stop's MARKET sell into the bid
+        let fired = trade_at(&mut target, 60);
+        let kinds: Vec<&str> = fired.iter().map(|event| event.kind()).collect();
+        assert_eq!(
+            kinds[kinds.len() - 3..],
+            ["stop.triggered", "order.filled", "trade"]
+        );
+        let Some(PublishEngineEvent::Trade {
+            price, account_id, ..
+        }) = fired.last()
+        else {
+            unreachable!()
+        };
+        assert_eq!((*price, *account_id), (Price(58), AccountId(3)));
+        assert!(target.trailing_stops.is_empty());
+
+        // Cancelling an account's orders takes its stops along
+        target.handle_command(&stop(5, "BUY", 1, 10)).unwrap();
+        let cancelled = events(
+            target
+                .handle_command(&json!({
+                    "type": "order.cancel_all",
+                    "market_id": "1",
+                    "account_id": "5",
+                    "ts": "2000",
+                }))
+                .unwrap(),
+        );
+        let [
+            PublishEngineEvent::StopCancelled {
+                trigger, reason, ..
+            },
+        ] = cancelled.as_slice()
+        else {
+            panic!("expected stop.cancelled, got {:?}", cancelled);
+        };
+        assert_eq!(*trigger, Price(68));
+        assert_eq!(*reason, CancelReason::CancelAll);
+        assert!(target.trailing_stops.is_empty());
+    }
+
+    #[test]
+    fn fired_stops_fire_the_next_ones_in_turn() {
+        let mut engine = MatchingEngine::new(false);
+        trade_at(&mut engine, 60);
+        for price in [59, 57, 53] {
+            engine.handle_command(&limit(4, "BUY", price, 1)).unwrap();
+        }
+        let mut stop_ids = Vec::new();
+        for trail in [1, 3, 7] {
+            let placed = events(engine.handle_command(&stop(3, "SELL", 1, trail)).unwrap());
+            let [PublishEngineEvent::StopPlaced { stop_id, .. }] = placed.as_slice() else {
+                panic!("expected stop.placed, got {:?}", placed);
+            };
+            stop_ids.push(stop_id.0);
+        }
+        // Stops count apart from the 5 orders of the book
+        assert_eq!(stop_ids, [1, 2, 3]);
+        assert_eq!(engine.books["outcome-1"].next_order_id, OrderId(6));
+
+        // Each fired sell trades into the next bid down and fires the next stop
+        let outputs = engine.handle_command(&limit(1, "SELL", 59, 1)).unwrap();
+        let triggered: Vec<u64> = events(outputs)
+            .iter()
+            .filter_map(|event| match event {
+                PublishEngineEvent::StopTriggered { stop_id, .. } => Some(stop_id.0),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(triggered, [1, 2, 3]);
+        assert!(engine.trailing_stops.is_empty());
+
+        // The stop ids go on where they were after a handoff
+        let snapshot = engine.release_market(1);
+        let mut target = MatchingEngine::new(false);
+        target.adopt_market(1, Some(snapshot), Vec::new(), &BTreeSet::new());
+        target.handle_command(&limit(2, "BUY", 50, 1)).unwrap();
+        target.handle_command(&limit(1, "SELL", 50, 1)).unwrap();
+        let placed = events(target.handle_command(&stop(3, "SELL", 1, 5)).unwrap());
+        let [PublishEngineEvent::StopPlaced { stop_id, .. }] = placed.as_slice() else {
+            panic!("expected stop.placed, got {:?}", placed);
+        };
+        assert_eq!(*stop_id, OrderId(4));
+    }
+}
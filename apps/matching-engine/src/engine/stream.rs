@@ -1,68 +1,198 @@
-use crate::engine::order::OrderWire;
-use crate::engine::{engine::MatchingEngine, order::Order};
+use crate::engine::command::EngineOutput;
+use crate::engine::engine::MatchingEngine;
+use crate::engine::follower::Promotion;
+use crate::engine::matching_thread::{
+    COMMAND_QUEUE_CAPACITY, CoreCommand, CoreResult, RESULT_QUEUE_CAPACITY, spawn_matching_core,
+};
+use crate::engine::order::MarketHandoffWire;
+use crate::engine::query::{EngineQuery, QueryRequest};
 use crate::error::{EngineError, EngineResult};
-use crate::infra::ledger::append_events_to_ledger;
+use crate::infra::dead_letter::{DeadLetter, dead_letter};
+use crate::infra::lease::{Fence, Lease};
+use crate::infra::ledger::{append_events_to_ledger, last_ledgered_entry, read_ledger};
+use crate::infra::metrics::METRICS;
+use crate::infra::shard_map::{
+    CONSUMER_GROUP, assigned_markets, command_stream, load_snapshot, market_of_stream,
+    save_snapshots,
+};
 use crate::infra::view_emitter::ViewEmitter;
-use redis::Value as RedisValue;
 use redis::aio::Connection;
+use redis::streams::{
+    StreamClaimReply, StreamId, StreamPendingCountReply, StreamPendingReply, StreamReadOptions,
+    StreamReadReply,
+};
+use redis::{AsyncCommands, Value as RedisValue};
 use serde_json::Value as SerdeJsonValue;
-use std::time::Duration;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::{oneshot, watch};
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
 use tracing::{debug, error, info, warn};
 
-const STREAM_KEY: &str = "orders.commands.stream";
-const GROUP: &str = "engine-group";
 const BLOCK_TIMEOUT_MS: usize = 1000;
 const BATCH_SIZE: usize = 50;
-const AUTOCLAIM_IDLE_TIME_MS: usize = 30000; // 30 seconds
+/// How often the shard map is re-read to pick up assignment changes.
+const SHARD_REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+/// How long an entry stays pending before it is delivered again.
+const PENDING_RETRY_IDLE: Duration = Duration::from_secs(30);
+/// Deliveries after the first one before a command is dead-lettered, when
+/// `COMMAND_MAX_RETRIES` is not set.
+pub const DEFAULT_MAX_RETRIES: u64 = 3;
+/// Most core results the publisher writes before ACKing them together.
+const PUBLISH_BATCH_SIZE: usize = 256;
+const PUBLISH_RETRY_MAX_BACKOFF: Duration = Duration::from_secs(5);
+/// How often the core is asked for overdue cancel-on-disconnect deadlines.
+const HEARTBEAT_SWEEP_INTERVAL: Duration = Duration::from_millis(500);
+/// How often the core is asked for batches that are over; well below the
+/// shortest batch interval.
+const BATCH_CLEAR_POLL_INTERVAL: Duration = Duration::from_millis(20);
+/// How often the core is asked for indicatives due again.
+const INDICATIVE_REFRESH_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
+/// How the stream loop consumes the command streams.
+#[derive(Debug, Clone)]
+pub struct StreamConfig {
+    /// Claim markets nobody owns yet, see [`crate::infra::shard_map`]
+    pub claim_unassigned: bool,
+    /// Deliveries after the first one before a pending command is moved to
+    /// the dead-letter stream
+    pub max_retries: u64,
+}
+
+impl Default for StreamConfig {
+    fn default() -> Self {
+        Self {
+            claim_unassigned: true,
+            max_retries: DEFAULT_MAX_RETRIES,
+        }
+    }
+}
+
+/// Runs the engine against the command streams of the markets it owns.
+///
+/// Three stages are connected by bounded channels:
+/// - this task reads entries, stamps and ledgers them, and feeds the core
+/// - the matching core applies them on its own thread, without any I/O
+/// - the publisher task writes the outputs and ACKs the entries
+///
+/// A slow Redis round-trip therefore only delays the edges; matching keeps
+/// going until the queues fill up. Which markets are read follows the shard
+/// map, see [`crate::infra::shard_map`].
+///
+/// `engine` must hold the lease of `promotion`; the lease is renewed in the
+/// background and the loop stops once it is fenced off.
+///
+/// `queries` are answered by the core between commands, see
+/// [`crate::engine::query`].
+///
+/// When `shutdown` flips to `true` the loop stops reading and drains, see
+/// [`drain`], then returns the engine.
 pub async fn start_order_stream_loop(
     redis_url: String,
-    mut engine: MatchingEngine,
-    mut view_emitter: ViewEmitter,
-) -> EngineResult<()> {
+    engine: MatchingEngine,
+    view_emitter: ViewEmitter,
+    config: StreamConfig,
+    promotion: Promotion,
+    queries: Receiver<QueryRequest>,
+    mut shutdown: watch::Receiver<bool>,
+) -> EngineResult<MatchingEngine> {
     let client = redis::Client::open(redis_url)
         .map_err(|e| EngineError::Configuration(format!("Invalid Redis URL: {}", e)))?;
-    let mut conn = client
+    let conn = client
+        .get_async_connection()
+        .await
+        .map_err(EngineError::Redis)?;
+    let lease_conn = client
+        .get_async_connection()
+        .await
+        .map_err(EngineError::Redis)?;
+    let sweep_conn = client
         .get_async_connection()
         .await
-        .map_err(|e| EngineError::Redis(e))?;
-    // Create consumer group (ignore error if already exists)
-    let _: Result<(), redis::RedisError> = redis::cmd("XGROUP")
-        .arg("CREATE")
-        .arg(STREAM_KEY)
-        .arg(GROUP)
-        .arg("0")
-        .arg("MKSTREAM")
-        .query_async(&mut conn)
-        .await;
-    let consumer_name = std::env::var("ENGINE_ID").unwrap_or_else(|_| {
-        let default = "engine-1".to_string();
-        warn!("ENGINE_ID not set, using default: {}", default);
-        default
-    });
+        .map_err(EngineError::Redis)?;
+    let batch_conn = client
+        .get_async_connection()
+        .await
+        .map_err(EngineError::Redis)?;
+    let refresh_conn = client
+        .get_async_connection()
+        .await
+        .map_err(EngineError::Redis)?;
+    let engine_id = engine.engine_id.clone();
 
     info!(
         "Starting order stream consumer: {} for group: {}",
-        consumer_name, GROUP
+        engine_id, CONSUMER_GROUP
     );
 
-    // Reclaim pending messages on startup
-    if let Err(e) =
-        reclaim_pending_messages(&mut conn, &consumer_name, &mut engine, &mut view_emitter).await
-    {
-        error!("Failed to reclaim pending messages: {}", e);
-        // Don't fail startup, just log the error
-    }
+    let (command_tx, command_rx) = mpsc::channel(COMMAND_QUEUE_CAPACITY);
+    let (result_tx, result_rx) = mpsc::channel(RESULT_QUEUE_CAPACITY);
+    let core = spawn_matching_core(engine, command_rx, result_tx)
+        .map_err(|e| EngineError::Internal(format!("Failed to start matching core: {}", e)))?;
+    let publisher = tokio::spawn(run_publisher(view_emitter, result_rx));
+    let renewal = tokio::spawn(renew_lease(
+        promotion.lease.clone(),
+        lease_conn,
+        promotion.fence.clone(),
+    ));
+    let query_forwarder = tokio::spawn(forward_queries(queries, command_tx.clone()));
+    let heartbeat_sweeper = tokio::spawn(queue_when_due(
+        sweep_conn,
+        command_tx.clone(),
+        EngineQuery::HeartbeatDeadlines,
+        "heartbeat.sweep",
+        HEARTBEAT_SWEEP_INTERVAL,
+    ));
+    let batch_clearer = tokio::spawn(queue_when_due(
+        batch_conn,
+        command_tx.clone(),
+        EngineQuery::BatchDeadlines,
+        "batch.clear",
+        BATCH_CLEAR_POLL_INTERVAL,
+    ));
+    let indicative_refresher = tokio::spawn(queue_when_due(
+        refresh_conn,
+        command_tx.clone(),
+        EngineQuery::IndicativeDeadlines,
+        "auction.refresh",
+        INDICATIVE_REFRESH_POLL_INTERVAL,
+    ));
 
-    // Main processing loop
-    loop {
-        match process_stream_batch(&mut conn, &consumer_name, &mut engine, &mut view_emitter).await
-        {
+    let mut ingest = Ingest {
+        conn,
+        engine_id,
+        config,
+        fence: promotion.fence.clone(),
+        commands: command_tx,
+        restored: promotion.markets.clone(),
+        owned: BTreeSet::new(),
+        draining: BTreeSet::new(),
+        last_refresh: None,
+        last_retry: Instant::now(),
+    };
+
+    // Main processing loop, checked for shutdown between batches so a batch
+    // is never abandoned halfway through the ledger
+    while !*shutdown.borrow() {
+        match ingest.run_once().await {
             Ok(_) => {
                 // Successful batch processing
                 debug!("Processed batch successfully");
             }
+            Err(e @ EngineError::Fenced(_)) => {
+                METRICS.record_error(&e);
+                return Err(e);
+            }
             Err(e) => {
+                METRICS.record_error(&e);
+                if ingest.commands.is_closed() {
+                    return Err(EngineError::Internal(
+                        "Matching core stopped unexpectedly".to_string(),
+                    ));
+                }
                 // Log error but continue processing
                 error!(
                     "Error processing stream batch (severity: {}): {}",
@@ -73,246 +203,793 @@ pub async fn start_order_stream_loop(
                 // For critical errors, we might want to back off
                 if matches!(e.severity(), crate::error::ErrorSeverity::Critical) {
                     warn!("Critical error encountered, backing off for 5 seconds");
-                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    tokio::select! {
+                        _ = tokio::time::sleep(Duration::from_secs(5)) => {}
+                        _ = shutdown.changed() => {}
+                    }
                 }
             }
         }
     }
+
+    info!("Shutdown requested, draining");
+    renewal.abort();
+    // Their senders would keep the core running
+    query_forwarder.abort();
+    heartbeat_sweeper.abort();
+    batch_clearer.abort();
+    indicative_refresher.abort();
+    let _ = query_forwarder.await;
+    let _ = heartbeat_sweeper.await;
+    let _ = batch_clearer.await;
+    let _ = indicative_refresher.await;
+    drain(ingest, core, publisher, promotion.lease).await
 }
 
-/// Reclaim messages that were pending but not acknowledged
-async fn reclaim_pending_messages(
-    conn: &mut Connection,
-    consumer_name: &str,
-    engine: &mut MatchingEngine,
-    view_emitter: &mut ViewEmitter,
-) -> EngineResult<()> {
-    info!("Attempting to reclaim pending messages");
-
-    let reclaimed: RedisValue = redis::cmd("XAUTOCLAIM")
-        .arg(STREAM_KEY)
-        .arg(GROUP)
-        .arg(consumer_name)
-        .arg(AUTOCLAIM_IDLE_TIME_MS)
-        .arg("0-0")
-        .query_async(conn)
-        .await
-        .map_err(|e| {
-            EngineError::StreamProcessing(format!("Failed to autoclaim messages: {}", e))
-        })?;
+/// Hands queries to the core through the command queue.
+async fn forward_queries(mut queries: Receiver<QueryRequest>, commands: Sender<CoreCommand>) {
+    while let Some(request) = queries.recv().await {
+        if commands.send(CoreCommand::Query(request)).await.is_err() {
+            return;
+        }
+    }
+}
 
-    process_stream_reply(conn, engine, STREAM_KEY, GROUP, reclaimed, view_emitter).await?;
+/// Queues a `command_type` command on the stream of every market whose
+/// deadline, as answered to `query`, has passed.
+///
+/// Drives the time-based commands: `heartbeat.sweep` for overdue
+/// cancel-on-disconnect deadlines, see [`crate::engine::heartbeat`],
+/// `batch.clear` for batches that are over, see [`crate::engine::batch`],
+/// and `auction.refresh` for indicatives due again, see
+/// [`crate::engine::auction`].
+/// They go through the stream like any other command, so they are ledgered
+/// and replay the same. Each deadline is queued once; a command applied in
+/// the meantime that moves the deadline just turns it into a no-op.
+async fn queue_when_due(
+    mut conn: Connection,
+    commands: Sender<CoreCommand>,
+    query: EngineQuery,
+    command_type: &'static str,
+    period: Duration,
+) {
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut queued: BTreeMap<u32, i64> = BTreeMap::new();
+    loop {
+        interval.tick().await;
+        let (reply, answer) = oneshot::channel();
+        let request = QueryRequest {
+            query: query.clone(),
+            reply,
+        };
+        if commands.send(CoreCommand::Query(request)).await.is_err() {
+            return;
+        }
+        let Ok(Some(answer)) = answer.await else {
+            continue;
+        };
+        let deadlines: BTreeMap<u32, i64> = serde_json::from_value(answer).unwrap_or_default();
+        queued.retain(|market_id, _| deadlines.contains_key(market_id));
+        let now = chrono::Utc::now().timestamp_millis();
+        for (market_id, deadline) in deadlines {
+            if deadline >= now || queued.get(&market_id) == Some(&deadline) {
+                continue;
+            }
+            let added: redis::RedisResult<String> = conn
+                .xadd(
+                    command_stream(market_id),
+                    "*",
+                    &[
+                        ("type", command_type.to_string()),
+                        ("market_id", market_id.to_string()),
+                    ],
+                )
+                .await;
+            match added {
+                Ok(_) => {
+                    queued.insert(market_id, deadline);
+                }
+                Err(e) => warn!(
+                    "Failed to queue {} of market {}: {}",
+                    command_type, market_id, e
+                ),
+            }
+        }
+    }
+}
+
+/// Finishes the work in flight after reading stopped.
+///
+/// The core applies every command already queued, the publisher writes and
+/// ACKs all their outputs, then every market the engine holds is
+/// snapshotted so the next owner does not replay its ledger from scratch.
+/// The lease is released last, letting a standby take over right away.
+async fn drain(
+    ingest: Ingest,
+    core: thread::JoinHandle<MatchingEngine>,
+    publisher: JoinHandle<()>,
+    lease: Lease,
+) -> EngineResult<MatchingEngine> {
+    let Ingest {
+        mut conn,
+        fence,
+        commands,
+        ..
+    } = ingest;
+    let engine = finish_in_flight(commands, core, publisher).await?;
 
-    info!("Pending message reclaim completed");
-    Ok(())
+    let snapshots: Vec<_> = engine
+        .markets()
+        .into_iter()
+        .map(|market_id| engine.snapshot_market(market_id))
+        .collect();
+    match save_snapshots(&mut conn, &fence, &snapshots).await {
+        Ok(()) => info!("Saved final snapshots of {} markets", snapshots.len()),
+        // The ledgers still hold everything, the next owner just replays more
+        Err(e) => error!("Failed to save final snapshots: {}", e),
+    }
+    if let Err(e) = lease.release(&mut conn).await {
+        warn!("Failed to release lease: {}", e);
+    }
+    Ok(engine)
 }
 
-/// Process a single batch of messages from the stream
-async fn process_stream_batch(
-    conn: &mut Connection,
-    consumer_name: &str,
-    engine: &mut MatchingEngine,
-    view_emitter: &mut ViewEmitter,
-) -> EngineResult<()> {
-    let reply: RedisValue = redis::cmd("XREADGROUP")
-        .arg("GROUP")
-        .arg(GROUP)
-        .arg(consumer_name)
-        .arg("BLOCK")
-        .arg(BLOCK_TIMEOUT_MS)
-        .arg("COUNT")
-        .arg(BATCH_SIZE)
-        .arg("STREAMS")
-        .arg(STREAM_KEY)
-        .arg(">")
-        .query_async(conn)
+/// Closes the command queue and waits for the core to apply what is left in
+/// it, then for the publisher to write all of their outputs.
+///
+/// Returns the engine only once nothing is in flight any more, so that what
+/// is snapshotted afterwards has all been published.
+async fn finish_in_flight(
+    commands: Sender<CoreCommand>,
+    core: thread::JoinHandle<MatchingEngine>,
+    publisher: JoinHandle<()>,
+) -> EngineResult<MatchingEngine> {
+    // Closing the queue lets the core stop once it is empty, which in turn
+    // closes the publisher's queue
+    drop(commands);
+    let engine = tokio::task::spawn_blocking(move || core.join())
+        .await
+        .map_err(|e| EngineError::Internal(format!("Failed to join matching core: {}", e)))?
+        .map_err(|_| EngineError::Internal("Matching core panicked".to_string()))?;
+    publisher
         .await
-        .map_err(|e| EngineError::StreamProcessing(format!("Failed to read from stream: {}", e)))?;
+        .map_err(|e| EngineError::Internal(format!("Failed to join publisher: {}", e)))?;
+    Ok(engine)
+}
 
-    process_stream_reply(conn, engine, STREAM_KEY, GROUP, reply, view_emitter).await
+/// Reads the command streams of the owned markets and feeds the core.
+struct Ingest {
+    conn: Connection,
+    engine_id: String,
+    config: StreamConfig,
+    fence: Fence,
+    commands: Sender<CoreCommand>,
+    /// Markets the engine already caught up with while following
+    restored: BTreeSet<u32>,
+    owned: BTreeSet<u32>,
+    /// Markets with a handoff in flight. Their stream is no longer read and
+    /// the entries after the handoff stay pending for the next owner.
+    draining: BTreeSet<u32>,
+    last_refresh: Option<Instant>,
+    last_retry: Instant,
 }
 
-/// Process the reply from XREADGROUP or XAUTOCLAIM
-pub async fn process_stream_reply(
-    conn: &mut Connection,
-    engine: &mut MatchingEngine,
-    stream_key: &str,
-    group: &str,
-    reply: RedisValue,
-    view_emitter: &mut ViewEmitter,
-) -> EngineResult<()> {
-    let RedisValue::Bulk(streams) = reply else {
-        // Empty response (timeout), not an error
-        return Ok(());
-    };
+impl Ingest {
+    async fn run_once(&mut self) -> EngineResult<()> {
+        if self
+            .last_refresh
+            .is_none_or(|at| at.elapsed() >= SHARD_REFRESH_INTERVAL)
+        {
+            self.refresh_shards().await?;
+            self.last_refresh = Some(Instant::now());
+        }
+        if self.last_retry.elapsed() >= PENDING_RETRY_IDLE {
+            self.last_retry = Instant::now();
+            let markets: Vec<u32> = self.owned.difference(&self.draining).copied().collect();
+            for market_id in markets {
+                self.reclaim_pending_messages(
+                    market_id,
+                    PENDING_RETRY_IDLE,
+                    &BTreeSet::new(),
+                    false,
+                )
+                .await?;
+            }
+        }
+        self.process_stream_batch().await
+    }
 
-    for stream in streams {
-        let RedisValue::Bulk(items) = stream else {
-            warn!("Unexpected stream format, skipping");
-            continue;
-        };
+    /// Adopts newly assigned markets and forgets the ones handed away.
+    async fn refresh_shards(&mut self) -> EngineResult<()> {
+        let assigned = assigned_markets(
+            &mut self.conn,
+            &self.engine_id,
+            self.config.claim_unassigned,
+        )
+        .await?;
+        let lost: Vec<u32> = self.owned.difference(&assigned).copied().collect();
+        for market_id in lost {
+            info!("Market {} is no longer assigned to this engine", market_id);
+            self.owned.remove(&market_id);
+            self.draining.remove(&market_id);
+        }
+        let gained: Vec<u32> = assigned.difference(&self.owned).copied().collect();
+        for market_id in gained {
+            self.adopt(market_id).await?;
+        }
+        self.observe_pending().await
+    }
 
-        if items.len() < 2 {
-            warn!("Stream items has insufficient data, skipping");
-            continue;
+    /// Records how many entries of each owned stream are pending.
+    async fn observe_pending(&mut self) -> EngineResult<()> {
+        let markets: Vec<u32> = self.owned.iter().copied().collect();
+        let mut pipe = redis::pipe();
+        for market_id in &markets {
+            pipe.xpending(command_stream(*market_id), CONSUMER_GROUP);
         }
+        let replies: Vec<StreamPendingReply> = pipe.query_async(&mut self.conn).await?;
+        METRICS.observe_stream_pending(
+            markets
+                .into_iter()
+                .zip(replies)
+                .map(|(market_id, reply)| (market_id, reply.count() as u64))
+                .collect::<BTreeMap<_, _>>(),
+        );
+        Ok(())
+    }
 
-        let RedisValue::Bulk(entries) = &items[1] else {
-            warn!("Entries is not a bulk type, skipping");
-            continue;
-        };
+    /// Restores a market from its snapshot and ledger, then takes over the
+    /// entries the previous owner left pending on its stream.
+    ///
+    /// Pending entries already in the ledger were applied, but the
+    /// publisher writes outputs and ACKs together, so their outputs never
+    /// went out. The core applies them with outputs while replaying the
+    /// ledger, and they are published and ACKed like live commands. A
+    /// market a follower already caught up with replayed them silently, so
+    /// it is restored again.
+    async fn adopt(&mut self, market_id: u32) -> EngineResult<()> {
+        let stream_key = command_stream(market_id);
+        // Create consumer group (ignore error if already exists)
+        let _: Result<(), redis::RedisError> = redis::cmd("XGROUP")
+            .arg("CREATE")
+            .arg(&stream_key)
+            .arg(CONSUMER_GROUP)
+            .arg("0")
+            .arg("MKSTREAM")
+            .query_async(&mut self.conn)
+            .await;
 
-        for entry in entries {
-            if let Err(e) =
-                process_single_entry(conn, engine, stream_key, group, entry, view_emitter).await
-            {
-                // Log the error but continue processing other entries
-                error!(
-                    "Failed to process entry (severity: {}): {}",
-                    e.severity(),
-                    e
-                );
+        let applied = last_ledgered_entry(&mut self.conn, market_id)
+            .await?
+            .and_then(|id| parse_stream_id(&id));
+        let unpublished: BTreeMap<String, u64> = self
+            .pending_deliveries(&stream_key, Duration::ZERO)
+            .await?
+            .into_iter()
+            .filter(|(id, _)| is_ledgered(id, applied))
+            .collect();
+        if !unpublished.is_empty() && self.restored.remove(&market_id) {
+            info!(
+                "Market {} has {} applied entries never published, restoring it again",
+                market_id,
+                unpublished.len()
+            );
+        }
+        let mut regenerated = BTreeSet::new();
+        if !self.restored.remove(&market_id) {
+            let snapshot = load_snapshot(&mut self.conn, market_id).await?;
+            let after_id = snapshot.as_ref().and_then(|s| s.ledger_id.clone());
+            let ledger = read_ledger(&mut self.conn, market_id, after_id.as_deref()).await?;
+            info!(
+                "Adopting market {} (snapshot: {}, {} ledger entries to replay)",
+                market_id,
+                snapshot.is_some(),
+                ledger.len()
+            );
+            let mut to_publish = BTreeMap::new();
+            for entry in &ledger {
+                let Some(entry_id) = &entry.entry_id else {
+                    continue;
+                };
+                if let Some(attempts) = unpublished.get(entry_id) {
+                    regenerated.insert(entry_id.clone());
+                    to_publish.insert(entry.ledger_id.clone(), (entry_id.clone(), *attempts));
+                }
             }
+            self.send(CoreCommand::Adopt {
+                market_id,
+                snapshot: snapshot.map(Box::new),
+                ledger: ledger
+                    .into_iter()
+                    .map(|entry| (entry.ledger_id, entry.payload))
+                    .collect(),
+                unpublished: to_publish,
+            })
+            .await?;
         }
+        self.owned.insert(market_id);
+        self.reclaim_pending_messages(market_id, Duration::ZERO, &regenerated, true)
+            .await
     }
 
-    Ok(())
-}
+    /// Ids and deliveries of the entries pending on `stream_key` for at
+    /// least `min_idle`, whichever consumer they were delivered to.
+    async fn pending_deliveries(
+        &mut self,
+        stream_key: &str,
+        min_idle: Duration,
+    ) -> EngineResult<Vec<(String, u64)>> {
+        let mut pending = Vec::new();
+        let mut start = "-".to_string();
+        loop {
+            let page: StreamPendingCountReply = redis::cmd("XPENDING")
+                .arg(stream_key)
+                .arg(CONSUMER_GROUP)
+                .arg("IDLE")
+                .arg(min_idle.as_millis() as u64)
+                .arg(&start)
+                .arg("+")
+                .arg(BATCH_SIZE)
+                .query_async(&mut self.conn)
+                .await
+                .map_err(|e| {
+                    EngineError::StreamProcessing(format!("Failed to read pending messages: {}", e))
+                })?;
+            let read = page.ids.len();
+            if let Some(last) = page.ids.last() {
+                // Exclusive start for the next page
+                start = format!("({}", last.id);
+            }
+            pending.extend(
+                page.ids
+                    .into_iter()
+                    .map(|p| (p.id, p.times_delivered as u64)),
+            );
+            if read < BATCH_SIZE {
+                return Ok(pending);
+            }
+        }
+    }
 
-/// Process a single stream entry
-async fn process_single_entry(
-    conn: &mut Connection,
-    engine: &mut MatchingEngine,
-    stream_key: &str,
-    group: &str,
-    entry: &RedisValue,
-    view_emitter: &mut ViewEmitter,
-) -> EngineResult<()> {
-    let RedisValue::Bulk(pair) = entry else {
-        return Err(EngineError::InvalidMessage(
-            "Entry is not a bulk type".to_string(),
-        ));
-    };
+    /// Delivers again the entries pending on a market's stream for at least
+    /// `min_idle`, whichever consumer they were delivered to, except those
+    /// in `publishing`.
+    ///
+    /// Entries not in the ledger yet never reached a core and are forwarded
+    /// again. Entries in the ledger were applied, so they are never applied
+    /// twice. They are claimed to count their deliveries, and dead-lettered
+    /// as applied once their outputs are given up on:
+    /// - right away when `adopting`, since the previous owner is gone and
+    ///   the ledger tail replayed no longer covers them
+    /// - after `max_retries` deliveries besides the first otherwise, so
+    ///   commands still in flight here get time to be published
+    ///
+    /// Entries not in the ledger delivered more than `max_retries` times
+    /// besides the first are dead-lettered instead of forwarded.
+    async fn reclaim_pending_messages(
+        &mut self,
+        market_id: u32,
+        min_idle: Duration,
+        publishing: &BTreeSet<String>,
+        adopting: bool,
+    ) -> EngineResult<()> {
+        let stream_key = command_stream(market_id);
+        let applied = last_ledgered_entry(&mut self.conn, market_id)
+            .await?
+            .and_then(|id| parse_stream_id(&id));
+        let pending: Vec<(String, u64)> = self
+            .pending_deliveries(&stream_key, min_idle)
+            .await?
+            .into_iter()
+            .filter(|(id, _)| !publishing.contains(id))
+            .collect();
+        for page in pending.chunks(BATCH_SIZE) {
+            let ids: Vec<&str> = page.iter().map(|(id, _)| id.as_str()).collect();
+            let claimed: StreamClaimReply = self
+                .conn
+                .xclaim(
+                    &stream_key,
+                    CONSUMER_GROUP,
+                    &self.engine_id,
+                    min_idle.as_millis() as u64,
+                    &ids,
+                )
+                .await?;
+            let delivered: HashMap<&str, u64> = page
+                .iter()
+                .map(|(id, attempts)| (id.as_str(), *attempts))
+                .collect();
+            let mut dead_letters = Vec::new();
+            let mut entries = Vec::new();
+            let mut unpublished = 0;
+            for entry in claimed.ids {
+                let attempts = delivered.get(entry.id.as_str()).copied().unwrap_or(1);
+                let exhausted = attempts > self.config.max_retries;
+                if is_ledgered(&entry.id, applied) {
+                    if adopting || exhausted {
+                        let payload = entry_payload(&entry);
+                        dead_letters.push(DeadLetter::new(
+                            market_id,
+                            entry.id,
+                            payload,
+                            &EngineError::OutputsLost,
+                            attempts,
+                        ));
+                    } else {
+                        unpublished += 1;
+                    }
+                } else if exhausted {
+                    let error = EngineError::RetriesExhausted(attempts);
+                    let payload = entry_payload(&entry);
+                    dead_letters.push(DeadLetter::new(
+                        market_id, entry.id, payload, &error, attempts,
+                    ));
+                } else {
+                    entries.push((market_id, entry, attempts + 1));
+                }
+            }
+            if unpublished > 0 {
+                warn!(
+                    "{} applied messages from {} are still not published",
+                    unpublished, stream_key
+                );
+            }
+            if !dead_letters.is_empty() {
+                warn!(
+                    "Dead-lettering {} messages from {}",
+                    dead_letters.len(),
+                    stream_key
+                );
+                dead_letter(&mut self.conn, &self.fence, &dead_letters).await?;
+            }
+            if !entries.is_empty() {
+                info!(
+                    "Reclaimed {} pending messages from {}",
+                    entries.len(),
+                    stream_key
+                );
+                self.forward(entries).await?;
+            }
+        }
+        Ok(())
+    }
 
-    if pair.len() < 2 {
-        return Err(EngineError::InvalidMessage(
-            "Entry pair has insufficient data".to_string(),
-        ));
+    /// Process a single batch of messages from the owned streams
+    async fn process_stream_batch(&mut self) -> EngineResult<()> {
+        let keys: Vec<String> = self
+            .owned
+            .difference(&self.draining)
+            .map(|market_id| command_stream(*market_id))
+            .collect();
+        if keys.is_empty() {
+            tokio::time::sleep(Duration::from_millis(BLOCK_TIMEOUT_MS as u64)).await;
+            return Ok(());
+        }
+        let ids = vec![">"; keys.len()];
+        let options = StreamReadOptions::default()
+            .group(CONSUMER_GROUP, &self.engine_id)
+            .block(BLOCK_TIMEOUT_MS)
+            .count(BATCH_SIZE);
+        let reply: Option<StreamReadReply> = self
+            .conn
+            .xread_options(&keys, &ids, &options)
+            .await
+            .map_err(|e| {
+            EngineError::StreamProcessing(format!("Failed to read from stream: {}", e))
+        })?;
+        let Some(reply) = reply else {
+            // Empty response (timeout), not an error
+            return Ok(());
+        };
+        let mut entries = Vec::new();
+        for key in reply.keys {
+            let Some(market_id) = market_of_stream(&key.key) else {
+                warn!("Unexpected stream {}, skipping", key.key);
+                continue;
+            };
+            entries.extend(key.ids.into_iter().map(|entry| (market_id, entry, 1)));
+        }
+        self.forward(entries).await
     }
 
-    let id = match &pair[0] {
-        RedisValue::Data(bytes) => String::from_utf8_lossy(bytes).into_owned(),
-        _ => {
-            return Err(EngineError::InvalidMessage(
-                "Entry ID is not data type".to_string(),
-            ));
+    /// Ledgers stream entries and hands them to the matching core.
+    ///
+    /// The whole batch is stamped and appended to the ledgers in one round
+    /// trip before any of it is matched, so the ledger order is the matching
+    /// order. If the append fails nothing is forwarded and the entries stay
+    /// pending until they are retried.
+    ///
+    /// `entries` are `(market_id, entry, attempts)`.
+    async fn forward(&mut self, entries: Vec<(u32, StreamId, u64)>) -> EngineResult<()> {
+        let mut batch = Vec::new();
+        for (market_id, entry, attempts) in entries {
+            if self.draining.contains(&market_id) {
+                debug!(
+                    "Market {} is being handed off, leaving {} pending",
+                    market_id, entry.id
+                );
+                continue;
+            }
+            let payload = stamp(entry_payload(&entry));
+            if self.is_handoff_away(&payload) {
+                self.draining.insert(market_id);
+            }
+            batch.push((entry.id, market_id, payload, attempts));
         }
-    };
-    debug!("Processing message with ID: {}", id);
 
-    let mut map = serde_json::Map::new();
-    if let RedisValue::Bulk(kvs) = &pair[1] {
-        let mut iter = kvs.iter();
-        while let (Some(RedisValue::Data(k)), Some(RedisValue::Data(v))) =
-            (iter.next(), iter.next())
+        let ledgered: Vec<(u32, &str, &SerdeJsonValue)> = batch
+            .iter()
+            .map(|(entry_id, market_id, payload, _)| (*market_id, entry_id.as_str(), payload))
+            .collect();
+        let ledger_ids = append_events_to_ledger(&mut self.conn, &self.fence, &ledgered)
+            .await
+            .map_err(|e| match e {
+                EngineError::Fenced(_) => e,
+                e => EngineError::Ledger(format!("Failed to append to ledger: {}", e)),
+            })?;
+        for ((entry_id, market_id, payload, attempts), ledger_id) in
+            batch.into_iter().zip(ledger_ids)
         {
-            map.insert(
-                String::from_utf8_lossy(k).into_owned(),
-                SerdeJsonValue::String(String::from_utf8_lossy(v).into_owned()),
-            );
+            debug!("Forwarding message {} to matching core", entry_id);
+            self.send(CoreCommand::Apply {
+                entry_id,
+                market_id,
+                ledger_id,
+                payload,
+                attempts,
+            })
+            .await?;
         }
+        Ok(())
     }
 
-    let payload = SerdeJsonValue::Object(map);
+    /// Whether `payload` hands its market to another engine.
+    fn is_handoff_away(&self, payload: &SerdeJsonValue) -> bool {
+        if payload.get("type").and_then(|v| v.as_str()) != Some("market.handoff") {
+            return false;
+        }
+        serde_json::from_value::<MarketHandoffWire>(payload.clone()).is_ok_and(|wire| {
+            wire.market_id.parse::<u32>().is_ok() && wire.target_engine != self.engine_id
+        })
+    }
 
-    match handle_message(conn, engine, &payload, view_emitter).await {
-        Ok(_) => {
-            // Successfully processed, acknowledge the message
-            let _: Result<(), redis::RedisError> = redis::cmd("XACK")
-                .arg(stream_key)
-                .arg(group)
-                .arg(&id)
-                .query_async(conn)
-                .await;
+    async fn send(&self, command: CoreCommand) -> EngineResult<()> {
+        self.commands
+            .send(command)
+            .await
+            .map_err(|_| EngineError::Internal("Matching core stopped".to_string()))
+    }
+}
+
+/// `(millis, sequence)` of a stream entry id, which orders like the stream.
+fn parse_stream_id(id: &str) -> Option<(u64, u64)> {
+    let (millis, sequence) = id.split_once('-')?;
+    Some((millis.parse().ok()?, sequence.parse().ok()?))
+}
+
+/// Whether the stream entry `id` is at or before `applied`, the last entry
+/// in its market's ledger, and so was applied.
+fn is_ledgered(id: &str, applied: Option<(u64, u64)>) -> bool {
+    applied.is_some_and(|applied| parse_stream_id(id).is_some_and(|id| id <= applied))
+}
+
+/// The field map of a stream entry as a JSON object of strings
+fn entry_payload(entry: &StreamId) -> SerdeJsonValue {
+    let map = entry
+        .map
+        .iter()
+        .filter_map(|(key, value)| match value {
+            RedisValue::Data(bytes) => Some((
+                key.clone(),
+                SerdeJsonValue::String(String::from_utf8_lossy(bytes).into_owned()),
+            )),
+            _ => None,
+        })
+        .collect();
+    SerdeJsonValue::Object(map)
+}
 
-            debug!("Message {} processed and acknowledged", id);
-            Ok(())
+/// Stamps the receive time on a live command.
+///
+/// The stamped payload is what gets ledgered, so replayed commands carry
+/// their original `ts` and time-dependent state such as the VWAP decay is
+/// rebuilt exactly as it was.
+fn stamp(mut payload: SerdeJsonValue) -> SerdeJsonValue {
+    if let Some(map) = payload.as_object_mut() {
+        map.entry("ts").or_insert_with(|| {
+            SerdeJsonValue::String(chrono::Utc::now().timestamp_millis().to_string())
+        });
+    }
+    payload
+}
+
+/// Writes the outputs of the matching core and ACKs the commands.
+///
+/// Results are drained in batches: the outputs of the whole batch and the
+/// `XACK`s of its entries go out in one `MULTI`/`EXEC`. A handed-off market
+/// is therefore never assigned to its new owner while commands it already
+/// applied are still pending. Commands that failed with a non-retryable error
+/// are dead-lettered in the same transaction. A failed write is retried with
+/// backoff instead of being dropped: the commands have already been applied,
+/// so leaving them pending would apply them twice on reclaim.
+async fn run_publisher(mut view_emitter: ViewEmitter, mut results: Receiver<CoreResult>) {
+    let mut batch = Vec::with_capacity(PUBLISH_BATCH_SIZE);
+    while results.recv_many(&mut batch, PUBLISH_BATCH_SIZE).await > 0 {
+        let mut acks = Vec::with_capacity(batch.len());
+        let mut outputs = Vec::new();
+        let mut dead_letters = Vec::new();
+        for result in batch.drain(..) {
+            match result.outcome {
+                Ok(result_outputs) => {
+                    debug!("Message {} processed", result.entry_id);
+                    outputs.extend(result_outputs);
+                    acks.push((result.market_id, result.entry_id));
+                }
+                // Check if error is retryable
+                Err(e) if e.is_retryable() => {
+                    warn!(
+                        "Message {} failed with retryable error, leaving pending: {}",
+                        result.entry_id, e
+                    );
+                }
+                Err(e) => {
+                    error!(
+                        "Message {} failed with non-retryable error: {}. Dead-lettering it",
+                        result.entry_id, e
+                    );
+                    dead_letters.push(DeadLetter::new(
+                        result.market_id,
+                        result.entry_id,
+                        result.payload,
+                        &e,
+                        result.attempts,
+                    ));
+                }
+            }
         }
-        Err(e) => {
-            // Check if error is retryable
-            if e.is_retryable() {
-                warn!(
-                    "Message {} failed with retryable error, leaving pending: {}",
-                    id, e
-                );
-                // Don't ACK - message will be retried
-            } else {
-                error!(
-                    "Message {} failed with non-retryable error: {}. ACKing to prevent reprocessing",
-                    id, e
-                );
-                // ACK to prevent infinite retries of bad messages
-                let _: Result<(), redis::RedisError> = redis::cmd("XACK")
-                    .arg(stream_key)
-                    .arg(group)
-                    .arg(&id)
-                    .query_async(conn)
-                    .await;
+        if let Err(e) = publish_with_retry(&mut view_emitter, &outputs, &acks, &dead_letters).await
+        {
+            error!("Stopping publisher: {}", e);
+            break;
+        }
+        for output in &outputs {
+            if let EngineOutput::Event(event) = output {
+                METRICS.record_event(event.kind());
             }
-            Err(e)
         }
+        debug!("Acknowledged {} messages", acks.len());
     }
+    info!("Publisher stopped");
 }
 
-/// Handle an individual message based on its type
-pub async fn handle_message(
-    redis_conn: &mut Connection,
-    engine: &mut MatchingEngine,
-    payload: &SerdeJsonValue,
+/// Retries until the batch is written or this engine is fenced off.
+async fn publish_with_retry(
     view_emitter: &mut ViewEmitter,
+    outputs: &[EngineOutput],
+    acks: &[(u32, String)],
+    dead_letters: &[DeadLetter],
 ) -> EngineResult<()> {
-    let msg_type = payload
-        .get("type")
-        .and_then(|v| v.as_str())
-        .ok_or_else(|| EngineError::MissingField("type".to_string()))?;
-    match msg_type {
-        "order.new" => handle_new_order(redis_conn, engine, payload, view_emitter).await,
-        _ => Err(EngineError::UnknownEventType(msg_type.to_string())),
+    let mut backoff = Duration::from_millis(50);
+    loop {
+        let e = match view_emitter
+            .publish_batch(outputs, acks, dead_letters)
+            .await
+        {
+            Ok(()) => return Ok(()),
+            Err(e @ EngineError::Fenced(_)) => {
+                METRICS.record_error(&e);
+                return Err(e);
+            }
+            Err(e) => e,
+        };
+        METRICS.record_error(&e);
+        error!(
+            "Failed to publish {} engine outputs, retrying in {:?}: {}",
+            outputs.len(),
+            backoff,
+            e
+        );
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(PUBLISH_RETRY_MAX_BACKOFF);
     }
 }
 
-/// Handle a new order message
-async fn handle_new_order(
-    redis_conn: &mut Connection,
-    engine: &mut MatchingEngine,
-    payload: &SerdeJsonValue,
-    view_emitter: &mut ViewEmitter,
-) -> EngineResult<()> {
-    if !view_emitter.is_replay_mode {
-        append_events_to_ledger(redis_conn, payload.clone())
-            .await
-            .map_err(|e| EngineError::Ledger(format!("Failed to append to ledger: {}", e)))?;
+/// Keeps the leader lease alive for the term of `fence`.
+///
+/// Losing it is only logged here: the successor's fencing token makes every
+/// later write of this engine fail, which stops the loop.
+async fn renew_lease(lease: Lease, mut conn: Connection, fence: Fence) {
+    let mut interval = tokio::time::interval(lease.ttl() / 3);
+    loop {
+        interval.tick().await;
+        match lease.renew(&mut conn, &fence).await {
+            Ok(true) => {}
+            Ok(false) => {
+                error!(
+                    "Lost the lease for term {} to another instance",
+                    fence.token()
+                );
+                return;
+            }
+            Err(e) => warn!("Failed to renew lease: {}", e),
+        }
     }
-    let wire =
-        serde_json::from_value::<OrderWire>(payload.clone()).map_err(|e| EngineError::Json(e))?;
-    let order = Order::try_from(wire)
-        .map_err(|e| EngineError::OrderValidation(format!("Order validation failed: {}", e)))?;
-    let (publish_events, orderbook, fair_prices_and_total_volumes) =
-        engine.order_execution(redis_conn, &order).await;
-    let book_depth = orderbook.depth(None);
-    if !view_emitter.is_replay_mode {
-        view_emitter
-            .emit_book_depth(&order.outcome_id, book_depth)
-            .await
-            .map_err(|e| EngineError::ViewEmission(format!("Failed to emit book depth: {}", e)))?;
-        view_emitter
-            .emit_market_data(&order.market_id, &fair_prices_and_total_volumes)
-            .await
-            .map_err(|e| EngineError::ViewEmission(format!("Failed to emit market data: {}", e)))?;
-        view_emitter
-            .emit_events(publish_events)
-            .await
-            .map_err(|e| EngineError::ViewEmission(format!("Failed to emit events: {}", e)))?;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stream_ids_order_numerically() {
+        assert_eq!(
+            parse_stream_id("1700000000000-12"),
+            Some((1_700_000_000_000, 12))
+        );
+        assert!(parse_stream_id("999-5") < parse_stream_id("1000-0"));
+        assert!(parse_stream_id("1000-2") < parse_stream_id("1000-10"));
+        assert_eq!(parse_stream_id("not-an-id"), None);
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn draining_publishes_everything_before_returning_the_engine() {
+        use serde_json::json;
+        use std::sync::{Arc, Mutex};
+
+        // A queue of one keeps results waiting on the publisher
+        let (command_tx, command_rx) = mpsc::channel(COMMAND_QUEUE_CAPACITY);
+        let (result_tx, mut result_rx) = mpsc::channel::<CoreResult>(1);
+        let core = spawn_matching_core(MatchingEngine::new(false), command_rx, result_tx).unwrap();
+        let published = Arc::new(Mutex::new(Vec::new()));
+        let (release, released) = oneshot::channel::<()>();
+        let publisher = tokio::spawn({
+            let published = published.clone();
+            async move {
+                let _ = released.await;
+                while let Some(result) = result_rx.recv().await {
+                    tokio::time::sleep(Duration::from_millis(2)).await;
+                    assert!(result.outcome.is_ok());
+                    published.lock().unwrap().push(result.entry_id);
+                }
+            }
+        });
+
+        let entry_ids: Vec<String> = (1..=20).map(|i| format!("{}-0", i)).collect();
+        for (i, entry_id) in entry_ids.iter().enumerate() {
+            let payload = json!({
+                "type": "order.new",
+                "outcome_id": "outcome-1",
+                "outcome_name": "Yes",
+                "market_id": "1",
+                "account_id": "1",
+                "side": "BUY",
+                "order_type": "LIMIT",
+                "price": (10 + i).to_string(),
+                "qty_remaining": "1",
+                "qty_original": "1",
+                "time_in_force": "GTC",
+                "ts": "1000",
+            });
+            command_tx
+                .send(CoreCommand::Apply {
+                    entry_id: entry_id.clone(),
+                    market_id: 1,
+                    ledger_id: entry_id.clone(),
+                    payload,
+                    attempts: 1,
+                })
+                .await
+                .unwrap();
+        }
+        assert!(published.lock().unwrap().is_empty());
+
+        release.send(()).unwrap();
+        let engine = finish_in_flight(command_tx, core, publisher).await.unwrap();
+        // Every command was applied, and its outputs written, before the
+        // engine came back to be snapshotted
+        assert_eq!(*published.lock().unwrap(), entry_ids);
+        assert_eq!(engine.books["outcome-1"].resting_orders(), entry_ids.len());
+        assert_eq!(engine.ledger_positions[&1], "20-0");
     }
-    Ok(())
 }
@@ -0,0 +1,151 @@
+use crate::orderbook::{Price, Quantity};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+/// Length of the rolling volume window.
+pub const ROLLING_WINDOW_MS: i64 = 24 * 60 * 60 * 1000;
+/// Trades are aggregated into buckets of this width inside the rolling window,
+/// which bounds memory to `ROLLING_WINDOW_MS / BUCKET_MS` entries per outcome.
+const BUCKET_MS: i64 = 60 * 1000;
+
+/// Traded notional (in price units), contracts and number of trades.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VolumeTotals {
+    pub notional: u64,
+    pub contracts: u64,
+    pub trades: u64,
+}
+
+impl VolumeTotals {
+    fn add_fill(&mut self, price: Price, quantity: Quantity) {
+        self.notional = self
+            .notional
+            .saturating_add(price.value().saturating_mul(quantity.value()));
+        self.contracts = self.contracts.saturating_add(quantity.value());
+        self.trades = self.trades.saturating_add(1);
+    }
+
+    fn subtract(&mut self, other: &VolumeTotals) {
+        self.notional = self.notional.saturating_sub(other.notional);
+        self.contracts = self.contracts.saturating_sub(other.contracts);
+        self.trades = self.trades.saturating_sub(other.trades);
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct VolumeBucket {
+    start: i64,
+    totals: VolumeTotals,
+}
+
+impl VolumeBucket {
+    /// Whether the bucket is out of the rolling window ending at `now`.
+    fn expired(&self, now: i64) -> bool {
+        self.start + BUCKET_MS <= now - ROLLING_WINDOW_MS
+    }
+}
+
+/// Lifetime and rolling 24h trading statistics of an outcome.
+///
+/// Fills are timestamped with the command `ts`, so replaying the ledger
+/// rebuilds exactly the same buckets. Buckets only leave the window when a
+/// fill is recorded; reads leave out the ones that have expired since.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OutcomeVolume {
+    total: VolumeTotals,
+    rolling: VolumeTotals,
+    buckets: VecDeque<VolumeBucket>,
+}
+
+impl OutcomeVolume {
+    /// Records one fill executed at `ts`.
+    pub fn record_fill(&mut self, price: Price, quantity: Quantity, ts: i64) {
+        if quantity.value() == 0 {
+            return;
+        }
+        self.total.add_fill(price, quantity);
+        self.expire(ts);
+        let start = ts - ts.rem_euclid(BUCKET_MS);
+        match self.buckets.back_mut() {
+            // Out-of-order timestamps land in the newest bucket
+            Some(bucket) if bucket.start >= start => bucket.totals.add_fill(price, quantity),
+            _ => {
+                let mut totals = VolumeTotals::default();
+                totals.add_fill(price, quantity);
+                self.buckets.push_back(VolumeBucket { start, totals });
+            }
+        }
+        self.rolling.add_fill(price, quantity);
+    }
+
+    /// Drops buckets that have fallen out of the rolling window ending at `now`.
+    fn expire(&mut self, now: i64) {
+        while let Some(bucket) = self.buckets.front() {
+            if !bucket.expired(now) {
+                break;
+            }
+            self.rolling.subtract(&bucket.totals);
+            self.buckets.pop_front();
+        }
+    }
+
+    /// Totals since the outcome's first trade.
+    pub fn total(&self) -> VolumeTotals {
+        self.total
+    }
+
+    /// Totals of the rolling window ending at `now`.
+    pub fn rolling(&self, now: i64) -> VolumeTotals {
+        let mut rolling = self.rolling;
+        for bucket in self.buckets.iter().take_while(|bucket| bucket.expired(now)) {
+            rolling.subtract(&bucket.totals);
+        }
+        rolling
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_notional_and_contracts_apart() {
+        let mut volume = OutcomeVolume::default();
+        volume.record_fill(Price(40), Quantity(10), 0);
+        volume.record_fill(Price(60), Quantity(5), 1);
+        assert_eq!(
+            volume.total(),
+            VolumeTotals {
+                notional: 700,
+                contracts: 15,
+                trades: 2,
+            }
+        );
+        assert_eq!(volume.rolling(1), volume.total());
+    }
+
+    #[test]
+    fn rolling_window_expires_old_buckets() {
+        let mut volume = OutcomeVolume::default();
+        volume.record_fill(Price(50), Quantity(10), 0);
+        volume.record_fill(Price(50), Quantity(2), ROLLING_WINDOW_MS / 2);
+
+        let later = VolumeTotals {
+            notional: 100,
+            contracts: 2,
+            trades: 1,
+        };
+        assert_eq!(volume.rolling(ROLLING_WINDOW_MS + BUCKET_MS), later);
+        assert_eq!(volume.total().contracts, 12);
+        assert_eq!(
+            volume.rolling(2 * ROLLING_WINDOW_MS),
+            VolumeTotals::default()
+        );
+
+        // Reading does not expire anything; the next fill does
+        assert_eq!(volume.buckets.len(), 2);
+        volume.record_fill(Price(50), Quantity(1), ROLLING_WINDOW_MS + BUCKET_MS);
+        assert_eq!(volume.buckets.len(), 2);
+        assert_eq!(volume.rolling(ROLLING_WINDOW_MS + BUCKET_MS).contracts, 3);
+    }
+}
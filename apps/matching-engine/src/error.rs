@@ -35,6 +35,15 @@ pub enum EngineError {
     ViewEmission(String),
     #[error("Unknown event type: {0}")]
     UnknownEventType(String),
+    #[error("Market {0} is not owned by this engine")]
+    MarketNotOwned(u32),
+    #[error("Fencing token {0} is stale, another engine took over")]
+    Fenced(u64),
+    #[error("Command was delivered {0} times without being processed")]
+    RetriesExhausted(u64),
+    /// The command is in the ledger, so it must not be re-driven
+    #[error("Command was applied but its outputs were never published")]
+    OutputsLost,
     #[error("Internal error: {0}")]
     Internal(String),
 }
@@ -57,6 +66,8 @@ impl EngineError {
             EngineError::StreamProcessing(_) => true,
             EngineError::ViewEmission(_) => true,
             EngineError::Ledger(_) => true,
+            // Left pending for the engine the market was handed off to
+            EngineError::MarketNotOwned(_) => true,
 
             // Validation and logic errors are not retryable
             EngineError::OrderValidation(_)
@@ -79,10 +90,13 @@ impl EngineError {
         match self {
             EngineError::Configuration(_) => ErrorSeverity::Critical,
             EngineError::Internal(_) => ErrorSeverity::Critical,
+            EngineError::Fenced(_) => ErrorSeverity::Critical,
 
             EngineError::OrderBook(_)
             | EngineError::OrderExecution { .. }
-            | EngineError::Ledger(_) => ErrorSeverity::High,
+            | EngineError::Ledger(_)
+            | EngineError::RetriesExhausted(_)
+            | EngineError::OutputsLost => ErrorSeverity::High,
 
             EngineError::Redis(_) | EngineError::StreamProcessing(_) => ErrorSeverity::Medium,
 
@@ -96,6 +110,31 @@ impl EngineError {
         }
     }
 
+    /// Stable name of the variant, used as a metrics label
+    pub fn kind(&self) -> &'static str {
+        match self {
+            EngineError::OrderBook(_) => "order_book",
+            EngineError::Redis(_) => "redis",
+            EngineError::Json(_) => "json",
+            EngineError::OrderValidation(_) => "order_validation",
+            EngineError::InvalidOrderType(_) => "invalid_order_type",
+            EngineError::MissingField(_) => "missing_field",
+            EngineError::InvalidMessage(_) => "invalid_message",
+            EngineError::Ledger(_) => "ledger",
+            EngineError::StreamProcessing(_) => "stream_processing",
+            EngineError::Configuration(_) => "configuration",
+            EngineError::OrderExecution { .. } => "order_execution",
+            EngineError::Snapshot(_) => "snapshot",
+            EngineError::ViewEmission(_) => "view_emission",
+            EngineError::UnknownEventType(_) => "unknown_event_type",
+            EngineError::MarketNotOwned(_) => "market_not_owned",
+            EngineError::Fenced(_) => "fenced",
+            EngineError::RetriesExhausted(_) => "retries_exhausted",
+            EngineError::OutputsLost => "outputs_lost",
+            EngineError::Internal(_) => "internal",
+        }
+    }
+
     /// Convert to a user-facing error message
     pub fn user_message(&self) -> String {
         match self {
@@ -122,7 +161,7 @@ impl EngineError {
 }
 
 /// Error severity levels for logging and monitoring
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub enum ErrorSeverity {
     Low,      // Expected errors, validation failures
     Medium,   // Temporary failures, network issues
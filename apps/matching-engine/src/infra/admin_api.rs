@@ -0,0 +1,132 @@
+//! Read-only HTTP/JSON API over the live engine.
+//!
+//! Routes:
+//! - `GET /stats`
+//! - `GET /books/{outcome_id}/depth[?limit=N]`
+//! - `GET /books/{outcome_id}/quote`: best bid/ask, mid and spread
+//! - `GET /books/{outcome_id}/orders/{order_id}`
+//! - `GET /books/{outcome_id}/levels/{buy|sell}/{price}`: orders at a price
+//! - `GET /accounts/{account_id}/orders`: resting orders across all books
+//!
+//! Every answer comes from the matching core, see [`crate::engine::query`].
+
+use crate::{
+    engine::query::{EngineQuery, QueryHandle},
+    infra::http::{Request, Response},
+    orderbook::{OrderId, Price, Side, order::AccountId},
+};
+use std::str::FromStr;
+use tracing::warn;
+
+/// Answers `request`, see the module docs for the routes.
+pub async fn route(queries: &QueryHandle, request: &Request) -> Response {
+    let query = match parse_query(request) {
+        Ok(Some(query)) => query,
+        Ok(None) => return Response::not_found(),
+        Err(message) => return Response::error(400, &message),
+    };
+    match queries.ask(query).await {
+        Ok(Some(answer)) => Response::json(&answer),
+        Ok(None) => Response::not_found(),
+        Err(e) => {
+            warn!("Admin query {} failed: {}", request.path, e);
+            Response::error(503, &e.to_string())
+        }
+    }
+}
+
+/// The query a request asks for, `None` if no route matches.
+fn parse_query(request: &Request) -> Result<Option<EngineQuery>, String> {
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    let query = match segments.as_slice() {
+        ["stats"] => EngineQuery::Stats,
+        ["books", outcome_id, "depth"] => EngineQuery::Depth {
+            outcome_id: outcome_id.to_string(),
+            limit: query_param(request, "limit")
+                .map(|limit| parse(limit, "limit"))
+                .transpose()?,
+        },
+        ["books", outcome_id, "quote"] => EngineQuery::Quote {
+            outcome_id: outcome_id.to_string(),
+        },
+        ["books", outcome_id, "orders", order_id] => EngineQuery::Order {
+            outcome_id: outcome_id.to_string(),
+            order_id: OrderId(parse(order_id, "order id")?),
+        },
+        ["books", outcome_id, "levels", side, price] => EngineQuery::OrdersAtPrice {
+            outcome_id: outcome_id.to_string(),
+            side: match *side {
+                "buy" => Side::Buy,
+                "sell" => Side::Sell,
+                _ => return Err(format!("Invalid side '{}', must be buy or sell", side)),
+            },
+            price: Price(parse(price, "price")?),
+        },
+        ["accounts", account_id, "orders"] => EngineQuery::AccountOrders {
+            account_id: AccountId(parse(account_id, "account id")?),
+        },
+        _ => return Ok(None),
+    };
+    Ok(Some(query))
+}
+
+fn query_param<'a>(request: &'a Request, name: &str) -> Option<&'a str> {
+    request
+        .query
+        .as_deref()?
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find_map(|(key, value)| (key == name).then_some(value))
+}
+
+fn parse<T: FromStr>(value: &str, what: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid {} '{}'", what, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(target: &str) -> Request {
+        let (path, query) = match target.split_once('?') {
+            Some((path, query)) => (path, Some(query.to_string())),
+            None => (target, None),
+        };
+        Request {
+            method: "GET".to_string(),
+            path: path.to_string(),
+            query,
+        }
+    }
+
+    #[test]
+    fn parses_routes() {
+        assert_eq!(parse_query(&get("/stats")), Ok(Some(EngineQuery::Stats)));
+        assert_eq!(
+            parse_query(&get("/books/o-1/depth?limit=5")),
+            Ok(Some(EngineQuery::Depth {
+                outcome_id: "o-1".to_string(),
+                limit: Some(5),
+            }))
+        );
+        assert_eq!(
+            parse_query(&get("/books/o-1/levels/sell/60")),
+            Ok(Some(EngineQuery::OrdersAtPrice {
+                outcome_id: "o-1".to_string(),
+                side: Side::Sell,
+                price: Price(60),
+            }))
+        );
+        assert_eq!(
+            parse_query(&get("/accounts/7/orders")),
+            Ok(Some(EngineQuery::AccountOrders {
+                account_id: AccountId(7),
+            }))
+        );
+        assert!(parse_query(&get("/books/o-1/orders/abc")).is_err());
+        assert!(parse_query(&get("/books/o-1/levels/up/60")).is_err());
+        assert_eq!(parse_query(&get("/books/o-1")), Ok(None));
+    }
+}
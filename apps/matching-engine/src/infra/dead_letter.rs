@@ -0,0 +1,203 @@
+//! Dead-letter stream for commands the engine gives up on.
+//!
+//! A command lands in `orders.commands.dlq` when applying it failed with a
+//! non-retryable error, or when it was delivered more than the configured
+//! number of times without being processed. The entry keeps the original
+//! payload, so it can be re-driven to its market's stream once the cause is
+//! fixed. Commands already in their market's ledger were applied, and
+//! only their outputs were lost; they are marked `applied` and never
+//! re-driven.
+
+use crate::{
+    error::{EngineError, EngineResult, ErrorSeverity},
+    infra::{
+        lease::Fence,
+        shard_map::{CONSUMER_GROUP, command_stream},
+    },
+};
+use redis::{AsyncCommands, streams::StreamRangeReply};
+use serde_json::Value;
+
+pub const DLQ_STREAM: &str = "orders.commands.dlq";
+
+/// A command taken off its market's stream for good.
+#[derive(Debug, Clone)]
+pub struct DeadLetter {
+    pub market_id: u32,
+    pub entry_id: String,
+    pub payload: Value,
+    pub error: String,
+    pub severity: ErrorSeverity,
+    /// Deliveries of the command, the failed one included
+    pub attempts: u64,
+    /// Whether the command is in its market's ledger
+    pub applied: bool,
+}
+
+impl DeadLetter {
+    pub fn new(
+        market_id: u32,
+        entry_id: String,
+        payload: Value,
+        error: &EngineError,
+        attempts: u64,
+    ) -> Self {
+        Self {
+            market_id,
+            entry_id,
+            payload,
+            error: error.to_string(),
+            severity: error.severity(),
+            attempts,
+            applied: matches!(error, EngineError::OutputsLost),
+        }
+    }
+
+    /// Adds the `XADD` to the dead-letter stream and the `XACK` of the
+    /// original entry to `pipe`.
+    pub fn queue(&self, pipe: &mut redis::Pipeline) -> EngineResult<()> {
+        pipe.xadd(
+            DLQ_STREAM,
+            "*",
+            &[
+                ("market_id", self.market_id.to_string()),
+                ("entry_id", self.entry_id.clone()),
+                ("payload", serde_json::to_string(&self.payload)?),
+                ("error", self.error.clone()),
+                ("severity", self.severity.to_string()),
+                ("attempts", self.attempts.to_string()),
+                ("applied", self.applied.to_string()),
+            ],
+        )
+        .ignore()
+        .xack(
+            command_stream(self.market_id),
+            CONSUMER_GROUP,
+            &[&self.entry_id],
+        )
+        .ignore();
+        Ok(())
+    }
+}
+
+/// Dead-letters `letters` in one `MULTI`/`EXEC`, if `fence` is still current.
+pub async fn dead_letter(
+    redis: &mut redis::aio::Connection,
+    fence: &Fence,
+    letters: &[DeadLetter],
+) -> EngineResult<()> {
+    if letters.is_empty() {
+        return Ok(());
+    }
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    for letter in letters {
+        letter.queue(&mut pipe)?;
+    }
+    fence.query(redis, &pipe).await
+}
+
+/// Up to `count` dead-lettered entries, oldest first, as `(id, fields)`.
+pub async fn list_dead_letters(
+    redis: &mut redis::aio::Connection,
+    count: usize,
+) -> EngineResult<Vec<(String, Vec<(String, String)>)>> {
+    let reply: StreamRangeReply = redis.xrange_count(DLQ_STREAM, "-", "+", count).await?;
+    Ok(reply
+        .ids
+        .into_iter()
+        .map(|entry| {
+            let mut fields: Vec<(String, String)> = entry
+                .map
+                .keys()
+                .filter_map(|key| Some((key.clone(), entry.get::<String>(key)?)))
+                .collect();
+            fields.sort();
+            (entry.id, fields)
+        })
+        .collect())
+}
+
+/// Moves up to `count` dead-lettered commands, oldest first, back to the
+/// streams of their markets and returns how many were moved. Applied
+/// commands are left where they are.
+///
+/// Each command is appended as a new entry without its old `ts`, so it is
+/// stamped and ledgered again like any fresh command. The append and the
+/// `XDEL` from the dead-letter stream run in one `MULTI`/`EXEC`.
+pub async fn redrive_dead_letters(
+    redis: &mut redis::aio::Connection,
+    count: usize,
+) -> EngineResult<usize> {
+    let mut moved = 0;
+    let mut start = "-".to_string();
+    while moved < count {
+        let reply: StreamRangeReply = redis
+            .xrange_count(DLQ_STREAM, &start, "+", count - moved)
+            .await?;
+        let Some(last) = reply.ids.last() else {
+            break;
+        };
+        // Exclusive start for the next page, past applied commands
+        start = format!("({}", last.id);
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        let mut page = 0;
+        for entry in &reply.ids {
+            if entry.get::<String>("applied").as_deref() == Some("true") {
+                continue;
+            }
+            page += 1;
+            let market_id = entry
+                .get::<u32>("market_id")
+                .ok_or_else(|| EngineError::MissingField("dead letter market_id".to_string()))?;
+            let payload = entry
+                .get::<String>("payload")
+                .ok_or_else(|| EngineError::MissingField("dead letter payload".to_string()))?;
+            pipe.xadd(command_stream(market_id), "*", &redrive_fields(&payload)?)
+                .ignore()
+                .xdel(DLQ_STREAM, &[&entry.id])
+                .ignore();
+        }
+        if page > 0 {
+            let _: () = pipe.query_async(redis).await?;
+            moved += page;
+        }
+    }
+    Ok(moved)
+}
+
+/// Stream fields of a dead-lettered payload, minus the receive stamp.
+fn redrive_fields(payload: &str) -> EngineResult<Vec<(String, String)>> {
+    let Value::Object(map) = serde_json::from_str(payload)? else {
+        return Err(EngineError::InvalidMessage(
+            "dead letter payload is not an object".to_string(),
+        ));
+    };
+    Ok(map
+        .into_iter()
+        .filter(|(key, _)| key != "ts")
+        .map(|(key, value)| match value {
+            Value::String(value) => (key, value),
+            value => (key, value.to_string()),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redrive_drops_the_receive_stamp() {
+        let fields = redrive_fields(r#"{"type":"order.new","market_id":"7","ts":"1000"}"#).unwrap();
+        assert_eq!(
+            fields,
+            vec![
+                ("market_id".to_string(), "7".to_string()),
+                ("type".to_string(), "order.new".to_string()),
+            ]
+        );
+        assert!(redrive_fields("[]").is_err());
+    }
+}
@@ -0,0 +1,212 @@
+//! A minimal HTTP/1.1 server for the engine's operational endpoints.
+//!
+//! Only what scrapers and operators need: `GET` requests without a body,
+//! one request per connection. Matching never waits on it; handlers run on
+//! the tokio runtime.
+
+use std::{future::Future, net::SocketAddr, time::Duration};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::{debug, info, warn};
+
+/// Largest request head accepted.
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+/// How long a client gets to send its request.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    /// Raw query string, without the `?`
+    pub query: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+impl Response {
+    pub fn text(body: String) -> Self {
+        Self {
+            status: 200,
+            content_type: "text/plain; charset=utf-8",
+            body,
+        }
+    }
+
+    pub fn json(body: &serde_json::Value) -> Self {
+        Self {
+            status: 200,
+            content_type: "application/json",
+            body: body.to_string(),
+        }
+    }
+
+    pub fn not_found() -> Self {
+        Self::error(404, "not found")
+    }
+
+    pub fn error(status: u16, message: &str) -> Self {
+        Self {
+            status,
+            content_type: "text/plain; charset=utf-8",
+            body: format!("{}\n", message),
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            503 => "Service Unavailable",
+            _ => "Internal Server Error",
+        }
+    }
+}
+
+/// Serves `handler` on `listener` until the task is dropped.
+pub async fn serve<H, F>(listener: TcpListener, handler: H)
+where
+    H: Fn(Request) -> F + Clone + Send + 'static,
+    F: Future<Output = Response> + Send,
+{
+    if let Ok(addr) = listener.local_addr() {
+        info!("HTTP server listening on {}", addr);
+    }
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Failed to accept HTTP connection: {}", e);
+                continue;
+            }
+        };
+        let handler = handler.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, peer, handler).await {
+                debug!("HTTP connection from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+async fn handle_connection<H, F>(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    handler: H,
+) -> std::io::Result<()>
+where
+    H: Fn(Request) -> F,
+    F: Future<Output = Response>,
+{
+    let response = match tokio::time::timeout(READ_TIMEOUT, read_head(&mut stream)).await {
+        Ok(Ok(head)) => match parse_request(&head) {
+            Some(request) if request.method == "GET" => {
+                debug!("{} {} from {}", request.method, request.path, peer);
+                handler(request).await
+            }
+            Some(_) => Response::error(405, "method not allowed"),
+            None => Response::error(400, "bad request"),
+        },
+        Ok(Err(e)) => return Err(e),
+        Err(_) => return Ok(()),
+    };
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.reason(),
+        response.content_type,
+        response.body.len()
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(response.body.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Reads up to the blank line ending the request head.
+async fn read_head(stream: &mut TcpStream) -> std::io::Result<String> {
+    let mut head = Vec::new();
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        if head.len() > MAX_REQUEST_BYTES {
+            return Err(std::io::Error::other("request head too large"));
+        }
+        let read = stream.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        head.extend_from_slice(&buf[..read]);
+    }
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+fn parse_request(head: &str) -> Option<Request> {
+    let mut parts = head.lines().next()?.split_whitespace();
+    let method = parts.next()?.to_string();
+    let target = parts.next()?;
+    if !parts.next()?.starts_with("HTTP/") {
+        return None;
+    }
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path, Some(query.to_string())),
+        None => (target, None),
+    };
+    Some(Request {
+        method,
+        path: path.to_string(),
+        query,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_request_line() {
+        assert_eq!(
+            parse_request("GET /metrics?x=1 HTTP/1.1\r\nHost: a\r\n\r\n"),
+            Some(Request {
+                method: "GET".to_string(),
+                path: "/metrics".to_string(),
+                query: Some("x=1".to_string()),
+            })
+        );
+        assert_eq!(parse_request("GET /metrics\r\n\r\n"), None);
+        assert_eq!(parse_request(""), None);
+    }
+
+    #[tokio::test]
+    async fn serves_handler_responses() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(serve(listener, |request: Request| async move {
+            match request.path.as_str() {
+                "/metrics" => Response::text("up 1\n".to_string()),
+                _ => Response::not_found(),
+            }
+        }));
+
+        for (path, expected) in [
+            ("/metrics", "HTTP/1.1 200 OK"),
+            ("/nope", "HTTP/1.1 404 Not Found"),
+        ] {
+            let mut stream = TcpStream::connect(addr).await.unwrap();
+            stream
+                .write_all(format!("GET {} HTTP/1.1\r\n\r\n", path).as_bytes())
+                .await
+                .unwrap();
+            let mut reply = String::new();
+            stream.read_to_string(&mut reply).await.unwrap();
+            assert!(reply.starts_with(expected), "{}", reply);
+        }
+    }
+}
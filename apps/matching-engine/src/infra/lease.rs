@@ -0,0 +1,180 @@
+//! Leader lease and fencing for engines sharing an `ENGINE_ID`.
+//!
+//! Every instance started with the same `ENGINE_ID` competes for
+//! `engine.lease.{engine_id}`, a key set with a TTL and renewed by its
+//! holder. The holder is the leader and consumes the command streams; the
+//! others follow the ledgers until the lease expires.
+//!
+//! Taking the lease also increments `engine.fence.{engine_id}`. The new value
+//! is the leader's fencing token and every write to the ledgers and to
+//! `engine.events` checks it in the same `MULTI`/`EXEC`, so a leader that
+//! stalled past its lease cannot write once a successor took over.
+
+use crate::error::{EngineError, EngineResult};
+use redis::{AsyncCommands, FromRedisValue, Script};
+use std::time::Duration;
+
+/// Lease TTL used when `ENGINE_LEASE_TTL_MS` is not set.
+pub const DEFAULT_LEASE_TTL: Duration = Duration::from_secs(5);
+const LEASE_KEY_PREFIX: &str = "engine.lease.";
+const FENCE_KEY_PREFIX: &str = "engine.fence.";
+
+/// Takes the lease if nobody holds it and returns the new fencing token.
+const ACQUIRE_SCRIPT: &str = r"
+if redis.call('SET', KEYS[1], ARGV[1], 'NX', 'PX', ARGV[2]) then
+    return redis.call('INCR', KEYS[2])
+end
+return false
+";
+
+/// Extends the lease, or takes it back if it lapsed without a successor.
+const RENEW_SCRIPT: &str = r"
+local holder = redis.call('GET', KEYS[1])
+if holder == ARGV[1] then
+    return redis.call('PEXPIRE', KEYS[1], ARGV[2])
+end
+if not holder and redis.call('GET', KEYS[2]) == ARGV[3] then
+    redis.call('SET', KEYS[1], ARGV[1], 'PX', ARGV[2])
+    return 1
+end
+return 0
+";
+
+/// Drops the lease if it is still ours.
+const RELEASE_SCRIPT: &str = r"
+if redis.call('GET', KEYS[1]) == ARGV[1] then
+    return redis.call('DEL', KEYS[1])
+end
+return 0
+";
+
+pub fn lease_key(engine_id: &str) -> String {
+    format!("{}{}", LEASE_KEY_PREFIX, engine_id)
+}
+
+pub fn fence_key(engine_id: &str) -> String {
+    format!("{}{}", FENCE_KEY_PREFIX, engine_id)
+}
+
+/// One instance's claim on the leadership of an `ENGINE_ID`.
+#[derive(Debug, Clone)]
+pub struct Lease {
+    key: String,
+    fence_key: String,
+    /// Identifies this process as the holder
+    holder: String,
+    ttl: Duration,
+}
+
+impl Lease {
+    pub fn new(engine_id: &str, ttl: Duration) -> Self {
+        Self {
+            key: lease_key(engine_id),
+            fence_key: fence_key(engine_id),
+            holder: format!("{}-{:016x}", std::process::id(), rand::random::<u64>()),
+            ttl,
+        }
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Takes the lease if it is free, returning the fence of the new term.
+    pub async fn try_acquire(
+        &self,
+        redis: &mut redis::aio::Connection,
+    ) -> EngineResult<Option<Fence>> {
+        let token: Option<u64> = Script::new(ACQUIRE_SCRIPT)
+            .key(&self.key)
+            .key(&self.fence_key)
+            .arg(&self.holder)
+            .arg(self.ttl.as_millis() as u64)
+            .invoke_async(redis)
+            .await?;
+        Ok(token.map(|token| Fence {
+            key: self.fence_key.clone(),
+            token,
+        }))
+    }
+
+    /// Extends the lease for `fence`'s term. `false` means a successor took
+    /// over and this instance must stop leading.
+    pub async fn renew(
+        &self,
+        redis: &mut redis::aio::Connection,
+        fence: &Fence,
+    ) -> EngineResult<bool> {
+        let renewed: i64 = Script::new(RENEW_SCRIPT)
+            .key(&self.key)
+            .key(&self.fence_key)
+            .arg(&self.holder)
+            .arg(self.ttl.as_millis() as u64)
+            .arg(fence.token)
+            .invoke_async(redis)
+            .await?;
+        Ok(renewed == 1)
+    }
+
+    /// Gives the lease up so a follower can take over without waiting for
+    /// the TTL.
+    pub async fn release(&self, redis: &mut redis::aio::Connection) -> EngineResult<()> {
+        let _: i64 = Script::new(RELEASE_SCRIPT)
+            .key(&self.key)
+            .arg(&self.holder)
+            .invoke_async(redis)
+            .await?;
+        Ok(())
+    }
+}
+
+/// The fencing token of a leadership term.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fence {
+    key: String,
+    token: u64,
+}
+
+impl Fence {
+    pub fn token(&self) -> u64 {
+        self.token
+    }
+
+    /// Runs `pipe`, which must be atomic, only if this term is still current.
+    ///
+    /// The fence key is `WATCH`ed before the check, so a successor taking
+    /// over between the check and the `EXEC` aborts the transaction too.
+    pub async fn query<T: FromRedisValue>(
+        &self,
+        redis: &mut redis::aio::Connection,
+        pipe: &redis::Pipeline,
+    ) -> EngineResult<T> {
+        let _: () = redis::cmd("WATCH")
+            .arg(&self.key)
+            .query_async(redis)
+            .await?;
+        let current: Option<u64> = redis.get(&self.key).await?;
+        if current != Some(self.token) {
+            let _: () = redis::cmd("UNWATCH").query_async(redis).await?;
+            return Err(EngineError::Fenced(self.token));
+        }
+        let result: Option<T> = pipe.query_async(redis).await?;
+        result.ok_or(EngineError::Fenced(self.token))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lease_keys_are_per_engine() {
+        let lease = Lease::new("engine-2", Duration::from_secs(5));
+        assert_eq!(lease.key, "engine.lease.engine-2");
+        assert_eq!(lease.fence_key, "engine.fence.engine-2");
+        assert_ne!(
+            lease.holder,
+            Lease::new("engine-2", Duration::from_secs(5)).holder
+        );
+    }
+}
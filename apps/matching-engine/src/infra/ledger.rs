@@ -1,17 +1,97 @@
-use crate::error::EngineResult;
-use redis::AsyncCommands;
+use crate::error::{EngineError, EngineResult};
+use crate::infra::lease::Fence;
+use crate::infra::shard_map::ledger_stream;
+use redis::{AsyncCommands, streams::StreamRangeReply};
 use serde_json::Value;
 
+/// Entries read per round trip when replaying a ledger.
+const READ_BATCH_SIZE: usize = 1000;
+
+/// A command as recorded in a market's ledger.
+#[derive(Debug, Clone)]
+pub struct LedgerEntry {
+    pub ledger_id: String,
+    /// Command stream entry the command was read from
+    pub entry_id: Option<String>,
+    pub payload: Value,
+}
+
+/// Appends a batch of `(market_id, entry_id, payload)` commands to their
+/// markets' ledgers in one `MULTI`/`EXEC`, so either the whole batch is
+/// recorded or none of it is. Nothing is written once `fence` is stale.
+///
+/// The command stream entry id is kept next to the payload, which tells a
+/// new leader which pending entries were already applied.
+///
+/// Returns the ledger entry id of every command, in order.
 pub async fn append_events_to_ledger(
     redis: &mut redis::aio::Connection,
-    payload: Value,
-) -> EngineResult<()> {
-    let _: String = redis
-        .xadd(
-            "engine.ledger",
+    fence: &Fence,
+    commands: &[(u32, &str, &Value)],
+) -> EngineResult<Vec<String>> {
+    if commands.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    for (market_id, entry_id, payload) in commands {
+        pipe.xadd(
+            ledger_stream(*market_id),
             "*",
-            &[("payload", serde_json::to_string(&payload)?)],
-        )
+            &[
+                ("payload", serde_json::to_string(payload)?),
+                ("entry_id", entry_id.to_string()),
+            ],
+        );
+    }
+    fence.query(redis, &pipe).await
+}
+
+/// Command stream entry id of the last command in `market_id`'s ledger.
+pub async fn last_ledgered_entry(
+    redis: &mut redis::aio::Connection,
+    market_id: u32,
+) -> EngineResult<Option<String>> {
+    let reply: StreamRangeReply = redis
+        .xrevrange_count(ledger_stream(market_id), "+", "-", 1)
         .await?;
-    Ok(())
+    Ok(reply
+        .ids
+        .first()
+        .and_then(|id| id.get::<String>("entry_id")))
+}
+
+/// Reads `market_id`'s ledger after `after_id`, or from the start.
+pub async fn read_ledger(
+    redis: &mut redis::aio::Connection,
+    market_id: u32,
+    after_id: Option<&str>,
+) -> EngineResult<Vec<LedgerEntry>> {
+    let key = ledger_stream(market_id);
+    let mut entries = Vec::new();
+    // Exclusive range start, so the entry the snapshot ends on is skipped
+    let mut start = match after_id {
+        Some(id) => format!("({}", id),
+        None => "-".to_string(),
+    };
+    loop {
+        let reply: StreamRangeReply = redis
+            .xrange_count(&key, &start, "+", READ_BATCH_SIZE)
+            .await?;
+        let read = reply.ids.len();
+        for id in reply.ids {
+            let payload = id.get::<String>("payload").ok_or_else(|| {
+                EngineError::MissingField("ledger entry missing payload".to_string())
+            })?;
+            start = format!("({}", id.id);
+            entries.push(LedgerEntry {
+                entry_id: id.get::<String>("entry_id"),
+                payload: serde_json::from_str(&payload)?,
+                ledger_id: id.id,
+            });
+        }
+        if read < READ_BATCH_SIZE {
+            return Ok(entries);
+        }
+    }
 }
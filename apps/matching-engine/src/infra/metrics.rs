@@ -0,0 +1,389 @@
+//! Process-wide metrics, rendered in the Prometheus text format.
+//!
+//! Everything is recorded into [`METRICS`]: the matching core, the ingest
+//! and publisher tasks and the follower all run on different threads, and a
+//! global registry keeps the instrumentation out of their signatures. The
+//! registry is served on `GET /metrics`, see [`crate::infra::http`].
+//!
+//! Counters only ever grow while the process runs; gauges are overwritten
+//! with the latest observation.
+
+use crate::{
+    engine::engine::MatchingEngine,
+    error::{EngineError, ErrorSeverity},
+    orderbook::{OrderBook, Side},
+};
+use once_cell::sync::Lazy;
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+pub static METRICS: Lazy<Metrics> = Lazy::new(Metrics::default);
+
+/// Upper bounds of the matching latency buckets, in seconds.
+const LATENCY_BUCKETS: [f64; 14] = [
+    0.000_001,
+    0.000_002_5,
+    0.000_005,
+    0.000_01,
+    0.000_025,
+    0.000_05,
+    0.000_1,
+    0.000_25,
+    0.000_5,
+    0.001,
+    0.002_5,
+    0.005,
+    0.01,
+    0.05,
+];
+
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// By `(type, result)`
+    commands: Mutex<BTreeMap<(String, &'static str), u64>>,
+    matching_latency: Histogram,
+    events: Mutex<BTreeMap<&'static str, u64>>,
+    errors: Mutex<BTreeMap<(&'static str, ErrorSeverity), u64>>,
+    books: Mutex<BTreeMap<String, BookGauges>>,
+    /// Entries delivered but not ACKed, by market
+    stream_pending: Mutex<BTreeMap<u32, u64>>,
+    replay_pending: AtomicU64,
+    replayed: AtomicU64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct BookGauges {
+    resting_orders: usize,
+    bid_levels: usize,
+    ask_levels: usize,
+}
+
+impl BookGauges {
+    fn of(book: &OrderBook) -> Self {
+        Self {
+            resting_orders: book.resting_orders(),
+            bid_levels: book.price_levels(Side::Buy),
+            ask_levels: book.price_levels(Side::Sell),
+        }
+    }
+}
+
+impl Metrics {
+    /// Counts a command applied by the matching core and how long it took.
+    ///
+    /// Commands without a known `type` are counted as `unknown`, so a bad
+    /// producer cannot blow up the label set.
+    pub fn record_command(&self, command_type: Option<&str>, ok: bool, latency: Duration) {
+        let command_type = command_type.unwrap_or("unknown").to_string();
+        let result = if ok { "ok" } else { "error" };
+        *lock(&self.commands)
+            .entry((command_type, result))
+            .or_default() += 1;
+        self.matching_latency.observe(latency);
+    }
+
+    pub fn record_event(&self, kind: &'static str) {
+        *lock(&self.events).entry(kind).or_default() += 1;
+    }
+
+    pub fn record_error(&self, error: &EngineError) {
+        *lock(&self.errors)
+            .entry((error.kind(), error.severity()))
+            .or_default() += 1;
+    }
+
+    /// Updates the gauges of one book after it changed.
+    pub fn observe_book(&self, outcome_id: &str, book: &OrderBook) {
+        lock(&self.books).insert(outcome_id.to_string(), BookGauges::of(book));
+    }
+
+    /// Replaces the gauges of all books, dropping the ones `engine` no
+    /// longer holds.
+    pub fn observe_books(&self, engine: &MatchingEngine) {
+        *lock(&self.books) = engine
+            .books
+            .iter()
+            .map(|(outcome_id, book)| (outcome_id.clone(), BookGauges::of(book)))
+            .collect();
+    }
+
+    /// Replaces the pending entry counts of the streams read.
+    pub fn observe_stream_pending(&self, pending: BTreeMap<u32, u64>) {
+        *lock(&self.stream_pending) = pending;
+    }
+
+    /// Ledger entries about to be replayed for a market being restored.
+    pub fn start_replay(&self, entries: usize) {
+        self.replay_pending.store(entries as u64, Ordering::Relaxed);
+    }
+
+    pub fn record_replayed(&self, entries: usize) {
+        let entries = entries as u64;
+        self.replayed.fetch_add(entries, Ordering::Relaxed);
+        let _ = self
+            .replay_pending
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |pending| {
+                Some(pending.saturating_sub(entries))
+            });
+    }
+
+    /// Everything recorded so far, in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        header(
+            &mut out,
+            "engine_commands_total",
+            "counter",
+            "Commands applied by the matching core",
+        );
+        for ((command_type, result), count) in lock(&self.commands).iter() {
+            sample(
+                &mut out,
+                "engine_commands_total",
+                &[("type", command_type), ("result", result)],
+                *count,
+            );
+        }
+        header(
+            &mut out,
+            "engine_matching_latency_seconds",
+            "histogram",
+            "Time the matching core spent applying a command",
+        );
+        self.matching_latency
+            .render(&mut out, "engine_matching_latency_seconds");
+        header(
+            &mut out,
+            "engine_events_emitted_total",
+            "counter",
+            "Events written to engine.events",
+        );
+        for (kind, count) in lock(&self.events).iter() {
+            sample(
+                &mut out,
+                "engine_events_emitted_total",
+                &[("type", kind)],
+                *count,
+            );
+        }
+        header(
+            &mut out,
+            "engine_errors_total",
+            "counter",
+            "Errors by variant and severity",
+        );
+        for ((kind, severity), count) in lock(&self.errors).iter() {
+            sample(
+                &mut out,
+                "engine_errors_total",
+                &[("kind", kind), ("severity", &severity.to_string())],
+                *count,
+            );
+        }
+        let books = lock(&self.books).clone();
+        header(
+            &mut out,
+            "engine_book_resting_orders",
+            "gauge",
+            "Orders resting in a book",
+        );
+        for (outcome_id, gauges) in &books {
+            sample(
+                &mut out,
+                "engine_book_resting_orders",
+                &[("outcome_id", outcome_id)],
+                gauges.resting_orders,
+            );
+        }
+        header(
+            &mut out,
+            "engine_book_price_levels",
+            "gauge",
+            "Price levels on one side of a book",
+        );
+        for (outcome_id, gauges) in &books {
+            for (side, levels) in [("bid", gauges.bid_levels), ("ask", gauges.ask_levels)] {
+                sample(
+                    &mut out,
+                    "engine_book_price_levels",
+                    &[("outcome_id", outcome_id), ("side", side)],
+                    levels,
+                );
+            }
+        }
+        header(
+            &mut out,
+            "engine_stream_pending_entries",
+            "gauge",
+            "Entries of a market's command stream delivered but not yet ACKed",
+        );
+        for (market_id, pending) in lock(&self.stream_pending).iter() {
+            sample(
+                &mut out,
+                "engine_stream_pending_entries",
+                &[("market_id", &market_id.to_string())],
+                *pending,
+            );
+        }
+        header(
+            &mut out,
+            "engine_replay_pending_entries",
+            "gauge",
+            "Ledger entries left to replay for the market being restored",
+        );
+        sample(
+            &mut out,
+            "engine_replay_pending_entries",
+            &[],
+            self.replay_pending.load(Ordering::Relaxed),
+        );
+        header(
+            &mut out,
+            "engine_replayed_entries_total",
+            "counter",
+            "Ledger entries replayed while restoring or following markets",
+        );
+        sample(
+            &mut out,
+            "engine_replayed_entries_total",
+            &[],
+            self.replayed.load(Ordering::Relaxed),
+        );
+        out
+    }
+}
+
+/// Latency histogram with fixed buckets, see [`LATENCY_BUCKETS`].
+#[derive(Debug)]
+struct Histogram {
+    /// Per bucket, not cumulative; the last one counts the overflow
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    sum_nanos: AtomicU64,
+    count: AtomicU64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::new(0)),
+            sum_nanos: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Histogram {
+    fn observe(&self, value: Duration) {
+        let seconds = value.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| seconds <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_nanos
+            .fetch_add(value.as_nanos() as u64, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str) {
+        let bucket_name = format!("{}_bucket", name);
+        let mut cumulative = 0;
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            sample(out, &bucket_name, &[("le", &bound.to_string())], cumulative);
+        }
+        cumulative += self.buckets[LATENCY_BUCKETS.len()].load(Ordering::Relaxed);
+        sample(out, &bucket_name, &[("le", "+Inf")], cumulative);
+        let sum = self.sum_nanos.load(Ordering::Relaxed) as f64 / 1e9;
+        let _ = writeln!(out, "{}_sum {}", name, sum);
+        sample(
+            out,
+            &format!("{}_count", name),
+            &[],
+            self.count.load(Ordering::Relaxed),
+        );
+    }
+}
+
+fn header(out: &mut String, name: &str, metric_type: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, metric_type);
+}
+
+fn sample(out: &mut String, name: &str, labels: &[(&str, &str)], value: impl std::fmt::Display) {
+    out.push_str(name);
+    if !labels.is_empty() {
+        let labels: Vec<String> = labels
+            .iter()
+            .map(|(key, value)| format!("{}=\"{}\"", key, escape_label(value)))
+            .collect();
+        let _ = write!(out, "{{{}}}", labels.join(","));
+    }
+    let _ = writeln!(out, " {}", value);
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// A panic while recording must not take the metrics down with it.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_prometheus_text() {
+        let metrics = Metrics::default();
+        metrics.record_command(Some("order.new"), true, Duration::from_micros(3));
+        metrics.record_command(Some("order.new"), true, Duration::from_secs(1));
+        metrics.record_command(None, false, Duration::from_micros(3));
+        metrics.record_error(&EngineError::MissingField("type".to_string()));
+        metrics.record_event("trade");
+        metrics.observe_stream_pending(BTreeMap::from([(7, 2)]));
+        metrics.start_replay(10);
+        metrics.record_replayed(4);
+
+        let text = metrics.render();
+        for line in [
+            "# TYPE engine_commands_total counter",
+            r#"engine_commands_total{type="order.new",result="ok"} 2"#,
+            r#"engine_commands_total{type="unknown",result="error"} 1"#,
+            r#"engine_matching_latency_seconds_bucket{le="0.0000025"} 0"#,
+            r#"engine_matching_latency_seconds_bucket{le="0.000005"} 2"#,
+            r#"engine_matching_latency_seconds_bucket{le="0.05"} 2"#,
+            r#"engine_matching_latency_seconds_bucket{le="+Inf"} 3"#,
+            "engine_matching_latency_seconds_count 3",
+            r#"engine_errors_total{kind="missing_field",severity="LOW"} 1"#,
+            r#"engine_events_emitted_total{type="trade"} 1"#,
+            r#"engine_stream_pending_entries{market_id="7"} 2"#,
+            "engine_replay_pending_entries 6",
+            "engine_replayed_entries_total 4",
+        ] {
+            assert!(
+                text.lines().any(|l| l == line),
+                "missing {}\n{}",
+                line,
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn escapes_label_values() {
+        assert_eq!(escape_label("a\"b\\c\nd"), "a\\\"b\\\\c\\nd");
+    }
+}
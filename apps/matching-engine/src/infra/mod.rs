@@ -1,4 +1,9 @@
+pub mod admin_api;
+pub mod dead_letter;
+pub mod http;
+pub mod lease;
 pub mod ledger;
-pub mod ledger_replay;
+pub mod metrics;
 pub mod redis_streams;
+pub mod shard_map;
 pub mod view_emitter;
@@ -1,12 +1,28 @@
 use crate::engine::engine::MatchingEngine;
-use crate::engine::stream::start_order_stream_loop;
+use crate::engine::follower::Promotion;
+use crate::engine::query::QueryRequest;
+use crate::engine::stream::{StreamConfig, start_order_stream_loop};
 use crate::error::EngineResult;
 use crate::infra::view_emitter::ViewEmitter;
+use tokio::sync::{mpsc, watch};
 
 pub async fn start_command_stream_loop(
     redis_url: String,
     engine: MatchingEngine,
     view_emitter: ViewEmitter,
-) -> EngineResult<()> {
-    start_order_stream_loop(redis_url, engine, view_emitter).await
+    config: StreamConfig,
+    promotion: Promotion,
+    queries: mpsc::Receiver<QueryRequest>,
+    shutdown: watch::Receiver<bool>,
+) -> EngineResult<MatchingEngine> {
+    start_order_stream_loop(
+        redis_url,
+        engine,
+        view_emitter,
+        config,
+        promotion,
+        queries,
+        shutdown,
+    )
+    .await
 }
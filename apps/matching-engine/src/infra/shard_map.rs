@@ -0,0 +1,121 @@
+//! Market sharding across engine instances.
+//!
+//! Every market has its own command stream, `orders.commands.{market_id}`,
+//! and its own ledger, `engine.ledger.{market_id}`. The `engine.shard_map`
+//! hash maps a market id to the `ENGINE_ID` that owns it; only the owner
+//! reads the market's stream, so a book is never matched by two engines.
+//!
+//! Producers add the market id to `orders.commands.markets` when they write
+//! to its stream. Engines started with `ENGINE_CLAIM_UNASSIGNED` take markets
+//! that have no owner yet with `HSETNX`, so the first claim wins.
+//!
+//! Moving a market is done with a `market.handoff` command written to the
+//! market's own stream:
+//! 1. the owner applies every command before it, then snapshots and drops the
+//!    market, and stops forwarding the market's later entries
+//! 2. the publisher stores the snapshot under `engine.snapshot.{market_id}`
+//!    and points the shard map at the target, in the same `MULTI`/`EXEC`
+//! 3. the target sees the new assignment, restores the snapshot, replays the
+//!    ledger after the snapshot's `ledger_id`, claims the entries still
+//!    pending on the stream and starts consuming it
+
+use crate::{engine::snapshot::MarketSnapshot, error::EngineResult, infra::lease::Fence};
+use redis::AsyncCommands;
+use std::collections::{BTreeSet, HashMap};
+
+pub const SHARD_MAP_KEY: &str = "engine.shard_map";
+pub const MARKETS_KEY: &str = "orders.commands.markets";
+/// Consumer group every engine reads the command streams with.
+pub const CONSUMER_GROUP: &str = "engine-group";
+const COMMAND_STREAM_PREFIX: &str = "orders.commands.";
+const LEDGER_STREAM_PREFIX: &str = "engine.ledger.";
+const SNAPSHOT_KEY_PREFIX: &str = "engine.snapshot.";
+
+pub fn command_stream(market_id: u32) -> String {
+    format!("{}{}", COMMAND_STREAM_PREFIX, market_id)
+}
+
+pub fn ledger_stream(market_id: u32) -> String {
+    format!("{}{}", LEDGER_STREAM_PREFIX, market_id)
+}
+
+pub fn snapshot_key(market_id: u32) -> String {
+    format!("{}{}", SNAPSHOT_KEY_PREFIX, market_id)
+}
+
+/// Market id of an `orders.commands.{market_id}` stream key.
+pub fn market_of_stream(key: &str) -> Option<u32> {
+    key.strip_prefix(COMMAND_STREAM_PREFIX)?.parse().ok()
+}
+
+/// Markets currently assigned to `engine_id`, claiming unowned ones first
+/// when `claim_unassigned` is set.
+pub async fn assigned_markets(
+    redis: &mut redis::aio::Connection,
+    engine_id: &str,
+    claim_unassigned: bool,
+) -> EngineResult<BTreeSet<u32>> {
+    let mut shard_map: HashMap<u32, String> = redis.hgetall(SHARD_MAP_KEY).await?;
+    if claim_unassigned {
+        let markets: Vec<u32> = redis.smembers(MARKETS_KEY).await?;
+        for market_id in markets {
+            if shard_map.contains_key(&market_id) {
+                continue;
+            }
+            let claimed: bool = redis.hset_nx(SHARD_MAP_KEY, market_id, engine_id).await?;
+            if claimed {
+                shard_map.insert(market_id, engine_id.to_string());
+            }
+        }
+    }
+    Ok(shard_map
+        .into_iter()
+        .filter(|(_, owner)| owner == engine_id)
+        .map(|(market_id, _)| market_id)
+        .collect())
+}
+
+pub async fn load_snapshot(
+    redis: &mut redis::aio::Connection,
+    market_id: u32,
+) -> EngineResult<Option<MarketSnapshot>> {
+    let snapshot: Option<String> = redis.get(snapshot_key(market_id)).await?;
+    Ok(match snapshot {
+        Some(snapshot) => Some(serde_json::from_str(&snapshot)?),
+        None => None,
+    })
+}
+
+/// Stores `snapshots` in one `MULTI`/`EXEC`, if `fence` is still current.
+pub async fn save_snapshots(
+    redis: &mut redis::aio::Connection,
+    fence: &Fence,
+    snapshots: &[MarketSnapshot],
+) -> EngineResult<()> {
+    if snapshots.is_empty() {
+        return Ok(());
+    }
+    let mut pipe = redis::pipe();
+    pipe.atomic();
+    for snapshot in snapshots {
+        pipe.set(
+            snapshot_key(snapshot.market_id),
+            serde_json::to_string(snapshot)?,
+        )
+        .ignore();
+    }
+    fence.query(redis, &pipe).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stream_keys_round_trip() {
+        assert_eq!(command_stream(42), "orders.commands.42");
+        assert_eq!(market_of_stream(&command_stream(42)), Some(42));
+        assert_eq!(market_of_stream("orders.commands.stream"), None);
+        assert_eq!(market_of_stream("engine.ledger.42"), None);
+    }
+}
@@ -1,77 +1,283 @@
 use crate::{
-    engine::publish_events::PublishEngineEvent,
-    error::EngineResult,
-    orderbook::{Depth, Price},
+    engine::{
+        command::EngineOutput, engine::OutcomeMarketData, fair_price::FairPriceStrategy,
+        publish_events::PublishEngineEvent,
+    },
+    error::{EngineError, EngineResult},
+    infra::{
+        dead_letter::DeadLetter,
+        lease::Fence,
+        shard_map::{CONSUMER_GROUP, SHARD_MAP_KEY, command_stream, snapshot_key},
+    },
+    orderbook::{Depth, DepthDelta},
 };
-use redis::AsyncCommands;
 use serde_json::json;
+use std::{fmt, str::FromStr};
+
+pub const EVENTS_STREAM: &str = "engine.events";
+
+/// How `engine.events` is kept from growing without bound.
+///
+/// Trimming is approximate (`~`), which lets Redis drop whole macro nodes
+/// and keeps the cost of each `XADD` constant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamTrim {
+    /// Keep roughly the newest `n` entries.
+    MaxLen(usize),
+    /// Keep roughly the entries younger than this many milliseconds.
+    MinIdAge(u64),
+}
+
+impl fmt::Display for StreamTrim {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StreamTrim::MaxLen(len) => write!(f, "maxlen:{}", len),
+            StreamTrim::MinIdAge(age_ms) => write!(f, "minid:{}", age_ms),
+        }
+    }
+}
+
+impl FromStr for StreamTrim {
+    type Err = EngineError;
+
+    /// Accepts `maxlen:<entries>` or `minid:<retention_ms>`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            EngineError::Configuration(format!(
+                "Invalid stream trim '{}'. Must be 'maxlen:<entries>' or 'minid:<retention_ms>'",
+                s
+            ))
+        };
+        let (name, arg) = s.trim().split_once(':').ok_or_else(invalid)?;
+        match name.to_lowercase().as_str() {
+            "maxlen" => arg.parse().map(StreamTrim::MaxLen).map_err(|_| invalid()),
+            "minid" => arg.parse().map(StreamTrim::MinIdAge).map_err(|_| invalid()),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Where and how engine outputs are written.
+#[derive(Debug, Clone, Default)]
+pub struct ViewEmitterConfig {
+    /// Trimming applied to every `XADD` on `engine.events`; `None` keeps
+    /// the stream forever.
+    pub trim: Option<StreamTrim>,
+    /// Channel that `book.depth` and `book.delta` are also published on, so
+    /// the websocket gateway gets depth without going through the stream
+    /// consumers.
+    pub pubsub_channel: Option<String>,
+}
 
 pub struct ViewEmitter {
     redis: redis::aio::Connection,
     stream: &'static str,
-    pub is_replay_mode: bool,
+    config: ViewEmitterConfig,
+    /// Leadership term every write is checked against
+    fence: Fence,
 }
 
 impl ViewEmitter {
-    pub fn new(redis: redis::aio::Connection, replay: bool) -> Self {
+    pub fn new(redis: redis::aio::Connection, config: ViewEmitterConfig, fence: Fence) -> Self {
         Self {
             redis,
-            stream: "engine.events",
-            is_replay_mode: replay,
+            stream: EVENTS_STREAM,
+            config,
+            fence,
         }
     }
-    pub async fn emit_book_depth(&mut self, outcome_id: &str, depth: Depth) -> EngineResult<()> {
-        let event = json!({
-            "type": "book.depth",
-            "outcome_id": outcome_id,
-            "bids": depth.bids,
-            "asks": depth.asks,
-            "timestamp": chrono::Utc::now().timestamp_millis(),
-        });
-        let payload = serde_json::to_string(&event)?;
-        let _: String = self
-            .redis
-            .xadd(self.stream, "*", &[("payload", payload)])
-            .await?;
-        Ok(())
-    }
-    pub async fn emit_market_data(
+
+    /// Writes the outputs of a batch of commands in a single pipeline,
+    /// acknowledges the `(market_id, entry_id)` commands they came from and
+    /// dead-letters the commands that failed.
+    ///
+    /// The pipeline runs as one `MULTI`/`EXEC`, so a failed batch wrote
+    /// nothing and can be retried without duplicating events. Once another
+    /// engine took over, it fails with [`EngineError::Fenced`].
+    pub async fn publish_batch(
         &mut self,
-        market_id: &u32,
-        fair_prices_and_total_volumes: &Vec<(String, Price, Price)>,
+        outputs: &[EngineOutput],
+        acks: &[(u32, String)],
+        dead_letters: &[DeadLetter],
     ) -> EngineResult<()> {
-        let current_fair_price_and_total_volume: Vec<serde_json::Value> =
-            fair_prices_and_total_volumes
-                .iter()
-                .map(|(outcome_id, fair_price, total_volume)| {
-                    json!({
-                        "outcomeId": outcome_id,
-                        "fairPrice": fair_price,
-                        "totalVolume": total_volume,
-                    })
-                })
-                .collect();
-        let event = json!({
-            "type": "market.data",
-            "marketId": market_id,
-            "data": current_fair_price_and_total_volume,
-            "timestamp": chrono::Utc::now().timestamp_millis(),
-        });
-        let payload = serde_json::to_string(&event)?;
-        let _: String = self
-            .redis
-            .xadd(self.stream, "*", &[("payload", payload)])
-            .await?;
-        Ok(())
+        if outputs.is_empty() && acks.is_empty() && dead_letters.is_empty() {
+            return Ok(());
+        }
+        let timestamp = chrono::Utc::now().timestamp_millis();
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        for output in outputs {
+            match output {
+                EngineOutput::FairPrice { outcome_id, price } => {
+                    pipe.set(format!("fair_price:{}", outcome_id), price.0.to_string())
+                        .ignore();
+                    continue;
+                }
+                EngineOutput::Handoff {
+                    market_id,
+                    target_engine,
+                    snapshot,
+                } => {
+                    pipe.set(snapshot_key(*market_id), serde_json::to_string(snapshot)?)
+                        .ignore()
+                        .hset(SHARD_MAP_KEY, market_id, target_engine)
+                        .ignore();
+                    continue;
+                }
+                _ => {}
+            }
+            let payload = event_payload(output, timestamp)?;
+            let xadd = pipe.cmd("XADD").arg(self.stream);
+            match self.config.trim {
+                Some(StreamTrim::MaxLen(len)) => {
+                    xadd.arg("MAXLEN").arg("~").arg(len);
+                }
+                Some(StreamTrim::MinIdAge(age_ms)) => {
+                    let min_id = timestamp.saturating_sub(age_ms as i64).max(0);
+                    xadd.arg("MINID").arg("~").arg(format!("{}-0", min_id));
+                }
+                None => {}
+            }
+            xadd.arg("*").arg("payload").arg(&payload).ignore();
+            if let Some(channel) = &self.config.pubsub_channel
+                && matches!(
+                    output,
+                    EngineOutput::BookDepth { .. } | EngineOutput::BookDelta { .. }
+                )
+            {
+                pipe.publish(channel, &payload).ignore();
+            }
+        }
+        for (market_id, entry_id) in acks {
+            pipe.xack(command_stream(*market_id), CONSUMER_GROUP, &[entry_id])
+                .ignore();
+        }
+        for letter in dead_letters {
+            letter.queue(&mut pipe)?;
+        }
+        self.fence.query(&mut self.redis, &pipe).await
     }
-    pub async fn emit_events(&mut self, events: Vec<PublishEngineEvent>) -> EngineResult<()> {
-        for event in events {
-            let payload: String = serde_json::to_string(&event)?;
-            let _: String = self
-                .redis
-                .xadd(self.stream, "*", &[("payload", payload)])
-                .await?;
+}
+
+/// JSON payload of an `engine.events` entry.
+///
+/// `FairPrice` and `Handoff` are written as keys rather than events and have
+/// no payload.
+fn event_payload(output: &EngineOutput, timestamp: i64) -> EngineResult<String> {
+    let event = match output {
+        EngineOutput::BookDepth { outcome_id, depth } => book_depth(outcome_id, depth, timestamp),
+        EngineOutput::BookDelta { outcome_id, delta } => book_delta(outcome_id, delta, timestamp),
+        EngineOutput::MarketData {
+            market_id,
+            strategy,
+            data,
+        } => market_data(market_id, *strategy, data, timestamp),
+        EngineOutput::Event(event) => {
+            return Ok(serde_json::to_string::<PublishEngineEvent>(event)?);
         }
-        Ok(())
+        EngineOutput::FairPrice { .. } | EngineOutput::Handoff { .. } => {
+            return Err(EngineError::Internal(
+                "output is not an engine event".to_string(),
+            ));
+        }
+    };
+    Ok(serde_json::to_string(&event)?)
+}
+
+fn book_depth(outcome_id: &str, depth: &Depth, timestamp: i64) -> serde_json::Value {
+    json!({
+        "type": "book.depth",
+        "outcome_id": outcome_id,
+        "bids": depth.bids,
+        "asks": depth.asks,
+        "seq": depth.seq,
+        "timestamp": timestamp,
+    })
+}
+
+fn book_delta(outcome_id: &str, delta: &DepthDelta, timestamp: i64) -> serde_json::Value {
+    json!({
+        "type": "book.delta",
+        "outcome_id": outcome_id,
+        "bids": delta.bids,
+        "asks": delta.asks,
+        "seq": delta.seq,
+        "timestamp": timestamp,
+    })
+}
+
+fn market_data(
+    market_id: &u32,
+    fair_price_strategy: FairPriceStrategy,
+    market_data: &[OutcomeMarketData],
+    timestamp: i64,
+) -> serde_json::Value {
+    let current_fair_price_and_total_volume: Vec<serde_json::Value> = market_data
+        .iter()
+        .map(|data| {
+            json!({
+                "outcomeId": data.outcome_id,
+                "fairPrice": data.fair_price,
+                "totalVolume": data.total_volume.notional,
+                "totalContracts": data.total_volume.contracts,
+                "totalTrades": data.total_volume.trades,
+                "volume24h": data.rolling_volume.notional,
+                "contracts24h": data.rolling_volume.contracts,
+                "trades24h": data.rolling_volume.trades,
+            })
+        })
+        .collect();
+    json!({
+        "type": "market.data",
+        "marketId": market_id,
+        "fairPriceStrategy": fair_price_strategy.to_string(),
+        "data": current_fair_price_and_total_volume,
+        "timestamp": timestamp,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::{Price, Quantity};
+
+    #[test]
+    fn parses_stream_trim() {
+        assert_eq!(
+            "maxlen:100000".parse::<StreamTrim>().unwrap(),
+            StreamTrim::MaxLen(100_000)
+        );
+        assert_eq!(
+            "MINID:86400000".parse::<StreamTrim>().unwrap(),
+            StreamTrim::MinIdAge(86_400_000)
+        );
+        assert!("maxlen".parse::<StreamTrim>().is_err());
+        assert!("maxlen:-1".parse::<StreamTrim>().is_err());
+        assert!("ttl:10".parse::<StreamTrim>().is_err());
+    }
+
+    #[test]
+    fn book_depth_payload_shape() {
+        let output = EngineOutput::BookDepth {
+            outcome_id: "outcome-1".to_string(),
+            depth: Depth {
+                asks: vec![(Price(60), Quantity(5))],
+                bids: vec![],
+                seq: 3,
+            },
+        };
+        let payload: serde_json::Value =
+            serde_json::from_str(&event_payload(&output, 42).unwrap()).unwrap();
+        assert_eq!(
+            payload,
+            json!({
+                "type": "book.depth",
+                "outcome_id": "outcome-1",
+                "bids": [],
+                "asks": [[60, 5]],
+                "seq": 3,
+                "timestamp": 42,
+            })
+        );
     }
 }
@@ -0,0 +1,5 @@
+pub mod engine;
+pub mod error;
+pub mod infra;
+pub mod orderbook;
+pub mod workload;
@@ -1,21 +1,30 @@
-mod engine;
-mod error;
-mod infra;
-mod orderbook;
-
-use crate::{
-    engine::engine::MatchingEngine,
+use dotenvy::dotenv;
+use matching_engine::{
+    engine::{
+        engine::{DEFAULT_ENGINE_ID, MatchingEngine},
+        fair_price::FairPriceStrategy,
+        follower::follow_until_leader,
+        query::QueryHandle,
+        stream::{DEFAULT_MAX_RETRIES, StreamConfig},
+    },
     error::{EngineError, EngineResult},
     infra::{
-        ledger_replay::replay_ledger, redis_streams::start_command_stream_loop,
-        view_emitter::ViewEmitter,
+        admin_api,
+        http::{self, Request, Response},
+        lease::{DEFAULT_LEASE_TTL, Lease},
+        metrics::METRICS,
+        redis_streams::start_command_stream_loop,
+        view_emitter::{StreamTrim, ViewEmitter, ViewEmitterConfig},
     },
 };
-use dotenvy::dotenv;
-use std::env;
+use std::{env, net::SocketAddr, time::Duration};
+use tokio::sync::watch;
 use tracing::{error, info, warn};
 use tracing_subscriber::{EnvFilter, fmt, prelude::*};
 
+const DEFAULT_METRICS_PORT: u16 = 9100;
+const DEFAULT_ADMIN_PORT: u16 = 9101;
+
 #[tokio::main]
 async fn main() -> EngineResult<()> {
     dotenv().ok();
@@ -46,64 +55,262 @@ fn init_logging() -> EngineResult<()> {
 async fn run_engine() -> EngineResult<()> {
     let config = load_configuration()?;
     info!("Configuration loaded successfully");
+    // Served from standby on too, so replay progress can be watched
+    if let Some(addr) = config.metrics_addr {
+        let listener = tokio::net::TcpListener::bind(addr).await.map_err(|e| {
+            EngineError::Configuration(format!("Failed to bind METRICS_ADDR {}: {}", addr, e))
+        })?;
+        tokio::spawn(http::serve(listener, route));
+    }
+    let (queries, query_rx) = QueryHandle::new();
+    if let Some(addr) = config.admin_addr {
+        let listener = tokio::net::TcpListener::bind(addr).await.map_err(|e| {
+            EngineError::Configuration(format!("Failed to bind ADMIN_ADDR {}: {}", addr, e))
+        })?;
+        tokio::spawn(http::serve(listener, move |request: Request| {
+            let queries = queries.clone();
+            async move { admin_api::route(&queries, &request).await }
+        }));
+    }
     let redis_client = create_redis_client(&config.redis_url)?;
     let mut redis_conn = redis_client
         .get_async_connection()
         .await
         .map_err(|e| EngineError::Configuration(format!("Failed to connect to Redis: {}", e)))?;
-    {
-        info!("Starting ledger replay...");
-        let view_emitter_conn = redis_client.get_async_connection().await.map_err(|e| {
-            EngineError::Configuration(format!("Failed to create view emitter connection: {}", e))
-        })?;
-        let mut engine = MatchingEngine::new(true);
-        let view_emitter = ViewEmitter::new(view_emitter_conn, true);
-        replay_ledger(&mut redis_conn, &mut engine, view_emitter)
-            .await
-            .map_err(|e| EngineError::Ledger(format!("Ledger replay failed: {}", e)))?;
-        let stats = engine.stats();
-        info!(
-            "Ledger replay completed - {} order books restored",
-            stats.total_books
-        );
-    }
+    let mut engine = MatchingEngine::new(false)
+        .with_default_fair_price_strategy(config.fair_price_strategy)
+        .with_engine_id(&config.engine_id);
+    let lease = Lease::new(&config.engine_id, config.lease_ttl);
+    let (shutdown_tx, mut shutdown) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        let _ = shutdown_tx.send(true);
+    });
+    // Standby until this instance leads; markets adopted later are restored
+    // by the stream loop
+    let promotion = tokio::select! {
+        promotion = follow_until_leader(&mut redis_conn, &mut engine, &lease) => promotion?,
+        _ = shutdown.changed() => {
+            info!("Shutdown requested while on standby");
+            // In case the lease was taken just before the signal
+            lease.release(&mut redis_conn).await?;
+            return Ok(());
+        }
+    };
+    let stats = engine.stats();
+    info!(
+        "Leading as {} - {} order books restored",
+        config.engine_id, stats.total_books
+    );
     let view_emitter_conn = redis_client.get_async_connection().await.map_err(|e| {
         EngineError::Configuration(format!("Failed to create view emitter connection: {}", e))
     })?;
-    let view_emitter = ViewEmitter::new(view_emitter_conn, false);
+    let view_emitter = ViewEmitter::new(
+        view_emitter_conn,
+        config.view_emitter,
+        promotion.fence.clone(),
+    );
     info!("View emitter initialized");
-    let engine = MatchingEngine::new(false);
     info!("Starting command stream processing...");
-    start_command_stream_loop(config.redis_url, engine, view_emitter)
-        .await
-        .map_err(|e| EngineError::StreamProcessing(format!("Stream processing failed: {}", e)))?;
+    let engine = start_command_stream_loop(
+        config.redis_url,
+        engine,
+        view_emitter,
+        config.stream,
+        promotion,
+        query_rx,
+        shutdown,
+    )
+    .await
+    .map_err(|e| EngineError::StreamProcessing(format!("Stream processing failed: {}", e)))?;
+    let stats = engine.stats();
+    info!(
+        "Drained - {} markets, {} order books, {} resting orders",
+        stats.total_markets, stats.total_books, stats.resting_orders
+    );
 
     Ok(())
 }
 
+async fn route(request: Request) -> Response {
+    match request.path.as_str() {
+        "/metrics" => Response {
+            content_type: "text/plain; version=0.0.4; charset=utf-8",
+            ..Response::text(METRICS.render())
+        },
+        _ => Response::not_found(),
+    }
+}
+
+/// Resolves on the first SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            error!("Failed to listen for SIGINT: {}", e);
+            std::future::pending::<()>().await;
+        }
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                error!("Failed to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => info!("Received SIGINT"),
+        _ = terminate => info!("Received SIGTERM"),
+    }
+}
+
 #[derive(Debug, Clone)]
 struct AppConfig {
     redis_url: String,
+    engine_id: String,
+    stream: StreamConfig,
+    lease_ttl: Duration,
+    fair_price_strategy: FairPriceStrategy,
+    view_emitter: ViewEmitterConfig,
+    metrics_addr: Option<SocketAddr>,
+    admin_addr: Option<SocketAddr>,
 }
 
 fn load_configuration() -> EngineResult<AppConfig> {
     let redis_url = env::var("REDIS_URL").map_err(|_| {
         EngineError::Configuration("REDIS_URL environment variable is not set".to_string())
     })?;
-    env::var("ENGINE_ID").unwrap_or_else(|_| {
-        let default = "engine-1".to_string();
+    let engine_id = env::var("ENGINE_ID").unwrap_or_else(|_| {
+        let default = DEFAULT_ENGINE_ID.to_string();
         warn!("ENGINE_ID not set, using default: {}", default);
         default
     });
+    let claim_unassigned = match env::var("ENGINE_CLAIM_UNASSIGNED") {
+        Ok(value) => value.parse::<bool>().map_err(|_| {
+            EngineError::Configuration(format!(
+                "Invalid ENGINE_CLAIM_UNASSIGNED '{}'. Must be 'true' or 'false'",
+                value
+            ))
+        })?,
+        Err(_) => true,
+    };
+    let max_retries = match env::var("COMMAND_MAX_RETRIES") {
+        Ok(value) => value.parse::<u64>().map_err(|_| {
+            EngineError::Configuration(format!(
+                "Invalid COMMAND_MAX_RETRIES '{}'. Must be a non-negative number",
+                value
+            ))
+        })?,
+        Err(_) => DEFAULT_MAX_RETRIES,
+    };
+    let lease_ttl = match env::var("ENGINE_LEASE_TTL_MS") {
+        Ok(value) => value
+            .parse::<u64>()
+            .ok()
+            .filter(|ms| *ms > 0)
+            .map(Duration::from_millis)
+            .ok_or_else(|| {
+                EngineError::Configuration(format!(
+                    "Invalid ENGINE_LEASE_TTL_MS '{}'. Must be a positive number of milliseconds",
+                    value
+                ))
+            })?,
+        Err(_) => DEFAULT_LEASE_TTL,
+    };
     if !redis_url.starts_with("redis://") && !redis_url.starts_with("rediss://") {
         return Err(EngineError::Configuration(
             "REDIS_URL must start with redis:// or rediss://".to_string(),
         ));
     }
-    Ok(AppConfig { redis_url })
+    let fair_price_strategy = match env::var("FAIR_PRICE_STRATEGY") {
+        Ok(value) => value.parse::<FairPriceStrategy>().map_err(|e| {
+            EngineError::Configuration(format!("Invalid FAIR_PRICE_STRATEGY: {}", e))
+        })?,
+        Err(_) => FairPriceStrategy::default(),
+    };
+    let trim = match env::var("EVENTS_STREAM_TRIM") {
+        Ok(value) if !value.is_empty() => Some(value.parse::<StreamTrim>().map_err(|e| {
+            EngineError::Configuration(format!("Invalid EVENTS_STREAM_TRIM: {}", e))
+        })?),
+        _ => None,
+    };
+    let pubsub_channel = env::var("EVENTS_PUBSUB_CHANNEL")
+        .ok()
+        .filter(|channel| !channel.is_empty());
+    let metrics_addr = listen_addr(
+        "METRICS_ADDR",
+        SocketAddr::from(([0, 0, 0, 0], DEFAULT_METRICS_PORT)),
+    )?;
+    // Account orders are exposed, so only locally unless configured
+    let admin_addr = listen_addr(
+        "ADMIN_ADDR",
+        SocketAddr::from(([127, 0, 0, 1], DEFAULT_ADMIN_PORT)),
+    )?;
+    Ok(AppConfig {
+        redis_url,
+        engine_id,
+        stream: StreamConfig {
+            claim_unassigned,
+            max_retries,
+        },
+        lease_ttl,
+        fair_price_strategy,
+        view_emitter: ViewEmitterConfig {
+            trim,
+            pubsub_channel,
+        },
+        metrics_addr,
+        admin_addr,
+    })
+}
+
+/// Address to serve on from `var`, `None` if it is `off`.
+fn listen_addr(var: &str, default: SocketAddr) -> EngineResult<Option<SocketAddr>> {
+    match env::var(var) {
+        Ok(value) if value == "off" => Ok(None),
+        Ok(value) if !value.is_empty() => value.parse::<SocketAddr>().map(Some).map_err(|_| {
+            EngineError::Configuration(format!(
+                "Invalid {} '{}'. Must be a socket address like {} or 'off'",
+                var, value, default
+            ))
+        }),
+        _ => Ok(Some(default)),
+    }
 }
 
 fn create_redis_client(redis_url: &str) -> EngineResult<redis::Client> {
     redis::Client::open(redis_url)
         .map_err(|e| EngineError::Configuration(format!("Invalid Redis URL: {}", e)))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn sigterm_requests_shutdown() {
+        let mut signal = Box::pin(shutdown_signal());
+        // Polling it once installs the handlers, so SIGTERM no longer kills
+        // the test process
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), &mut signal)
+                .await
+                .is_err()
+        );
+        let status = std::process::Command::new("kill")
+            .args(["-TERM", &std::process::id().to_string()])
+            .status()
+            .unwrap();
+        assert!(status.success());
+        tokio::time::timeout(Duration::from_secs(5), signal)
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,108 @@
+//! How a taker's quantity is shared among the orders resting at a price.
+//!
+//! Levels are always matched best price first; the [`Allocation`] of a book
+//! only decides which orders of a level the quantity goes to.
+//!
+//! Pro-rata shares are rounded down, then the lots left over by the rounding
+//! go one each to the orders in time priority. The fills of a level therefore
+//! always add up to exactly the quantity taken from it, and no order gets more
+//! than it has remaining.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Allocation algorithm of a book, chosen with
+/// [`crate::orderbook::OrderBookBuilder::with_allocation`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Allocation {
+    /// Strict price-time priority: the oldest order fills first
+    #[default]
+    Fifo,
+    /// Every order of the level gets a share proportional to its visible
+    /// quantity, so icebergs take part with their shown slice
+    ProRata,
+    /// The oldest order of the level fills first, the rest is shared pro-rata
+    /// among the others
+    TopOrderProRata,
+}
+
+impl fmt::Display for Allocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Allocation::Fifo => write!(f, "fifo"),
+            Allocation::ProRata => write!(f, "pro_rata"),
+            Allocation::TopOrderProRata => write!(f, "top_order_pro_rata"),
+        }
+    }
+}
+
+impl FromStr for Allocation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "fifo" => Ok(Allocation::Fifo),
+            "pro_rata" => Ok(Allocation::ProRata),
+            "top_order_pro_rata" => Ok(Allocation::TopOrderProRata),
+            _ => Err(format!(
+                "Invalid allocation: '{}'. Must be 'fifo', 'pro_rata' or 'top_order_pro_rata'",
+                s
+            )),
+        }
+    }
+}
+
+/// Shares `quantity` among orders of `sizes`, given in time priority.
+///
+/// Returns one share per order. Their sum is `quantity`, capped at the sum of
+/// `sizes`, and no share exceeds its order's size.
+pub(crate) fn pro_rata(quantity: u64, sizes: &[u64]) -> Vec<u64> {
+    // Sizes can add up past u64::MAX
+    let total: u128 = sizes.iter().map(|size| *size as u128).sum();
+    if quantity as u128 >= total {
+        return sizes.to_vec();
+    }
+    let mut shares: Vec<u64> = sizes
+        .iter()
+        .map(|size| (quantity as u128 * *size as u128 / total) as u64)
+        .collect();
+    // quantity < total, so every rounded-down share of a non-empty order is
+    // below its size and the lots left are fewer than the orders
+    let mut left = quantity - shares.iter().sum::<u64>();
+    for (share, size) in shares.iter_mut().zip(sizes) {
+        if left == 0 {
+            break;
+        }
+        if *share < *size {
+            *share += 1;
+            left -= 1;
+        }
+    }
+    shares
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pro_rata_shares_add_up_exactly() {
+        assert_eq!(pro_rata(10, &[10, 30, 60]), vec![1, 3, 6]);
+        // Shares of 7 / 3 round down to 2; the lot left goes to the oldest
+        assert_eq!(pro_rata(7, &[5, 5, 5]), vec![3, 2, 2]);
+        assert_eq!(pro_rata(1, &[1, 100]), vec![1, 0]);
+        assert_eq!(pro_rata(50, &[10, 20]), vec![10, 20]);
+        assert_eq!(pro_rata(0, &[10, 20]), vec![0, 0]);
+
+        let sizes = [u64::MAX / 4, u64::MAX / 4, 3];
+        let shares = pro_rata(u64::MAX / 3, &sizes);
+        assert_eq!(shares.iter().sum::<u64>(), u64::MAX / 3);
+        assert!(shares.iter().zip(&sizes).all(|(share, size)| share <= size));
+
+        // The sizes add up past u64::MAX
+        assert_eq!(pro_rata(10, &[u64::MAX, u64::MAX]), vec![5, 5]);
+        assert_eq!(pro_rata(u64::MAX, &[u64::MAX, 2]), vec![u64::MAX - 1, 1]);
+    }
+}
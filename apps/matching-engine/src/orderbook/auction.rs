@@ -0,0 +1,476 @@
+//! Call auctions.
+//!
+//! While a book is in [`TradingPhase::Auction`], limit orders rest without
+//! matching even when they cross. [`OrderBook::indicative_uncross`] tells at
+//! any time where the book would clear, and [`OrderBook::uncross`] ends the
+//! auction by executing every crossing order at that single price.
+//!
+//! The uncross price is the one that executes the most volume. Ties go to the
+//! price leaving the smallest imbalance, then follow the market pressure: the
+//! highest price when buyers are left over, the lowest when sellers are, and
+//! otherwise the one nearest the midpoint between the lowest and the highest.
+//! Prices between two levels count as much as the levels themselves.
+//!
+//! An uncross executes whole queues without looking at fill constraints, so
+//! a book where all-or-none or minimum quantity orders rest cannot go into
+//! auction, and such orders are refused during one.
+//!
+//! Starting and ending an auction are journaled like order operations, so
+//! replaying a journal goes through the same phases.
+
+use crate::orderbook::enums::{
+    JournalOp, OrderOptions, OrderStatus, Side, TimeInForce, TradingPhase,
+};
+use crate::orderbook::errors::{ErrorType, Result, make_error};
+use crate::orderbook::journal::JournalLog;
+use crate::orderbook::level::{PriceLevel, RestingOrders};
+use crate::orderbook::order::AccountId;
+use crate::orderbook::utils::{current_timestamp_millis, safe_add};
+use crate::orderbook::{OrderBook, OrderId, Price, Quantity};
+use std::collections::BTreeSet;
+
+/// Where a crossed book clears.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Uncross {
+    pub price: Price,
+    pub volume: Quantity,
+    /// Quantity left unmatched at `price` on the heavier side
+    pub imbalance: Quantity,
+    /// The heavier side, `None` when both sides match exactly
+    pub imbalance_side: Option<Side>,
+}
+
+/// One side of an [`AuctionFill`], as the order stands after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuctionParty {
+    pub order_id: OrderId,
+    pub account_id: AccountId,
+    pub orig_qty: Quantity,
+    pub remaining_qty: Quantity,
+    pub time_in_force: TimeInForce,
+    pub status: OrderStatus,
+}
+
+/// A bid and an ask matched by the uncross.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuctionFill {
+    pub price: Price,
+    pub quantity: Quantity,
+    pub buy: AuctionParty,
+    pub sell: AuctionParty,
+}
+
+/// What ending an auction executed; `uncross` is `None` when the book was not
+/// crossed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuctionResult {
+    pub uncross: Option<Uncross>,
+    pub fills: Vec<AuctionFill>,
+    /// Journal entry of the uncross, if the book journals
+    pub log: Option<JournalLog>,
+}
+
+impl OrderBook {
+    pub fn phase(&self) -> TradingPhase {
+        self.phase
+    }
+
+    pub fn in_auction(&self) -> bool {
+        self.phase == TradingPhase::Auction
+    }
+
+    /// Stops matching: from now on limit orders only rest, and orders that
+    /// cannot rest are rejected, until [`OrderBook::uncross`].
+    ///
+    /// Returns the journal entry of the start, if the book journals.
+    ///
+    /// # Errors
+    /// Returns `Err` if the book may not go into auction, see
+    /// [`OrderBook::check_auction`].
+    pub fn start_auction(&mut self) -> Result<Option<JournalLog>> {
+        self.check_auction()?;
+        self.phase = TradingPhase::Auction;
+        Ok(self.journal_phase(JournalOp::StartAuction, OrderOptions::StartAuction))
+    }
+
+    /// Whether [`OrderBook::start_auction`] would succeed: a book already in
+    /// auction stays in it, and any other may start one unless all-or-none
+    /// or minimum quantity orders rest in it.
+    pub fn check_auction(&self) -> Result<()> {
+        let constrained = self
+            .orders
+            .values()
+            .any(|order| order.all_or_none || order.min_qty.is_some());
+        if !self.in_auction() && constrained {
+            return Err(make_error(ErrorType::ConstrainedOrdersResting));
+        }
+        Ok(())
+    }
+
+    /// Where the book would clear if the auction ended now, `None` if it is
+    /// not crossed.
+    pub fn indicative_uncross(&self) -> Option<Uncross> {
+        let (best_bid, best_ask) = (self.best_bid()?, self.best_ask()?);
+        if best_bid < best_ask {
+            return None;
+        }
+        // Only prices between the best ask and the best bid can execute.
+        // Demand and supply only change at a level, so each level and each
+        // gap between two of them clears alike throughout.
+        let mut prices = self
+            .bids
+            .range(best_ask..)
+            .chain(self.asks.range(..=best_bid))
+            .map(|(price, _)| *price)
+            .collect::<BTreeSet<Price>>()
+            .into_iter()
+            .peekable();
+        let mut candidates: Vec<(Price, Price, u64, u64)> = Vec::new();
+        while let Some(price) = prices.next() {
+            let mut ranges = vec![(price, price)];
+            if let Some(next) = prices.peek()
+                && next.value() > price.value() + 1
+            {
+                ranges.push((Price(price.value() + 1), Price(next.value() - 1)));
+            }
+            for (low, high) in ranges {
+                let demand = level_volume(self.bids.range(low..).map(|(_, level)| level));
+                let supply = level_volume(self.asks.range(..=low).map(|(_, level)| level));
+                candidates.push((low, high, demand, supply));
+            }
+        }
+
+        let volume = candidates
+            .iter()
+            .map(|(_, _, demand, supply)| *demand.min(supply))
+            .max()?;
+        let best: Vec<&(Price, Price, u64, u64)> = candidates
+            .iter()
+            .filter(|(_, _, demand, supply)| *demand.min(supply) == volume)
+            .collect();
+        let imbalance = best
+            .iter()
+            .map(|(_, _, demand, supply)| demand.abs_diff(*supply))
+            .min()?;
+        let best: Vec<&(Price, Price, u64, u64)> = best
+            .into_iter()
+            .filter(|(_, _, demand, supply)| demand.abs_diff(*supply) == imbalance)
+            .collect();
+        let (lowest, highest) = (best[0].0, best[best.len() - 1].1);
+        let (price, demand, supply) = if best.iter().all(|(_, _, demand, supply)| demand > supply) {
+            let (_, high, demand, supply) = best[best.len() - 1];
+            (*high, demand, supply)
+        } else if best.iter().all(|(_, _, demand, supply)| demand < supply) {
+            let (low, _, demand, supply) = best[0];
+            (*low, demand, supply)
+        } else {
+            // The tied prices need not be contiguous: take the one nearest
+            // the middle of them, the lower one when two are as near
+            let middle = (lowest.value() + highest.value()) / 2;
+            best.iter()
+                .map(|(low, high, demand, supply)| {
+                    (
+                        Price(middle.clamp(low.value(), high.value())),
+                        demand,
+                        supply,
+                    )
+                })
+                .min_by_key(|(price, _, _)| price.value().abs_diff(middle))?
+        };
+        Some(Uncross {
+            price,
+            volume: Quantity(volume),
+            imbalance: Quantity(imbalance),
+            imbalance_side: match demand.cmp(supply) {
+                std::cmp::Ordering::Greater => Some(Side::Buy),
+                std::cmp::Ordering::Less => Some(Side::Sell),
+                std::cmp::Ordering::Equal => None,
+            },
+        })
+    }
+
+    /// Ends the auction: executes the crossing orders at the uncross price,
+    /// in price-time priority, and resumes continuous matching.
+    ///
+    /// The book is no longer crossed afterwards.
+    pub fn uncross(&mut self) -> AuctionResult {
+        self.phase = TradingPhase::Continuous;
+        let log = self.journal_phase(JournalOp::Uncross, OrderOptions::Uncross);
+        let Some(uncross) = self.indicative_uncross() else {
+            self.reprice_pegs();
+            return AuctionResult {
+                uncross: None,
+                fills: Vec::new(),
+                log,
+            };
+        };
+        let mut fills = Vec::new();
+        let mut left = uncross.volume;
+        while left.value() > 0 {
+            let (Some(mut bid), Some(mut ask)) = (self.bids.last_entry(), self.asks.first_entry())
+            else {
+                break;
+            };
+            let quantity = Quantity(
+                left.value()
+                    .min(front_visible(&mut self.orders, bid.get()).value())
+                    .min(front_visible(&mut self.orders, ask.get()).value()),
+            );
+            let buy = fill_front(&mut self.orders, bid.get_mut(), quantity);
+            let sell = fill_front(&mut self.orders, ask.get_mut(), quantity);
+            self.changed_bids.insert(*bid.key());
+            self.changed_asks.insert(*ask.key());
+            if bid.get().is_empty() {
+                bid.remove();
+            }
+            if ask.get().is_empty() {
+                ask.remove();
+            }
+            fills.push(AuctionFill {
+                price: uncross.price,
+                quantity,
+                buy,
+                sell,
+            });
+            left = left - quantity;
+        }
+        // Pegs sat still during the auction
+        self.reprice_pegs();
+        AuctionResult {
+            uncross: Some(uncross),
+            fills,
+            log,
+        }
+    }
+
+    fn journal_phase(&mut self, op: JournalOp, o: OrderOptions) -> Option<JournalLog> {
+        if !self.journaling {
+            return None;
+        }
+        self.last_op = safe_add(self.last_op, 1);
+        Some(JournalLog {
+            op_id: self.last_op,
+            ts: current_timestamp_millis(),
+            op,
+            o,
+        })
+    }
+}
+
+fn level_volume<'a>(levels: impl Iterator<Item = &'a PriceLevel>) -> u64 {
+    levels.map(|level| level.total_qty.value()).sum()
+}
+
+fn front_visible(orders: &mut RestingOrders, level: &PriceLevel) -> Quantity {
+    orders
+        .front_mut(level)
+        .map_or(Quantity(0), |order| order.visible_qty())
+}
+
+/// Fills `quantity` of the order at the front of `level`, see
+/// [`RestingOrders::fill`].
+fn fill_front(
+    orders: &mut RestingOrders,
+    level: &mut PriceLevel,
+    quantity: Quantity,
+) -> AuctionParty {
+    let order_id = orders
+        .front_mut(level)
+        .expect("uncross volume is covered by resting orders")
+        .id;
+    let order = orders.fill(level, &order_id, quantity);
+    AuctionParty {
+        order_id: order.id,
+        account_id: order.account_id,
+        orig_qty: order.orig_qty,
+        remaining_qty: order.remaining_qty(),
+        time_in_force: order.time_in_force,
+        status: order.status,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::{LimitOrderOptions, OrderBookBuilder};
+
+    #[test]
+    fn uncross_executes_everything_at_one_price() {
+        let mut ob = OrderBookBuilder::new("outcome").build();
+        ob.start_auction().unwrap();
+        for (side, price, qty) in [
+            (Side::Buy, 55, 10),
+            (Side::Buy, 52, 5),
+            (Side::Buy, 50, 10),
+            (Side::Sell, 48, 8),
+            (Side::Sell, 51, 6),
+            (Side::Sell, 54, 10),
+        ] {
+            let report = ob
+                .limit_raw(side, qty, price, None, None, AccountId(1))
+                .unwrap();
+            assert!(report.fills.is_empty());
+        }
+        assert!(ob.market_raw(AccountId(2), Side::Buy, 1).is_err());
+        assert!(
+            ob.limit_raw(Side::Buy, 1, 60, Some(TimeInForce::IOC), None, AccountId(2))
+                .is_err()
+        );
+
+        // 51 and 52 both execute 14 with one contract of buy surplus; buyers
+        // are left over, so the higher one wins
+        let expected = Uncross {
+            price: Price(52),
+            volume: Quantity(14),
+            imbalance: Quantity(1),
+            imbalance_side: Some(Side::Buy),
+        };
+        assert_eq!(ob.indicative_uncross(), Some(expected));
+
+        let result = ob.uncross();
+        assert_eq!(result.uncross, Some(expected));
+        let fills: Vec<(u64, u64, u64)> = result
+            .fills
+            .iter()
+            .map(|f| (f.quantity.value(), f.buy.order_id.0, f.sell.order_id.0))
+            .collect();
+        assert_eq!(fills, vec![(8, 1, 4), (2, 1, 5), (4, 2, 5)]);
+        assert!(result.fills.iter().all(|f| f.price == Price(52)));
+        assert_eq!(result.fills[2].buy.remaining_qty, Quantity(1));
+
+        assert!(!ob.in_auction());
+        assert_eq!(ob.best_bid(), Some(Price(52)));
+        assert_eq!(ob.best_ask(), Some(Price(54)));
+        assert_eq!(ob.indicative_uncross(), None);
+        assert_eq!(ob.depth(None).asks, vec![(Price(54), Quantity(10))]);
+    }
+
+    #[test]
+    fn journal_replay_goes_through_the_auction() {
+        let mut ob = OrderBookBuilder::new("outcome")
+            .with_journaling(true)
+            .build();
+        let mut logs = Vec::new();
+        logs.extend(
+            ob.limit_raw(Side::Buy, 5, 50, None, None, AccountId(1))
+                .unwrap()
+                .log,
+        );
+        logs.extend(ob.start_auction().unwrap());
+        for (side, price) in [(Side::Buy, 60), (Side::Sell, 45)] {
+            logs.extend(
+                ob.limit_raw(side, 8, price, None, None, AccountId(2))
+                    .unwrap()
+                    .log,
+            );
+        }
+        let result = ob.uncross();
+        assert_eq!(result.fills.len(), 1);
+        logs.extend(result.log);
+        logs.extend(
+            ob.limit_raw(Side::Sell, 2, 50, None, None, AccountId(3))
+                .unwrap()
+                .log,
+        );
+        assert_eq!(logs.len(), 6);
+
+        // The crossing orders rest until the uncross, as they did
+        let mut replayed = OrderBookBuilder::new("outcome").build();
+        replayed.replay_logs(logs.clone()).unwrap();
+        assert!(!replayed.in_auction());
+        assert_eq!(replayed.depth(None), ob.depth(None));
+        assert_eq!(replayed.depth(None).bids, vec![(Price(50), Quantity(3))]);
+
+        let mut halfway = OrderBookBuilder::new("outcome").build();
+        halfway.replay_logs(logs[..4].to_vec()).unwrap();
+        assert!(halfway.in_auction());
+        assert_eq!(halfway.indicative_uncross().unwrap().volume, Quantity(8));
+    }
+
+    #[test]
+    fn constrained_orders_keep_the_book_out_of_auction() {
+        let mut ob = OrderBookBuilder::new("outcome").build();
+        let aon = ob
+            .limit(
+                LimitOrderOptions::new(Side::Sell, 10, 60, None, None, AccountId(1))
+                    .with_all_or_none(),
+            )
+            .unwrap()
+            .order_id;
+        let err = ob.start_auction().unwrap_err();
+        assert_eq!(err.code, ErrorType::ConstrainedOrdersResting.code());
+        assert!(!ob.in_auction());
+
+        ob.cancel(aon).unwrap();
+        let min =
+            LimitOrderOptions::new(Side::Buy, 10, 50, None, None, AccountId(1)).with_min_qty(2);
+        let min = ob.limit(min).unwrap().order_id;
+        assert!(ob.start_auction().is_err());
+        ob.cancel(min).unwrap();
+        ob.start_auction().unwrap();
+        // Nor can they arrive during the auction
+        let aon =
+            LimitOrderOptions::new(Side::Sell, 10, 60, None, None, AccountId(1)).with_all_or_none();
+        assert!(ob.limit(aon).is_err());
+        ob.start_auction().unwrap();
+    }
+
+    #[test]
+    fn mixed_ties_clear_at_the_midpoint() {
+        let mut ob = OrderBookBuilder::new("outcome").build();
+        ob.start_auction().unwrap();
+        ob.limit_raw(Side::Buy, 5, 60, None, None, AccountId(1))
+            .unwrap();
+        ob.limit_raw(Side::Sell, 5, 40, None, None, AccountId(2))
+            .unwrap();
+        // Every price from 40 to 60 clears 5 with nothing left over
+        let uncross = ob.indicative_uncross().unwrap();
+        assert_eq!(uncross.price, Price(50));
+        assert_eq!(uncross.imbalance, Quantity(0));
+
+        // A price between two levels can clear better than either
+        ob.limit_raw(Side::Sell, 5, 50, None, None, AccountId(2))
+            .unwrap();
+        ob.limit_raw(Side::Buy, 5, 45, None, None, AccountId(1))
+            .unwrap();
+        let uncross = ob.indicative_uncross().unwrap();
+        assert_eq!(uncross.price, Price(47));
+        assert_eq!(uncross.volume, Quantity(5));
+        assert_eq!(uncross.imbalance, Quantity(0));
+        assert_eq!(uncross.imbalance_side, None);
+
+        let restored = OrderBookBuilder::new("outcome")
+            .with_snapshot(ob.snapshot())
+            .build();
+        assert!(restored.in_auction());
+    }
+
+    #[test]
+    fn mixed_pressure_clears_nearest_the_midpoint() {
+        let mut ob = OrderBookBuilder::new("outcome").build();
+        ob.start_auction().unwrap();
+        for (side, price) in [
+            (Side::Buy, 60),
+            (Side::Buy, 50),
+            (Side::Sell, 40),
+            (Side::Sell, 51),
+        ] {
+            ob.limit_raw(side, 5, price, None, None, AccountId(1))
+                .unwrap();
+        }
+        // Every price from 40 to 60 clears 5 and leaves 5 over, buyers up to
+        // 50 and sellers from 51: the midpoint is 50
+        let uncross = ob.indicative_uncross().unwrap();
+        assert_eq!(uncross.price, Price(50));
+        assert_eq!(uncross.imbalance, Quantity(5));
+        assert_eq!(uncross.imbalance_side, Some(Side::Buy));
+
+        // Past 55 the sellers leave 6 over, so the tie is 40 to 55 and its
+        // midpoint, 47, still falls among the buyers
+        ob.limit_raw(Side::Sell, 1, 56, None, None, AccountId(1))
+            .unwrap();
+        let uncross = ob.indicative_uncross().unwrap();
+        assert_eq!(uncross.price, Price(47));
+        assert_eq!(uncross.imbalance_side, Some(Side::Buy));
+    }
+}
@@ -7,26 +7,33 @@
 //!
 //! # Example
 //! ```rust
-//! use rust_order_book::{OrderBookBuilder, Side, MarketOrderOptions};
+//! use matching_engine::orderbook::{OrderBookBuilder, Side, MarketOrderOptions, order::AccountId};
 //!
 //! let mut ob = OrderBookBuilder::new("BTCUSD").with_journaling(true).build();
 //!
-//! let result = ob.market(MarketOrderOptions::new(Side::Buy, 10_000));
+//! let result = ob.market(MarketOrderOptions::new(Side::Buy, 10_000, AccountId(1)));
 //! ```
-use crate::orderbook::enums::{JournalOp, OrderOptions, OrderStatus, OrderType, Side, TimeInForce};
+use crate::orderbook::allocation::{Allocation, pro_rata};
+use crate::orderbook::enums::{
+    JournalOp, OrderOptions, OrderStatus, OrderType, Side, TimeInForce, TradingPhase,
+};
 use crate::orderbook::errors::{ErrorType, Result, make_error};
 use crate::orderbook::journal::{JournalLog, Snapshot};
+use crate::orderbook::level::{PriceLevel, RestingOrders};
 use crate::orderbook::order::{
-    AccountId, LimitOrder, LimitOrderOptions, MarketOrder, MarketOrderOptions, OrderId, Price,
-    Quantity,
+    AccountId, LimitOrder, LimitOrderOptions, MAX_PRICE, MarketOrder, MarketOrderOptions,
+    MarketProtection, NotionalOrderOptions, OrderId, Price, Quantity,
 };
+use crate::orderbook::peg::{Peg, Repricing};
 use crate::orderbook::report::{ExecutionReport, ExecutionReportParams, FillReport};
 use crate::orderbook::utils::{current_timestamp_millis, safe_add};
-use std::collections::VecDeque;
-use std::collections::{BTreeMap, HashMap};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use std::fmt;
 use std::ops::{Add, Div, Sub};
 
+/// Number of resting orders a book can hold before its storage grows.
+const ORDER_CAPACITY: usize = 100_000;
+
 /// Configuration options for initializing a new [`OrderBook`].
 ///
 /// # Fields
@@ -39,14 +46,32 @@ use std::ops::{Add, Div, Sub};
 #[derive(Debug, Clone, Default)]
 pub struct OrderBookOptions {
     pub journaling: bool,
+    pub allocation: Allocation,
     pub snapshot: Option<Snapshot>,
     pub replay_logs: Option<Vec<JournalLog>>,
 }
 
+/// Aggregated price levels of both sides, best price first.
+///
+/// `seq` is the book's depth sequence number at the time the depth was taken,
+/// so consumers can line it up with subsequent [`DepthDelta`]s.
 #[derive(Debug, PartialEq)]
 pub struct Depth {
     pub asks: Vec<(Price, Quantity)>, // (price, volume)
     pub bids: Vec<(Price, Quantity)>, // (price, volume)
+    pub seq: u64,
+}
+
+/// Levels changed since the previous delta, with their new volume.
+///
+/// A volume of zero means the level was removed. Deltas are numbered
+/// consecutively, so a gap in `seq` tells a consumer to resync from a full
+/// [`Depth`].
+#[derive(Debug, PartialEq)]
+pub struct DepthDelta {
+    pub asks: Vec<(Price, Quantity)>, // (price, volume)
+    pub bids: Vec<(Price, Quantity)>, // (price, volume)
+    pub seq: u64,
 }
 
 /// A limit order book implementation with support for market orders,
@@ -58,10 +83,18 @@ pub struct OrderBook {
     pub(crate) last_op: u64,
     pub(crate) symbol: String,
     pub(crate) next_order_id: OrderId,
-    pub(crate) orders: HashMap<OrderId, LimitOrder>,
-    pub(crate) asks: BTreeMap<Price, VecDeque<OrderId>>,
-    pub(crate) bids: BTreeMap<Price, VecDeque<OrderId>>,
+    pub(crate) orders: RestingOrders,
+    pub(crate) asks: BTreeMap<Price, PriceLevel>,
+    pub(crate) bids: BTreeMap<Price, PriceLevel>,
     pub(crate) journaling: bool,
+    pub(crate) depth_seq: u64,
+    pub(crate) changed_asks: BTreeSet<Price>,
+    pub(crate) changed_bids: BTreeSet<Price>,
+    pub(crate) phase: TradingPhase,
+    pub(crate) allocation: Allocation,
+    /// Ids of pegged orders, some of which may no longer rest
+    pub(crate) pegged: BTreeSet<OrderId>,
+    pub(crate) repriced: Vec<Repricing>,
 }
 
 impl OrderBook {
@@ -75,7 +108,7 @@ impl OrderBook {
     ///
     /// # Example
     /// ```
-    /// use rust_order_book::{OrderBook, OrderBookOptions};
+    /// use matching_engine::orderbook::{OrderBook, OrderBookOptions};
     /// let ob = OrderBook::new("BTCUSD", OrderBookOptions::default());
     /// ```
     pub fn new(symbol: &str, opts: OrderBookOptions) -> Self {
@@ -83,10 +116,17 @@ impl OrderBook {
             symbol: symbol.to_string(),
             last_op: 0,
             next_order_id: OrderId(1),
-            orders: HashMap::with_capacity(100_000),
+            orders: RestingOrders::with_capacity(ORDER_CAPACITY),
             asks: BTreeMap::new(),
             bids: BTreeMap::new(),
             journaling: opts.journaling,
+            depth_seq: 0,
+            changed_asks: BTreeSet::new(),
+            changed_bids: BTreeSet::new(),
+            phase: TradingPhase::Continuous,
+            allocation: opts.allocation,
+            pegged: BTreeSet::new(),
+            repriced: Vec::new(),
         }
     }
 
@@ -95,10 +135,24 @@ impl OrderBook {
         &self.symbol
     }
 
+    /// How takers are allocated among the orders of a price level
+    pub fn allocation(&self) -> Allocation {
+        self.allocation
+    }
+
+    /// Changes the allocation from the next match on; resting orders keep
+    /// their place in the queue.
+    pub fn set_allocation(&mut self, allocation: Allocation) {
+        self.allocation = allocation;
+    }
+
     /// Executes a market order against the order book.
     ///
     /// The order will immediately match with the best available opposite orders
-    /// until the quantity is filled or the book is exhausted.
+    /// until the quantity is filled or the book is exhausted. With a
+    /// protection, matching also stops at the protection price and the
+    /// remainder is cancelled: the report is then `Canceled`, with the fills
+    /// made before the bound.
     ///
     /// # Parameters
     /// - `options`: A [`MarketOrderOptions`] struct specifying the side and size.
@@ -124,16 +178,23 @@ impl OrderBook {
             account_id: order.account_id,
         });
 
+        let limit_price = options
+            .protection
+            .map(|protection| self.protection_price(order.side, protection));
         let mut fills = Vec::new();
         let remaining_qty = match order.side {
-            Side::Buy => self.match_with_asks(order.remaining_qty(), &mut fills, None),
-            Side::Sell => self.match_with_bids(order.remaining_qty(), &mut fills, None),
+            Side::Buy => self.match_with_asks(order.remaining_qty(), &mut fills, limit_price, None),
+            Side::Sell => {
+                self.match_with_bids(order.remaining_qty(), &mut fills, limit_price, None)
+            }
         };
         order.executed_qty = order.orig_qty.sub(remaining_qty);
-        order.status = if order.remaining_qty().value() > 0 {
-            OrderStatus::PartiallyFilled
-        } else {
+        order.status = if order.remaining_qty().value() == 0 {
             OrderStatus::Filled
+        } else if limit_price.is_some() {
+            OrderStatus::Canceled
+        } else {
+            OrderStatus::PartiallyFilled
         };
 
         report.remaining_qty = order.remaining_qty();
@@ -151,6 +212,7 @@ impl OrderBook {
                 o: OrderOptions::Market(options),
             })
         }
+        self.reprice_pegs();
 
         Ok(report)
     }
@@ -160,17 +222,112 @@ impl OrderBook {
         side: Side,
         quantity: u64,
     ) -> Result<ExecutionReport> {
-        self.market(MarketOrderOptions {
-            side,
-            quantity: Quantity(quantity),
-            account_id,
-        })
+        self.market(MarketOrderOptions::new(side, quantity, account_id))
+    }
+
+    /// Executes a notional market buy against the order book.
+    ///
+    /// The order spends up to `notional` on whole contracts, walking the asks
+    /// from the best price up until what is left of the budget cannot pay for
+    /// one more. The report gives the contracts bought as its executed
+    /// quantity and the amount spent as [`ExecutionReport::notional`].
+    ///
+    /// The order is `Filled` once its budget is used up, down to less than a
+    /// contract. If the asks, or those within its protection, run out first,
+    /// it is `Canceled` with the rest of the budget unspent.
+    ///
+    /// # Errors
+    /// Returns `Err` if the budget does not pay for a single contract at the
+    /// best ask.
+    pub fn market_notional(&mut self, options: NotionalOrderOptions) -> Result<ExecutionReport> {
+        self.validate_notional_order(&options)?;
+
+        let market = MarketOrderOptions {
+            side: Side::Buy,
+            quantity: Quantity(0),
+            account_id: options.account_id,
+            protection: options.protection,
+        };
+        let mut order = MarketOrder::new(self.new_order_id(), market);
+        let limit_price = options
+            .protection
+            .map(|protection| self.protection_price(Side::Buy, protection));
+        let mut fills = Vec::new();
+        let budget_left = self.match_notional(options.notional, &mut fills, limit_price);
+        order.executed_qty = fills.iter().map(|fill| fill.quantity).sum();
+        order.orig_qty = order.executed_qty;
+        // The budget is used up once it cannot pay for a contract at the next
+        // ask within reach, or at the last price paid if none is left
+        let next_price = self
+            .best_ask()
+            .filter(|ask| limit_price.is_none_or(|limit| *ask <= limit))
+            .or(fills.last().map(|fill| fill.price));
+        order.status = match next_price {
+            Some(price) if budget_left < price.value() => OrderStatus::Filled,
+            _ => OrderStatus::Canceled,
+        };
+
+        let mut report = ExecutionReport::new(ExecutionReportParams {
+            id: order.id,
+            order_type: OrderType::Market,
+            side: order.side,
+            quantity: order.orig_qty,
+            status: order.status,
+            time_in_force: None,
+            price: None,
+            post_only: false,
+            account_id: order.account_id,
+        });
+        report.remaining_qty = Quantity(0);
+        report.executed_qty = order.executed_qty;
+        report.taker_qty = order.executed_qty;
+        report.fills = fills;
+
+        if self.journaling {
+            self.last_op = safe_add(self.last_op, 1);
+            report.log = Some(JournalLog {
+                op_id: self.last_op,
+                ts: current_timestamp_millis(),
+                op: JournalOp::MarketNotional,
+                o: OrderOptions::MarketNotional(options),
+            })
+        }
+        self.reprice_pegs();
+
+        Ok(report)
+    }
+
+    /// The limit a market order of `side` under `protection` matches up to.
+    fn protection_price(&self, side: Side, protection: MarketProtection) -> Price {
+        match (protection, side) {
+            (MarketProtection::Price(price), _) => price,
+            // The validation made sure the opposite side is not empty
+            (MarketProtection::Ticks(ticks), Side::Buy) => {
+                let best_ask = self.best_ask().unwrap_or(Price(0));
+                Price(best_ask.value().saturating_add(ticks))
+            }
+            (MarketProtection::Ticks(ticks), Side::Sell) => {
+                let best_bid = self.best_bid().unwrap_or(Price(0));
+                Price(best_bid.value().saturating_sub(ticks))
+            }
+        }
     }
 
     /// Submits a new limit order to the order book.
     ///
     /// The order will be matched partially or fully if opposing liquidity exists,
-    /// otherwise it will rest in the book until matched or canceled.
+    /// otherwise it will rest in the book until matched or canceled. During an
+    /// auction it rests without matching, see [`OrderBook::uncross`].
+    ///
+    /// With a minimum quantity, no fill of the order, as taker or resting,
+    /// is smaller than that minimum or what is left of it. An all-or-none
+    /// order only trades on arrival if it fills completely, and otherwise
+    /// rests, even across the opposite side, until an order can take all of
+    /// it. Matching passes over resting orders a fill does not suit, leaving
+    /// them their place in the queue, so an order of any kind can rest
+    /// across the constrained orders it could not trade with. Uncrosses do
+    /// not look at these constraints, so constrained orders are refused
+    /// during auctions, and auctions do not start while they rest.
     ///
     /// # Parameters
     /// - `options`: A [`LimitOrderOptions`] with side, price, size, time-in-force and post_only.
@@ -184,6 +341,10 @@ impl OrderBook {
         self.validate_limit_order(&options)?;
 
         let mut order = LimitOrder::new(self.new_order_id(), options);
+        if let Some(peg) = options.peg {
+            // The validation made sure the peg has a price
+            order.price = self.peg_price(order.side, peg).unwrap_or(order.price);
+        }
         let mut report = ExecutionReport::new(ExecutionReportParams {
             id: order.id,
             order_type: OrderType::Limit,
@@ -196,12 +357,26 @@ impl OrderBook {
             account_id: order.account_id,
         });
 
+        // An all-or-none order only trades if it fills completely on arrival
+        let executes = !order.all_or_none
+            || self.fills_completely(order.side, order.remaining_qty(), order.price);
         let mut fills = Vec::new();
-        let remaining_qty = match order.side {
-            Side::Buy => self.match_with_asks(order.remaining_qty(), &mut fills, Some(order.price)),
-            Side::Sell => {
-                self.match_with_bids(order.remaining_qty(), &mut fills, Some(order.price))
-            }
+        let remaining_qty = match (self.phase, order.side) {
+            // Crossing orders wait for the uncross
+            (TradingPhase::Auction, _) => order.remaining_qty(),
+            (TradingPhase::Continuous, _) if !executes => order.remaining_qty(),
+            (TradingPhase::Continuous, Side::Buy) => self.match_with_asks(
+                order.remaining_qty(),
+                &mut fills,
+                Some(order.price),
+                order.min_qty,
+            ),
+            (TradingPhase::Continuous, Side::Sell) => self.match_with_bids(
+                order.remaining_qty(),
+                &mut fills,
+                Some(order.price),
+                order.min_qty,
+            ),
         };
         order.executed_qty = order.orig_qty.sub(remaining_qty);
         order.taker_qty = order.orig_qty.sub(order.remaining_qty());
@@ -216,17 +391,18 @@ impl OrderBook {
                 if order.executed_qty != Quantity(0) {
                     order.status = OrderStatus::PartiallyFilled;
                 }
-                self.orders.insert(order.id, order);
+                order.show_next_slice();
+                if order.peg.is_some() {
+                    self.pegged.insert(order.id);
+                }
                 if order.side == Side::Buy {
-                    self.bids
-                        .entry(order.price)
-                        .or_default()
-                        .push_back(order.id);
+                    let level = self.bids.entry(order.price).or_default();
+                    self.orders.push_back(level, order);
+                    self.changed_bids.insert(order.price);
                 } else {
-                    self.asks
-                        .entry(order.price)
-                        .or_default()
-                        .push_back(order.id);
+                    let level = self.asks.entry(order.price).or_default();
+                    self.orders.push_back(level, order);
+                    self.changed_asks.insert(order.price);
                 }
             }
         } else {
@@ -249,6 +425,7 @@ impl OrderBook {
                 o: OrderOptions::Limit(options),
             })
         }
+        self.reprice_pegs();
 
         Ok(report)
     }
@@ -268,6 +445,10 @@ impl OrderBook {
             time_in_force,
             post_only,
             account_id,
+            display_qty: None,
+            peg: None,
+            min_qty: None,
+            all_or_none: false,
         })
     }
 
@@ -282,24 +463,25 @@ impl OrderBook {
     /// # Errors
     /// Returns `Err` if the order is not found.
     pub fn cancel(&mut self, id: OrderId) -> Result<ExecutionReport> {
-        let mut order = match self.orders.remove(&id) {
-            Some(o) => o,
+        let (side, price) = match self.orders.get(&id) {
+            Some(o) => (o.side, o.price),
             None => return Err(make_error(ErrorType::OrderNotFound)),
         };
 
-        let book_side = match order.side {
-            Side::Buy => &mut self.bids,
-            Side::Sell => &mut self.asks,
+        let (book_side, changed) = match side {
+            Side::Buy => (&mut self.bids, &mut self.changed_bids),
+            Side::Sell => (&mut self.asks, &mut self.changed_asks),
         };
-
-        if let Some(queue) = book_side.get_mut(&order.price) {
-            if let Some(pos) = queue.iter().position(|x| *x == id) {
-                queue.remove(pos);
-            }
-            if queue.is_empty() {
-                book_side.remove(&order.price);
-            }
+        let Some(level) = book_side.get_mut(&price) else {
+            return Err(make_error(ErrorType::InvalidPriceLevel));
+        };
+        let Some(mut order) = self.orders.remove(level, &id) else {
+            return Err(make_error(ErrorType::OrderNotFound));
+        };
+        if level.is_empty() {
+            book_side.remove(&price);
         }
+        changed.insert(price);
 
         order.status = OrderStatus::Canceled;
 
@@ -330,6 +512,7 @@ impl OrderBook {
                 o: OrderOptions::Cancel(order.id),
             })
         }
+        self.reprice_pegs();
 
         Ok(report)
     }
@@ -364,7 +547,17 @@ impl OrderBook {
         price: Option<Price>,
         quantity: Option<Quantity>,
     ) -> Result<ExecutionReport> {
+        if price.is_none() && quantity.is_none() {
+            return Err(make_error(ErrorType::InvalidPriceOrQuantity));
+        }
         let old_journaling = self.journaling;
+        // An iceberg stays one, a peg keeps following its reference, and
+        // fill constraints carry over
+        let resting = self.orders.get(&id).copied();
+        let display_qty = resting.and_then(|order| order.display_qty);
+        let peg = resting.and_then(|order| order.peg);
+        let min_qty = resting.and_then(|order| order.min_qty);
+        let all_or_none = resting.is_some_and(|order| order.all_or_none);
         // Temporary disable journaling
         self.journaling = false;
         let report = match self.cancel(id) {
@@ -376,55 +569,41 @@ impl OrderBook {
             }
         };
 
-        let mut report = match (price, quantity) {
-            (None, Some(quantity)) => self.limit(LimitOrderOptions {
-                side: report.side,
-                quantity,
-                price: report.price,
-                time_in_force: Some(report.time_in_force),
-                post_only: Some(report.post_only),
-                account_id: report.account_id,
-            }),
-            (Some(price), None) => self.limit(LimitOrderOptions {
-                side: report.side,
-                quantity: report.remaining_qty,
-                price,
-                time_in_force: Some(report.time_in_force),
-                post_only: Some(report.post_only),
-                account_id: report.account_id,
+        // The price of a peg is its cap
+        let limit_price = price.unwrap_or(peg.map_or(report.price, |peg| peg.limit));
+        let mut report = self.limit(LimitOrderOptions {
+            side: report.side,
+            quantity: quantity.unwrap_or(report.remaining_qty),
+            price: limit_price,
+            time_in_force: Some(report.time_in_force),
+            post_only: Some(report.post_only),
+            account_id: report.account_id,
+            display_qty,
+            peg: peg.map(|peg| Peg {
+                limit: limit_price,
+                ..peg
             }),
-            (Some(price), Some(quantity)) => self.limit(LimitOrderOptions {
-                side: report.side,
-                quantity,
-                price,
-                time_in_force: Some(report.time_in_force),
-                post_only: Some(report.post_only),
-                account_id: report.account_id,
-            }),
-            (None, None) => {
-                // Restore previous journaling value before returning
-                self.journaling = old_journaling;
-                return Err(make_error(ErrorType::InvalidPriceOrQuantity));
-            }
-        };
+            min_qty,
+            all_or_none,
+        });
 
         // Restore previous journaling value
         self.journaling = old_journaling;
 
-        if let Ok(r) = report.as_mut() {
-            if self.journaling {
-                self.last_op = safe_add(self.last_op, 1);
-                r.log = Some(JournalLog {
-                    op_id: self.last_op,
-                    ts: current_timestamp_millis(),
-                    op: JournalOp::Modify,
-                    o: OrderOptions::Modify {
-                        id,
-                        price,
-                        quantity,
-                    },
-                });
-            }
+        if let Ok(r) = report.as_mut()
+            && self.journaling
+        {
+            self.last_op = safe_add(self.last_op, 1);
+            r.log = Some(JournalLog {
+                op_id: self.last_op,
+                ts: current_timestamp_millis(),
+                op: JournalOp::Modify,
+                o: OrderOptions::Modify {
+                    id,
+                    price,
+                    quantity,
+                },
+            });
         }
         report
     }
@@ -446,12 +625,8 @@ impl OrderBook {
             Side::Sell => self.asks.get(&price),
         };
 
-        if let Some(q) = queue {
-            for id in q {
-                if let Some(order) = self.orders.get(id) {
-                    orders.push(*order);
-                }
-            }
+        if let Some(level) = queue {
+            orders.extend(self.orders.iter_level(level).copied());
         }
         orders
     }
@@ -463,6 +638,35 @@ impl OrderBook {
         }
     }
 
+    /// Get all resting orders of an account, oldest first
+    pub fn account_orders(&self, account_id: AccountId) -> Vec<LimitOrder> {
+        self.orders
+            .account_order_ids(account_id)
+            .iter()
+            .filter_map(|id| self.orders.get(id).copied())
+            .collect()
+    }
+
+    /// Cancels every resting order of `account_id`, or of everyone when
+    /// `None`, oldest first.
+    ///
+    /// # Returns
+    /// One [`ExecutionReport`] per cancelled order, as [`OrderBook::cancel`]
+    /// gives them.
+    pub fn cancel_all(&mut self, account_id: Option<AccountId>) -> Vec<ExecutionReport> {
+        let ids = match account_id {
+            Some(account_id) => self.orders.account_order_ids(account_id),
+            None => {
+                let mut ids: Vec<OrderId> = self.orders.values().map(|o| o.id).collect();
+                ids.sort();
+                ids
+            }
+        };
+        ids.into_iter()
+            .filter_map(|id| self.cancel(id).ok())
+            .collect()
+    }
+
     /// Get the best bid price, if any
     pub fn best_bid(&self) -> Option<Price> {
         self.bids.last_key_value().map(|(price, _)| *price)
@@ -489,6 +693,19 @@ impl OrderBook {
         }
     }
 
+    /// Get the number of resting orders
+    pub fn resting_orders(&self) -> usize {
+        self.orders.len()
+    }
+
+    /// Get the number of price levels on one side of the book
+    pub fn price_levels(&self, side: Side) -> usize {
+        match side {
+            Side::Buy => self.bids.len(),
+            Side::Sell => self.asks.len(),
+        }
+    }
+
     /// Creates a complete snapshot of the current order book state.
     ///
     /// The snapshot includes all internal data necessary to fully restore the order book:
@@ -497,18 +714,31 @@ impl OrderBook {
     /// - `last_op`: the ID of the last operation performed
     /// - `next_order_id`: the next available order ID
     /// - `ts`: a timestamp representing when the snapshot was taken
+    /// - `depth_seq`: the sequence number of the last depth delta
+    /// - `phase`: whether the book is in an auction
     ///
     /// This function **does not fail** and can be called at any time.
     /// It returns a [`Snapshot`] struct, which can later be used with [`OrderBook::restore_snapshot`]
     /// to recreate the order book state exactly as it was at the moment of the snapshot.
     pub fn snapshot(&self) -> Snapshot {
+        let queues = |side: &BTreeMap<Price, PriceLevel>| {
+            side.iter()
+                .map(|(price, level)| {
+                    let queue = self.orders.iter_level(level).map(|o| o.id).collect();
+                    (*price, queue)
+                })
+                .collect()
+        };
         Snapshot {
-            orders: self.orders.clone(),
-            bids: self.bids.clone(),
-            asks: self.asks.clone(),
+            orders: self.orders.values().map(|o| (o.id, *o)).collect(),
+            bids: queues(&self.bids),
+            asks: queues(&self.asks),
             last_op: self.last_op,
             next_order_id: self.next_order_id,
             ts: current_timestamp_millis(),
+            depth_seq: self.depth_seq,
+            phase: self.phase,
+            allocation: Some(self.allocation),
         }
     }
 
@@ -519,18 +749,54 @@ impl OrderBook {
     ///
     /// # Parameters
     /// - `snapshot`: The snapshot to load into the order book.
-    pub fn restore_snapshot(&mut self, snapshot: Snapshot) {
-        self.orders = snapshot.orders;
-        self.bids = snapshot.bids;
-        self.asks = snapshot.asks;
+    pub fn restore_snapshot(&mut self, mut snapshot: Snapshot) {
+        // Leave headroom: growing the slab right after a restore would copy every order
+        let capacity = snapshot.orders.len().saturating_mul(2).max(ORDER_CAPACITY);
+        self.orders = RestingOrders::with_capacity(capacity);
+        self.bids = self.levels_from_queues(snapshot.bids, &mut snapshot.orders);
+        self.asks = self.levels_from_queues(snapshot.asks, &mut snapshot.orders);
         self.last_op = snapshot.last_op;
         self.next_order_id = snapshot.next_order_id;
+        self.depth_seq = snapshot.depth_seq;
+        self.phase = snapshot.phase;
+        if let Some(allocation) = snapshot.allocation {
+            self.allocation = allocation;
+        }
+        self.pegged = self
+            .orders
+            .values()
+            .filter(|order| order.peg.is_some())
+            .map(|order| order.id)
+            .collect();
+        self.repriced.clear();
+        // Every level may have changed: consumers must resync from a full depth
+        self.changed_bids = self.bids.keys().copied().collect();
+        self.changed_asks = self.asks.keys().copied().collect();
+    }
+
+    fn levels_from_queues(
+        &mut self,
+        queues: BTreeMap<Price, VecDeque<OrderId>>,
+        orders: &mut HashMap<OrderId, LimitOrder>,
+    ) -> BTreeMap<Price, PriceLevel> {
+        queues
+            .into_iter()
+            .map(|(price, queue)| {
+                let mut level = PriceLevel::default();
+                for order in queue.iter().filter_map(|id| orders.remove(id)) {
+                    self.orders.push_back(&mut level, order);
+                }
+                (price, level)
+            })
+            .filter(|(_, level)| !level.is_empty())
+            .collect()
     }
 
     /// Replays a sequence of journal logs to reconstruct the order book state.
     ///
     /// Each log entry represents a previously executed operation, such as a market order,
-    /// limit order, cancel, or modify. This function applies each operation in order.
+    /// limit order, cancel, modify, or the start or end of an auction. This function applies
+    /// each operation in order.
     ///
     /// # Parameters
     ///
@@ -547,15 +813,32 @@ impl OrderBook {
 
         for log in &logs {
             match &log.o {
-                OrderOptions::Market(opts) => self.market(*opts)?,
-                OrderOptions::Limit(opts) => self.limit(*opts)?,
-                OrderOptions::Cancel(id) => self.cancel(*id)?,
+                OrderOptions::Market(opts) => {
+                    self.market(*opts)?;
+                }
+                OrderOptions::MarketNotional(opts) => {
+                    self.market_notional(*opts)?;
+                }
+                OrderOptions::Limit(opts) => {
+                    self.limit(*opts)?;
+                }
+                OrderOptions::Cancel(id) => {
+                    self.cancel(*id)?;
+                }
                 OrderOptions::Modify {
                     id,
                     price,
                     quantity,
-                } => self.modify(*id, *price, *quantity)?,
-            };
+                } => {
+                    self.modify(*id, *price, *quantity)?;
+                }
+                OrderOptions::StartAuction => {
+                    self.start_auction()?;
+                }
+                OrderOptions::Uncross => {
+                    self.uncross();
+                }
+            }
         }
         Ok(())
     }
@@ -563,7 +846,8 @@ impl OrderBook {
     /// Returns the current depth of the order book.
     ///
     /// The depth includes aggregated quantities at each price level
-    /// for both the bid and ask sides.
+    /// for both the bid and ask sides. Icebergs only count with the slice
+    /// they show.
     ///
     /// # Parameters
     /// - `limit`: Optional maximum number of price levels per side
@@ -575,33 +859,58 @@ impl OrderBook {
         Depth {
             asks: self.get_asks_prices_and_volume(levels),
             bids: self.get_bids_prices_and_volume(levels),
+            seq: self.depth_seq,
         }
     }
 
     fn get_asks_prices_and_volume(&self, levels: usize) -> Vec<(Price, Quantity)> {
-        let mut asks = Vec::with_capacity(levels);
-        for (ask_price, queue) in self.asks.iter() {
-            let volume: Quantity = queue
-                .iter()
-                .filter_map(|id| self.orders.get(id))
-                .map(|order| order.remaining_qty())
-                .sum();
-            asks.push((*ask_price, volume));
-        }
-        asks
+        self.asks
+            .iter()
+            .take(levels)
+            .map(|(price, level)| (*price, level.visible_qty))
+            .collect()
     }
 
     fn get_bids_prices_and_volume(&self, levels: usize) -> Vec<(Price, Quantity)> {
-        let mut bids = Vec::with_capacity(levels);
-        for (bid_price, queue) in self.bids.iter().rev() {
-            let volume: Quantity = queue
-                .iter()
-                .filter_map(|id| self.orders.get(id))
-                .map(|order| order.remaining_qty())
-                .sum();
-            bids.push((*bid_price, volume));
+        self.bids
+            .iter()
+            .rev()
+            .take(levels)
+            .map(|(price, level)| (*price, level.visible_qty))
+            .collect()
+    }
+
+    /// Returns the levels changed since the last call, or `None` if the book
+    /// did not change.
+    ///
+    /// Each returned delta advances the depth sequence number by one.
+    pub fn take_depth_delta(&mut self) -> Option<DepthDelta> {
+        if self.changed_asks.is_empty() && self.changed_bids.is_empty() {
+            return None;
         }
-        bids
+        let level_volume = |side: &BTreeMap<Price, PriceLevel>, price: Price| {
+            (
+                price,
+                side.get(&price)
+                    .map(|level| level.visible_qty)
+                    .unwrap_or(Quantity(0)),
+            )
+        };
+        let asks = std::mem::take(&mut self.changed_asks)
+            .into_iter()
+            .map(|price| level_volume(&self.asks, price))
+            .collect();
+        let bids = std::mem::take(&mut self.changed_bids)
+            .into_iter()
+            .rev()
+            .map(|price| level_volume(&self.bids, price))
+            .collect();
+        self.depth_seq = safe_add(self.depth_seq, 1);
+        Some(DepthDelta {
+            asks,
+            bids,
+            seq: self.depth_seq,
+        })
     }
 
     fn match_with_asks(
@@ -609,6 +918,7 @@ impl OrderBook {
         quantity_to_fill: Quantity,
         fills: &mut Vec<FillReport>,
         limit_price: Option<Price>,
+        taker_min: Option<Quantity>,
     ) -> Quantity {
         // Early exit if the side is empty
         if self.asks.is_empty() {
@@ -616,17 +926,25 @@ impl OrderBook {
         }
         let mut remaining_qty = quantity_to_fill;
         let mut filled_prices = Vec::new();
-        for (ask_price, queue) in self.asks.iter_mut() {
+        for (ask_price, level) in self.asks.iter_mut() {
             if remaining_qty.value() == 0 {
                 break;
             }
-            if let Some(limit_price) = limit_price {
-                if limit_price < *ask_price {
-                    break;
-                }
+            if let Some(limit_price) = limit_price
+                && limit_price < *ask_price
+            {
+                break;
             }
-            remaining_qty = Self::process_queue(&mut self.orders, queue, remaining_qty, fills);
-            if queue.is_empty() {
+            remaining_qty = Self::process_queue(
+                &mut self.orders,
+                level,
+                remaining_qty,
+                fills,
+                self.allocation,
+                taker_min,
+            );
+            self.changed_asks.insert(*ask_price);
+            if level.is_empty() {
                 filled_prices.push(*ask_price);
             }
         }
@@ -636,11 +954,54 @@ impl OrderBook {
         remaining_qty
     }
 
+    /// Buys whole contracts from the asks for up to `budget`, and returns
+    /// what is left of it.
+    fn match_notional(
+        &mut self,
+        budget: u64,
+        fills: &mut Vec<FillReport>,
+        limit_price: Option<Price>,
+    ) -> u64 {
+        let mut budget_left = budget;
+        let mut filled_prices = Vec::new();
+        for (ask_price, level) in self.asks.iter_mut() {
+            if let Some(limit_price) = limit_price
+                && limit_price < *ask_price
+            {
+                break;
+            }
+            // Asks only get dearer, so once a contract is out of reach here
+            // it is everywhere after
+            let quantity = budget_left / ask_price.value();
+            if quantity == 0 {
+                break;
+            }
+            let remaining_qty = Self::process_queue(
+                &mut self.orders,
+                level,
+                Quantity(quantity),
+                fills,
+                self.allocation,
+                None,
+            );
+            budget_left -= (quantity - remaining_qty.value()) * ask_price.value();
+            self.changed_asks.insert(*ask_price);
+            if level.is_empty() {
+                filled_prices.push(*ask_price);
+            }
+        }
+        for price in filled_prices {
+            self.asks.remove(&price);
+        }
+        budget_left
+    }
+
     fn match_with_bids(
         &mut self,
         quantity_to_fill: Quantity,
         fills: &mut Vec<FillReport>,
         limit_price: Option<Price>,
+        taker_min: Option<Quantity>,
     ) -> Quantity {
         // Early exit if the side is empty
         if self.bids.is_empty() {
@@ -648,17 +1009,25 @@ impl OrderBook {
         }
         let mut remaining_qty = quantity_to_fill;
         let mut filled_prices = Vec::new();
-        for (bid_price, queue) in self.bids.iter_mut().rev() {
+        for (bid_price, level) in self.bids.iter_mut().rev() {
             if remaining_qty.value() == 0 {
                 break;
             }
-            if let Some(limit_price) = limit_price {
-                if limit_price > *bid_price {
-                    break;
-                }
+            if let Some(limit_price) = limit_price
+                && limit_price > *bid_price
+            {
+                break;
             }
-            remaining_qty = Self::process_queue(&mut self.orders, queue, remaining_qty, fills);
-            if queue.is_empty() {
+            remaining_qty = Self::process_queue(
+                &mut self.orders,
+                level,
+                remaining_qty,
+                fills,
+                self.allocation,
+                taker_min,
+            );
+            self.changed_bids.insert(*bid_price);
+            if level.is_empty() {
                 filled_prices.push(*bid_price);
             }
         }
@@ -668,47 +1037,148 @@ impl OrderBook {
         remaining_qty
     }
 
+    /// Fills up to `remaining_qty` from `level` and returns what is left.
+    ///
+    /// Fills a resting order or the taker could not accept, see
+    /// [`fill_allowed`], are not made: the resting order is passed over and
+    /// keeps its place in the queue.
     fn process_queue(
-        orders: &mut HashMap<OrderId, LimitOrder>,
-        order_queue: &mut VecDeque<OrderId>,
+        orders: &mut RestingOrders,
+        level: &mut PriceLevel,
+        remaining_qty: Quantity,
+        fills: &mut Vec<FillReport>,
+        allocation: Allocation,
+        taker_min: Option<Quantity>,
+    ) -> Quantity {
+        match allocation {
+            Allocation::Fifo => {
+                Self::process_queue_fifo(orders, level, remaining_qty, fills, taker_min)
+            }
+            Allocation::ProRata => {
+                Self::process_queue_pro_rata(orders, level, remaining_qty, fills, taker_min)
+            }
+            Allocation::TopOrderProRata => {
+                // The top order only gets its priority fill if it can take it
+                let top_qty = orders.front_mut(level).map_or(0, |order| {
+                    let quantity = Quantity(order.visible_qty().value().min(remaining_qty.value()));
+                    if fill_allowed(order, quantity, taker_min, remaining_qty) {
+                        quantity.value()
+                    } else {
+                        0
+                    }
+                });
+                let left =
+                    Self::process_queue_fifo(orders, level, Quantity(top_qty), fills, taker_min);
+                debug_assert_eq!(left.value(), 0);
+                Self::process_queue_pro_rata(
+                    orders,
+                    level,
+                    remaining_qty.sub(Quantity(top_qty)),
+                    fills,
+                    taker_min,
+                )
+            }
+        }
+    }
+
+    fn process_queue_fifo(
+        orders: &mut RestingOrders,
+        level: &mut PriceLevel,
         remaining_qty: Quantity,
         fills: &mut Vec<FillReport>,
+        taker_min: Option<Quantity>,
     ) -> Quantity {
         let mut quantity_left = remaining_qty;
-        while !order_queue.is_empty() && quantity_left.value() > 0 {
-            let Some(head_order_uuid) = order_queue.front() else {
+        // Orders passed over stay ahead of the next one to fill
+        let mut cursor = level.head();
+        let mut taker_needs = taker_fill_min(taker_min, quantity_left);
+        while quantity_left.value() > 0 {
+            let Some(key) = cursor else {
                 break;
             };
-            let Some(mut head_order) = orders.remove(head_order_uuid) else {
-                break;
-            };
-
-            if quantity_left < head_order.remaining_qty() {
-                head_order.executed_qty = head_order.executed_qty.add(quantity_left);
-                head_order.status = OrderStatus::PartiallyFilled;
-                fills.push(FillReport {
-                    order_id: head_order.id,
-                    price: head_order.price,
-                    quantity: quantity_left,
-                    status: head_order.status,
-                    account_id: head_order.account_id,
-                });
-                orders.insert(head_order.id, head_order);
-
-                quantity_left = Quantity(0);
-            } else {
-                order_queue.pop_front();
-                quantity_left = quantity_left.sub(head_order.remaining_qty());
+            let (next_order, next_key) = orders.at(key);
+            cursor = next_key;
+            // A partial fill leaves the order in place, unless it used up an
+            // iceberg's slice
+            let quantity = Quantity(quantity_left.value().min(next_order.visible_qty().value()));
+            if !fill_allowed(next_order, quantity, taker_min, quantity_left) {
+                continue;
+            }
+            let next_order_id = next_order.id;
+            let order = orders.fill(level, &next_order_id, quantity);
+            fills.push(FillReport {
+                order_id: order.id,
+                price: order.price,
+                quantity,
+                status: order.status,
+                account_id: order.account_id,
+            });
+            quantity_left = quantity_left.sub(quantity);
+            // An iceberg moved from the back of the queue is next again
+            if cursor.is_none() && order.status != OrderStatus::Filled {
+                cursor = level.tail();
+            }
+            // Once the taker has less left than its minimum, the orders it
+            // passed over may do again
+            if taker_fill_min(taker_min, quantity_left) < taker_needs {
+                taker_needs = taker_fill_min(taker_min, quantity_left);
+                cursor = level.head();
+            }
+        }
+        quantity_left
+    }
 
-                head_order.executed_qty = head_order.executed_qty.add(head_order.remaining_qty());
-                head_order.status = OrderStatus::Filled;
+    /// Shares `remaining_qty` among the orders of `level` in proportion to
+    /// their visible quantity, see [`crate::orderbook::allocation`].
+    ///
+    /// Icebergs only take part with their shown slice; once every slice is
+    /// used up, what is left is shared again among the next ones. An order
+    /// whose share it or the taker could not accept is left out, and the
+    /// share goes back to be shared among the others.
+    fn process_queue_pro_rata(
+        orders: &mut RestingOrders,
+        level: &mut PriceLevel,
+        remaining_qty: Quantity,
+        fills: &mut Vec<FillReport>,
+        taker_min: Option<Quantity>,
+    ) -> Quantity {
+        let mut quantity_left = remaining_qty;
+        let mut left_out = BTreeSet::new();
+        let mut taker_needs = taker_fill_min(taker_min, quantity_left);
+        while quantity_left.value() > 0 {
+            if taker_fill_min(taker_min, quantity_left) < taker_needs {
+                taker_needs = taker_fill_min(taker_min, quantity_left);
+                left_out.clear();
+            }
+            let queue: Vec<(OrderId, u64)> = orders
+                .iter_level(level)
+                .filter(|order| !left_out.contains(&order.id))
+                .map(|order| (order.id, order.visible_qty().value()))
+                .collect();
+            if queue.is_empty() {
+                break;
+            }
+            let sizes: Vec<u64> = queue.iter().map(|(_, size)| *size).collect();
+            let shares = pro_rata(quantity_left.value(), &sizes);
+            let round_left = quantity_left;
+            for ((order_id, _), share) in queue.into_iter().zip(shares) {
+                if share == 0 {
+                    continue;
+                }
+                let resting = orders.get(&order_id).expect("the order is in the level");
+                if !fill_allowed(resting, Quantity(share), taker_min, round_left) {
+                    left_out.insert(order_id);
+                    continue;
+                }
+                let order = orders.fill(level, &order_id, Quantity(share));
                 fills.push(FillReport {
-                    order_id: head_order.id,
-                    price: head_order.price,
-                    quantity: head_order.executed_qty,
-                    status: head_order.status,
-                    account_id: head_order.account_id,
+                    order_id,
+                    price: order.price,
+                    quantity: Quantity(share),
+                    status: order.status,
+                    account_id: order.account_id,
                 });
+                quantity_left = quantity_left.sub(Quantity(share));
             }
         }
         quantity_left
@@ -718,6 +1188,12 @@ impl OrderBook {
         if options.quantity.value() == 0 {
             return Err(make_error(ErrorType::InvalidQuantity));
         }
+        if self.phase == TradingPhase::Auction {
+            return Err(make_error(ErrorType::AuctionInProgress));
+        }
+        if options.protection == Some(MarketProtection::Price(Price(0))) {
+            return Err(make_error(ErrorType::InvalidPrice));
+        }
         if (options.side == Side::Buy && self.asks.is_empty())
             || (options.side == Side::Sell && self.bids.is_empty())
         {
@@ -726,117 +1202,827 @@ impl OrderBook {
         Ok(())
     }
 
-    fn validate_limit_order(&self, options: &LimitOrderOptions) -> Result<()> {
-        if options.quantity.value() == 0 {
+    fn validate_notional_order(&self, options: &NotionalOrderOptions) -> Result<()> {
+        if self.phase == TradingPhase::Auction {
+            return Err(make_error(ErrorType::AuctionInProgress));
+        }
+        if options.protection == Some(MarketProtection::Price(Price(0))) {
+            return Err(make_error(ErrorType::InvalidPrice));
+        }
+        let Some(best_ask) = self.best_ask() else {
+            return Err(make_error(ErrorType::OrderBookEmpty));
+        };
+        if options.notional < best_ask.value() {
+            return Err(make_error(ErrorType::InvalidQuantity));
+        }
+        Ok(())
+    }
+
+    /// Whether [`OrderBook::limit`] would accept `options` now.
+    pub(crate) fn validate_limit_order(&self, options: &LimitOrderOptions) -> Result<()> {
+        if options.quantity.value() == 0 || options.display_qty == Some(Quantity(0)) {
             return Err(make_error(ErrorType::InvalidQuantity));
         }
-        if options.price.value() == 0 {
+        if options.price.value() == 0 || options.price.value() > MAX_PRICE {
             return Err(make_error(ErrorType::InvalidPrice));
         }
+        // A peg never needs to move further than the whole price range
+        if options
+            .peg
+            .is_some_and(|peg| peg.offset.unsigned_abs() > MAX_PRICE)
+        {
+            return Err(make_error(ErrorType::InvalidPeg));
+        }
+        let constrained = options.all_or_none || options.min_qty.is_some();
         let time_in_force = options.time_in_force.unwrap_or(TimeInForce::GTC);
-        if time_in_force == TimeInForce::FOK
-            && !self.limit_order_is_fillable(options.side, options.quantity, options.price)
+        // An iceberg could never show enough of itself to meet a constraint,
+        // and orders that take their whole quantity on arrival take no
+        // minimum fill on top
+        if options.min_qty == Some(Quantity(0))
+            || (constrained && options.display_qty.is_some())
+            || (options.min_qty.is_some()
+                && (options.all_or_none || time_in_force == TimeInForce::FOK))
         {
-            return Err(make_error(ErrorType::OrderFOK));
+            return Err(make_error(ErrorType::InvalidQuantity));
         }
-        if options.post_only.unwrap_or(false) {
-            let crosses = match options.side {
-                Side::Buy => {
-                    if let Some((best_ask, _)) = self.asks.first_key_value() {
-                        options.price >= *best_ask
-                    } else {
-                        false
-                    }
-                }
-                Side::Sell => {
-                    if let Some((best_bid, _)) = self.bids.last_key_value() {
-                        options.price <= *best_bid
-                    } else {
-                        false
-                    }
+        // Uncrosses execute whole levels without looking at constraints
+        if constrained && self.phase == TradingPhase::Auction {
+            return Err(make_error(ErrorType::AuctionInProgress));
+        }
+        if let Some(peg) = options.peg {
+            // Pegs only rest, and are only repriced outside auctions
+            if self.phase == TradingPhase::Auction {
+                return Err(make_error(ErrorType::AuctionInProgress));
+            }
+            if time_in_force != TimeInForce::GTC || self.peg_price(options.side, peg).is_none() {
+                return Err(make_error(ErrorType::InvalidPeg));
+            }
+            return Ok(());
+        }
+        if self.phase == TradingPhase::Auction {
+            // Nothing executes before the uncross, so only resting orders make sense
+            return match time_in_force {
+                TimeInForce::GTC => Ok(()),
+                TimeInForce::IOC | TimeInForce::FOK => {
+                    Err(make_error(ErrorType::AuctionInProgress))
                 }
             };
-
-            if crosses {
-                return Err(make_error(ErrorType::OrderPostOnly));
-            }
+        }
+        if time_in_force == TimeInForce::FOK
+            && !self.fills_completely(options.side, options.quantity, options.price)
+        {
+            return Err(make_error(ErrorType::OrderFOK));
+        }
+        if options.post_only.unwrap_or(false) && self.crosses(options.side, options.price) {
+            return Err(make_error(ErrorType::OrderPostOnly));
         }
         Ok(())
     }
 
-    fn limit_order_is_fillable(&self, side: Side, quantity: Quantity, price: Price) -> bool {
-        if side == Side::Buy {
-            self.limit_buy_order_is_fillable(quantity, price)
-        } else {
-            self.limit_sell_order_is_fillable(quantity, price)
+    /// Whether an order on `side` at `price` would trade with the best
+    /// order on the other side, if nothing kept them apart.
+    fn crosses(&self, side: Side, price: Price) -> bool {
+        match side {
+            Side::Buy => self
+                .asks
+                .first_key_value()
+                .is_some_and(|(best_ask, _)| price >= *best_ask),
+            Side::Sell => self
+                .bids
+                .last_key_value()
+                .is_some_and(|(best_bid, _)| price <= *best_bid),
         }
     }
 
-    fn limit_buy_order_is_fillable(&self, quantity: Quantity, price: Price) -> bool {
-        let mut cumulative_qty = Quantity(0);
-        for (ask_price, queue) in self.asks.iter() {
-            if price >= *ask_price && cumulative_qty < quantity {
-                for id in queue.iter() {
-                    if let Some(order) = self.orders.get(id) {
-                        cumulative_qty += order.remaining_qty().value();
-                    }
-                }
-            } else {
-                break;
+    /// Whether `quantity` at up to `price` on `side` would fill completely.
+    ///
+    /// The crossing levels are walked in place, in the order matching would
+    /// reach them, see [`OrderBook::left_after_level`].
+    fn fills_completely(&self, side: Side, quantity: Quantity, price: Price) -> bool {
+        let mut asks;
+        let mut bids;
+        let crossing: &mut dyn Iterator<Item = &PriceLevel> = match side {
+            Side::Buy => {
+                asks = self.asks.range(..=price).map(|(_, level)| level);
+                &mut asks
+            }
+            Side::Sell => {
+                bids = self.bids.range(price..).rev().map(|(_, level)| level);
+                &mut bids
+            }
+        };
+        let mut quantity_left = quantity.value();
+        for level in crossing {
+            match self.left_after_level(level, quantity_left) {
+                Some(0) => return true,
+                Some(left) => quantity_left = left,
+                None => return false,
             }
         }
-        cumulative_qty >= quantity
+        false
     }
 
-    fn limit_sell_order_is_fillable(&self, quantity: Quantity, price: Price) -> bool {
-        let mut cumulative_qty = Quantity(0);
-        for (bid_price, queue) in self.bids.iter().rev() {
-            if price <= *bid_price && cumulative_qty < quantity {
-                for id in queue.iter() {
-                    if let Some(order) = self.orders.get(id) {
-                        cumulative_qty += order.remaining_qty().value()
+    /// What is left of `quantity_left` after a taker without a minimum fill
+    /// matches `level`, or `None` when that cannot be told without matching.
+    ///
+    /// In first in first out order an all-or-none or minimum quantity order
+    /// takes its fill only if what is left by the time it is reached meets
+    /// it, and the slices icebergs show after their first come once the
+    /// queue is through. Pro-rata shares of constrained orders depend on the
+    /// whole level, so such a level only counts when its other orders fill
+    /// what is left.
+    fn left_after_level(&self, level: &PriceLevel, quantity_left: u64) -> Option<u64> {
+        let mut left = quantity_left;
+        let mut orders = self.orders.iter_level(level);
+        match self.allocation {
+            Allocation::Fifo => {
+                let mut next_slices = 0;
+                for order in orders {
+                    if left == 0 {
+                        break;
+                    }
+                    let quantity = order.visible_qty().value().min(left);
+                    if quantity < order.min_fill().value() {
+                        continue;
                     }
+                    left -= quantity;
+                    next_slices += order.remaining_qty().value() - quantity;
+                }
+                Some(left - left.min(next_slices))
+            }
+            Allocation::ProRata | Allocation::TopOrderProRata => {
+                let mut unconstrained = 0;
+                if self.allocation == Allocation::TopOrderProRata {
+                    let top = orders.next()?;
+                    let quantity = top.visible_qty().value().min(left);
+                    if quantity >= top.min_fill().value() {
+                        // A constrained top order only keeps a rest once the
+                        // taker is done
+                        left -= quantity;
+                        unconstrained = top.remaining_qty().value() - quantity;
+                    } else {
+                        // The top order is shared pro-rata with the others
+                        orders = self.orders.iter_level(level);
+                    }
+                }
+                let mut constrained = false;
+                for order in orders {
+                    if order.min_fill().value() > 0 {
+                        constrained = true;
+                    } else {
+                        unconstrained += order.remaining_qty().value();
+                    }
+                }
+                let shared = level.total_qty.value() - (quantity_left - left);
+                match (constrained, unconstrained >= left) {
+                    (_, true) => Some(0),
+                    (false, false) => Some(left - left.min(shared)),
+                    (true, false) => None,
                 }
-            } else {
-                break;
             }
         }
-        cumulative_qty >= quantity
     }
 
-    fn new_order_id(&mut self) -> OrderId {
+    pub(crate) fn new_order_id(&mut self) -> OrderId {
         let id = self.next_order_id;
         self.next_order_id += 1;
         id
     }
 }
 
+/// Whether `maker` and a taker with `quantity_left` to fill and a minimum
+/// fill of `taker_min` both accept a fill of `quantity`.
+///
+/// A minimum never asks for more than what is left of its order, so the last
+/// piece of an order below its minimum can still execute.
+fn fill_allowed(
+    maker: &LimitOrder,
+    quantity: Quantity,
+    taker_min: Option<Quantity>,
+    quantity_left: Quantity,
+) -> bool {
+    quantity.value() >= maker.min_fill().value()
+        && quantity.value() >= taker_fill_min(taker_min, quantity_left)
+}
+
+/// Smallest fill a taker with `quantity_left` to fill accepts.
+fn taker_fill_min(taker_min: Option<Quantity>, quantity_left: Quantity) -> u64 {
+    taker_min.map_or(0, |min| min.value().min(quantity_left.value()))
+}
+
 impl fmt::Display for OrderBook {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         // --- ASKs ---
-        for (price, order_ids) in self.asks.iter().rev() {
-            let volume: Quantity = order_ids
-                .iter()
-                .filter_map(|id| self.orders.get(id))
-                .map(|order| order.remaining_qty())
-                .sum();
-
-            writeln!(f, "{} -> {}", price.value(), volume.value())?;
+        for (price, level) in self.asks.iter().rev() {
+            writeln!(f, "{} -> {}", price.value(), level.total_qty.value())?;
         }
 
         writeln!(f, "------------------------------------")?;
 
         // --- BIDs ---
-        for (price, order_ids) in self.bids.iter().rev() {
-            let volume: Quantity = order_ids
+        for (price, level) in self.bids.iter().rev() {
+            writeln!(f, "{} -> {}", price.value(), level.total_qty.value())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::OrderBookBuilder;
+
+    fn book_with_levels() -> OrderBook {
+        let mut ob = OrderBookBuilder::new("outcome").build();
+        for price in [40, 41, 42] {
+            ob.limit_raw(Side::Buy, 10, price, None, None, AccountId(1))
+                .unwrap();
+        }
+        for price in [60, 61, 62] {
+            ob.limit_raw(Side::Sell, 10, price, None, None, AccountId(2))
+                .unwrap();
+        }
+        ob
+    }
+
+    #[test]
+    fn depth_respects_level_limit() {
+        let ob = book_with_levels();
+        let depth = ob.depth(Some(2));
+        assert_eq!(
+            depth.asks,
+            vec![(Price(60), Quantity(10)), (Price(61), Quantity(10))]
+        );
+        assert_eq!(
+            depth.bids,
+            vec![(Price(42), Quantity(10)), (Price(41), Quantity(10))]
+        );
+        assert_eq!(ob.depth(None).asks.len(), 3);
+    }
+
+    #[test]
+    fn depth_delta_reports_only_changed_levels() {
+        let mut ob = book_with_levels();
+        let initial = ob.take_depth_delta().unwrap();
+        assert_eq!(initial.seq, 1);
+        assert_eq!(initial.asks.len(), 3);
+        assert_eq!(ob.take_depth_delta(), None);
+
+        // Sweeps 60 and part of 61
+        ob.market_raw(AccountId(3), Side::Buy, 15).unwrap();
+        let delta = ob.take_depth_delta().unwrap();
+        assert_eq!(delta.seq, 2);
+        assert_eq!(
+            delta.asks,
+            vec![(Price(60), Quantity(0)), (Price(61), Quantity(5))]
+        );
+        assert!(delta.bids.is_empty());
+        assert_eq!(ob.depth(Some(1)).seq, 2);
+    }
+
+    #[test]
+    fn cancel_updates_level_volume() {
+        let mut ob = OrderBookBuilder::new("outcome").build();
+        let first = ob
+            .limit_raw(Side::Buy, 10, 40, None, None, AccountId(1))
+            .unwrap();
+        ob.limit_raw(Side::Buy, 5, 40, None, None, AccountId(2))
+            .unwrap();
+        ob.take_depth_delta();
+
+        ob.cancel(first.order_id).unwrap();
+        assert_eq!(ob.depth(None).bids, vec![(Price(40), Quantity(5))]);
+        assert_eq!(
+            ob.take_depth_delta().unwrap().bids,
+            vec![(Price(40), Quantity(5))]
+        );
+    }
+
+    #[test]
+    fn fill_reports_only_the_quantity_taken_from_each_maker() {
+        let mut ob = OrderBookBuilder::new("outcome").build();
+        let maker = ob
+            .limit_raw(Side::Sell, 10, 50, None, None, AccountId(1))
+            .unwrap();
+        ob.limit_raw(Side::Sell, 10, 50, None, None, AccountId(2))
+            .unwrap();
+
+        ob.market_raw(AccountId(3), Side::Buy, 4).unwrap();
+        let report = ob.market_raw(AccountId(3), Side::Buy, 8).unwrap();
+        let fills: Vec<_> = report
+            .fills
+            .iter()
+            .map(|f| (f.order_id, f.quantity, f.status))
+            .collect();
+        assert_eq!(
+            fills,
+            vec![
+                (maker.order_id, Quantity(6), OrderStatus::Filled),
+                (OrderId(2), Quantity(2), OrderStatus::PartiallyFilled),
+            ]
+        );
+        assert_eq!(ob.depth(None).asks, vec![(Price(50), Quantity(8))]);
+        assert!(ob.get_order(maker.order_id).is_err());
+    }
+
+    #[test]
+    fn snapshot_restore_rebuilds_level_volume() {
+        let mut ob = book_with_levels();
+        ob.market_raw(AccountId(3), Side::Sell, 4).unwrap();
+        let snapshot = ob.snapshot();
+
+        let restored = OrderBookBuilder::new("outcome")
+            .with_snapshot(snapshot)
+            .build();
+        assert_eq!(
+            restored.depth(None),
+            Depth {
+                seq: 0,
+                ..ob.depth(None)
+            }
+        );
+    }
+
+    #[test]
+    fn account_orders_follow_fills_cancels_and_restore() {
+        let mut ob = book_with_levels();
+        ob.limit_raw(Side::Buy, 5, 39, None, None, AccountId(2))
+            .unwrap();
+        // Fills account 1's order at 42
+        ob.market_raw(AccountId(3), Side::Sell, 10).unwrap();
+        let prices = |ob: &OrderBook, account_id| -> Vec<u64> {
+            ob.account_orders(AccountId(account_id))
                 .iter()
-                .filter_map(|id| self.orders.get(id))
-                .map(|order| order.remaining_qty())
-                .sum();
+                .map(|o| o.price.0)
+                .collect()
+        };
+        assert_eq!(prices(&ob, 1), vec![40, 41]);
+        assert_eq!(prices(&ob, 2), vec![60, 61, 62, 39]);
+
+        let restored = OrderBookBuilder::new("outcome")
+            .with_snapshot(ob.snapshot())
+            .build();
+        assert_eq!(prices(&restored, 2), vec![60, 61, 62, 39]);
+
+        let cancelled = ob.cancel_all(Some(AccountId(2)));
+        assert_eq!(cancelled.len(), 4);
+        assert!(cancelled.iter().all(|r| r.status == OrderStatus::Canceled));
+        assert!(prices(&ob, 2).is_empty());
+        assert!(ob.depth(None).asks.is_empty());
+        assert_eq!(ob.resting_orders(), 2);
+
+        assert_eq!(ob.cancel_all(None).len(), 2);
+        assert_eq!(ob.resting_orders(), 0);
+    }
 
-            writeln!(f, "{} -> {}", price.value(), volume.value())?;
+    #[test]
+    fn protected_market_orders_stop_at_their_bound() {
+        let protected = |protection| {
+            MarketOrderOptions::new(Side::Buy, 25, AccountId(3)).with_protection(protection)
+        };
+
+        // Sweeps 60 and 61, then stops short of 62
+        let mut ob = book_with_levels();
+        let report = ob
+            .market(protected(MarketProtection::Price(Price(61))))
+            .unwrap();
+        assert_eq!(report.status, OrderStatus::Canceled);
+        assert_eq!(report.executed_qty, Quantity(20));
+        assert_eq!(report.remaining_qty, Quantity(5));
+        assert_eq!(ob.depth(None).asks, vec![(Price(62), Quantity(10))]);
+        assert_eq!(ob.resting_orders(), 4);
+
+        // One tick above the best ask on arrival is the same bound
+        let mut ob = book_with_levels();
+        let report = ob.market(protected(MarketProtection::Ticks(1))).unwrap();
+        assert_eq!(report.executed_qty, Quantity(20));
+
+        // Sells count their ticks down from the best bid
+        let mut ob = book_with_levels();
+        let sell = MarketOrderOptions::new(Side::Sell, 15, AccountId(3))
+            .with_protection(MarketProtection::Ticks(0));
+        let report = ob.market(sell).unwrap();
+        assert_eq!(report.status, OrderStatus::Canceled);
+        assert_eq!(report.executed_qty, Quantity(10));
+
+        // Within the bound the order fills as usual
+        let mut ob = book_with_levels();
+        let report = ob
+            .market(protected(MarketProtection::Price(Price(99))))
+            .unwrap();
+        assert_eq!(report.status, OrderStatus::Filled);
+
+        assert!(
+            ob.market(protected(MarketProtection::Price(Price(0))))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn notional_orders_buy_whole_contracts_within_budget() {
+        // 10 at 60 for 600, then 6 at 61 for 366; 34 is left, short of 61
+        let mut ob = book_with_levels();
+        let report = ob
+            .market_notional(NotionalOrderOptions::new(1_000, AccountId(3)))
+            .unwrap();
+        assert_eq!(report.status, OrderStatus::Filled);
+        assert_eq!(report.executed_qty, Quantity(16));
+        assert_eq!(report.remaining_qty, Quantity(0));
+        assert_eq!(report.notional(), 966);
+        assert_eq!(
+            ob.depth(None).asks,
+            vec![(Price(61), Quantity(4)), (Price(62), Quantity(10))]
+        );
+
+        // Buying every ask still leaves budget
+        let mut ob = book_with_levels();
+        let report = ob
+            .market_notional(NotionalOrderOptions::new(5_000, AccountId(3)))
+            .unwrap();
+        assert_eq!(report.status, OrderStatus::Canceled);
+        assert_eq!(report.executed_qty, Quantity(30));
+        assert_eq!(report.notional(), 1_830);
+
+        // The protection stops it at 60
+        let mut ob = book_with_levels();
+        let options = NotionalOrderOptions::new(1_000, AccountId(3))
+            .with_protection(MarketProtection::Price(Price(60)));
+        let report = ob.market_notional(options).unwrap();
+        assert_eq!(report.status, OrderStatus::Canceled);
+        assert_eq!(report.notional(), 600);
+
+        // Not even one contract at the best ask
+        assert!(
+            ob.market_notional(NotionalOrderOptions::new(60, AccountId(3)))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn icebergs_show_one_slice_and_requeue_it() {
+        let mut ob = OrderBookBuilder::new("outcome")
+            .with_journaling(true)
+            .build();
+        let mut logs = Vec::new();
+        let iceberg = LimitOrderOptions::new(Side::Sell, 100, 50, None, None, AccountId(1))
+            .with_display_qty(10);
+        let mut place = |ob: &mut OrderBook, options| {
+            let report = ob.limit(options).unwrap();
+            logs.extend(report.log);
+            report
+        };
+        let iceberg = place(&mut ob, iceberg).order_id;
+        let visible = place(
+            &mut ob,
+            LimitOrderOptions::new(Side::Sell, 5, 50, None, None, AccountId(2)),
+        );
+        assert_eq!(ob.depth(None).asks, vec![(Price(50), Quantity(15))]);
+
+        // The slice is used up, so the next one queues behind the later order
+        let report = ob.market_raw(AccountId(3), Side::Buy, 12).unwrap();
+        logs.extend(report.log);
+        let fills: Vec<_> = report
+            .fills
+            .iter()
+            .map(|f| (f.order_id, f.quantity))
+            .collect();
+        assert_eq!(
+            fills,
+            vec![(iceberg, Quantity(10)), (visible.order_id, Quantity(2))]
+        );
+        assert_eq!(ob.depth(None).asks, vec![(Price(50), Quantity(13))]);
+        let queue = |ob: &OrderBook| -> Vec<OrderId> {
+            ob.get_orders_at_price(Price(50), Side::Sell)
+                .iter()
+                .map(|o| o.id)
+                .collect()
+        };
+        assert_eq!(queue(&ob), vec![visible.order_id, iceberg]);
+
+        // Snapshots and journal replay rebuild the same queue and slices
+        let restored = OrderBookBuilder::new("outcome")
+            .with_snapshot(ob.snapshot())
+            .build();
+        let mut replayed = OrderBookBuilder::new("outcome").build();
+        replayed.replay_logs(logs).unwrap();
+        for book in [&restored, &replayed] {
+            assert_eq!(queue(book), queue(&ob));
+            assert_eq!(book.depth(None).asks, ob.depth(None).asks);
         }
 
-        Ok(())
+        // Alone at its price, an iceberg fills slice after slice
+        ob.cancel(visible.order_id).unwrap();
+        let report = ob.market_raw(AccountId(3), Side::Buy, 25).unwrap();
+        let fills: Vec<u64> = report.fills.iter().map(|f| f.quantity.value()).collect();
+        assert_eq!(fills, vec![10, 10, 5]);
+        assert_eq!(ob.depth(None).asks, vec![(Price(50), Quantity(5))]);
+
+        // Cancelling takes the hidden reserve along with the slice
+        let report = ob.cancel(iceberg).unwrap();
+        assert_eq!(report.remaining_qty, Quantity(65));
+        assert!(ob.depth(None).asks.is_empty());
+        assert_eq!(ob.resting_orders(), 0);
+    }
+
+    #[test]
+    fn pro_rata_allocations_fill_exactly_the_taker() {
+        let fills_of = |allocation: Allocation, taker: u64| {
+            let mut ob = OrderBookBuilder::new("outcome")
+                .with_allocation(allocation)
+                .build();
+            for (account, qty) in [(1, 10), (2, 30), (3, 60)] {
+                ob.limit_raw(Side::Sell, qty, 50, None, None, AccountId(account))
+                    .unwrap();
+            }
+            ob.limit_raw(Side::Sell, 10, 51, None, None, AccountId(4))
+                .unwrap();
+            let report = ob.market_raw(AccountId(9), Side::Buy, taker).unwrap();
+            let fills: Vec<(u64, u64)> = report
+                .fills
+                .iter()
+                .map(|f| (f.account_id.0, f.quantity.value()))
+                .collect();
+            assert_eq!(
+                fills.iter().map(|(_, qty)| qty).sum::<u64>(),
+                taker.min(110)
+            );
+            (fills, ob)
+        };
+
+        assert_eq!(fills_of(Allocation::Fifo, 25).0, vec![(1, 10), (2, 15)]);
+        // 25 is shared 2.5 / 7.5 / 15: rounded down to 2 / 7 / 15, and the
+        // lot left goes to the oldest order
+        let (fills, ob) = fills_of(Allocation::ProRata, 25);
+        assert_eq!(fills, vec![(1, 3), (2, 7), (3, 15)]);
+        assert_eq!(ob.depth(None).asks[0], (Price(50), Quantity(75)));
+        // The oldest order fills first, then 15 is shared 5 / 10 by the others
+        assert_eq!(
+            fills_of(Allocation::TopOrderProRata, 25).0,
+            vec![(1, 10), (2, 5), (3, 10)]
+        );
+        // Levels still go best price first
+        let (fills, ob) = fills_of(Allocation::ProRata, 105);
+        assert_eq!(fills, vec![(1, 10), (2, 30), (3, 60), (4, 5)]);
+        assert_eq!(ob.resting_orders(), 1);
+
+        let restored = OrderBookBuilder::new("outcome")
+            .with_snapshot(ob.snapshot())
+            .build();
+        assert_eq!(restored.allocation(), Allocation::ProRata);
+    }
+
+    fn fills_by_order(report: &ExecutionReport) -> Vec<(OrderId, u64)> {
+        report
+            .fills
+            .iter()
+            .map(|f| (f.order_id, f.quantity.value()))
+            .collect()
+    }
+
+    #[test]
+    fn constrained_orders_are_passed_over_and_keep_their_place() {
+        let mut ob = OrderBookBuilder::new("outcome").build();
+        let sell =
+            |ob: &mut OrderBook, options: LimitOrderOptions| ob.limit(options).unwrap().order_id;
+        let aon = sell(
+            &mut ob,
+            LimitOrderOptions::new(Side::Sell, 10, 50, None, None, AccountId(1)).with_all_or_none(),
+        );
+        let min = sell(
+            &mut ob,
+            LimitOrderOptions::new(Side::Sell, 20, 50, None, None, AccountId(2)).with_min_qty(5),
+        );
+        let plain = sell(
+            &mut ob,
+            LimitOrderOptions::new(Side::Sell, 3, 50, None, None, AccountId(3)),
+        );
+        let above = sell(
+            &mut ob,
+            LimitOrderOptions::new(Side::Sell, 10, 51, None, None, AccountId(3)),
+        );
+
+        // Too small for both constrained orders: only the order behind them
+        // fills, then the next level
+        let report = ob.market_raw(AccountId(9), Side::Buy, 4).unwrap();
+        assert_eq!(fills_by_order(&report), vec![(plain, 3), (above, 1)]);
+
+        // The all-or-none order is still first in line and fills whole; the
+        // 2 left are below the minimum, so they go to the next level
+        let report = ob.market_raw(AccountId(9), Side::Buy, 12).unwrap();
+        assert_eq!(fills_by_order(&report), vec![(aon, 10), (above, 2)]);
+
+        // An order arriving later queues behind the one passed over
+        let later = sell(
+            &mut ob,
+            LimitOrderOptions::new(Side::Sell, 5, 50, None, None, AccountId(4)),
+        );
+        let queue: Vec<OrderId> = ob
+            .get_orders_at_price(Price(50), Side::Sell)
+            .iter()
+            .map(|o| o.id)
+            .collect();
+        assert_eq!(queue, vec![min, later]);
+        let report = ob.market_raw(AccountId(9), Side::Buy, 6).unwrap();
+        assert_eq!(fills_by_order(&report), vec![(min, 6)]);
+
+        // Below its minimum, what is left of an order can still fill whole
+        ob.market_raw(AccountId(9), Side::Buy, 11).unwrap();
+        assert_eq!(ob.get_order(min).unwrap().remaining_qty(), Quantity(3));
+        let report = ob.market_raw(AccountId(9), Side::Buy, 3).unwrap();
+        assert_eq!(fills_by_order(&report), vec![(min, 3)]);
+    }
+
+    #[test]
+    fn taker_constraints_apply_on_arrival() {
+        let mut ob = OrderBookBuilder::new("outcome").build();
+        let small = ob
+            .limit_raw(Side::Sell, 3, 50, None, None, AccountId(1))
+            .unwrap()
+            .order_id;
+        let large = ob
+            .limit_raw(Side::Sell, 8, 50, None, None, AccountId(2))
+            .unwrap()
+            .order_id;
+
+        // 3 is below the taker's minimum; once 2 are left, 2 is enough
+        let report = ob
+            .limit(
+                LimitOrderOptions::new(Side::Buy, 10, 50, None, None, AccountId(3)).with_min_qty(5),
+            )
+            .unwrap();
+        assert_eq!(fills_by_order(&report), vec![(large, 8), (small, 2)]);
+        assert_eq!(report.status, OrderStatus::Filled);
+
+        // An all-or-none order that cannot fill whole rests untouched across
+        // the ask it cannot take, or is cancelled as an IOC
+        let aon =
+            LimitOrderOptions::new(Side::Buy, 5, 50, None, None, AccountId(3)).with_all_or_none();
+        let report = ob.limit(aon).unwrap();
+        assert_eq!(report.status, OrderStatus::New);
+        assert!(report.fills.is_empty());
+        assert_eq!(ob.depth(None).bids, vec![(Price(50), Quantity(5))]);
+        assert_eq!(ob.depth(None).asks, vec![(Price(50), Quantity(1))]);
+        let report = ob
+            .limit(LimitOrderOptions {
+                time_in_force: Some(TimeInForce::IOC),
+                ..aon
+            })
+            .unwrap();
+        assert_eq!(report.status, OrderStatus::Canceled);
+        assert!(report.fills.is_empty());
+
+        // A plain order too small for the order it crosses rests across it
+        // all the same
+        let mut ob = OrderBookBuilder::new("outcome").build();
+        ob.limit(
+            LimitOrderOptions::new(Side::Sell, 10, 50, None, None, AccountId(1)).with_min_qty(4),
+        )
+        .unwrap();
+        let report = ob
+            .limit_raw(Side::Buy, 3, 50, None, None, AccountId(2))
+            .unwrap();
+        assert_eq!(report.status, OrderStatus::New);
+        assert_eq!(ob.depth(None).bids, vec![(Price(50), Quantity(3))]);
+        // A plain order the size of the minimum still trades with it
+        let report = ob
+            .limit_raw(Side::Buy, 4, 50, None, None, AccountId(2))
+            .unwrap();
+        assert_eq!(report.status, OrderStatus::Filled);
+
+        // A FOK counts only what it could really fill: 10 rest at 60 but the
+        // all-or-none order among them cannot take 5
+        let mut ob = OrderBookBuilder::new("outcome").build();
+        ob.limit(
+            LimitOrderOptions::new(Side::Sell, 8, 60, None, None, AccountId(1)).with_all_or_none(),
+        )
+        .unwrap();
+        ob.limit_raw(Side::Sell, 2, 60, None, None, AccountId(2))
+            .unwrap();
+        let fok = ob.limit_raw(Side::Buy, 5, 60, Some(TimeInForce::FOK), None, AccountId(3));
+        assert!(matches!(fok, Err(e) if e.code == ErrorType::OrderFOK.code()));
+        assert_eq!(ob.depth(None).asks, vec![(Price(60), Quantity(10))]);
+        let fok = ob
+            .limit_raw(
+                Side::Buy,
+                10,
+                60,
+                Some(TimeInForce::FOK),
+                None,
+                AccountId(3),
+            )
+            .unwrap();
+        assert_eq!(fok.status, OrderStatus::Filled);
+
+        // An iceberg fills slice after slice before the next level, where
+        // the 2 left of 8 do not meet all-or-none
+        let mut ob = OrderBookBuilder::new("outcome").build();
+        ob.limit(
+            LimitOrderOptions::new(Side::Sell, 6, 60, None, None, AccountId(1)).with_display_qty(3),
+        )
+        .unwrap();
+        ob.limit(
+            LimitOrderOptions::new(Side::Sell, 5, 61, None, None, AccountId(2)).with_all_or_none(),
+        )
+        .unwrap();
+        let fok = ob.limit_raw(Side::Buy, 8, 61, Some(TimeInForce::FOK), None, AccountId(3));
+        assert!(matches!(fok, Err(e) if e.code == ErrorType::OrderFOK.code()));
+        let fok = ob
+            .limit_raw(
+                Side::Buy,
+                11,
+                61,
+                Some(TimeInForce::FOK),
+                None,
+                AccountId(3),
+            )
+            .unwrap();
+        assert_eq!(fok.status, OrderStatus::Filled);
+
+        // Orders that take their whole quantity on arrival take no minimum
+        let fok =
+            LimitOrderOptions::new(Side::Buy, 5, 60, Some(TimeInForce::FOK), None, AccountId(3))
+                .with_min_qty(2);
+        assert!(ob.limit(fok).is_err());
+        let all_or_none = LimitOrderOptions::new(Side::Buy, 5, 60, None, None, AccountId(3))
+            .with_all_or_none()
+            .with_min_qty(2);
+        assert!(ob.limit(all_or_none).is_err());
+
+        // Constraints do not go with icebergs
+        let iceberg = LimitOrderOptions::new(Side::Sell, 10, 60, None, None, AccountId(1))
+            .with_display_qty(2)
+            .with_min_qty(2);
+        assert!(ob.limit(iceberg).is_err());
+    }
+
+    #[test]
+    fn pro_rata_shares_skip_orders_below_their_minimum() {
+        let fills_of = |allocation: Allocation, taker: u64| {
+            let mut ob = OrderBookBuilder::new("outcome")
+                .with_allocation(allocation)
+                .build();
+            ob.limit(
+                LimitOrderOptions::new(Side::Sell, 10, 50, None, None, AccountId(1))
+                    .with_all_or_none(),
+            )
+            .unwrap();
+            ob.limit(
+                LimitOrderOptions::new(Side::Sell, 30, 50, None, None, AccountId(2))
+                    .with_min_qty(10),
+            )
+            .unwrap();
+            ob.limit_raw(Side::Sell, 60, 50, None, None, AccountId(3))
+                .unwrap();
+            let report = ob.market_raw(AccountId(9), Side::Buy, taker).unwrap();
+            assert_eq!(report.executed_qty, Quantity(taker));
+            let mut filled = [0; 3];
+            for fill in &report.fills {
+                filled[fill.account_id.0 as usize - 1] += fill.quantity.value();
+            }
+            filled
+        };
+
+        // Shares of 2.5 and 7.5 are below both minimums, so the plain order
+        // takes everything
+        assert_eq!(fills_of(Allocation::ProRata, 25), [0, 0, 25]);
+        // A share of 15 meets the minimum, and 5 does not meet all-or-none
+        assert_eq!(fills_of(Allocation::ProRata, 50), [0, 15, 35]);
+        // The top order cannot take 5 on its own, so it gets no priority
+        // fill, and the rest is shared without it
+        assert_eq!(fills_of(Allocation::TopOrderProRata, 5), [0, 0, 5]);
+        assert_eq!(fills_of(Allocation::TopOrderProRata, 40), [10, 10, 20]);
+
+        // A FOK that needs the constrained orders' shares is refused rather
+        // than matched to find out
+        let mut ob = OrderBookBuilder::new("outcome")
+            .with_allocation(Allocation::ProRata)
+            .build();
+        ob.limit(
+            LimitOrderOptions::new(Side::Sell, 10, 50, None, None, AccountId(1)).with_all_or_none(),
+        )
+        .unwrap();
+        ob.limit_raw(Side::Sell, 20, 50, None, None, AccountId(2))
+            .unwrap();
+        let fok = ob.limit_raw(
+            Side::Buy,
+            30,
+            50,
+            Some(TimeInForce::FOK),
+            None,
+            AccountId(3),
+        );
+        assert!(matches!(fok, Err(e) if e.code == ErrorType::OrderFOK.code()));
+        let fok = ob
+            .limit_raw(
+                Side::Buy,
+                20,
+                50,
+                Some(TimeInForce::FOK),
+                None,
+                AccountId(3),
+            )
+            .unwrap();
+        assert_eq!(fok.status, OrderStatus::Filled);
     }
 }
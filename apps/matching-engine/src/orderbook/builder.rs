@@ -5,14 +5,14 @@
 //!
 //! # Example
 //! ```rust
-//! use rust_order_book::OrderBookBuilder;
+//! use matching_engine::orderbook::OrderBookBuilder;
 //!
 //! let ob = OrderBookBuilder::new("BTCUSD")
 //!     .with_journaling(true)
 //!     .build();
 //! ```
 
-use crate::orderbook::{JournalLog, OrderBook, OrderBookOptions, Snapshot};
+use crate::orderbook::{Allocation, JournalLog, OrderBook, OrderBookOptions, Snapshot};
 
 /// A builder for constructing an [`OrderBook`] with custom options.
 ///
@@ -83,6 +83,19 @@ impl OrderBookBuilder {
         self
     }
 
+    /// Sets how takers are allocated among the orders of a price level.
+    ///
+    /// A snapshot attached with [`OrderBookBuilder::with_snapshot`] keeps the
+    /// allocation it was taken with.
+    ///
+    /// # Parameters
+    /// - `allocation`: FIFO (the default), pro-rata, or top order then
+    ///   pro-rata, see [`Allocation`]
+    pub fn with_allocation(mut self, allocation: Allocation) -> Self {
+        self.options.allocation = allocation;
+        self
+    }
+
     /// Builds and returns a fully configured [`OrderBook`] instance.
     ///
     /// # Returns
@@ -7,7 +7,9 @@ use std::str::FromStr;
 
 use serde::{Deserialize, Serialize};
 
-use crate::orderbook::{LimitOrderOptions, MarketOrderOptions, OrderId, Price, Quantity};
+use crate::orderbook::{
+    LimitOrderOptions, MarketOrderOptions, NotionalOrderOptions, OrderId, Price, Quantity,
+};
 
 /// Represents the type of order being placed.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -57,6 +59,18 @@ impl FromStr for TimeInForce {
     }
 }
 
+/// How a book treats incoming orders.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TradingPhase {
+    /// Orders match as soon as they arrive.
+    #[default]
+    Continuous,
+    /// Limit orders accumulate without matching until the book is uncrossed
+    /// at a single price.
+    Auction,
+}
+
 /// Represents the current status of an order.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
@@ -82,17 +96,24 @@ pub enum OrderStatus {
 pub enum JournalOp {
     /// Market order
     Market,
+    /// Market buy for a notional amount
+    MarketNotional,
     /// Limit order
     Limit,
     /// Modify order (cancel and create new order with updated price and quantity)
     Modify,
     /// Cancel (delete) order
     Cancel,
+    /// Start a call auction
+    StartAuction,
+    /// End a call auction
+    Uncross,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum OrderOptions {
     Market(MarketOrderOptions),
+    MarketNotional(NotionalOrderOptions),
     Limit(LimitOrderOptions),
     Modify {
         id: OrderId,
@@ -100,4 +121,6 @@ pub enum OrderOptions {
         quantity: Option<Quantity>,
     },
     Cancel(OrderId),
+    StartAuction,
+    Uncross,
 }
@@ -17,6 +17,9 @@ pub(crate) enum ErrorType {
     OrderPostOnly,
     OrderIOC,
     OrderFOK,
+    AuctionInProgress,
+    InvalidPeg,
+    ConstrainedOrdersResting,
 
     // 12xx Internal error
     InsufficientQuantity,
@@ -38,8 +41,11 @@ impl ErrorType {
             ErrorType::OrderPostOnly => 1104,
             ErrorType::OrderIOC => 1105,
             ErrorType::OrderFOK => 1106,
+            ErrorType::AuctionInProgress => 1107,
+            ErrorType::InvalidPeg => 1108,
             ErrorType::OrderAlredyExists => 1109,
             ErrorType::OrderNotFound => 1110,
+            ErrorType::ConstrainedOrdersResting => 1111,
 
             // 12xx Internal error
             ErrorType::OrderBookEmpty => 1200,
@@ -65,8 +71,17 @@ impl ErrorType {
                 "IOC order rejected: no immediate liquidity available at requested price"
             }
             ErrorType::OrderFOK => "FOK order rejected: unable to fill entire quantity immediately",
+            ErrorType::AuctionInProgress => {
+                "Order rejected: only resting limit orders are accepted during an auction"
+            }
+            ErrorType::InvalidPeg => {
+                "Pegged order rejected: it must rest and follow a price in the book"
+            }
             ErrorType::OrderAlredyExists => "Order already exists",
             ErrorType::OrderNotFound => "Order not found",
+            ErrorType::ConstrainedOrdersResting => {
+                "Auction refused: all-or-none or minimum quantity orders are resting"
+            }
 
             // 12xx Internal error
             ErrorType::OrderBookEmpty => "Order book is empty",
@@ -131,8 +146,11 @@ pub(crate) fn default_message_for_code(code: u32) -> Cow<'static, str> {
         1104 => Cow::Borrowed(ErrorType::OrderPostOnly.message()),
         1105 => Cow::Borrowed(ErrorType::OrderIOC.message()),
         1106 => Cow::Borrowed(ErrorType::OrderFOK.message()),
+        1107 => Cow::Borrowed(ErrorType::AuctionInProgress.message()),
+        1108 => Cow::Borrowed(ErrorType::InvalidPeg.message()),
         1109 => Cow::Borrowed(ErrorType::OrderAlredyExists.message()),
         1110 => Cow::Borrowed(ErrorType::OrderNotFound.message()),
+        1111 => Cow::Borrowed(ErrorType::ConstrainedOrdersResting.message()),
 
         // 12xx Internal error
         1200 => Cow::Borrowed(ErrorType::InsufficientQuantity.message()),
@@ -222,8 +240,23 @@ mod tests {
                 1106,
                 "FOK order rejected: unable to fill entire quantity immediately",
             ),
+            (
+                ErrorType::AuctionInProgress,
+                1107,
+                "Order rejected: only resting limit orders are accepted during an auction",
+            ),
+            (
+                ErrorType::InvalidPeg,
+                1108,
+                "Pegged order rejected: it must rest and follow a price in the book",
+            ),
             (ErrorType::OrderAlredyExists, 1109, "Order already exists"),
             (ErrorType::OrderNotFound, 1110, "Order not found"),
+            (
+                ErrorType::ConstrainedOrdersResting,
+                1111,
+                "Auction refused: all-or-none or minimum quantity orders are resting",
+            ),
             (ErrorType::OrderBookEmpty, 1200, "Order book is empty"),
             (
                 ErrorType::InsufficientQuantity,
@@ -8,7 +8,7 @@ use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap, VecDeque};
 
 use crate::orderbook::{
-    OrderId, Price,
+    Allocation, OrderId, Price, TradingPhase,
     enums::{JournalOp, OrderOptions},
     order::LimitOrder,
 };
@@ -39,4 +39,13 @@ pub struct Snapshot {
     pub last_op: u64,
     pub next_order_id: OrderId,
     pub ts: i64,
+    /// Depth sequence number, so deltas continue without a gap after restore.
+    #[serde(default)]
+    pub depth_seq: u64,
+    #[serde(default)]
+    pub phase: TradingPhase,
+    /// Overrides the allocation the book was built with; absent in snapshots
+    /// taken before allocations were configurable
+    #[serde(default)]
+    pub allocation: Option<Allocation>,
 }
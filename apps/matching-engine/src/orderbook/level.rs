@@ -0,0 +1,304 @@
+//! Price level storage for the order book.
+//!
+//! Resting orders live in a single [`RestingOrders`] slab. Each slab entry is
+//! a node of an intrusive doubly linked list, and a [`PriceLevel`] only keeps
+//! the head and tail of its list together with the aggregate remaining
+//! quantity. This gives:
+//! - O(1) append at the back of a level
+//! - O(1) cancel from anywhere in a level (id -> slab index -> unlink)
+//! - in-place fills of the head order, without removing and reinserting it
+//! - depth queries that read one number per level
+//!
+//! The resting orders of each account are indexed as well, so they can be
+//! listed or cancelled without scanning the book.
+//!
+//! Icebergs count fully in a level's total quantity, which is what can
+//! execute, but only their shown slice counts in its visible quantity, which
+//! is what depth publishes.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+use slab::Slab;
+
+use crate::orderbook::{
+    OrderId, OrderStatus, Quantity,
+    order::{AccountId, LimitOrder},
+};
+
+/// Aggregate of the orders resting at one price.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(crate) struct PriceLevel {
+    head: Option<usize>,
+    tail: Option<usize>,
+    len: usize,
+    pub(crate) total_qty: Quantity,
+    pub(crate) visible_qty: Quantity,
+}
+
+impl PriceLevel {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Key of the order at the front of the queue, see [`RestingOrders::at`].
+    pub(crate) fn head(&self) -> Option<usize> {
+        self.head
+    }
+
+    /// Key of the order at the back of the queue.
+    pub(crate) fn tail(&self) -> Option<usize> {
+        self.tail
+    }
+}
+
+#[derive(Debug, Clone)]
+struct OrderNode {
+    order: LimitOrder,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+/// Every resting order of a book, linked into per-level FIFO queues.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RestingOrders {
+    nodes: Slab<OrderNode>,
+    index: HashMap<OrderId, usize>,
+    /// Ids ascend with arrival, so each set is in time order
+    accounts: BTreeMap<AccountId, BTreeSet<OrderId>>,
+}
+
+impl RestingOrders {
+    pub(crate) fn with_capacity(capacity: usize) -> Self {
+        Self {
+            nodes: Slab::with_capacity(capacity),
+            index: HashMap::with_capacity(capacity),
+            accounts: BTreeMap::new(),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub(crate) fn get(&self, id: &OrderId) -> Option<&LimitOrder> {
+        self.index.get(id).map(|key| &self.nodes[*key].order)
+    }
+
+    pub(crate) fn values(&self) -> impl Iterator<Item = &LimitOrder> {
+        self.nodes.iter().map(|(_, node)| &node.order)
+    }
+
+    /// Ids of `account_id`'s resting orders, oldest first.
+    pub(crate) fn account_order_ids(&self, account_id: AccountId) -> Vec<OrderId> {
+        self.accounts
+            .get(&account_id)
+            .map(|ids| ids.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Appends `order` at the back of `level`.
+    pub(crate) fn push_back(&mut self, level: &mut PriceLevel, order: LimitOrder) {
+        let remaining_qty = order.remaining_qty();
+        let visible_qty = order.visible_qty();
+        let id = order.id;
+        self.accounts
+            .entry(order.account_id)
+            .or_default()
+            .insert(id);
+        let key = self.nodes.insert(OrderNode {
+            order,
+            prev: level.tail,
+            next: None,
+        });
+        match level.tail {
+            Some(tail) => self.nodes[tail].next = Some(key),
+            None => level.head = Some(key),
+        }
+        level.tail = Some(key);
+        level.len += 1;
+        level.total_qty = level.total_qty + remaining_qty;
+        level.visible_qty = level.visible_qty + visible_qty;
+        self.index.insert(id, key);
+    }
+
+    /// Unlinks the order `id` from `level`, wherever it sits in the queue.
+    pub(crate) fn remove(&mut self, level: &mut PriceLevel, id: &OrderId) -> Option<LimitOrder> {
+        let key = self.index.remove(id)?;
+        let node = self.nodes.remove(key);
+        if let Some(ids) = self.accounts.get_mut(&node.order.account_id) {
+            ids.remove(id);
+            if ids.is_empty() {
+                self.accounts.remove(&node.order.account_id);
+            }
+        }
+        match node.prev {
+            Some(prev) => self.nodes[prev].next = node.next,
+            None => level.head = node.next,
+        }
+        match node.next {
+            Some(next) => self.nodes[next].prev = node.prev,
+            None => level.tail = node.prev,
+        }
+        level.len -= 1;
+        level.total_qty = level.total_qty - node.order.remaining_qty();
+        level.visible_qty = level.visible_qty - node.order.visible_qty();
+        Some(node.order)
+    }
+
+    /// Executes `quantity` of the order `id` of `level`, at most its visible
+    /// quantity, and returns the order as the fill left it.
+    ///
+    /// A filled order leaves the level. An iceberg whose slice is used up
+    /// shows its next one at the back of the queue, losing time priority.
+    pub(crate) fn fill(
+        &mut self,
+        level: &mut PriceLevel,
+        id: &OrderId,
+        quantity: Quantity,
+    ) -> LimitOrder {
+        let key = self.index[id];
+        let order = &mut self.nodes[key].order;
+        debug_assert!(quantity <= order.visible_qty());
+        order.executed_qty = order.executed_qty + quantity;
+        if order.display_qty.is_some() {
+            order.peak_qty = order.peak_qty - quantity;
+        }
+        order.status = if order.remaining_qty().value() == 0 {
+            OrderStatus::Filled
+        } else {
+            OrderStatus::PartiallyFilled
+        };
+        let order = *order;
+        level.total_qty = level.total_qty - quantity;
+        level.visible_qty = level.visible_qty - quantity;
+        if order.status == OrderStatus::Filled {
+            self.remove(level, id);
+        } else if order.visible_qty().value() == 0 {
+            let mut order = self.remove(level, id).expect("the order is resting");
+            order.show_next_slice();
+            self.push_back(level, order);
+        }
+        order
+    }
+
+    /// The order at the front of `level`'s queue.
+    pub(crate) fn front_mut(&mut self, level: &PriceLevel) -> Option<&mut LimitOrder> {
+        level.head.map(|key| &mut self.nodes[key].order)
+    }
+
+    /// The order at `key` together with the key of the one queued after it.
+    ///
+    /// Keys stay valid until their order leaves the book, so a walk through
+    /// a level can go on past orders that fill or move behind it.
+    pub(crate) fn at(&self, key: usize) -> (&LimitOrder, Option<usize>) {
+        let node = &self.nodes[key];
+        (&node.order, node.next)
+    }
+
+    /// Iterates over `level`'s orders in time priority.
+    pub(crate) fn iter_level<'a>(
+        &'a self,
+        level: &PriceLevel,
+    ) -> impl Iterator<Item = &'a LimitOrder> + 'a {
+        let mut cursor = level.head;
+        std::iter::from_fn(move || {
+            let node = &self.nodes[cursor?];
+            cursor = node.next;
+            Some(&node.order)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::{LimitOrderOptions, Side, order::AccountId};
+
+    fn order(id: u64, quantity: u64) -> LimitOrder {
+        account_order(id, quantity, 1)
+    }
+
+    fn account_order(id: u64, quantity: u64, account_id: u64) -> LimitOrder {
+        LimitOrder::new(
+            OrderId(id),
+            LimitOrderOptions::new(Side::Buy, quantity, 50, None, None, AccountId(account_id)),
+        )
+    }
+
+    fn ids(orders: &RestingOrders, level: &PriceLevel) -> Vec<u64> {
+        orders.iter_level(level).map(|o| o.id.0).collect()
+    }
+
+    #[test]
+    fn remove_unlinks_from_any_position() {
+        let mut orders = RestingOrders::default();
+        let mut level = PriceLevel::default();
+        for id in 1..=4 {
+            orders.push_back(&mut level, order(id, 10));
+        }
+
+        orders.remove(&mut level, &OrderId(2)).unwrap();
+        assert_eq!(ids(&orders, &level), vec![1, 3, 4]);
+        orders.remove(&mut level, &OrderId(1)).unwrap();
+        orders.remove(&mut level, &OrderId(4)).unwrap();
+        assert_eq!(ids(&orders, &level), vec![3]);
+        assert_eq!(level.len, 1);
+        assert_eq!(level.total_qty, Quantity(10));
+
+        orders.remove(&mut level, &OrderId(3)).unwrap();
+        assert!(level.is_empty());
+        assert!(orders.remove(&mut level, &OrderId(3)).is_none());
+
+        // Slots are reused and the level links up again from scratch
+        orders.push_back(&mut level, order(5, 7));
+        assert_eq!(ids(&orders, &level), vec![5]);
+        assert_eq!(level.total_qty, Quantity(7));
+    }
+
+    #[test]
+    fn account_index_follows_inserts_and_removals() {
+        let mut orders = RestingOrders::default();
+        let mut level = PriceLevel::default();
+        for (id, account_id) in [(1, 7), (2, 8), (3, 7)] {
+            orders.push_back(&mut level, account_order(id, 10, account_id));
+        }
+        assert_eq!(
+            orders.account_order_ids(AccountId(7)),
+            vec![OrderId(1), OrderId(3)]
+        );
+
+        orders.remove(&mut level, &OrderId(1)).unwrap();
+        assert_eq!(orders.account_order_ids(AccountId(7)), vec![OrderId(3)]);
+        orders.remove(&mut level, &OrderId(2)).unwrap();
+        assert!(orders.account_order_ids(AccountId(8)).is_empty());
+        assert!(!orders.accounts.contains_key(&AccountId(8)));
+    }
+
+    #[test]
+    fn keys_survive_fills_and_moves_around_them() {
+        let mut orders = RestingOrders::default();
+        let mut level = PriceLevel::default();
+        let mut iceberg = LimitOrder::new(
+            OrderId(1),
+            LimitOrderOptions::new(Side::Buy, 10, 50, None, None, AccountId(1)).with_display_qty(4),
+        );
+        iceberg.show_next_slice();
+        orders.push_back(&mut level, iceberg);
+        orders.push_back(&mut level, order(2, 10));
+        orders.push_back(&mut level, order(3, 10));
+
+        let (first, next) = orders.at(level.head().unwrap());
+        assert_eq!(first.id, OrderId(1));
+        // The iceberg moves behind the others, the order after it stays put
+        orders.fill(&mut level, &OrderId(1), Quantity(4));
+        let (second, next) = orders.at(next.unwrap());
+        assert_eq!(second.id, OrderId(2));
+        orders.fill(&mut level, &OrderId(2), Quantity(10));
+        let (third, next) = orders.at(next.unwrap());
+        assert_eq!(third.id, OrderId(3));
+        let (moved, next) = orders.at(next.unwrap());
+        assert_eq!(moved.id, OrderId(1));
+        assert_eq!(next, None);
+        assert_eq!(level.tail(), Some(orders.index[&OrderId(1)]));
+    }
+}
@@ -1,16 +1,26 @@
+pub mod allocation;
+pub mod auction;
 pub mod book;
 pub mod builder;
 pub mod enums;
 pub mod errors;
 pub mod journal;
+pub(crate) mod level;
 pub mod order;
+pub mod peg;
 pub mod report;
 pub mod utils;
 
-pub use book::{Depth, OrderBook, OrderBookOptions};
+pub use allocation::Allocation;
+pub use auction::{AuctionFill, AuctionParty, AuctionResult, Uncross};
+pub use book::{Depth, DepthDelta, OrderBook, OrderBookOptions};
 pub use builder::OrderBookBuilder;
-pub use enums::{OrderStatus, OrderType, Side, TimeInForce};
+pub use enums::{OrderStatus, OrderType, Side, TimeInForce, TradingPhase};
 pub use errors::OrderBookError;
 pub use journal::{JournalLog, Snapshot};
-pub use order::{LimitOrderOptions, MarketOrderOptions, OrderId, Price, Quantity};
+pub use order::{
+    LimitOrderOptions, MarketOrderOptions, MarketProtection, NotionalOrderOptions, OrderId, Price,
+    Quantity,
+};
+pub use peg::{Peg, PegReference, Repricing};
 pub use report::ExecutionReport;
@@ -13,10 +13,11 @@ use std::{
 
 use crate::orderbook::{
     OrderStatus, OrderType, Side, TimeInForce,
+    peg::{Peg, PegReference},
     utils::{current_timestamp_millis, safe_add, safe_sub},
 };
 
-#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize, Eq, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize, Eq, Hash, PartialOrd, Ord)]
 pub struct OrderId(pub u64);
 impl AddAssign<u64> for OrderId {
     fn add_assign(&mut self, rhs: u64) {
@@ -38,6 +39,9 @@ impl fmt::Display for AccountId {
     }
 }
 
+/// Highest price an order can have; peg offsets stay within it as well.
+pub const MAX_PRICE: u64 = 1_000_000_000;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, PartialOrd, Ord)]
 pub struct Price(pub u64);
 impl Price {
@@ -77,7 +81,7 @@ impl Div for Price {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize, PartialOrd)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize, PartialOrd)]
 pub struct Quantity(pub u64);
 impl Quantity {
     pub fn value(self) -> u64 {
@@ -125,11 +129,13 @@ impl Mul<Price> for Quantity {
 /// # Fields
 /// - `side`: Buy or Sell
 /// - `quantity`: The total amount to trade
+/// - `protection`: Optional bound on the prices the order may trade at
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct MarketOrderOptions {
     pub side: Side,
     pub quantity: Quantity,
     pub account_id: AccountId,
+    pub protection: Option<MarketProtection>,
 }
 impl MarketOrderOptions {
     pub fn new(side: Side, quantity: u64, account_id: AccountId) -> Self {
@@ -137,8 +143,53 @@ impl MarketOrderOptions {
             side,
             quantity: Quantity(quantity),
             account_id,
+            protection: None,
         }
     }
+
+    pub fn with_protection(mut self, protection: MarketProtection) -> Self {
+        self.protection = Some(protection);
+        self
+    }
+}
+
+/// Options for submitting a notional market buy to the order book.
+///
+/// Instead of a number of contracts, the order gives the amount to spend: it
+/// buys whole contracts from the best asks up while the budget affords them.
+///
+/// # Fields
+/// - `notional`: The budget, in price units
+/// - `protection`: Optional bound on the prices the order may trade at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NotionalOrderOptions {
+    pub notional: u64,
+    pub account_id: AccountId,
+    pub protection: Option<MarketProtection>,
+}
+impl NotionalOrderOptions {
+    pub fn new(notional: u64, account_id: AccountId) -> Self {
+        Self {
+            notional,
+            account_id,
+            protection: None,
+        }
+    }
+
+    pub fn with_protection(mut self, protection: MarketProtection) -> Self {
+        self.protection = Some(protection);
+        self
+    }
+}
+
+/// Worst price a market order may trade at; what is left once matching
+/// reaches it is cancelled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MarketProtection {
+    /// Highest price of a buy, lowest price of a sell
+    Price(Price),
+    /// Ticks, of one price unit, away from the best opposite price on arrival
+    Ticks(u64),
 }
 
 #[derive(Debug)]
@@ -178,6 +229,14 @@ impl MarketOrder {
 /// - `price`: Limit price
 /// - `time_in_force`: Optional TIF setting (default: GTC)
 /// - `post_only`: Optional post-only flag (default: false)
+/// - `display_qty`: Optional slice shown in depth while resting, making the
+///   order an iceberg (default: all of it)
+/// - `peg`: Optional price the order follows instead of resting at `price`,
+///   which then caps it (default: none)
+/// - `min_qty`: Optional smallest quantity each of its fills may be, or all
+///   that is left of it if less (default: none)
+/// - `all_or_none`: Whether the order only ever executes all of its
+///   remaining quantity at once (default: false)
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct LimitOrderOptions {
     pub side: Side,
@@ -186,6 +245,10 @@ pub struct LimitOrderOptions {
     pub time_in_force: Option<TimeInForce>,
     pub post_only: Option<bool>,
     pub account_id: AccountId,
+    pub display_qty: Option<Quantity>,
+    pub peg: Option<Peg>,
+    pub min_qty: Option<Quantity>,
+    pub all_or_none: bool,
 }
 impl LimitOrderOptions {
     pub fn new(
@@ -203,8 +266,37 @@ impl LimitOrderOptions {
             time_in_force,
             post_only,
             account_id,
+            display_qty: None,
+            peg: None,
+            min_qty: None,
+            all_or_none: false,
         }
     }
+
+    pub fn with_display_qty(mut self, display_qty: u64) -> Self {
+        self.display_qty = Some(Quantity(display_qty));
+        self
+    }
+
+    /// Pegs the order `offset` away from `reference`, never past `price`.
+    pub fn with_peg(mut self, reference: PegReference, offset: i64) -> Self {
+        self.peg = Some(Peg {
+            reference,
+            offset,
+            limit: self.price,
+        });
+        self
+    }
+
+    pub fn with_min_qty(mut self, min_qty: u64) -> Self {
+        self.min_qty = Some(Quantity(min_qty));
+        self
+    }
+
+    pub fn with_all_or_none(mut self) -> Self {
+        self.all_or_none = true;
+        self
+    }
 }
 
 /// `LimitOrder` is `pub` so that it can be exposed in public APIs such as
@@ -228,6 +320,20 @@ pub struct LimitOrder {
     pub(crate) maker_qty: Quantity,
     pub(crate) status: OrderStatus,
     pub(crate) account_id: AccountId,
+    /// Size of the slices an iceberg shows
+    #[serde(default)]
+    pub(crate) display_qty: Option<Quantity>,
+    /// What is left of the slice an iceberg shows
+    #[serde(default)]
+    pub(crate) peak_qty: Quantity,
+    /// How a pegged order is repriced, see [`crate::orderbook::peg`]
+    #[serde(default)]
+    pub(crate) peg: Option<Peg>,
+    /// Smallest fill the order accepts
+    #[serde(default)]
+    pub(crate) min_qty: Option<Quantity>,
+    #[serde(default)]
+    pub(crate) all_or_none: bool,
 }
 
 impl LimitOrder {
@@ -246,12 +352,43 @@ impl LimitOrder {
             maker_qty: Quantity(0),
             status: OrderStatus::New,
             account_id: options.account_id,
+            display_qty: options.display_qty,
+            peak_qty: Quantity(0),
+            peg: options.peg,
+            min_qty: options.min_qty,
+            all_or_none: options.all_or_none,
         }
     }
 
     pub(crate) fn remaining_qty(&self) -> Quantity {
         self.orig_qty.sub(self.executed_qty)
     }
+
+    /// Quantity shown in depth, and executable in one go, while resting.
+    pub(crate) fn visible_qty(&self) -> Quantity {
+        match self.display_qty {
+            Some(_) => self.peak_qty,
+            None => self.remaining_qty(),
+        }
+    }
+
+    /// Smallest fill the order accepts now: all that is left of it if it
+    /// is all-or-none, else its minimum quantity, or what is left if less.
+    pub(crate) fn min_fill(&self) -> Quantity {
+        let remaining = self.remaining_qty().value();
+        match (self.all_or_none, self.min_qty) {
+            (true, _) => Quantity(remaining),
+            (false, Some(min_qty)) => Quantity(min_qty.value().min(remaining)),
+            (false, None) => Quantity(0),
+        }
+    }
+
+    /// Shows the next slice of an iceberg from its hidden reserve.
+    pub(crate) fn show_next_slice(&mut self) {
+        if let Some(display_qty) = self.display_qty {
+            self.peak_qty = Quantity(display_qty.value().min(self.remaining_qty().value()));
+        }
+    }
 }
 
 pub(crate) fn get_order_time_in_force(time_in_force: Option<TimeInForce>) -> TimeInForce {
@@ -0,0 +1,351 @@
+//! Pegged limit orders.
+//!
+//! A pegged order rests at a price derived from the book: the best bid, the
+//! best ask or the mid price, plus an offset. Its limit price is a cap the
+//! peg never goes past, up for a buy and down for a sell.
+//!
+//! References only look at non-pegged orders, so pegs never follow each
+//! other. Pegs are passive: whatever their reference, they stop one tick
+//! short of the opposite side and never take liquidity.
+//!
+//! After every operation that changes the book, pegs are repriced in order
+//! id order, which is arrival order. A peg whose price changes leaves its
+//! level and joins the back of the new one, losing time priority, and is
+//! reported by [`OrderBook::take_repriced`]. Replaying the same operations
+//! therefore moves the same pegs to the same places.
+
+use crate::orderbook::enums::{Side, TradingPhase};
+use crate::orderbook::order::{AccountId, LimitOrder, MAX_PRICE};
+use crate::orderbook::{OrderBook, OrderId, Price, Quantity};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+/// Price a pegged order follows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PegReference {
+    BestBid,
+    BestAsk,
+    /// [`OrderBook::mid_price`] of the non-pegged orders
+    Mid,
+}
+
+impl fmt::Display for PegReference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PegReference::BestBid => write!(f, "best_bid"),
+            PegReference::BestAsk => write!(f, "best_ask"),
+            PegReference::Mid => write!(f, "mid"),
+        }
+    }
+}
+
+impl FromStr for PegReference {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "best_bid" => Ok(PegReference::BestBid),
+            "best_ask" => Ok(PegReference::BestAsk),
+            "mid" => Ok(PegReference::Mid),
+            _ => Err(format!(
+                "Invalid peg reference: '{}'. Must be 'best_bid', 'best_ask' or 'mid'",
+                s
+            )),
+        }
+    }
+}
+
+/// How a pegged order is priced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Peg {
+    pub reference: PegReference,
+    /// Price units added to the reference, negative to go below it
+    pub offset: i64,
+    /// Highest price of a buy, lowest price of a sell
+    pub limit: Price,
+}
+
+/// A pegged order moved to a new price.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Repricing {
+    pub order_id: OrderId,
+    pub account_id: AccountId,
+    pub side: Side,
+    pub previous_price: Price,
+    pub price: Price,
+    pub remaining_qty: Quantity,
+}
+
+impl OrderBook {
+    /// Pegged orders moved since the last call, in the order they moved.
+    pub fn take_repriced(&mut self) -> Vec<Repricing> {
+        std::mem::take(&mut self.repriced)
+    }
+
+    /// Where a pegged order of `side` rests now, `None` if its reference is
+    /// missing or it would have to rest below one price unit.
+    pub(crate) fn peg_price(&self, side: Side, peg: Peg) -> Option<Price> {
+        self.peg_price_from(side, peg, self.peg_references())
+    }
+
+    /// [`OrderBook::peg_price`] from the `(bid, ask)` references of
+    /// [`OrderBook::peg_references`].
+    fn peg_price_from(
+        &self,
+        side: Side,
+        peg: Peg,
+        (bid, ask): (Option<Price>, Option<Price>),
+    ) -> Option<Price> {
+        let reference = match peg.reference {
+            PegReference::BestBid => bid?.value(),
+            PegReference::BestAsk => ask?.value(),
+            PegReference::Mid => ((bid?.value() as u128 + ask?.value() as u128) / 2) as u64,
+        };
+        // Validation keeps prices and offsets within MAX_PRICE
+        let target = (reference as i64).saturating_add(peg.offset);
+        let price = match side {
+            Side::Buy => {
+                let price = target.min(peg.limit.value() as i64);
+                match self.best_ask() {
+                    Some(ask) => price.min(ask.value() as i64 - 1),
+                    None => price,
+                }
+            }
+            Side::Sell => {
+                let price = target.max(peg.limit.value() as i64).min(MAX_PRICE as i64);
+                match self.best_bid() {
+                    Some(bid) => price.max(bid.value() as i64 + 1),
+                    None => price,
+                }
+            }
+        };
+        (price >= 1).then_some(Price(price as u64))
+    }
+
+    /// Best bid and best ask among non-pegged orders, which pegs follow.
+    fn peg_references(&self) -> (Option<Price>, Option<Price>) {
+        let primary = |level| {
+            self.orders
+                .iter_level(level)
+                .any(|order: &LimitOrder| order.peg.is_none())
+        };
+        let bid = self.bids.iter().rev().find(|(_, level)| primary(level));
+        let ask = self.asks.iter().find(|(_, level)| primary(level));
+        (bid.map(|(price, _)| *price), ask.map(|(price, _)| *price))
+    }
+
+    /// Moves every pegged order whose price changed, see the module docs.
+    pub(crate) fn reprice_pegs(&mut self) {
+        if self.phase == TradingPhase::Auction || self.pegged.is_empty() {
+            return;
+        }
+        // Pegs moving cannot change what they follow
+        let references = self.peg_references();
+        let ids: Vec<OrderId> = self.pegged.iter().copied().collect();
+        for id in ids {
+            // Filled and cancelled pegs are only forgotten here
+            let Some(order) = self.orders.get(&id).copied() else {
+                self.pegged.remove(&id);
+                continue;
+            };
+            let Some(peg) = order.peg else {
+                continue;
+            };
+            let Some(price) = self.peg_price_from(order.side, peg, references) else {
+                continue;
+            };
+            if price == order.price {
+                continue;
+            }
+            let (book_side, changed) = match order.side {
+                Side::Buy => (&mut self.bids, &mut self.changed_bids),
+                Side::Sell => (&mut self.asks, &mut self.changed_asks),
+            };
+            let level = book_side
+                .get_mut(&order.price)
+                .expect("resting orders have a level");
+            let mut order = self
+                .orders
+                .remove(level, &id)
+                .expect("the order is resting");
+            if level.is_empty() {
+                book_side.remove(&order.price);
+            }
+            changed.insert(order.price);
+            changed.insert(price);
+            let previous_price = order.price;
+            order.price = price;
+            let level = book_side.entry(price).or_default();
+            self.orders.push_back(level, order);
+            self.repriced.push(Repricing {
+                order_id: id,
+                account_id: order.account_id,
+                side: order.side,
+                previous_price,
+                price,
+                remaining_qty: order.remaining_qty(),
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::orderbook::errors::ErrorType;
+    use crate::orderbook::{LimitOrderOptions, OrderBookBuilder};
+
+    #[test]
+    fn parses_peg_references() {
+        for reference in [
+            PegReference::BestBid,
+            PegReference::BestAsk,
+            PegReference::Mid,
+        ] {
+            assert_eq!(reference.to_string().parse::<PegReference>(), Ok(reference));
+        }
+        assert!("last".parse::<PegReference>().is_err());
+    }
+
+    #[test]
+    fn pegs_follow_their_reference_within_their_cap() {
+        let mut ob = OrderBookBuilder::new("outcome").build();
+        ob.limit_raw(Side::Buy, 10, 40, None, None, AccountId(1))
+            .unwrap();
+        ob.limit_raw(Side::Sell, 10, 60, None, None, AccountId(1))
+            .unwrap();
+        // One above the best bid, up to 45
+        let peg = ob
+            .limit(
+                LimitOrderOptions::new(Side::Buy, 5, 45, None, None, AccountId(2))
+                    .with_peg(PegReference::BestBid, 1),
+            )
+            .unwrap();
+        assert_eq!(peg.price, Price(41));
+        // Five above the mid
+        let mid = ob
+            .limit(
+                LimitOrderOptions::new(Side::Sell, 5, 1, None, None, AccountId(3))
+                    .with_peg(PegReference::Mid, 5),
+            )
+            .unwrap();
+        assert_eq!(mid.price, Price(55));
+        assert!(ob.take_repriced().is_empty());
+
+        // The best bid moves up: the bid peg hits its cap, and the mid moves
+        ob.limit_raw(Side::Buy, 10, 50, None, None, AccountId(1))
+            .unwrap();
+        let moves: Vec<(OrderId, Price, Price)> = ob
+            .take_repriced()
+            .iter()
+            .map(|r| (r.order_id, r.previous_price, r.price))
+            .collect();
+        assert_eq!(
+            moves,
+            vec![
+                (peg.order_id, Price(41), Price(45)),
+                (mid.order_id, Price(55), Price(60)),
+            ]
+        );
+
+        // A peg never crosses: with the reference at 58, the bid peg would
+        // be past the ask, so it stops a tick short of it
+        let mut ob = OrderBookBuilder::new("outcome").build();
+        ob.limit_raw(Side::Buy, 10, 58, None, None, AccountId(1))
+            .unwrap();
+        ob.limit_raw(Side::Sell, 10, 60, None, None, AccountId(1))
+            .unwrap();
+        let report = ob
+            .limit(
+                LimitOrderOptions::new(Side::Buy, 5, 99, None, None, AccountId(2))
+                    .with_peg(PegReference::BestBid, 5),
+            )
+            .unwrap();
+        assert_eq!(report.price, Price(59));
+        assert!(report.fills.is_empty());
+
+        // Offsets past the price range are refused, whichever way they go
+        for offset in [
+            i64::MIN,
+            -(MAX_PRICE as i64) - 1,
+            MAX_PRICE as i64 + 1,
+            i64::MAX,
+        ] {
+            let far = LimitOrderOptions::new(Side::Sell, 5, 70, None, None, AccountId(3))
+                .with_peg(PegReference::Mid, offset);
+            let err = ob.limit(far).unwrap_err();
+            assert_eq!(err.code, ErrorType::InvalidPeg.code());
+        }
+        // Within it, a sell peg stops at the highest price
+        let report = ob
+            .limit(
+                LimitOrderOptions::new(Side::Sell, 5, 70, None, None, AccountId(3))
+                    .with_peg(PegReference::Mid, MAX_PRICE as i64),
+            )
+            .unwrap();
+        assert_eq!(report.price, Price(MAX_PRICE));
+    }
+
+    #[test]
+    fn repriced_pegs_queue_last_and_replay_the_same() {
+        let mut ob = OrderBookBuilder::new("outcome")
+            .with_journaling(true)
+            .build();
+        let mut logs = Vec::new();
+        let mut place = |ob: &mut OrderBook, options| {
+            let report = ob.limit(options).unwrap();
+            logs.extend(report.log);
+            report.order_id
+        };
+        place(
+            &mut ob,
+            LimitOrderOptions::new(Side::Buy, 10, 40, None, None, AccountId(1)),
+        );
+        let peg = place(
+            &mut ob,
+            LimitOrderOptions::new(Side::Buy, 5, 99, None, None, AccountId(2))
+                .with_peg(PegReference::BestBid, 0),
+        );
+        let second = place(
+            &mut ob,
+            LimitOrderOptions::new(Side::Buy, 5, 99, None, None, AccountId(2))
+                .with_peg(PegReference::BestBid, 0),
+        );
+        let joined = place(
+            &mut ob,
+            LimitOrderOptions::new(Side::Buy, 5, 42, None, None, AccountId(3)),
+        );
+        let queue = |ob: &OrderBook| -> Vec<OrderId> {
+            ob.get_orders_at_price(Price(42), Side::Buy)
+                .iter()
+                .map(|o| o.id)
+                .collect()
+        };
+        // Both pegs moved up in arrival order, behind the order that set
+        // the new best bid
+        assert_eq!(queue(&ob), vec![joined, peg, second]);
+        assert_eq!(ob.take_repriced().len(), 2);
+
+        // Pegs never reference each other, so they follow the best bid
+        // back down once its order leaves
+        let report = ob.cancel(joined).unwrap();
+        logs.extend(report.log);
+        assert_eq!(ob.get_order(peg).unwrap().price, Price(40));
+
+        let restored = OrderBookBuilder::new("outcome")
+            .with_snapshot(ob.snapshot())
+            .build();
+        let mut replayed = OrderBookBuilder::new("outcome").build();
+        replayed.replay_logs(logs).unwrap();
+        for mut book in [restored, replayed] {
+            assert_eq!(book.depth(None).bids, ob.depth(None).bids);
+            book.take_repriced();
+            book.limit_raw(Side::Buy, 1, 45, None, None, AccountId(1))
+                .unwrap();
+            let moves: Vec<OrderId> = book.take_repriced().iter().map(|r| r.order_id).collect();
+            assert_eq!(moves, vec![peg, second]);
+        }
+    }
+}
@@ -120,4 +120,13 @@ impl ExecutionReport {
             account_id: params.account_id,
         }
     }
+
+    /// Amount traded as the taker: price times quantity, summed over
+    /// `fills`.
+    pub fn notional(&self) -> u64 {
+        self.fills
+            .iter()
+            .map(|fill| fill.price.value() * fill.quantity.value())
+            .sum()
+    }
 }
@@ -0,0 +1,180 @@
+//! Synthetic prediction-market order flow.
+//!
+//! Shared by the criterion benchmarks and the `loadgen` binary so both
+//! exercise the engine with the same shape of traffic:
+//! - prices are cents in `1..=99`, clustered a few ticks around a fair
+//!   probability that drifts as a bounded random walk
+//! - sizes are heavy tailed: mostly a handful of contracts, occasionally
+//!   hundreds
+//! - a configurable share of takers are market orders, and of the remaining
+//!   operations a share are cancels of resting orders
+
+use rand::{Rng, SeedableRng, rngs::SmallRng};
+
+use crate::orderbook::{Side, order::AccountId};
+
+/// Lowest and highest tradable price of a binary outcome, in cents.
+pub const MIN_PRICE: u64 = 1;
+pub const MAX_PRICE: u64 = 99;
+
+/// Shape of the generated flow.
+#[derive(Debug, Clone, Copy)]
+pub struct WorkloadConfig {
+    /// Starting fair probability in cents.
+    pub fair_price: u64,
+    /// Share of operations that are market orders.
+    pub market_ratio: f64,
+    /// Share of operations that cancel a previously placed order.
+    pub cancel_ratio: f64,
+    /// Number of distinct accounts placing orders.
+    pub accounts: u64,
+    /// Largest size a single order may have.
+    pub max_quantity: u64,
+}
+
+impl Default for WorkloadConfig {
+    fn default() -> Self {
+        Self {
+            fair_price: 50,
+            market_ratio: 0.1,
+            cancel_ratio: 0.3,
+            accounts: 1_000,
+            max_quantity: 1_000,
+        }
+    }
+}
+
+/// One generated operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyntheticOrder {
+    Limit {
+        side: Side,
+        price: u64,
+        quantity: u64,
+        account_id: AccountId,
+    },
+    Market {
+        side: Side,
+        quantity: u64,
+        account_id: AccountId,
+    },
+    /// Cancel the resting order chosen by the consumer; `pick` is a uniform
+    /// random number it can use to select among the orders it knows about.
+    Cancel { pick: u64 },
+}
+
+/// Deterministic generator of [`SyntheticOrder`]s for a single outcome.
+pub struct OrderFlow {
+    rng: SmallRng,
+    config: WorkloadConfig,
+    fair_price: f64,
+}
+
+impl OrderFlow {
+    pub fn new(config: WorkloadConfig, seed: u64) -> Self {
+        Self {
+            rng: SmallRng::seed_from_u64(seed),
+            fair_price: config.fair_price.clamp(MIN_PRICE, MAX_PRICE) as f64,
+            config,
+        }
+    }
+
+    /// Current fair probability in cents.
+    pub fn fair_price(&self) -> u64 {
+        self.fair_price.round() as u64
+    }
+
+    pub fn next_order(&mut self) -> SyntheticOrder {
+        self.drift();
+        let roll: f64 = self.rng.r#gen();
+        if roll < self.config.cancel_ratio {
+            return SyntheticOrder::Cancel {
+                pick: self.rng.r#gen(),
+            };
+        }
+        let side = if self.rng.gen_bool(0.5) {
+            Side::Buy
+        } else {
+            Side::Sell
+        };
+        let quantity = self.quantity();
+        let account_id = AccountId(self.rng.gen_range(1..=self.config.accounts.max(1)));
+        if roll < self.config.cancel_ratio + self.config.market_ratio {
+            return SyntheticOrder::Market {
+                side,
+                quantity,
+                account_id,
+            };
+        }
+        SyntheticOrder::Limit {
+            side,
+            price: self.limit_price(side),
+            quantity,
+            account_id,
+        }
+    }
+
+    /// Bounded random walk of the fair probability, a fraction of a cent per step.
+    fn drift(&mut self) {
+        let step: f64 = self.rng.gen_range(-0.25..=0.25);
+        self.fair_price = (self.fair_price + step).clamp(MIN_PRICE as f64, MAX_PRICE as f64);
+    }
+
+    /// Passive orders rest a geometric number of ticks behind the fair price;
+    /// roughly one in ten crosses it and trades on arrival.
+    fn limit_price(&mut self, side: Side) -> u64 {
+        let mut ticks: i64 = 0;
+        while ticks < 20 && self.rng.gen_bool(0.6) {
+            ticks += 1;
+        }
+        if self.rng.gen_bool(0.1) {
+            ticks = -ticks.min(3);
+        }
+        let fair = self.fair_price.round() as i64;
+        let price = match side {
+            Side::Buy => fair - 1 - ticks,
+            Side::Sell => fair + 1 + ticks,
+        };
+        price.clamp(MIN_PRICE as i64, MAX_PRICE as i64) as u64
+    }
+
+    /// Pareto-distributed size with shape 1.5: median around 2 contracts,
+    /// with a long tail up to `max_quantity`.
+    fn quantity(&mut self) -> u64 {
+        let u: f64 = self.rng.gen_range(f64::EPSILON..1.0);
+        let size = u.powf(-1.0 / 1.5).floor() as u64;
+        size.clamp(1, self.config.max_quantity.max(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flow_is_deterministic_and_in_range() {
+        let config = WorkloadConfig::default();
+        let mut a = OrderFlow::new(config, 7);
+        let mut b = OrderFlow::new(config, 7);
+        let mut kinds = [0usize; 3];
+        for _ in 0..10_000 {
+            let order = a.next_order();
+            assert_eq!(order, b.next_order());
+            match order {
+                SyntheticOrder::Limit {
+                    price, quantity, ..
+                } => {
+                    assert!((MIN_PRICE..=MAX_PRICE).contains(&price));
+                    assert!((1..=config.max_quantity).contains(&quantity));
+                    kinds[0] += 1;
+                }
+                SyntheticOrder::Market { quantity, .. } => {
+                    assert!(quantity >= 1);
+                    kinds[1] += 1;
+                }
+                SyntheticOrder::Cancel { .. } => kinds[2] += 1,
+            }
+        }
+        assert!(kinds.iter().all(|count| *count > 0));
+    }
+}